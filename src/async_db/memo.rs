@@ -0,0 +1,306 @@
+//! Read-through caching for [`AsyncDB`] and [`AsyncNamespace`], see
+//! [`AsyncDB::get_or_insert_with`].
+//!
+//! Concurrent misses of the same key are de-duplicated with flights: the
+//! first task to miss a key computes its value while the others wait for it,
+//! then they read the value it set instead of computing it again. The same
+//! flights make [`AsyncDB::get_or_revalidate`] refresh a stale value only once,
+//! however many tasks read it meanwhile.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::sync::{Mutex as FlightLock, OwnedMutexGuard};
+
+use super::{AsyncDB, AsyncNamespace, Handle};
+use crate::db::errors::TransientError;
+
+/// The name of the namespace, `None` for the default keyspace, and the key
+/// of a value being computed.
+type FlightKey = (Option<String>, String);
+
+/// The computations of missing or stale values in progress.
+#[derive(Debug, Default)]
+pub(super) struct Flights {
+    locks: Mutex<HashMap<FlightKey, Arc<FlightLock<()>>>>,
+}
+
+/// The right to compute the value of a key, held until the computation ends.
+struct Flight {
+    flights: Arc<Flights>,
+    key: FlightKey,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl Flights {
+    /// Waits until no other task computes the value of `key`, then takes the
+    /// right to compute it.
+    async fn enter(self: &Arc<Self>, key: FlightKey) -> Flight {
+        let mut flight = Flight {
+            flights: Arc::clone(self),
+            key,
+            guard: None,
+        };
+        let lock = self.lock_of(&flight.key);
+        flight.guard = Some(lock.lock_owned().await);
+        flight
+    }
+
+    /// Takes the right to compute the value of `key`, `None` if another task
+    /// computes it.
+    fn try_enter(self: &Arc<Self>, key: FlightKey) -> Option<Flight> {
+        let mut flight = Flight {
+            flights: Arc::clone(self),
+            key,
+            guard: None,
+        };
+        flight.guard = Some(self.lock_of(&flight.key).try_lock_owned().ok()?);
+        Some(flight)
+    }
+
+    fn lock_of(&self, key: &FlightKey) -> Arc<FlightLock<()>> {
+        let mut locks = self.locks.lock().expect("Flights lock poisoned");
+        Arc::clone(locks.entry(key.clone()).or_default())
+    }
+
+    /// Forgets the lock of `key` once no task holds or waits for it.
+    fn release(&self, key: &FlightKey) {
+        let mut locks = self.locks.lock().expect("Flights lock poisoned");
+        if locks.get(key).is_some_and(|l| Arc::strong_count(l) == 1) {
+            locks.remove(key);
+        }
+    }
+}
+
+impl Drop for Flight {
+    fn drop(&mut self) {
+        // NOTE: The guard holds the lock alive, drop it before looking for other holders.
+        self.guard = None;
+        self.flights.release(&self.key);
+    }
+}
+
+/// Returns whether a value expiring at `deadline` entered its last `stale`
+/// period, in which it is served while being computed again.
+fn is_stale(deadline: Option<u64>, stale: Duration) -> bool {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Cant get SystemTime")
+        .as_secs();
+    deadline.is_some_and(|d| d <= now + stale.as_secs())
+}
+
+impl Handle {
+    async fn cached(&self, key: &str) -> Result<Option<(String, Option<u64>)>, TransientError> {
+        let key = key.to_string();
+        self.run(move |db, ks| db.get_cached_in(ks, &key)).await
+    }
+
+    fn flight_key(&self, key: &str) -> FlightKey {
+        (self.name.clone(), key.to_string())
+    }
+
+    async fn get_or_insert_with<F, Fut, E>(
+        &self,
+        key: &str,
+        ttl: Option<Duration>,
+        f: F,
+    ) -> Result<String, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<String, E>>,
+        E: From<TransientError>,
+    {
+        if let Some((val, _)) = self.cached(key).await? {
+            return Ok(val);
+        }
+        self.insert_with(key, ttl, f).await
+    }
+
+    /// Computes the missing value of `key` with `f` and sets it, unless
+    /// another task did it first.
+    async fn insert_with<F, Fut, E>(
+        &self,
+        key: &str,
+        ttl: Option<Duration>,
+        f: F,
+    ) -> Result<String, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<String, E>>,
+        E: From<TransientError>,
+    {
+        let _flight = self.flights.enter(self.flight_key(key)).await;
+        // NOTE: The task that held the flight before this one may have set the value.
+        if let Some((val, _)) = self.cached(key).await? {
+            return Ok(val);
+        }
+        let val = f().await?;
+        self.set(key, &val, ttl).await?;
+        Ok(val)
+    }
+
+    async fn get_or_revalidate<F, Fut, E>(
+        &self,
+        key: &str,
+        ttl: Duration,
+        stale: Duration,
+        f: F,
+    ) -> Result<String, E>
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<String, E>> + Send + 'static,
+        E: From<TransientError>,
+    {
+        let lifetime = ttl + stale;
+        let Some((val, deadline)) = self.cached(key).await? else {
+            return self.insert_with(key, Some(lifetime), f).await;
+        };
+
+        if is_stale(deadline, stale)
+            && let Some(flight) = self.flights.try_enter(self.flight_key(key))
+        {
+            let (handle, key) = (self.clone(), key.to_string());
+            tokio::spawn(async move {
+                let _flight = flight;
+                // NOTE: The value may have been refreshed since it was read.
+                if let Ok(Some((_, deadline))) = handle.cached(&key).await
+                    && !is_stale(deadline, stale)
+                {
+                    return;
+                }
+                // NOTE: On failure the stale value is served until it expires.
+                let Ok(val) = f().await else {
+                    return;
+                };
+                let _ = handle.set(&key, &val, Some(lifetime)).await;
+            });
+        }
+        Ok(val)
+    }
+}
+
+impl AsyncDB {
+    /// Retrieves the value for a given key, or computes it with `f` and sets
+    /// it with `ttl` if the key doesn't exist or its TTL elapsed, like
+    /// [`DB::try_get_or_insert_with`](crate::DB::try_get_or_insert_with).
+    ///
+    /// Concurrent calls missing the same key compute it only once: the first
+    /// one runs `f` while the others wait for the value it sets. If `f` fails,
+    /// nothing is set and the next waiting call runs its own `f`.
+    ///
+    /// ```no_run
+    /// use epoch_db::{async_db::AsyncDB, db::errors::TransientError};
+    /// use std::{path::Path, time::Duration};
+    ///
+    /// # async fn run() -> Result<(), TransientError> {
+    /// let db = AsyncDB::open(Path::new("./my_database")).await?;
+    /// let name = db
+    ///     .get_or_insert_with("user:1:name", Some(Duration::from_secs(60)), || async {
+    ///         Ok::<_, TransientError>("Alice".to_string())
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of `f`, or an error if the value cannot be
+    /// retrieved, is not valid UTF-8 or cannot be set.
+    pub async fn get_or_insert_with<F, Fut, E>(
+        &self,
+        key: &str,
+        ttl: Option<Duration>,
+        f: F,
+    ) -> Result<String, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<String, E>>,
+        E: From<TransientError>,
+    {
+        self.handle.get_or_insert_with(key, ttl, f).await
+    }
+
+    /// Retrieves the value for a given key like [`AsyncDB::get_or_insert_with`],
+    /// serving it for `stale` more after `ttl` while it is computed again.
+    ///
+    /// The value is set with a TTL of `ttl + stale`. Once less than `stale`
+    /// of it remains, a read returns the value right away and computes it
+    /// again with `f` in the background, resetting its TTL. A value that
+    /// isn't read during its stale period expires, and the next read
+    /// computes it like a miss.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of `f` on a miss, or an error if the value cannot
+    /// be retrieved, is not valid UTF-8 or cannot be set. Errors of the
+    /// background computations are ignored.
+    pub async fn get_or_revalidate<F, Fut, E>(
+        &self,
+        key: &str,
+        ttl: Duration,
+        stale: Duration,
+        f: F,
+    ) -> Result<String, E>
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<String, E>> + Send + 'static,
+        E: From<TransientError>,
+    {
+        self.handle.get_or_revalidate(key, ttl, stale, f).await
+    }
+}
+
+impl AsyncNamespace {
+    /// Retrieves the value for a given key, or computes it with `f` and sets
+    /// it with `ttl` if the key doesn't exist or its TTL elapsed, see
+    /// [`AsyncDB::get_or_insert_with`].
+    ///
+    /// If `ttl` is `None`, the default TTL of the namespace is used, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of `f`, or an error if the namespace was dropped,
+    /// the value cannot be retrieved, is not valid UTF-8 or cannot be set.
+    pub async fn get_or_insert_with<F, Fut, E>(
+        &self,
+        key: &str,
+        ttl: Option<Duration>,
+        f: F,
+    ) -> Result<String, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<String, E>>,
+        E: From<TransientError>,
+    {
+        self.handle.get_or_insert_with(key, ttl, f).await
+    }
+
+    /// Retrieves the value for a given key like
+    /// [`AsyncNamespace::get_or_insert_with`], serving it for `stale` more
+    /// after `ttl` while it is computed again, see [`AsyncDB::get_or_revalidate`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of `f` on a miss, or an error if the namespace was
+    /// dropped, the value cannot be retrieved, is not valid UTF-8 or cannot be set.
+    pub async fn get_or_revalidate<F, Fut, E>(
+        &self,
+        key: &str,
+        ttl: Duration,
+        stale: Duration,
+        f: F,
+    ) -> Result<String, E>
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<String, E>> + Send + 'static,
+        E: From<TransientError>,
+    {
+        self.handle.get_or_revalidate(key, ttl, stale, f).await
+    }
+}
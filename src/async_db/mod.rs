@@ -0,0 +1,939 @@
+//! The `async_db` module provides [`AsyncDB`], a wrapper of `DB` for
+//! tokio-based services. It is only available with the `async` feature.
+//!
+//! Every operation of `DB` may block on disk, so each one is run on tokio's
+//! blocking thread pool with `spawn_blocking` instead of on the reactor.
+//! Reading watchers and flushes, including the ones of [`Durability::Flushed`]
+//! writes, are truly asynchronous and don't use a blocking thread.
+//!
+//! Snapshots and leases have no asynchronous version, since they borrow the
+//! `DB` until they are dropped, nor do the Redis imports. Use them through
+//! [`AsyncDB::db`] from a blocking task instead.
+
+mod memo;
+
+use std::{
+    io::{Read, Write},
+    panic,
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use tokio::task;
+
+use memo::Flights;
+
+use crate::{
+    DB, DBConfig, Durability, Metadata,
+    db::{
+        batch::Entry,
+        blob::ValueReader,
+        errors::TransientError,
+        export::{ExportOptions, ImportOptions, ImportReport},
+        keyspace::Keyspace,
+        verify::VerifyReport,
+    },
+    namespace::{Namespace, NamespaceConfig, NamespaceStats},
+    watch::Watcher,
+};
+
+/// An asynchronous handle to a [`DB`].
+///
+/// It is cheap to clone, every clone shares the same database. The
+/// underlying `DB` is closed once the last handle is dropped, on the blocking
+/// thread pool when dropped inside a tokio runtime, so reopening the database
+/// right after may have to wait for it to be closed.
+///
+/// ```no_run
+/// use epoch_db::async_db::AsyncDB;
+/// use std::path::Path;
+///
+/// # async fn run() -> Result<(), epoch_db::db::errors::TransientError> {
+/// let db = AsyncDB::open(Path::new("./my_database")).await?;
+/// db.set("user:1", "Alice", None).await?;
+/// assert_eq!(Some("Alice".to_string()), db.get("user:1").await?);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct AsyncDB {
+    handle: Handle,
+}
+
+/// An asynchronous handle to a namespace, returned by [`AsyncDB::namespace`].
+#[derive(Debug, Clone)]
+pub struct AsyncNamespace {
+    handle: Handle,
+}
+
+/// The keyspace an async handle operates on.
+#[derive(Debug, Clone)]
+struct Handle {
+    db: Arc<DB>,
+    /// Name of the namespace, `None` for the default keyspace
+    name: Option<String>,
+    /// The computations of missing values in progress, shared by every
+    /// handle of the `AsyncDB`
+    flights: Arc<Flights>,
+}
+
+impl Handle {
+    /// Runs `f` against the current keyspace on the blocking thread pool.
+    async fn run<T: Send + 'static>(
+        &self,
+        f: impl Fn(&DB, &Keyspace) -> Result<T, TransientError> + Send + 'static,
+    ) -> Result<T, TransientError> {
+        let db = Arc::clone(&self.db);
+        let name = self.name.clone();
+
+        blocking(move || match name {
+            None => f(&db, &db.keyspace),
+            Some(name) => Namespace { db: &db, name }.with_keyspace(|ks| f(&db, ks)),
+        })
+        .await
+    }
+
+    async fn set(&self, key: &str, val: &str, ttl: Option<Duration>) -> Result<(), TransientError> {
+        let (key, val) = (key.to_string(), val.to_string());
+        self.run(move |db, ks| db.set_in(ks, &key, &val, ttl))
+            .await?;
+        self.persist().await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<String>, TransientError> {
+        let key = key.to_string();
+        self.run(move |db, ks| db.get_in(ks, &key)).await
+    }
+
+    async fn increment_frequency(&self, key: &str) -> Result<(), TransientError> {
+        let key = key.to_string();
+        self.run(move |db, ks| db.increment_frequency_in(ks, &key))
+            .await?;
+        self.persist().await
+    }
+
+    async fn remove(&self, key: &str) -> Result<(), TransientError> {
+        let key = key.to_string();
+        self.run(move |db, ks| db.remove_in(ks, &key)).await?;
+        self.persist().await
+    }
+
+    async fn get_metadata(&self, key: &str) -> Result<Option<Metadata>, TransientError> {
+        let key = key.to_string();
+        self.run(move |db, ks| db.get_metadata_in(ks, &key)).await
+    }
+
+    async fn set_reader(
+        &self,
+        key: &str,
+        reader: impl Read + Send + 'static,
+        ttl: Option<Duration>,
+    ) -> Result<(), TransientError> {
+        let db = Arc::clone(&self.db);
+        let (name, key) = (self.name.clone(), key.to_string());
+
+        blocking(move || {
+            // NOTE: The content can only be read once, so a clear racing the write isn't retried.
+            let ks = match name {
+                None => Arc::clone(&db.keyspace),
+                Some(name) => db.resolve_namespace(&name)?,
+            };
+            db.set_reader_in(&ks, &key, reader, ttl)
+        })
+        .await?;
+        self.persist().await
+    }
+
+    async fn get_reader(&self, key: &str) -> Result<Option<ValueReader>, TransientError> {
+        let key = key.to_string();
+        self.run(move |db, ks| db.get_reader_in(ks, &key)).await
+    }
+
+    async fn get_with_version(&self, key: &str) -> Result<Option<(String, u64)>, TransientError> {
+        let key = key.to_string();
+        self.run(move |db, ks| db.get_with_version_in(ks, &key))
+            .await
+    }
+
+    async fn set_if_version(
+        &self,
+        key: &str,
+        val: &str,
+        ttl: Option<Duration>,
+        expected_version: u64,
+    ) -> Result<u64, TransientError> {
+        let (key, val) = (key.to_string(), val.to_string());
+        let version = self
+            .run(move |db, ks| db.set_if_version_in(ks, &key, &val, ttl, expected_version))
+            .await?;
+        self.persist().await?;
+        Ok(version)
+    }
+
+    async fn set_tags(&self, key: &str, tags: &[&str]) -> Result<bool, TransientError> {
+        let key = key.to_string();
+        let tags: Vec<String> = tags.iter().map(|t| t.to_string()).collect();
+        let found = self
+            .run(move |db, ks| {
+                let tags: Vec<&str> = tags.iter().map(String::as_str).collect();
+                db.set_tags_in(ks, &key, &tags)
+            })
+            .await?;
+        self.persist().await?;
+        Ok(found)
+    }
+
+    async fn keys_with_tag(&self, tag: &str) -> Result<Vec<String>, TransientError> {
+        let tag = tag.to_string();
+        self.run(move |db, ks| db.keys_with_tag_in(ks, &tag)).await
+    }
+
+    async fn remove_by_tag(&self, tag: &str) -> Result<usize, TransientError> {
+        let tag = tag.to_string();
+        let removed = self
+            .run(move |db, ks| db.remove_by_tag_in(ks, &tag))
+            .await?;
+        self.persist().await?;
+        Ok(removed)
+    }
+
+    async fn mset(&self, items: &[(&str, &str, Option<Duration>)]) -> Result<(), TransientError> {
+        let items: Vec<(String, String, Option<Duration>)> = items
+            .iter()
+            .map(|(key, val, ttl)| (key.to_string(), val.to_string(), *ttl))
+            .collect();
+
+        self.run(move |db, ks| {
+            let items: Vec<(&str, &str, Option<Duration>)> = items
+                .iter()
+                .map(|(key, val, ttl)| (key.as_str(), val.as_str(), *ttl))
+                .collect();
+            db.mset_in(ks, &items)
+        })
+        .await?;
+        self.persist().await
+    }
+
+    async fn mget(&self, keys: &[&str]) -> Result<Vec<Option<Entry>>, TransientError> {
+        let keys: Vec<String> = keys.iter().map(|key| key.to_string()).collect();
+
+        self.run(move |db, ks| {
+            let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+            db.mget_in(ks, &keys)
+        })
+        .await
+    }
+
+    async fn mremove(&self, keys: &[&str]) -> Result<usize, TransientError> {
+        let keys: Vec<String> = keys.iter().map(|key| key.to_string()).collect();
+
+        let removed = self
+            .run(move |db, ks| {
+                let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+                db.mremove_in(ks, &keys)
+            })
+            .await?;
+        self.persist().await?;
+        Ok(removed)
+    }
+
+    async fn rank(&self, k: usize, hottest: bool) -> Result<Vec<(String, u64)>, TransientError> {
+        self.run(move |db, ks| db.rank_in(ks, k, hottest)).await
+    }
+
+    async fn stats(&self) -> Result<NamespaceStats, TransientError> {
+        self.run(|_, ks| Ok(NamespaceStats::collect(ks))).await
+    }
+
+    /// Flushes the writes made so far if the `DB` defaults to
+    /// [`Durability::Flushed`].
+    async fn persist(&self) -> Result<(), TransientError> {
+        if self.db.durability == Durability::Flushed {
+            flush(&self.db).await?;
+        }
+        Ok(())
+    }
+
+    fn watch(&self, prefix: &str, expirations_only: bool) -> Watcher {
+        Watcher::new(
+            &self.db,
+            self.name.as_deref(),
+            prefix.as_bytes(),
+            expirations_only,
+        )
+    }
+}
+
+/// Flushes every buffered write of `db` without blocking the thread.
+async fn flush(db: &DB) -> Result<usize, TransientError> {
+    db.sled_db
+        .flush_async()
+        .await
+        .map_err(|e| TransientError::SledError { error: e })
+}
+
+/// Runs `f` on the blocking thread pool, resuming its panic if it panicked.
+pub(crate) async fn blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    task::spawn_blocking(f)
+        .await
+        .unwrap_or_else(|e| panic::resume_unwind(e.into_panic()))
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        // NOTE: Closing the `DB` joins its background thread and flushes it, which blocks.
+        if Arc::strong_count(&self.db) == 1
+            && let Ok(runtime) = tokio::runtime::Handle::try_current()
+        {
+            let db = Arc::clone(&self.db);
+            runtime.spawn_blocking(move || drop(db));
+        }
+    }
+}
+
+impl AsyncDB {
+    /// Wraps an open `DB`.
+    pub fn new(db: DB) -> AsyncDB {
+        AsyncDB::from(Arc::new(db))
+    }
+
+    /// Creates a new database or opens an existing one at the specified path,
+    /// see [`DB::new`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database cannot be opened at the given path.
+    pub async fn open(path: &Path) -> Result<AsyncDB, TransientError> {
+        AsyncDB::with_config(DBConfig::new(path)).await
+    }
+
+    /// Creates a new database or opens an existing one using the given
+    /// configuration, see [`DB::with_config`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database cannot be opened at the configured path.
+    pub async fn with_config(config: DBConfig) -> Result<AsyncDB, TransientError> {
+        let db = blocking(move || {
+            DB::with_config(config).map_err(|e| TransientError::SledError { error: e })
+        })
+        .await?;
+        Ok(AsyncDB::new(db))
+    }
+
+    /// Returns the underlying `DB`, to run operations that have no async
+    /// version such as replication.
+    pub fn db(&self) -> &Arc<DB> {
+        &self.handle.db
+    }
+
+    /// Sets a key-value pair with an optional Time-To-Live (TTL), see [`DB::set`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transaction fails.
+    pub async fn set(
+        &self,
+        key: &str,
+        val: &str,
+        ttl: Option<Duration>,
+    ) -> Result<(), TransientError> {
+        self.handle.set(key, val, ttl).await
+    }
+
+    /// Retrieves the value for a given key, see [`DB::get`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value cannot be retrieved or is not valid UTF-8.
+    pub async fn get(&self, key: &str) -> Result<Option<String>, TransientError> {
+        self.handle.get(key).await
+    }
+
+    /// Atomically increments the frequency counter for a given key, see
+    /// [`DB::increment_frequency`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key does not exist or the transaction fails.
+    pub async fn increment_frequency(&self, key: &str) -> Result<(), TransientError> {
+        self.handle.increment_frequency(key).await
+    }
+
+    /// Removes a key-value pair and its metadata, see [`DB::remove`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transaction fails.
+    pub async fn remove(&self, key: &str) -> Result<(), TransientError> {
+        self.handle.remove(key).await
+    }
+
+    /// Retrieves the metadata for a given key, see [`DB::get_metadata`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the metadata cannot be retrieved or deserialized.
+    pub async fn get_metadata(&self, key: &str) -> Result<Option<Metadata>, TransientError> {
+        self.handle.get_metadata(key).await
+    }
+
+    /// Sets a key to the content of `reader`, see [`DB::set_reader`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `reader` fails, the content is not valid UTF-8 or
+    /// the transaction fails.
+    pub async fn set_reader(
+        &self,
+        key: &str,
+        reader: impl Read + Send + 'static,
+        ttl: Option<Duration>,
+    ) -> Result<(), TransientError> {
+        self.handle.set_reader(key, reader, ttl).await
+    }
+
+    /// Returns a reader streaming the value of a key, see [`DB::get_reader`].
+    ///
+    /// Reading it blocks on disk, so it should be read from a blocking task.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value cannot be retrieved.
+    pub async fn get_reader(&self, key: &str) -> Result<Option<ValueReader>, TransientError> {
+        self.handle.get_reader(key).await
+    }
+
+    /// Retrieves the value for a given key along with its version, see
+    /// [`DB::get_with_version`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value cannot be retrieved or is not valid UTF-8.
+    pub async fn get_with_version(
+        &self,
+        key: &str,
+    ) -> Result<Option<(String, u64)>, TransientError> {
+        self.handle.get_with_version(key).await
+    }
+
+    /// Sets a key-value pair if the version of the key is still
+    /// `expected_version`, returning its new version, see
+    /// [`DB::set_if_version`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TransientError::VersionConflict`] if the key changed since
+    /// its version was read, or an error if the transaction fails.
+    pub async fn set_if_version(
+        &self,
+        key: &str,
+        val: &str,
+        ttl: Option<Duration>,
+        expected_version: u64,
+    ) -> Result<u64, TransientError> {
+        self.handle
+            .set_if_version(key, val, ttl, expected_version)
+            .await
+    }
+
+    /// Replaces the tags of a key, see [`DB::set_tags`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the tags are invalid or the transaction fails.
+    pub async fn set_tags(&self, key: &str, tags: &[&str]) -> Result<bool, TransientError> {
+        self.handle.set_tags(key, tags).await
+    }
+
+    /// Returns the keys carrying `tag`, see [`DB::keys_with_tag`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the tag index cannot be read.
+    pub async fn keys_with_tag(&self, tag: &str) -> Result<Vec<String>, TransientError> {
+        self.handle.keys_with_tag(tag).await
+    }
+
+    /// Removes every key carrying `tag` in a single transaction, see
+    /// [`DB::remove_by_tag`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the tag index cannot be read or the transaction fails.
+    pub async fn remove_by_tag(&self, tag: &str) -> Result<usize, TransientError> {
+        self.handle.remove_by_tag(tag).await
+    }
+
+    /// Sets many key-value pairs in a single transaction, see [`DB::mset`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transaction fails.
+    pub async fn mset(
+        &self,
+        items: &[(&str, &str, Option<Duration>)],
+    ) -> Result<(), TransientError> {
+        self.handle.mset(items).await
+    }
+
+    /// Retrieves the values and metadata of many keys, see [`DB::mget`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the values cannot be retrieved or decoded.
+    pub async fn mget(&self, keys: &[&str]) -> Result<Vec<Option<Entry>>, TransientError> {
+        self.handle.mget(keys).await
+    }
+
+    /// Removes many keys in a single transaction, see [`DB::mremove`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transaction fails.
+    pub async fn mremove(&self, keys: &[&str]) -> Result<usize, TransientError> {
+        self.handle.mremove(keys).await
+    }
+
+    /// Returns the `k` most frequently accessed keys, see [`DB::top_k_by_frequency`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the index cannot be read.
+    pub async fn top_k_by_frequency(&self, k: usize) -> Result<Vec<(String, u64)>, TransientError> {
+        self.handle.rank(k, true).await
+    }
+
+    /// Returns the `k` least frequently accessed keys, see [`DB::coldest`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the index cannot be read.
+    pub async fn coldest(&self, k: usize) -> Result<Vec<(String, u64)>, TransientError> {
+        self.handle.rank(k, false).await
+    }
+
+    /// Returns the statistics of the default keyspace, see [`DB::stats`].
+    pub async fn stats(&self) -> NamespaceStats {
+        let db = Arc::clone(&self.handle.db);
+        blocking(move || db.stats()).await
+    }
+
+    /// Returns the sequence number of the most recent entry in the mutation
+    /// log, see [`DB::last_seq`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the sequence counter cannot be read.
+    pub async fn last_seq(&self) -> Result<u64, TransientError> {
+        let db = Arc::clone(&self.handle.db);
+        blocking(move || db.head_seq()).await
+    }
+
+    /// Writes every key along with its value and metadata to `writer`,
+    /// returning the number of keys exported, see [`DB::export`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the keys cannot be read, a value is not valid
+    /// UTF-8 or `writer` fails.
+    pub async fn export(
+        &self,
+        writer: impl Write + Send + 'static,
+        options: &ExportOptions,
+    ) -> Result<u64, TransientError> {
+        let (db, options) = (Arc::clone(&self.handle.db), options.clone());
+        blocking(move || db.export_records(writer, &options)).await
+    }
+
+    /// Writes the keys read from `reader`, as written by [`AsyncDB::export`],
+    /// see [`DB::import`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a record cannot be read, in which case the keys
+    /// before it stay imported, or a key cannot be written.
+    pub async fn import(
+        &self,
+        reader: impl Read + Send + 'static,
+        options: &ImportOptions,
+    ) -> Result<ImportReport, TransientError> {
+        let (db, options) = (Arc::clone(&self.handle.db), options.clone());
+        let report = blocking(move || db.import_records(reader, &options)).await?;
+        self.handle.persist().await?;
+        Ok(report)
+    }
+
+    /// Checks that the trees of every keyspace agree with each other, see
+    /// [`DB::verify`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a tree cannot be read.
+    pub async fn verify(&self) -> Result<VerifyReport, TransientError> {
+        let db = Arc::clone(&self.handle.db);
+        blocking(move || db.verify_trees()).await
+    }
+
+    /// Returns a handle to the namespace `name`, creating it with the default
+    /// settings if it doesn't exist yet, see [`DB::namespace`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the name is empty or the namespace trees cannot be opened.
+    pub async fn namespace(&self, name: &str) -> Result<AsyncNamespace, TransientError> {
+        self.open_namespace(name, None).await
+    }
+
+    /// Returns a handle to the namespace `name`, creating it or updating its
+    /// settings with `config`, see [`DB::namespace_with`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the name is empty or the namespace trees cannot be opened.
+    pub async fn namespace_with(
+        &self,
+        name: &str,
+        config: NamespaceConfig,
+    ) -> Result<AsyncNamespace, TransientError> {
+        self.open_namespace(name, Some(config)).await
+    }
+
+    /// Returns the names of every namespace, in alphabetical order.
+    pub fn namespaces(&self) -> Vec<String> {
+        self.handle.db.namespaces()
+    }
+
+    /// Drops the namespace `name` and every key it holds, see [`DB::drop_namespace`].
+    ///
+    /// Returns false if the namespace doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace trees cannot be dropped.
+    pub async fn drop_namespace(&self, name: &str) -> Result<bool, TransientError> {
+        let db = Arc::clone(&self.handle.db);
+        let name = name.to_string();
+        blocking(move || db.retire_namespace(&name, true, true)).await
+    }
+
+    /// Watches the changes made to the keys of the default keyspace starting
+    /// with `prefix`, see [`DB::watch`].
+    ///
+    /// The returned `Watcher` is a `Stream`.
+    pub fn watch(&self, prefix: &str) -> Watcher {
+        self.handle.watch(prefix, false)
+    }
+
+    /// Watches the keys of the default keyspace starting with `prefix` that
+    /// expire, see [`DB::watch_expirations`].
+    pub fn watch_expirations(&self, prefix: &str) -> Watcher {
+        self.handle.watch(prefix, true)
+    }
+
+    /// Flushes every pending write to disk, returning the number of bytes flushed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data cannot be written to disk.
+    pub async fn flush(&self) -> Result<usize, TransientError> {
+        flush(&self.handle.db).await
+    }
+
+    async fn open_namespace(
+        &self,
+        name: &str,
+        config: Option<NamespaceConfig>,
+    ) -> Result<AsyncNamespace, TransientError> {
+        let db = Arc::clone(&self.handle.db);
+        let name = name.to_string();
+
+        let name = blocking(move || {
+            db.open_namespace(&name, config, true)?;
+            Ok(name)
+        })
+        .await?;
+
+        Ok(AsyncNamespace {
+            handle: Handle {
+                db: Arc::clone(&self.handle.db),
+                name: Some(name),
+                flights: Arc::clone(&self.handle.flights),
+            },
+        })
+    }
+}
+
+impl From<Arc<DB>> for AsyncDB {
+    /// Wraps a `DB` that is shared with other threads, such as a
+    /// `ReplicationPrimary`.
+    fn from(db: Arc<DB>) -> AsyncDB {
+        AsyncDB {
+            handle: Handle {
+                db,
+                name: None,
+                flights: Arc::default(),
+            },
+        }
+    }
+}
+
+impl AsyncNamespace {
+    /// Returns the name of the namespace.
+    pub fn name(&self) -> &str {
+        self.handle.name.as_deref().unwrap_or_default()
+    }
+
+    /// Returns the settings of the namespace.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped.
+    pub fn config(&self) -> Result<NamespaceConfig, TransientError> {
+        Ok(self
+            .handle
+            .db
+            .resolve_namespace(self.name())?
+            .config
+            .clone())
+    }
+
+    /// Sets a key-value pair with an optional Time-To-Live (TTL), see [`Namespace::set`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped or the transaction fails.
+    pub async fn set(
+        &self,
+        key: &str,
+        val: &str,
+        ttl: Option<Duration>,
+    ) -> Result<(), TransientError> {
+        self.handle.set(key, val, ttl).await
+    }
+
+    /// Retrieves the value for a given key, see [`Namespace::get`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped, the value cannot be
+    /// retrieved or is not valid UTF-8.
+    pub async fn get(&self, key: &str) -> Result<Option<String>, TransientError> {
+        self.handle.get(key).await
+    }
+
+    /// Atomically increments the frequency counter for a given key, see
+    /// [`Namespace::increment_frequency`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped, the key does not exist
+    /// or the transaction fails.
+    pub async fn increment_frequency(&self, key: &str) -> Result<(), TransientError> {
+        self.handle.increment_frequency(key).await
+    }
+
+    /// Removes a key-value pair and its metadata, see [`Namespace::remove`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped or the transaction fails.
+    pub async fn remove(&self, key: &str) -> Result<(), TransientError> {
+        self.handle.remove(key).await
+    }
+
+    /// Retrieves the metadata for a given key, see [`Namespace::get_metadata`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped or the metadata cannot
+    /// be retrieved or deserialized.
+    pub async fn get_metadata(&self, key: &str) -> Result<Option<Metadata>, TransientError> {
+        self.handle.get_metadata(key).await
+    }
+
+    /// Sets a key to the content of `reader`, see [`Namespace::set_reader`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped or cleared while the
+    /// content was read, `reader` fails, the content is not valid UTF-8 or
+    /// the transaction fails.
+    pub async fn set_reader(
+        &self,
+        key: &str,
+        reader: impl Read + Send + 'static,
+        ttl: Option<Duration>,
+    ) -> Result<(), TransientError> {
+        self.handle.set_reader(key, reader, ttl).await
+    }
+
+    /// Returns a reader streaming the value of a key, see
+    /// [`Namespace::get_reader`].
+    ///
+    /// Reading it blocks on disk, so it should be read from a blocking task.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped or the value cannot be
+    /// retrieved.
+    pub async fn get_reader(&self, key: &str) -> Result<Option<ValueReader>, TransientError> {
+        self.handle.get_reader(key).await
+    }
+
+    /// Retrieves the value for a given key along with its version, see
+    /// [`Namespace::get_with_version`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped, the value cannot be
+    /// retrieved or is not valid UTF-8.
+    pub async fn get_with_version(
+        &self,
+        key: &str,
+    ) -> Result<Option<(String, u64)>, TransientError> {
+        self.handle.get_with_version(key).await
+    }
+
+    /// Sets a key-value pair if the version of the key is still
+    /// `expected_version`, returning its new version, see
+    /// [`Namespace::set_if_version`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TransientError::VersionConflict`] if the key changed since
+    /// its version was read, or an error if the namespace was dropped or the
+    /// transaction fails.
+    pub async fn set_if_version(
+        &self,
+        key: &str,
+        val: &str,
+        ttl: Option<Duration>,
+        expected_version: u64,
+    ) -> Result<u64, TransientError> {
+        self.handle
+            .set_if_version(key, val, ttl, expected_version)
+            .await
+    }
+
+    /// Replaces the tags of a key, see [`Namespace::set_tags`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped, the tags are invalid or
+    /// the transaction fails.
+    pub async fn set_tags(&self, key: &str, tags: &[&str]) -> Result<bool, TransientError> {
+        self.handle.set_tags(key, tags).await
+    }
+
+    /// Returns the keys carrying `tag`, see [`Namespace::keys_with_tag`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped or the tag index cannot be read.
+    pub async fn keys_with_tag(&self, tag: &str) -> Result<Vec<String>, TransientError> {
+        self.handle.keys_with_tag(tag).await
+    }
+
+    /// Removes every key carrying `tag` in a single transaction, see
+    /// [`Namespace::remove_by_tag`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped, the tag index cannot be
+    /// read or the transaction fails.
+    pub async fn remove_by_tag(&self, tag: &str) -> Result<usize, TransientError> {
+        self.handle.remove_by_tag(tag).await
+    }
+
+    /// Sets many key-value pairs in a single transaction, see [`Namespace::mset`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped or the transaction fails.
+    pub async fn mset(
+        &self,
+        items: &[(&str, &str, Option<Duration>)],
+    ) -> Result<(), TransientError> {
+        self.handle.mset(items).await
+    }
+
+    /// Retrieves the values and metadata of many keys, see [`Namespace::mget`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped or the values cannot be
+    /// retrieved or decoded.
+    pub async fn mget(&self, keys: &[&str]) -> Result<Vec<Option<Entry>>, TransientError> {
+        self.handle.mget(keys).await
+    }
+
+    /// Removes many keys in a single transaction, see [`Namespace::mremove`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped or the transaction fails.
+    pub async fn mremove(&self, keys: &[&str]) -> Result<usize, TransientError> {
+        self.handle.mremove(keys).await
+    }
+
+    /// Removes every key of the namespace, keeping its settings, see [`Namespace::clear`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped or its trees cannot be replaced.
+    pub async fn clear(&self) -> Result<(), TransientError> {
+        let db = Arc::clone(&self.handle.db);
+        let name = self.name().to_string();
+
+        blocking(move || {
+            if !db.retire_namespace(&name, false, true)? {
+                Err(TransientError::NamespaceNotFound)?
+            }
+            Ok(())
+        })
+        .await?;
+        self.handle.persist().await
+    }
+
+    /// Returns the `k` most frequently accessed keys, see
+    /// [`Namespace::top_k_by_frequency`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped or its index cannot be read.
+    pub async fn top_k_by_frequency(&self, k: usize) -> Result<Vec<(String, u64)>, TransientError> {
+        self.handle.rank(k, true).await
+    }
+
+    /// Returns the `k` least frequently accessed keys, see [`Namespace::coldest`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped or its index cannot be read.
+    pub async fn coldest(&self, k: usize) -> Result<Vec<(String, u64)>, TransientError> {
+        self.handle.rank(k, false).await
+    }
+
+    /// Returns the statistics of the namespace, see [`Namespace::stats`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped.
+    pub async fn stats(&self) -> Result<NamespaceStats, TransientError> {
+        self.handle.stats().await
+    }
+
+    /// Watches the changes made to the keys of the namespace starting with
+    /// `prefix`, see [`Namespace::watch`].
+    pub fn watch(&self, prefix: &str) -> Watcher {
+        self.handle.watch(prefix, false)
+    }
+
+    /// Watches the keys of the namespace starting with `prefix` that expire,
+    /// see [`Namespace::watch_expirations`].
+    pub fn watch_expirations(&self, prefix: &str) -> Watcher {
+        self.handle.watch(prefix, true)
+    }
+}
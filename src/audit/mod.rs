@@ -0,0 +1,506 @@
+//! The `audit` module keeps an append-only record of the mutations applied to
+//! a `DB`, for compliance, see [`DBConfig::audit`] and [`DB::audit`].
+//!
+//! Every mutation is written ahead to the mutation log in the transaction
+//! that applies it, along with the actor set with [`act_as`] on the calling
+//! thread. The background thread copies the entries to the `audit_tree`,
+//! which is rotated according to an [`AuditConfig`] instead of being trimmed
+//! like the mutation log, and the mutation log keeps every entry until it is
+//! copied, so no mutation goes unrecorded if the process stops in between.
+//!
+//! Values are not recorded, only the keys and their metadata, so the audit
+//! log neither duplicates the data it describes nor exposes values stored
+//! encrypted.
+//!
+//! [`DBConfig::audit`]: crate::DBConfig::audit
+
+use std::{
+    cell::RefCell,
+    error::Error,
+    fmt::{self, Display, Formatter},
+    marker::PhantomData,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bincode::{
+    error::{DecodeError, EncodeError},
+    serde::{decode_from_slice, encode_to_vec},
+};
+use serde::{Deserialize, Serialize};
+use sled::{Batch, Tree};
+
+use crate::{
+    DB, Metadata,
+    db::{errors::TransientError, open_tree},
+    namespace::NamespaceConfig,
+    oplog::{Mutation, OPLOG_SEQ_KEY, OpLogEntry, read_seq},
+};
+
+/// Key in the `state_tree` holding the sequence number of the last entry of
+/// the mutation log copied to the audit log.
+pub(crate) const AUDIT_SEQ_KEY: &[u8] = b"audit_seq";
+
+thread_local! {
+    /// The actor of the mutations made by the current thread.
+    static ACTOR: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Records `actor` as the author of the mutations made by the current thread
+/// until the returned guard is dropped.
+///
+/// Guards can be nested, dropping one restores the actor set before it.
+/// Mutations made without an actor, such as expirations, are recorded
+/// without one.
+///
+/// ```no_run
+/// use epoch_db::{DB, audit};
+/// use std::path::Path;
+///
+/// let db = DB::new(Path::new("./my_database")).unwrap();
+/// let _actor = audit::act_as("alice");
+/// db.set("key", "value", None).unwrap();
+/// ```
+pub fn act_as(actor: &str) -> ActorGuard {
+    let previous = ACTOR.with(|a| a.replace(Some(actor.to_string())));
+    ActorGuard {
+        previous,
+        _not_send: PhantomData,
+    }
+}
+
+/// Restores the previous actor of the thread when dropped, see [`act_as`].
+#[derive(Debug)]
+#[must_use = "the actor is only set until the guard is dropped"]
+pub struct ActorGuard {
+    previous: Option<String>,
+    /// The actor belongs to the thread that set it
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for ActorGuard {
+    fn drop(&mut self) {
+        ACTOR.with(|a| *a.borrow_mut() = self.previous.take());
+    }
+}
+
+/// Returns the actor set on the current thread with [`act_as`].
+pub(crate) fn current_actor() -> Option<String> {
+    ACTOR.with(|a| a.borrow().clone())
+}
+
+/// Settings of the audit log, see [`DBConfig::audit`](crate::DBConfig::audit).
+///
+/// By default every record is kept forever and changes of the metadata alone,
+/// such as frequency updates, aren't recorded.
+#[derive(Debug, Clone, Default)]
+pub struct AuditConfig {
+    pub(crate) max_entries: Option<u64>,
+    pub(crate) max_age: Option<Duration>,
+    pub(crate) include_metadata: bool,
+}
+
+impl AuditConfig {
+    /// Keeps every record and leaves out changes of the metadata alone.
+    pub fn new() -> AuditConfig {
+        AuditConfig::default()
+    }
+
+    /// Only keeps the records of the last `entries` mutations.
+    pub fn max_entries(mut self, entries: u64) -> AuditConfig {
+        self.max_entries = Some(entries.max(1));
+        self
+    }
+
+    /// Drops the records older than `age`.
+    pub fn max_age(mut self, age: Duration) -> AuditConfig {
+        self.max_age = Some(age);
+        self
+    }
+
+    /// Also records the changes of the frequency or the tags of a key, which
+    /// are as frequent as the reads when frequencies are tracked automatically.
+    pub fn include_metadata(mut self, include: bool) -> AuditConfig {
+        self.include_metadata = include;
+        self
+    }
+}
+
+/// A mutation as recorded in the audit log.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum AuditEvent {
+    /// The key was written, which may have changed its TTL.
+    Set {
+        /// The key that was written.
+        key: Vec<u8>,
+        /// The metadata of the key after the write.
+        #[serde(with = "crate::metadata::envelope")]
+        meta: Metadata,
+    },
+    /// The metadata of the key changed while its value stayed the same.
+    Meta {
+        /// The key whose metadata changed.
+        key: Vec<u8>,
+        /// The metadata of the key after the change.
+        #[serde(with = "crate::metadata::envelope")]
+        meta: Metadata,
+    },
+    /// The key was removed by the caller.
+    Remove {
+        /// The key that was removed.
+        key: Vec<u8>,
+    },
+    /// The key was removed because its TTL elapsed.
+    Expire {
+        /// The key that expired.
+        key: Vec<u8>,
+    },
+    /// The key was removed by the eviction policy of its namespace.
+    Evict {
+        /// The key that was evicted.
+        key: Vec<u8>,
+    },
+    /// Every key of the namespace was removed.
+    Clear,
+    /// The namespace was dropped.
+    DropNamespace,
+    /// The settings of the namespace were replaced.
+    Configure {
+        /// The new settings of the namespace.
+        config: NamespaceConfig,
+    },
+}
+
+impl AuditEvent {
+    /// Returns the key the event applies to, `None` for events on a whole namespace.
+    pub fn key(&self) -> Option<&[u8]> {
+        match self {
+            AuditEvent::Set { key, .. }
+            | AuditEvent::Meta { key, .. }
+            | AuditEvent::Remove { key }
+            | AuditEvent::Expire { key }
+            | AuditEvent::Evict { key } => Some(key),
+            AuditEvent::Clear | AuditEvent::DropNamespace | AuditEvent::Configure { .. } => None,
+        }
+    }
+}
+
+/// An entry of the audit log.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AuditRecord {
+    /// The sequence number of the mutation in the mutation log.
+    pub seq: u64,
+    /// Timestamp of the mutation, in seconds since the UNIX epoch
+    pub timestamp: u64,
+    /// The namespace the mutation applies to, `None` for the default keyspace.
+    pub namespace: Option<String>,
+    /// The actor set with [`act_as`] by the thread that made the mutation.
+    pub actor: Option<String>,
+    /// The mutation itself.
+    pub event: AuditEvent,
+}
+
+impl AuditRecord {
+    /// Serializes the `AuditRecord` instance into a byte vector using `bincode`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `EncodeError` if serialization fails.
+    pub fn to_u8(&self) -> Result<Vec<u8>, EncodeError> {
+        encode_to_vec(self, bincode::config::standard())
+    }
+
+    /// Deserializes an `AuditRecord` instance from a byte slice using `bincode`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `DecodeError` if deserialization fails.
+    pub fn from_u8(slice: &[u8]) -> Result<AuditRecord, DecodeError> {
+        Ok(decode_from_slice(slice, bincode::config::standard())?.0)
+    }
+
+    /// Returns the record of `entry`, or `None` if it changes the metadata
+    /// alone and `include_metadata` isn't set.
+    fn from_entry(entry: OpLogEntry, include_metadata: bool) -> Option<AuditRecord> {
+        let event = match entry.mutation {
+            Mutation::Set { key, meta, .. } => AuditEvent::Set { key, meta },
+            Mutation::Meta { key, meta } if include_metadata => AuditEvent::Meta { key, meta },
+            Mutation::Meta { .. } => return None,
+            Mutation::Remove { key } => AuditEvent::Remove { key },
+            Mutation::Expire { key } => AuditEvent::Expire { key },
+            Mutation::Evict { key } => AuditEvent::Evict { key },
+            Mutation::Clear => AuditEvent::Clear,
+            Mutation::DropNamespace => AuditEvent::DropNamespace,
+            Mutation::Configure { config } => AuditEvent::Configure { config },
+        };
+        Some(AuditRecord {
+            seq: entry.seq,
+            timestamp: entry.timestamp,
+            namespace: entry.namespace,
+            actor: entry.actor,
+            event,
+        })
+    }
+}
+
+impl Display for AuditRecord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} ",
+            self.seq,
+            self.timestamp,
+            self.actor.as_deref().unwrap_or("-"),
+            self.namespace.as_deref().unwrap_or("-"),
+        )?;
+        let key = |key: &[u8]| String::from_utf8_lossy(key).into_owned();
+        match &self.event {
+            AuditEvent::Set { key: k, meta } => {
+                write!(f, "set {} version={}", key(k), meta.version)?;
+                match meta.ttl {
+                    Some(ttl) => write!(f, " ttl={ttl}"),
+                    None => Ok(()),
+                }
+            }
+            AuditEvent::Meta { key: k, meta } => {
+                write!(f, "meta {} freq={}", key(k), meta.freq)
+            }
+            AuditEvent::Remove { key: k } => write!(f, "remove {}", key(k)),
+            AuditEvent::Expire { key: k } => write!(f, "expire {}", key(k)),
+            AuditEvent::Evict { key: k } => write!(f, "evict {}", key(k)),
+            AuditEvent::Clear => write!(f, "clear"),
+            AuditEvent::DropNamespace => write!(f, "drop"),
+            AuditEvent::Configure { .. } => write!(f, "configure"),
+        }
+    }
+}
+
+/// Selects the records returned by [`DB::audit`].
+///
+/// Every record matches an empty query.
+#[derive(Debug, Clone, Default)]
+pub struct AuditQuery {
+    key: Option<Vec<u8>>,
+    namespace: Option<String>,
+    actor: Option<String>,
+    since: Option<u64>,
+    until: Option<u64>,
+}
+
+impl AuditQuery {
+    /// Matches every record.
+    pub fn new() -> AuditQuery {
+        AuditQuery::default()
+    }
+
+    /// Only matches the records of `key`, in any keyspace unless
+    /// [`AuditQuery::namespace`] is set.
+    pub fn key(mut self, key: &str) -> AuditQuery {
+        self.key = Some(key.as_bytes().to_vec());
+        self
+    }
+
+    /// Only matches the records of the namespace `name`.
+    pub fn namespace(mut self, name: &str) -> AuditQuery {
+        self.namespace = Some(name.to_string());
+        self
+    }
+
+    /// Only matches the records of the mutations made by `actor`.
+    pub fn actor(mut self, actor: &str) -> AuditQuery {
+        self.actor = Some(actor.to_string());
+        self
+    }
+
+    /// Only matches the records made at or after `timestamp`, in seconds since the UNIX epoch.
+    pub fn since(mut self, timestamp: u64) -> AuditQuery {
+        self.since = Some(timestamp);
+        self
+    }
+
+    /// Only matches the records made at or before `timestamp`, in seconds since the UNIX epoch.
+    pub fn until(mut self, timestamp: u64) -> AuditQuery {
+        self.until = Some(timestamp);
+        self
+    }
+
+    fn matches(&self, record: &AuditRecord) -> bool {
+        // NOTE: Clearing or dropping a namespace applies to every key of it.
+        self.key
+            .as_deref()
+            .is_none_or(|k| record.event.key().is_none_or(|key| key == k))
+            && self
+                .namespace
+                .as_deref()
+                .is_none_or(|n| record.namespace.as_deref() == Some(n))
+            && self
+                .actor
+                .as_deref()
+                .is_none_or(|a| record.actor.as_deref() == Some(a))
+            && self.since.is_none_or(|t| record.timestamp >= t)
+            && self.until.is_none_or(|t| record.timestamp <= t)
+    }
+}
+
+/// The records matching a query, in commit order, returned by [`DB::audit`].
+pub struct AuditRecords {
+    iter: sled::Iter,
+    query: AuditQuery,
+}
+
+impl Iterator for AuditRecords {
+    type Item = Result<AuditRecord, TransientError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for i in self.iter.by_ref() {
+            let record = i
+                .map_err(|e| TransientError::SledError { error: e })
+                .and_then(|(_, record)| {
+                    AuditRecord::from_u8(&record).map_err(|_| TransientError::ParsingFromByteError)
+                });
+            match record {
+                Ok(record) if !self.query.matches(&record) => continue,
+                res => return Some(res),
+            }
+        }
+        None
+    }
+}
+
+/// The `audit_tree` and its settings.
+#[derive(Debug)]
+pub(crate) struct AuditLog {
+    tree: Arc<Tree>,
+    /// `None` if mutations aren't audited
+    config: Option<AuditConfig>,
+    /// Serializes the copies made by the background thread and by queries
+    lock: Mutex<()>,
+}
+
+impl AuditLog {
+    /// Opens the `audit_tree` of `db`, without creating it if `read_only` is set.
+    ///
+    /// When auditing is enabled for the first time, the mutations committed
+    /// from then on are recorded, the earlier ones aren't.
+    pub(crate) fn open(
+        db: &sled::Db,
+        state_tree: &Tree,
+        config: Option<AuditConfig>,
+        read_only: bool,
+    ) -> Result<AuditLog, sled::Error> {
+        if config.is_some() && state_tree.get(AUDIT_SEQ_KEY)?.is_none() {
+            let head = read_seq(state_tree.get(OPLOG_SEQ_KEY)?.as_deref());
+            state_tree.insert(AUDIT_SEQ_KEY, &head.to_be_bytes())?;
+        }
+
+        Ok(AuditLog {
+            tree: Arc::new(open_tree(db, "audit_tree", read_only)?),
+            config,
+            lock: Mutex::new(()),
+        })
+    }
+
+    /// Returns the sequence number of the last entry of the mutation log
+    /// copied to the audit log, or `None` if mutations aren't audited.
+    ///
+    /// The mutation log must keep the entries after it.
+    pub(crate) fn audited_seq(&self, state_tree: &Tree) -> Result<Option<u64>, TransientError> {
+        if self.config.is_none() {
+            return Ok(None);
+        }
+        let seq = state_tree
+            .get(AUDIT_SEQ_KEY)
+            .map_err(|e| TransientError::SledError { error: e })?;
+        Ok(seq.map(|s| read_seq(Some(&s))))
+    }
+
+    /// Copies the entries of the mutation log added since the last copy.
+    pub(crate) fn sync(&self, oplog_tree: &Tree, state_tree: &Tree) -> Result<(), TransientError> {
+        let Some(config) = &self.config else {
+            return Ok(());
+        };
+        let _lock = self.lock.lock().expect("Audit lock poisoned");
+        let sled_err = |e| TransientError::SledError { error: e };
+
+        let Some(from) = self.audited_seq(state_tree)? else {
+            return Ok(());
+        };
+
+        let mut batch = Batch::default();
+        let mut last = from;
+        for i in oplog_tree.range((from + 1).to_be_bytes()..) {
+            let (_, entry) = i.map_err(sled_err)?;
+            let entry =
+                OpLogEntry::from_u8(&entry).map_err(|_| TransientError::ParsingFromByteError)?;
+            last = entry.seq;
+            if let Some(record) = AuditRecord::from_entry(entry, config.include_metadata) {
+                let bytes = record
+                    .to_u8()
+                    .map_err(|_| TransientError::ParsingToByteError)?;
+                batch.insert(&record.seq.to_be_bytes(), bytes);
+            }
+        }
+        if last == from {
+            return Ok(());
+        }
+
+        self.tree.apply_batch(batch).map_err(sled_err)?;
+        state_tree
+            .insert(AUDIT_SEQ_KEY, &last.to_be_bytes())
+            .map_err(sled_err)?;
+        Ok(())
+    }
+
+    /// Drops the records beyond the limits of the configuration.
+    pub(crate) fn rotate(&self) -> Result<(), TransientError> {
+        let Some(config) = &self.config else {
+            return Ok(());
+        };
+        let sled_err = |e| TransientError::SledError { error: e };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Cant get SystemTime")
+            .as_secs();
+        let oldest = config.max_age.map(|age| now.saturating_sub(age.as_secs()));
+        let Some((last, _)) = self.tree.last().map_err(sled_err)? else {
+            return Ok(());
+        };
+        let last = read_seq(Some(&last));
+
+        while let Some((seq, record)) = self.tree.first().map_err(sled_err)? {
+            let too_many = config
+                .max_entries
+                .is_some_and(|max| last - read_seq(Some(&seq)) >= max);
+            // NOTE: A record that cannot be decoded has no age, it is dropped as expired.
+            let too_old = oldest.is_some_and(|oldest| {
+                !AuditRecord::from_u8(&record).is_ok_and(|r| r.timestamp >= oldest)
+            });
+            if !too_many && !too_old {
+                break;
+            }
+            self.tree.remove(seq).map_err(sled_err)?;
+        }
+        Ok(())
+    }
+}
+
+impl DB {
+    /// Returns the records of the audit log matching `query`, in commit order.
+    ///
+    /// If mutations are audited, the mutations committed so far are recorded
+    /// first, so the records include every mutation made before the call.
+    /// Records already in the audit log can be read even if auditing was
+    /// disabled since.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the mutations cannot be recorded. Reading a
+    /// record may fail if it cannot be deserialized.
+    pub fn audit(&self, query: &AuditQuery) -> Result<AuditRecords, Box<dyn Error>> {
+        self.audit_log.sync(&self.oplog_tree, &self.state_tree)?;
+        Ok(AuditRecords {
+            iter: self.audit_log.tree.iter(),
+            query: query.clone(),
+        })
+    }
+}
@@ -1,3 +1,134 @@
-fn main() {
-    todo!();
+//! Command line tool to maintain an epoch-db database.
+//!
+//! ```text
+//! epoch verify <path> [--repair]
+//! epoch audit <path> [--key <key>] [--namespace <name>] [--actor <actor>]
+//!                    [--since <timestamp>] [--until <timestamp>]
+//! ```
+//!
+//! Commands that only inspect the database open it read-only, so they can
+//! inspect it while another process has it open, as of the last flush of
+//! that process.
+
+use std::{env, path::Path, process::ExitCode};
+
+use epoch_db::{DB, DBConfig, audit::AuditQuery};
+
+const USAGE: &str = "Usage: epoch verify <path> [--repair]
+       epoch audit <path> [--key <key>] [--namespace <name>] [--actor <actor>]
+                          [--since <timestamp>] [--until <timestamp>]";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match args.as_slice() {
+        ["verify", path] => verify(Path::new(path), false),
+        ["verify", path, "--repair"] | ["verify", "--repair", path] => {
+            verify(Path::new(path), true)
+        }
+        ["audit", path, filters @ ..] => match audit_query(filters) {
+            Some(query) => audit(Path::new(path), &query),
+            None => {
+                eprintln!("{USAGE}");
+                ExitCode::from(2)
+            }
+        },
+        _ => {
+            eprintln!("{USAGE}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// Checks the database at `path` and prints the inconsistencies found,
+/// fixing them if `repair` is set.
+///
+/// Exits with 1 if inconsistencies were found and left unrepaired.
+fn verify(path: &Path, repair: bool) -> ExitCode {
+    let Some(db) = open(path, !repair) else {
+        return ExitCode::FAILURE;
+    };
+
+    let report = match if repair { db.repair() } else { db.verify() } {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Verification failed: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    for issue in &report.issues {
+        println!("{issue}");
+    }
+    let outcome = if repair { "repaired" } else { "found" };
+    println!(
+        "{} keys checked, {} inconsistencies {outcome}",
+        report.keys,
+        report.issues.len()
+    );
+
+    if report.is_consistent() || repair {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(1)
+    }
+}
+
+/// Parses the filters of `epoch audit`, or returns `None` if they are malformed.
+fn audit_query(filters: &[&str]) -> Option<AuditQuery> {
+    let mut query = AuditQuery::new();
+    for filter in filters.chunks(2) {
+        query = match filter {
+            ["--key", key] => query.key(key),
+            ["--namespace", name] => query.namespace(name),
+            ["--actor", actor] => query.actor(actor),
+            ["--since", timestamp] => query.since(timestamp.parse().ok()?),
+            ["--until", timestamp] => query.until(timestamp.parse().ok()?),
+            _ => return None,
+        };
+    }
+    Some(query)
+}
+
+/// Prints the records of the audit log of the database at `path` matching `query`.
+fn audit(path: &Path, query: &AuditQuery) -> ExitCode {
+    let Some(db) = open(path, true) else {
+        return ExitCode::FAILURE;
+    };
+
+    let records = match db.audit(query) {
+        Ok(records) => records,
+        Err(e) => {
+            eprintln!("Cannot read the audit log: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    for record in records {
+        match record {
+            Ok(record) => println!("{record}"),
+            Err(e) => {
+                eprintln!("Cannot read the audit log: {e}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+/// Opens the database at `path`, printing why if it cannot.
+fn open(path: &Path, read_only: bool) -> Option<DB> {
+    // NOTE: Opening a path that doesn't exist would create an empty database.
+    if !path.exists() {
+        eprintln!("No database at {}", path.display());
+        return None;
+    }
+
+    match DBConfig::new(path).read_only(read_only).open() {
+        Ok(db) => Some(db),
+        Err(e) => {
+            eprintln!("Cannot open {}: {e}", path.display());
+            None
+        }
+    }
 }
@@ -0,0 +1,201 @@
+//! This module contains the batch operations of the `DB`. Writes are applied
+//! to every key in a single transaction, and reads see a consistent view of
+//! every key they ask for.
+
+use std::{error::Error, sync::atomic::Ordering, time::Duration};
+
+use sled::{
+    IVec,
+    transaction::{ConflictableTransactionError, TransactionError, Transactional},
+};
+
+use crate::{
+    DB, Durability, Metadata,
+    db::{
+        errors::TransientError,
+        keyspace::{Keyspace, transaction_error},
+    },
+};
+
+/// A value along with its metadata, as returned by [`DB::mget`].
+pub type Entry = (String, Metadata);
+
+impl DB {
+    /// Sets many key-value pairs, each with an optional Time-To-Live (TTL),
+    /// in a single transaction.
+    ///
+    /// Either every pair is written or none is. If a key appears more than
+    /// once, the last pair wins.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transaction fails.
+    pub fn mset(&self, items: &[(&str, &str, Option<Duration>)]) -> Result<(), Box<dyn Error>> {
+        self.mset_with(items, self.durability)
+    }
+
+    /// Sets many key-value pairs like [`DB::mset`], made durable according to `durability`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transaction fails or the writes cannot be flushed.
+    pub fn mset_with(
+        &self,
+        items: &[(&str, &str, Option<Duration>)],
+        durability: Durability,
+    ) -> Result<(), Box<dyn Error>> {
+        self.mset_in(&self.keyspace, items)?;
+        Ok(self.persist(durability)?)
+    }
+
+    /// Retrieves the values and metadata of many keys, in the order of `keys`.
+    ///
+    /// Every key is read from the same consistent view of the database.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the values cannot be retrieved, a value is not
+    /// valid UTF-8 or its metadata cannot be deserialized.
+    pub fn mget(&self, keys: &[&str]) -> Result<Vec<Option<Entry>>, Box<dyn Error>> {
+        Ok(self.mget_in(&self.keyspace, keys)?)
+    }
+
+    /// Removes many keys and their metadata in a single transaction.
+    ///
+    /// Keys that don't exist are skipped. Returns the number of keys removed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transaction fails.
+    pub fn mremove(&self, keys: &[&str]) -> Result<usize, Box<dyn Error>> {
+        self.mremove_with(keys, self.durability)
+    }
+
+    /// Removes many keys like [`DB::mremove`], made durable according to `durability`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transaction fails or the removals cannot be flushed.
+    pub fn mremove_with(
+        &self,
+        keys: &[&str],
+        durability: Durability,
+    ) -> Result<usize, Box<dyn Error>> {
+        let removed = self.mremove_in(&self.keyspace, keys)?;
+        self.persist(durability)?;
+        Ok(removed)
+    }
+
+    /// Sets many key-value pairs in the given keyspace, see [`DB::mset`].
+    pub(crate) fn mset_in(
+        &self,
+        ks: &Keyspace,
+        items: &[(&str, &str, Option<Duration>)],
+    ) -> Result<(), TransientError> {
+        let deadlines: Vec<Option<u64>> =
+            items.iter().map(|(_, _, ttl)| ks.deadline(*ttl)).collect();
+        let mut stored = Vec::with_capacity(items.len());
+        for (key, val, _) in items {
+            match self.store_value(ks, key.as_bytes(), val.as_bytes()) {
+                Ok(value) => stored.push(value),
+                Err(e) => {
+                    stored.iter().for_each(|s| s.discard(ks));
+                    return Err(e);
+                }
+            }
+        }
+
+        let res = self.transact(ks, |tx| {
+            for ((key, _, _), (ttl_sec, stored)) in items.iter().zip(deadlines.iter().zip(&stored))
+            {
+                tx.write(key.as_bytes(), &stored.bytes, stored.len, *ttl_sec)?;
+            }
+            Ok(())
+        });
+        if res.is_err() {
+            stored.iter().for_each(|s| s.discard(ks));
+        }
+        res?;
+
+        ks.stats
+            .writes
+            .fetch_add(items.len() as u64, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Retrieves many keys of the given keyspace, see [`DB::mget`].
+    pub(crate) fn mget_in(
+        &self,
+        ks: &Keyspace,
+        keys: &[&str],
+    ) -> Result<Vec<Option<Entry>>, TransientError> {
+        let res = loop {
+            let l: Result<Vec<Option<(IVec, Metadata)>>, TransactionError<TransientError>> =
+                (&*ks.data_tree, &*ks.meta_tree).transaction(|(data, freq)| {
+                    let mut res = Vec::with_capacity(keys.len());
+                    for key in keys {
+                        let byte = key.as_bytes();
+                        let (Some(val), Some(meta)) = (data.get(byte)?, freq.get(byte)?) else {
+                            res.push(None);
+                            continue;
+                        };
+
+                        let meta = Metadata::from_u8(&meta).map_err(|_| {
+                            ConflictableTransactionError::Abort(
+                                TransientError::ParsingFromByteError,
+                            )
+                        })?;
+                        res.push(Some((val, meta)));
+                    }
+                    Ok(res)
+                });
+
+            // NOTE: The chunks of a value overwritten since the transaction are gone, in which
+            // case every key is read again so the entries stay consistent with each other.
+            let mut res: Vec<Option<Entry>> = Vec::with_capacity(keys.len());
+            for (key, found) in keys.iter().zip(l.map_err(transaction_error)?) {
+                let Some((stored, meta)) = found else {
+                    res.push(None);
+                    continue;
+                };
+                let Some(val) = self.read_value(ks, key.as_bytes(), &stored)? else {
+                    break;
+                };
+                let val = String::from_utf8(val).map_err(|_| TransientError::ParsingToUTF8Error)?;
+                res.push(Some((val, self.current_metadata(meta))));
+            }
+            if res.len() == keys.len() {
+                break res;
+            }
+        };
+
+        for (key, _) in keys.iter().zip(&res).filter(|(_, r)| r.is_some()) {
+            self.track_read(ks, key.as_bytes())?;
+        }
+
+        let hits = res.iter().filter(|r| r.is_some()).count() as u64;
+        ks.stats.hits.fetch_add(hits, Ordering::Relaxed);
+        ks.stats
+            .misses
+            .fetch_add(res.len() as u64 - hits, Ordering::Relaxed);
+        Ok(res)
+    }
+
+    /// Removes many keys from the given keyspace, see [`DB::mremove`].
+    pub(crate) fn mremove_in(&self, ks: &Keyspace, keys: &[&str]) -> Result<usize, TransientError> {
+        let removed = self.transact(ks, |tx| {
+            let mut removed = 0;
+            for key in keys {
+                if tx.delete(key.as_bytes())? {
+                    removed += 1;
+                }
+            }
+            Ok(removed)
+        })?;
+
+        ks.stats
+            .removes
+            .fetch_add(removed as u64, Ordering::Relaxed);
+        Ok(removed)
+    }
+}
@@ -0,0 +1,507 @@
+//! This module stores the values larger than [`DBConfig::chunk_size`] in
+//! chunks, see [`DB::set_reader`] and [`DB::get_reader`].
+//!
+//! The chunks of a value are stored in the `blob_tree` of its keyspace under
+//! an id unique to the write, and the `data_tree` holds a reference to them: a
+//! marker byte, the id, the length of the value and the number of chunks. The
+//! reference is written, and the chunks of the value it replaces removed, in a
+//! single transaction, so readers see either the old or the new value. Each
+//! chunk is compressed and encrypted on its own.
+//!
+//! [`DBConfig::chunk_size`]: crate::DBConfig::chunk_size
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    error::Error,
+    fmt::{self, Debug, Formatter},
+    io::{self, ErrorKind, Read},
+    mem,
+    str::from_utf8,
+    sync::{Arc, RwLock, atomic::Ordering},
+    time::Duration,
+};
+
+use sled::{
+    Tree,
+    transaction::{TransactionalTree, UnabortableTransactionError},
+};
+
+use crate::{
+    DB,
+    db::{codec::ValueCodec, errors::TransientError, keyspace::Keyspace},
+    oplog::{Mutation, OpLogEntry},
+};
+
+/// Marker of the references to chunked values.
+pub(crate) const BLOB: u8 = 0xFC;
+
+/// Length of a reference: the marker, the id, the length and the chunk count.
+const BLOB_REF_LEN: usize = 1 + 8 + 8 + 4;
+
+/// Where the chunks of a value are stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct BlobRef {
+    /// Unique to the write, shared by the keys of the chunks
+    pub(crate) id: u64,
+    /// The length of the value, in bytes
+    pub(crate) len: u64,
+    /// The number of chunks
+    chunks: u32,
+}
+
+impl BlobRef {
+    /// Parses the reference stored in the `data_tree`, `None` if `stored` is
+    /// an inline value.
+    pub(crate) fn parse(stored: &[u8]) -> Option<BlobRef> {
+        if stored.len() != BLOB_REF_LEN || stored[0] != BLOB {
+            return None;
+        }
+        Some(BlobRef {
+            id: u64::from_be_bytes(stored[1..9].try_into().ok()?),
+            len: u64::from_be_bytes(stored[9..17].try_into().ok()?),
+            chunks: u32::from_be_bytes(stored[17..21].try_into().ok()?),
+        })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(BLOB_REF_LEN);
+        bytes.push(BLOB);
+        bytes.extend(self.id.to_be_bytes());
+        bytes.extend(self.len.to_be_bytes());
+        bytes.extend(self.chunks.to_be_bytes());
+        bytes
+    }
+
+    /// Returns the key of the chunk at `index` in the `blob_tree`.
+    fn chunk_key(&self, index: u32) -> [u8; 12] {
+        let mut key = [0; 12];
+        key[..8].copy_from_slice(&self.id.to_be_bytes());
+        key[8..].copy_from_slice(&index.to_be_bytes());
+        key
+    }
+
+    /// Reads every chunk, returning `None` if one of them is missing because
+    /// the value was overwritten or removed in the meantime.
+    fn read(&self, codec: &ValueCodec, blobs: &Tree) -> Result<Option<Vec<u8>>, TransientError> {
+        let mut value = Vec::with_capacity(self.len as usize);
+        for index in 0..self.chunks {
+            let key = self.chunk_key(index);
+            let Some(chunk) = blobs
+                .get(key)
+                .map_err(|e| TransientError::SledError { error: e })?
+            else {
+                return Ok(None);
+            };
+            value.extend_from_slice(&codec.decode(&key, &chunk)?);
+        }
+        Ok(Some(value))
+    }
+
+    /// Returns `true` if every chunk is stored.
+    pub(crate) fn is_complete(&self, blobs: &Tree) -> Result<bool, sled::Error> {
+        for index in 0..self.chunks {
+            if !blobs.contains_key(self.chunk_key(index))? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Returns `true` if every chunk is stored, from inside a transaction.
+    pub(crate) fn is_complete_in(
+        &self,
+        blobs: &TransactionalTree,
+    ) -> Result<bool, UnabortableTransactionError> {
+        for index in 0..self.chunks {
+            if blobs.get(self.chunk_key(index))?.is_none() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Removes every chunk outside of a transaction, after a failed write or
+    /// once no snapshot can read the value.
+    pub(crate) fn discard(&self, blobs: &Tree) {
+        for index in 0..self.chunks {
+            let _ = blobs.remove(self.chunk_key(index));
+        }
+    }
+}
+
+/// Returns the id of the value a key of the `blob_tree` holds a chunk of.
+pub(crate) fn blob_id(chunk_key: &[u8]) -> Option<u64> {
+    if chunk_key.len() != 12 {
+        return None;
+    }
+    Some(u64::from_be_bytes(chunk_key[..8].try_into().ok()?))
+}
+
+/// Removes the chunks of the value stored as `stored`, if it is chunked.
+pub(crate) fn remove_chunks(
+    blobs: &TransactionalTree,
+    stored: &[u8],
+) -> Result<(), UnabortableTransactionError> {
+    if let Some(blob) = BlobRef::parse(stored) {
+        for index in 0..blob.chunks {
+            blobs.remove(&blob.chunk_key(index))?;
+        }
+    }
+    Ok(())
+}
+
+/// A value ready to be written to the `data_tree` of a keyspace, returned by
+/// [`DB::store_value`].
+pub(crate) struct StoredValue {
+    /// The value as encoded by the `ValueCodec`, or a reference to its chunks
+    pub(crate) bytes: Vec<u8>,
+    /// The length of the value, in bytes
+    pub(crate) len: usize,
+}
+
+impl StoredValue {
+    /// Removes the chunks of the value after the transaction writing it failed.
+    pub(crate) fn discard(&self, ks: &Keyspace) {
+        if let Some(blob) = BlobRef::parse(&self.bytes) {
+            blob.discard(&ks.blob_tree);
+        }
+    }
+}
+
+/// Checks that a value read in chunks is valid UTF-8, a character possibly
+/// spanning two chunks.
+#[derive(Default)]
+struct Utf8Check {
+    /// The start of a character cut by the end of the previous chunk
+    pending: Vec<u8>,
+}
+
+impl Utf8Check {
+    fn feed(&mut self, chunk: &[u8], last: bool) -> Result<(), TransientError> {
+        let bytes = if self.pending.is_empty() {
+            Cow::Borrowed(chunk)
+        } else {
+            Cow::Owned([mem::take(&mut self.pending).as_slice(), chunk].concat())
+        };
+        match from_utf8(&bytes) {
+            Ok(_) => Ok(()),
+            Err(e) if e.error_len().is_none() && !last => {
+                self.pending = bytes[e.valid_up_to()..].to_vec();
+                Ok(())
+            }
+            Err(_) => Err(TransientError::ParsingToUTF8Error),
+        }
+    }
+}
+
+/// Reads up to `limit` bytes from `reader`, fewer only at the end of it.
+fn read_chunk(reader: &mut impl Read, limit: usize) -> Result<Vec<u8>, TransientError> {
+    let mut chunk = Vec::new();
+    reader
+        .take(limit as u64)
+        .read_to_end(&mut chunk)
+        .map_err(|e| TransientError::IOError { error: e })?;
+    Ok(chunk)
+}
+
+/// Streams a value, returned by [`DB::get_reader`].
+///
+/// The chunks of a large value are read one at a time. Reading fails with
+/// [`ErrorKind::NotFound`] if the value is overwritten or removed before it
+/// has been read entirely.
+pub struct ValueReader {
+    codec: Arc<ValueCodec>,
+    blobs: Arc<Tree>,
+    /// Where the chunks are, `None` if the value was stored inline
+    blob: Option<BlobRef>,
+    /// The index of the next chunk to read
+    next: u32,
+    /// The chunk being read
+    buf: Vec<u8>,
+    pos: usize,
+    len: u64,
+}
+
+impl ValueReader {
+    /// Returns the length of the value, in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns `true` if the value is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Read for ValueReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.buf.len() {
+            let Some(blob) = self.blob.as_ref().filter(|b| self.next < b.chunks) else {
+                return Ok(0);
+            };
+            let key = blob.chunk_key(self.next);
+            let chunk = self.blobs.get(key)?.ok_or_else(|| {
+                io::Error::new(
+                    ErrorKind::NotFound,
+                    "The value was overwritten or removed while it was read",
+                )
+            })?;
+            self.buf = self
+                .codec
+                .decode(&key, &chunk)
+                .map_err(io::Error::other)?
+                .into_owned();
+            self.pos = 0;
+            self.next += 1;
+        }
+
+        let n = out.len().min(self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl Debug for ValueReader {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ValueReader")
+            .field("len", &self.len)
+            .field("chunked", &self.blob.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Decodes the values carried by mutation log entries, reading the chunks of
+/// large values from the keyspace they were written to.
+#[derive(Debug, Clone)]
+pub(crate) struct EntryDecoder {
+    codec: Arc<ValueCodec>,
+    default: Arc<Keyspace>,
+    namespaces: Arc<RwLock<HashMap<String, Arc<Keyspace>>>>,
+}
+
+impl EntryDecoder {
+    /// Decodes the value carried by `entry`, if any.
+    ///
+    /// A chunked value overwritten or removed since the entry was written is
+    /// gone, so the entry is reported as a [`Mutation::Meta`]: a later entry
+    /// of the log holds the value of the key.
+    pub(crate) fn decode(&self, mut entry: OpLogEntry) -> Result<OpLogEntry, TransientError> {
+        let Mutation::Set { key, value, meta } = &mut entry.mutation else {
+            return Ok(entry);
+        };
+
+        let Some(blob) = BlobRef::parse(value) else {
+            if let Cow::Owned(decoded) = self.codec.decode(key, value)? {
+                *value = decoded;
+            }
+            return Ok(entry);
+        };
+
+        let ks = match &entry.namespace {
+            None => Some(Arc::clone(&self.default)),
+            Some(name) => self
+                .namespaces
+                .read()
+                .expect("Namespaces lock poisoned")
+                .get(name)
+                .cloned(),
+        };
+        match ks.map(|ks| blob.read(&self.codec, &ks.blob_tree)) {
+            Some(Ok(Some(read))) => *value = read,
+            Some(Err(e)) => Err(e)?,
+            _ => {
+                entry.mutation = Mutation::Meta {
+                    key: mem::take(key),
+                    meta: meta.clone(),
+                }
+            }
+        }
+        Ok(entry)
+    }
+}
+
+impl DB {
+    /// Sets a key to the content of `reader` with an optional Time-To-Live
+    /// (TTL), see [`DB::set`].
+    ///
+    /// Values larger than [`DBConfig::chunk_size`](crate::DBConfig::chunk_size)
+    /// are stored in chunks, read from `reader` one at a time, and written
+    /// along with the key once the whole content has been read.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `reader` fails, its content is not valid UTF-8 or
+    /// the transaction fails.
+    pub fn set_reader(
+        &self,
+        key: &str,
+        reader: impl Read,
+        ttl: Option<Duration>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.set_reader_in(&self.keyspace, key, reader, ttl)?;
+        Ok(self.persist(self.durability)?)
+    }
+
+    /// Returns a reader streaming the value of a key, `None` if the key
+    /// doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value cannot be retrieved from the database.
+    pub fn get_reader(&self, key: &str) -> Result<Option<ValueReader>, Box<dyn Error>> {
+        Ok(self.get_reader_in(&self.keyspace, key)?)
+    }
+
+    /// Sets a key of the given keyspace to the content of `reader`, see [`DB::set_reader`].
+    pub(crate) fn set_reader_in(
+        &self,
+        ks: &Keyspace,
+        key: &str,
+        reader: impl Read,
+        ttl: Option<Duration>,
+    ) -> Result<(), TransientError> {
+        let ttl_sec = ks.deadline(ttl);
+        let stored = self.store_value(ks, key.as_bytes(), reader)?;
+
+        let res = self.transact(ks, |tx| {
+            tx.write(key.as_bytes(), &stored.bytes, stored.len, ttl_sec)
+        });
+        if res.is_err() {
+            stored.discard(ks);
+        }
+        res?;
+
+        ks.stats.writes.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Returns a reader over the value of a key of the given keyspace, see [`DB::get_reader`].
+    pub(crate) fn get_reader_in(
+        &self,
+        ks: &Keyspace,
+        key: &str,
+    ) -> Result<Option<ValueReader>, TransientError> {
+        let byte = key.as_bytes();
+        let Some(stored) = ks
+            .data_tree
+            .get(byte)
+            .map_err(|e| TransientError::SledError { error: e })?
+        else {
+            ks.stats.misses.fetch_add(1, Ordering::Relaxed);
+            return Ok(None);
+        };
+        ks.stats.hits.fetch_add(1, Ordering::Relaxed);
+        self.track_read(ks, byte)?;
+
+        let blob = BlobRef::parse(&stored);
+        let buf = match blob {
+            Some(_) => Vec::new(),
+            None => self.codec.decode(byte, &stored)?.into_owned(),
+        };
+        Ok(Some(ValueReader {
+            codec: Arc::clone(&self.codec),
+            blobs: Arc::clone(&ks.blob_tree),
+            len: blob.as_ref().map_or(buf.len() as u64, |b| b.len),
+            blob,
+            next: 0,
+            buf,
+            pos: 0,
+        }))
+    }
+
+    /// Reads the value of `key` from `reader` and prepares it to be written
+    /// to the `data_tree` of `ks`, writing its chunks to the `blob_tree` if
+    /// it is larger than the chunk size.
+    ///
+    /// The chunks are only reachable once the returned reference is written,
+    /// and must be discarded with [`StoredValue::discard`] if it isn't.
+    pub(crate) fn store_value(
+        &self,
+        ks: &Keyspace,
+        key: &[u8],
+        mut reader: impl Read,
+    ) -> Result<StoredValue, TransientError> {
+        self.check_writable()?;
+        let mut check = Utf8Check::default();
+        let mut chunk = read_chunk(&mut reader, self.chunk_size)?;
+        let mut next = if chunk.len() < self.chunk_size {
+            Vec::new()
+        } else {
+            read_chunk(&mut reader, self.chunk_size)?
+        };
+
+        if next.is_empty() {
+            check.feed(&chunk, true)?;
+            return Ok(StoredValue {
+                bytes: self.codec.encode(key, &chunk)?.into_owned(),
+                len: chunk.len(),
+            });
+        }
+
+        let mut blob = BlobRef {
+            id: self
+                .sled_db
+                .generate_id()
+                .map_err(|e| TransientError::SledError { error: e })?,
+            len: 0,
+            chunks: 0,
+        };
+        let mut write = || -> Result<(), TransientError> {
+            loop {
+                check.feed(&chunk, next.is_empty())?;
+                let chunk_key = blob.chunk_key(blob.chunks);
+                ks.blob_tree
+                    .insert(chunk_key, self.codec.encode(&chunk_key, &chunk)?.as_ref())
+                    .map_err(|e| TransientError::SledError { error: e })?;
+                blob.len += chunk.len() as u64;
+                blob.chunks += 1;
+
+                if next.is_empty() {
+                    return Ok(());
+                }
+                chunk = mem::take(&mut next);
+                if chunk.len() == self.chunk_size {
+                    next = read_chunk(&mut reader, self.chunk_size)?;
+                }
+            }
+        };
+        if let Err(e) = write() {
+            blob.discard(&ks.blob_tree);
+            return Err(e);
+        }
+
+        Ok(StoredValue {
+            bytes: blob.to_bytes(),
+            len: blob.len as usize,
+        })
+    }
+
+    /// Returns the value of `key` stored in `ks` as `stored`, reading its
+    /// chunks if it is chunked.
+    ///
+    /// Returns `None` if a chunk is missing because the value was overwritten
+    /// or removed since `stored` was read.
+    pub(crate) fn read_value(
+        &self,
+        ks: &Keyspace,
+        key: &[u8],
+        stored: &[u8],
+    ) -> Result<Option<Vec<u8>>, TransientError> {
+        match BlobRef::parse(stored) {
+            Some(blob) => blob.read(&self.codec, &ks.blob_tree),
+            None => Ok(Some(self.codec.decode(key, stored)?.into_owned())),
+        }
+    }
+
+    /// Returns the decoder of the values carried by mutation log entries.
+    pub(crate) fn entry_decoder(&self) -> EntryDecoder {
+        EntryDecoder {
+            codec: Arc::clone(&self.codec),
+            default: Arc::clone(&self.keyspace),
+            namespaces: Arc::clone(&self.namespaces),
+        }
+    }
+}
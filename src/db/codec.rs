@@ -0,0 +1,279 @@
+//! This module encodes the values stored in the `data_tree`, see
+//! [`Compression`] and the `encryption` module.
+//!
+//! Values are stored as written unless they are compressed or encrypted, in
+//! which case they start with a marker byte naming the codec. Values are
+//! UTF-8, which never starts with a byte above `0xF7`, so the markers are
+//! taken from that range and encoded and plain values can be mixed freely.
+
+#[cfg(feature = "encryption")]
+use std::sync::{Arc, atomic::AtomicBool};
+use std::{
+    borrow::Cow,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use sled::Tree;
+
+#[cfg(feature = "encryption")]
+use crate::db::encryption::{Cipher, KeyProvider, key_id};
+use crate::{
+    Compression,
+    db::{blob::BLOB, errors::TransientError},
+};
+
+/// Marker of the values compressed with zstd.
+const ZSTD: u8 = 0xFF;
+
+/// Marker of the values compressed with lz4.
+const LZ4: u8 = 0xFE;
+
+/// Marker of the encrypted values.
+pub(crate) const ENCRYPTED: u8 = 0xFD;
+
+/// Key in the `state_tree` holding a value encrypted with the current key,
+/// used to check the key when the database is opened.
+const ENCRYPTION_CHECK_KEY: &[u8] = b"encryption_check";
+
+/// Counters describing the compression of the values written since the
+/// `DB` was opened, returned by [`DB::compression_stats`](crate::DB::compression_stats).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompressionStats {
+    /// The number of values stored compressed
+    pub compressed: u64,
+    /// The number of values stored as is, because they were below the
+    /// threshold or didn't shrink
+    pub uncompressed: u64,
+    /// The size of the compressed values before compression, in bytes
+    pub input_bytes: u64,
+    /// The size of the compressed values after compression, in bytes
+    pub output_bytes: u64,
+}
+
+impl CompressionStats {
+    /// Returns the size of the compressed values after compression divided
+    /// by their size before, 1.0 if nothing was compressed.
+    pub fn ratio(&self) -> f64 {
+        if self.input_bytes == 0 {
+            return 1.0;
+        }
+        self.output_bytes as f64 / self.input_bytes as f64
+    }
+}
+
+/// Compresses, decompresses, encrypts and decrypts the values of a `DB`.
+#[derive(Debug, Default)]
+pub(crate) struct ValueCodec {
+    compression: Compression,
+    /// Values shorter than this are never compressed
+    threshold: usize,
+    /// Encrypts the values, `None` if encryption is disabled
+    #[cfg(feature = "encryption")]
+    cipher: Option<Cipher>,
+    /// Set once every value is encrypted with the current key
+    #[cfg(feature = "encryption")]
+    rotated: AtomicBool,
+    compressed: AtomicU64,
+    uncompressed: AtomicU64,
+    input_bytes: AtomicU64,
+    output_bytes: AtomicU64,
+}
+
+impl ValueCodec {
+    pub(crate) fn new(compression: Compression, threshold: usize) -> ValueCodec {
+        ValueCodec {
+            compression,
+            threshold,
+            ..ValueCodec::default()
+        }
+    }
+
+    /// Encrypts the values written from now on with the keys of `provider`.
+    #[cfg(feature = "encryption")]
+    pub(crate) fn with_encryption(mut self, provider: Option<Arc<dyn KeyProvider>>) -> ValueCodec {
+        self.cipher = provider.map(Cipher::new);
+        self
+    }
+
+    /// Returns the bytes to store for the value `val` of `key`, compressed if
+    /// it is worth it and encrypted if encryption is enabled.
+    #[cfg_attr(not(feature = "encryption"), allow(unused_variables))]
+    pub(crate) fn encode<'a>(
+        &self,
+        key: &[u8],
+        val: &'a [u8],
+    ) -> Result<Cow<'a, [u8]>, TransientError> {
+        let compressed = if val.len() < self.threshold {
+            None
+        } else {
+            self.compress(val)?
+        };
+
+        let stored = match compressed {
+            Some(stored) if stored.len() < val.len() => {
+                self.compressed.fetch_add(1, Ordering::Relaxed);
+                self.input_bytes
+                    .fetch_add(val.len() as u64, Ordering::Relaxed);
+                self.output_bytes
+                    .fetch_add(stored.len() as u64, Ordering::Relaxed);
+                Cow::Owned(stored)
+            }
+            _ => {
+                self.uncompressed.fetch_add(1, Ordering::Relaxed);
+                Cow::Borrowed(val)
+            }
+        };
+
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.cipher {
+            return Ok(Cow::Owned(cipher.encrypt(key, &stored)?));
+        }
+        Ok(stored)
+    }
+
+    /// Compresses `val` with the configured codec, `None` meaning no compression.
+    #[cfg_attr(not(any(feature = "zstd", feature = "lz4")), allow(unused_variables))]
+    fn compress(&self, val: &[u8]) -> Result<Option<Vec<u8>>, TransientError> {
+        match self.compression {
+            Compression::None => Ok(None),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd { level } => {
+                let mut stored = vec![ZSTD];
+                zstd::stream::copy_encode(val, &mut stored, level)
+                    .map_err(|e| TransientError::IOError { error: e })?;
+                Ok(Some(stored))
+            }
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => {
+                let mut stored = vec![LZ4];
+                stored.extend(lz4_flex::compress_prepend_size(val));
+                Ok(Some(stored))
+            }
+        }
+    }
+
+    /// Returns the value of `key` stored as `stored`, whatever codec it was
+    /// written with.
+    #[cfg_attr(not(feature = "encryption"), allow(unused_variables))]
+    pub(crate) fn decode<'a>(
+        &self,
+        key: &[u8],
+        stored: &'a [u8],
+    ) -> Result<Cow<'a, [u8]>, TransientError> {
+        #[cfg(feature = "encryption")]
+        if stored.first() == Some(&ENCRYPTED) {
+            let cipher = self
+                .cipher
+                .as_ref()
+                .ok_or(TransientError::UnknownEncryptionKey {
+                    id: key_id(stored)?,
+                })?;
+            let plain = cipher.decrypt(key, stored)?;
+            return Ok(Cow::Owned(decompress(&plain)?.into_owned()));
+        }
+        decompress(stored)
+    }
+
+    /// Returns the compression counters since the codec was created.
+    pub(crate) fn stats(&self) -> CompressionStats {
+        CompressionStats {
+            compressed: self.compressed.load(Ordering::Relaxed),
+            uncompressed: self.uncompressed.load(Ordering::Relaxed),
+            input_bytes: self.input_bytes.load(Ordering::Relaxed),
+            output_bytes: self.output_bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Checks that the database can be read with the configured encryption,
+    /// using a value encrypted when the database was last opened, which is
+    /// encrypted again with the current key if `record` is set.
+    #[cfg_attr(not(feature = "encryption"), expect(unused_variables))]
+    pub(crate) fn check_key(&self, state_tree: &Tree, record: bool) -> Result<(), sled::Error> {
+        let check = state_tree.get(ENCRYPTION_CHECK_KEY)?;
+
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.cipher {
+            if let Some(check) = &check {
+                cipher
+                    .decrypt(ENCRYPTION_CHECK_KEY, check)
+                    .map_err(|e| sled::Error::Unsupported(e.to_string()))?;
+                if cipher.is_current(check) || !record {
+                    return Ok(());
+                }
+            }
+            if !record {
+                return Ok(());
+            }
+            let check = cipher
+                .encrypt(ENCRYPTION_CHECK_KEY, ENCRYPTION_CHECK_KEY)
+                .map_err(|e| sled::Error::Unsupported(e.to_string()))?;
+            state_tree.insert(ENCRYPTION_CHECK_KEY, check)?;
+            return Ok(());
+        }
+
+        match check {
+            Some(_) => Err(sled::Error::Unsupported(
+                "The database is encrypted, configure its key provider with \
+                 DBConfig::encryption and enable the encryption feature"
+                    .to_string(),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Re-encrypts the value `key` stored as `stored` with the current key,
+    /// returning `None` if it already is.
+    #[cfg(feature = "encryption")]
+    pub(crate) fn reencrypt(
+        &self,
+        key: &[u8],
+        stored: &[u8],
+    ) -> Result<Option<Vec<u8>>, TransientError> {
+        let Some(cipher) = &self.cipher else {
+            return Ok(None);
+        };
+        // NOTE: References to chunked values are rewritten with their chunks.
+        if cipher.is_current(stored) || stored.first() == Some(&BLOB) {
+            return Ok(None);
+        }
+
+        let inner = if stored.first() == Some(&ENCRYPTED) {
+            Cow::Owned(cipher.decrypt(key, stored)?)
+        } else {
+            Cow::Borrowed(stored)
+        };
+        Ok(Some(cipher.encrypt(key, &inner)?))
+    }
+
+    /// Returns `true` while some values may not be encrypted with the current key.
+    #[cfg(feature = "encryption")]
+    pub(crate) fn rotating(&self) -> bool {
+        self.cipher.is_some() && !self.rotated.load(Ordering::Relaxed)
+    }
+
+    /// Records that every value is encrypted with the current key.
+    #[cfg(feature = "encryption")]
+    pub(crate) fn set_rotated(&self) {
+        self.rotated.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Decompresses a value stored by [`ValueCodec::compress`], returning other
+/// values as is.
+fn decompress(stored: &[u8]) -> Result<Cow<'_, [u8]>, TransientError> {
+    match stored.first() {
+        #[cfg(feature = "zstd")]
+        Some(&ZSTD) => Ok(Cow::Owned(
+            zstd::stream::decode_all(&stored[1..]).map_err(|_| TransientError::CorruptedValue)?,
+        )),
+        #[cfg(feature = "lz4")]
+        Some(&LZ4) => Ok(Cow::Owned(
+            lz4_flex::decompress_size_prepended(&stored[1..])
+                .map_err(|_| TransientError::CorruptedValue)?,
+        )),
+        Some(&codec) if [ZSTD, LZ4, ENCRYPTED, BLOB].contains(&codec) => {
+            Err(TransientError::UnsupportedCodec { codec })
+        }
+        _ => Ok(Cow::Borrowed(stored)),
+    }
+}
@@ -0,0 +1,282 @@
+//! This module defines `DBConfig`, the builder used to open a `DB` with
+//! non-default settings.
+
+use std::{
+    fmt::{self, Debug, Formatter},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+#[cfg(feature = "encryption")]
+use std::sync::Arc;
+
+#[cfg(feature = "encryption")]
+use crate::db::encryption::KeyProvider;
+use crate::{DB, audit::AuditConfig};
+
+/// The default size of the `sled` page cache, in bytes.
+pub const DEFAULT_CACHE_CAPACITY: u64 = 512 * 1024 * 1024;
+
+/// The default number of entries kept in the mutation log.
+pub const DEFAULT_OPLOG_RETENTION: u64 = 100_000;
+
+/// The default interval between two background flushes of the `sled` log.
+pub const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The default size below which values are stored uncompressed, in bytes.
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 512;
+
+/// The default size of the chunks large values are stored in, in bytes.
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// How values are compressed before they are stored.
+///
+/// Each value records the codec it was stored with, so changing the
+/// compression of an existing database only affects the values written
+/// afterwards. Values that don't shrink are stored uncompressed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Values are stored as is.
+    #[default]
+    None,
+    /// Values are compressed with zstd at the given level, from 1 to 22.
+    #[cfg(feature = "zstd")]
+    Zstd {
+        /// The compression level, higher is smaller and slower.
+        level: i32,
+    },
+    /// Values are compressed with lz4, faster than zstd but compressing less.
+    #[cfg(feature = "lz4")]
+    Lz4,
+}
+
+/// When a write is made durable.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Durability {
+    /// The write is buffered in memory and written to disk by the next
+    /// periodic flush, so it can be lost if the process crashes before that.
+    #[default]
+    Buffered,
+    /// The write is flushed to disk before the call returns.
+    Flushed,
+}
+
+/// How reads update the frequency of the key they read.
+///
+/// Whatever the mode, reads that don't update the frequency right away have
+/// their access time recorded by the background thread on its next pass, see
+/// [`Metadata::last_accessed_at`](crate::Metadata::last_accessed_at).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FrequencyTracking {
+    /// Frequencies only change through [`DB::increment_frequency`].
+    #[default]
+    Manual,
+    /// Every `one_in`-th read of a keyspace adds `one_in` to the frequency of
+    /// the key it read, so the frequencies stay accurate on average while
+    /// only one read in `one_in` pays for a transaction.
+    Sampled {
+        /// How many reads are covered by a single update.
+        one_in: u64,
+    },
+    /// Reads are counted in memory and added to the frequencies by the
+    /// background thread on its next pass, in a single transaction per
+    /// keyspace. Counts not written yet are lost if the process crashes.
+    Buffered,
+}
+
+/// Settings used to open a [`DB`].
+///
+/// ```no_run
+/// use epoch_db::DBConfig;
+/// use std::{path::Path, time::Duration};
+///
+/// let db = DBConfig::new(Path::new("./my_database"))
+///     .oplog_retention(10_000)
+///     .flush_interval(Some(Duration::from_millis(100)))
+///     .open()
+///     .unwrap();
+/// ```
+#[derive(Clone)]
+pub struct DBConfig {
+    pub(crate) path: PathBuf,
+    pub(crate) cache_capacity: u64,
+    pub(crate) oplog_retention: u64,
+    pub(crate) flush_interval: Option<Duration>,
+    pub(crate) durability: Durability,
+    pub(crate) frequency_tracking: FrequencyTracking,
+    pub(crate) frequency_half_life: Option<Duration>,
+    pub(crate) compression: Compression,
+    pub(crate) compression_threshold: usize,
+    pub(crate) chunk_size: usize,
+    pub(crate) audit: Option<AuditConfig>,
+    pub(crate) read_only: bool,
+    #[cfg(feature = "encryption")]
+    pub(crate) key_provider: Option<Arc<dyn KeyProvider>>,
+}
+
+impl Debug for DBConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("DBConfig");
+        debug
+            .field("path", &self.path)
+            .field("cache_capacity", &self.cache_capacity)
+            .field("oplog_retention", &self.oplog_retention)
+            .field("flush_interval", &self.flush_interval)
+            .field("durability", &self.durability)
+            .field("frequency_tracking", &self.frequency_tracking)
+            .field("frequency_half_life", &self.frequency_half_life)
+            .field("compression", &self.compression)
+            .field("compression_threshold", &self.compression_threshold)
+            .field("chunk_size", &self.chunk_size)
+            .field("audit", &self.audit)
+            .field("read_only", &self.read_only);
+        #[cfg(feature = "encryption")]
+        debug.field("encryption", &self.key_provider.is_some());
+        debug.finish()
+    }
+}
+
+impl DBConfig {
+    /// Creates a configuration for the database at `path` with default settings.
+    pub fn new(path: &Path) -> DBConfig {
+        DBConfig {
+            path: path.to_path_buf(),
+            cache_capacity: DEFAULT_CACHE_CAPACITY,
+            oplog_retention: DEFAULT_OPLOG_RETENTION,
+            flush_interval: Some(DEFAULT_FLUSH_INTERVAL),
+            durability: Durability::Buffered,
+            frequency_tracking: FrequencyTracking::Manual,
+            frequency_half_life: None,
+            compression: Compression::None,
+            compression_threshold: DEFAULT_COMPRESSION_THRESHOLD,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            audit: None,
+            read_only: false,
+            #[cfg(feature = "encryption")]
+            key_provider: None,
+        }
+    }
+
+    /// Sets the size of the `sled` page cache, in bytes.
+    pub fn cache_capacity(mut self, bytes: u64) -> DBConfig {
+        self.cache_capacity = bytes;
+        self
+    }
+
+    /// Sets how many entries of the mutation log are kept.
+    ///
+    /// Older entries are trimmed by the background thread. A follower that
+    /// falls further behind than this is re-bootstrapped from a snapshot.
+    pub fn oplog_retention(mut self, entries: u64) -> DBConfig {
+        self.oplog_retention = entries.max(1);
+        self
+    }
+
+    /// Sets how often buffered writes are flushed to disk in the background.
+    ///
+    /// `None` disables the periodic flush, leaving it to [`DB::flush`],
+    /// [`Durability::Flushed`] writes and dropping the `DB`.
+    pub fn flush_interval(mut self, interval: Option<Duration>) -> DBConfig {
+        self.flush_interval = interval;
+        self
+    }
+
+    /// Sets the durability of the writes that don't ask for one explicitly.
+    pub fn durability(mut self, durability: Durability) -> DBConfig {
+        self.durability = durability;
+        self
+    }
+
+    /// Sets how `get` and `mget` update the frequency of the keys they find.
+    pub fn frequency_tracking(mut self, tracking: FrequencyTracking) -> DBConfig {
+        self.frequency_tracking = match tracking {
+            FrequencyTracking::Sampled { one_in } => FrequencyTracking::Sampled {
+                one_in: one_in.max(1),
+            },
+            tracking => tracking,
+        };
+        self
+    }
+
+    /// Sets the half-life of [`Metadata::score`](crate::Metadata::score), the
+    /// decayed access frequency of a key, which eviction policies rank keys by.
+    ///
+    /// An access that happened one half-life ago counts half as much as an
+    /// access happening now. `None`, the default, disables the decay.
+    pub fn frequency_half_life(mut self, half_life: Option<Duration>) -> DBConfig {
+        self.frequency_half_life = half_life.filter(|h| !h.is_zero());
+        self
+    }
+
+    /// Sets how values are compressed before they are stored.
+    pub fn compression(mut self, compression: Compression) -> DBConfig {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets the size below which values are stored uncompressed, in bytes.
+    pub fn compression_threshold(mut self, bytes: usize) -> DBConfig {
+        self.compression_threshold = bytes;
+        self
+    }
+
+    /// Sets the size of the chunks values larger than it are stored in, in bytes.
+    ///
+    /// Chunks are written and read one at a time, see [`DB::set_reader`] and
+    /// [`DB::get_reader`]. Changing the chunk size only affects the values
+    /// written afterwards.
+    pub fn chunk_size(mut self, bytes: usize) -> DBConfig {
+        self.chunk_size = bytes.max(1);
+        self
+    }
+
+    /// Records every mutation in the audit log, see [`crate::audit`].
+    ///
+    /// The mutation log keeps its entries until they are recorded, even
+    /// beyond the `oplog_retention`.
+    pub fn audit(mut self, config: AuditConfig) -> DBConfig {
+        self.audit = Some(config);
+        self
+    }
+
+    /// Opens the database without writing to it, to inspect it.
+    ///
+    /// No background thread is spawned, so keys are neither expired nor
+    /// evicted, reads don't update the frequencies, the audit log isn't
+    /// copied and every write fails with [`TransientError::ReadOnly`].
+    /// Opening fails if the database doesn't exist or was written in an
+    /// older on-disk format, which only opening it for writing upgrades.
+    ///
+    /// `sled` writes to a database whenever it opens it, so the database is
+    /// copied to a temporary directory and opened from there, which leaves
+    /// its files untouched. It can be opened while another process has it
+    /// open, and then reflects the writes of that process as of its last
+    /// flush.
+    ///
+    /// [`TransientError::ReadOnly`]: crate::db::errors::TransientError::ReadOnly
+    pub fn read_only(mut self, read_only: bool) -> DBConfig {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Encrypts the values with the keys supplied by `provider`.
+    ///
+    /// Values written before encryption was enabled, or with a key that is no
+    /// longer current, are re-encrypted with the current key in the background.
+    /// Opening an encrypted database fails if the provider doesn't return the
+    /// key it was last opened with.
+    #[cfg(feature = "encryption")]
+    pub fn encryption(mut self, provider: impl KeyProvider + 'static) -> DBConfig {
+        self.key_provider = Some(Arc::new(provider));
+        self
+    }
+
+    /// Opens the database with this configuration.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `sled::Error` if the database cannot be opened at the given path.
+    pub fn open(self) -> Result<DB, sled::Error> {
+        DB::with_config(self)
+    }
+}
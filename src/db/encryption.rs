@@ -0,0 +1,264 @@
+//! This module implements the encryption at rest of the values, see
+//! [`DBConfig::encryption`](crate::DBConfig::encryption).
+//!
+//! Values are encrypted with ChaCha20-Poly1305 under a key supplied by a
+//! [`KeyProvider`], after they are compressed. An encrypted value is stored as
+//! a marker byte, the id of the key, a random nonce and the ciphertext. The
+//! key of the value is authenticated along with it, so an encrypted value
+//! cannot be moved to another key. Keys and metadata are not encrypted, since
+//! the indexes of the database need to read them.
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::{self, Debug, Formatter},
+    sync::Arc,
+};
+
+use chacha20poly1305::{
+    ChaCha20Poly1305, Nonce,
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+};
+use sled::Tree;
+
+use crate::{
+    DB,
+    db::{
+        codec::{ENCRYPTED, ValueCodec},
+        errors::TransientError,
+        keyspace::Keyspace,
+    },
+};
+
+/// A 256 bit encryption key.
+pub type EncryptionKey = [u8; 32];
+
+/// Length of the key id following the marker byte.
+const KEY_ID_LEN: usize = 4;
+
+/// Length of the nonce following the key id.
+const NONCE_LEN: usize = 12;
+
+/// Supplies the keys values are encrypted with.
+///
+/// Keys are identified by a number stored with every value. To rotate keys,
+/// make a new key current while still returning the old ones from
+/// [`KeyProvider::key`]: the values encrypted with an old key are re-encrypted
+/// in the background, see [`DB::reencrypt`](crate::DB::reencrypt).
+pub trait KeyProvider: Send + Sync {
+    /// Returns the id of the key new values are encrypted with, and the key.
+    fn current_key(&self) -> (u32, EncryptionKey);
+
+    /// Returns the key with the given id, `None` if the provider doesn't know it.
+    fn key(&self, id: u32) -> Option<EncryptionKey>;
+}
+
+/// A [`KeyProvider`] holding its keys in memory.
+///
+/// ```
+/// use epoch_db::db::encryption::StaticKeys;
+///
+/// let keys = StaticKeys::new(2, [2; 32]).with_retired_key(1, [1; 32]);
+/// ```
+#[derive(Clone)]
+pub struct StaticKeys {
+    current: u32,
+    keys: HashMap<u32, EncryptionKey>,
+}
+
+impl StaticKeys {
+    /// Creates a provider encrypting new values with `key`, identified by `id`.
+    pub fn new(id: u32, key: EncryptionKey) -> StaticKeys {
+        StaticKeys {
+            current: id,
+            keys: HashMap::from([(id, key)]),
+        }
+    }
+
+    /// Adds a key older values may still be encrypted with.
+    pub fn with_retired_key(mut self, id: u32, key: EncryptionKey) -> StaticKeys {
+        self.keys.entry(id).or_insert(key);
+        self
+    }
+}
+
+impl KeyProvider for StaticKeys {
+    fn current_key(&self) -> (u32, EncryptionKey) {
+        (self.current, self.keys[&self.current])
+    }
+
+    fn key(&self, id: u32) -> Option<EncryptionKey> {
+        self.keys.get(&id).copied()
+    }
+}
+
+impl Debug for StaticKeys {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut ids: Vec<&u32> = self.keys.keys().collect();
+        ids.sort();
+        f.debug_struct("StaticKeys")
+            .field("current", &self.current)
+            .field("ids", &ids)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Encrypts and decrypts values with the keys of a [`KeyProvider`].
+#[derive(Clone)]
+pub(crate) struct Cipher {
+    provider: Arc<dyn KeyProvider>,
+}
+
+impl Cipher {
+    pub(crate) fn new(provider: Arc<dyn KeyProvider>) -> Cipher {
+        Cipher { provider }
+    }
+
+    /// Encrypts `plain` with the current key, authenticating `aad` along with it.
+    pub(crate) fn encrypt(&self, aad: &[u8], plain: &[u8]) -> Result<Vec<u8>, TransientError> {
+        let (id, key) = self.provider.current_key();
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = ChaCha20Poly1305::new(&key.into())
+            .encrypt(&nonce, Payload { msg: plain, aad })
+            .map_err(|_| TransientError::EncryptionFailed)?;
+
+        let mut stored = Vec::with_capacity(1 + KEY_ID_LEN + NONCE_LEN + ciphertext.len());
+        stored.push(ENCRYPTED);
+        stored.extend(id.to_be_bytes());
+        stored.extend(nonce);
+        stored.extend(ciphertext);
+        Ok(stored)
+    }
+
+    /// Decrypts a value stored by [`Cipher::encrypt`] with the same `aad`.
+    pub(crate) fn decrypt(&self, aad: &[u8], stored: &[u8]) -> Result<Vec<u8>, TransientError> {
+        let id = key_id(stored)?;
+        let key = self
+            .provider
+            .key(id)
+            .ok_or(TransientError::UnknownEncryptionKey { id })?;
+        let nonce = Nonce::from_slice(&stored[1 + KEY_ID_LEN..1 + KEY_ID_LEN + NONCE_LEN]);
+
+        ChaCha20Poly1305::new(&key.into())
+            .decrypt(
+                nonce,
+                Payload {
+                    msg: &stored[1 + KEY_ID_LEN + NONCE_LEN..],
+                    aad,
+                },
+            )
+            .map_err(|_| TransientError::WrongEncryptionKey { id })
+    }
+
+    /// Returns `true` if `stored` is encrypted with the current key.
+    pub(crate) fn is_current(&self, stored: &[u8]) -> bool {
+        stored.first() == Some(&ENCRYPTED)
+            && key_id(stored).is_ok_and(|id| id == self.provider.current_key().0)
+    }
+}
+
+impl Debug for Cipher {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cipher")
+            .field("current", &self.provider.current_key().0)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DB {
+    /// Re-encrypts every value that isn't encrypted with the current key of
+    /// the key provider, returning the number of values rewritten.
+    ///
+    /// The background thread does the same in small batches after the `DB` is
+    /// opened, so this is only needed to finish a key rotation right away,
+    /// before retiring the old key. Entries of the mutation log keep the key
+    /// they were written with until they are trimmed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a value cannot be decrypted, once every other value
+    /// was re-encrypted, or if the trees cannot be read or written.
+    pub fn reencrypt(&self) -> Result<usize, Box<dyn Error>> {
+        self.check_writable()?;
+        let mut keyspaces = vec![Arc::clone(&self.keyspace)];
+        keyspaces.extend(
+            self.namespaces
+                .read()
+                .expect("Namespaces lock poisoned")
+                .values()
+                .cloned(),
+        );
+
+        let mut rewritten = 0;
+        let mut failed = None;
+        for ks in &keyspaces {
+            rewritten += reencrypt_keyspace(ks, &self.codec, usize::MAX, &mut |e| {
+                failed.get_or_insert(e);
+            })?;
+        }
+        if let Some(e) = failed {
+            Err(e)?
+        }
+        self.codec.set_rotated();
+        Ok(rewritten)
+    }
+}
+
+/// Re-encrypts up to `limit` values and chunks of values of `ks` with the
+/// current key, returning the number of them rewritten.
+///
+/// A value written in the meantime is left alone, since it was encrypted with
+/// the current key. A value that cannot be re-encrypted, such as one stored
+/// with an unknown key, is passed to `skip` and left as is.
+pub(crate) fn reencrypt_keyspace(
+    ks: &Keyspace,
+    codec: &ValueCodec,
+    limit: usize,
+    skip: &mut impl FnMut(TransientError),
+) -> Result<usize, TransientError> {
+    let rewritten = reencrypt_tree(&ks.data_tree, codec, limit, skip)?;
+    Ok(rewritten + reencrypt_tree(&ks.blob_tree, codec, limit - rewritten, skip)?)
+}
+
+/// Re-encrypts up to `limit` values of `tree`, authenticated with their key.
+fn reencrypt_tree(
+    tree: &Tree,
+    codec: &ValueCodec,
+    limit: usize,
+    skip: &mut impl FnMut(TransientError),
+) -> Result<usize, TransientError> {
+    let mut rewritten = 0;
+    for i in tree.iter() {
+        if rewritten == limit {
+            break;
+        }
+        let (key, stored) = i.map_err(|e| TransientError::SledError { error: e })?;
+        let new = match codec.reencrypt(&key, &stored) {
+            Ok(Some(new)) => new,
+            Ok(None) => continue,
+            Err(e) => {
+                skip(e);
+                continue;
+            }
+        };
+
+        let swapped = tree
+            .compare_and_swap(&key, Some(&stored), Some(new))
+            .map_err(|e| TransientError::SledError { error: e })?;
+        if swapped.is_ok() {
+            rewritten += 1;
+        }
+    }
+    Ok(rewritten)
+}
+
+/// Returns the id of the key an encrypted value was stored with.
+pub(crate) fn key_id(stored: &[u8]) -> Result<u32, TransientError> {
+    if stored.len() < 1 + KEY_ID_LEN + NONCE_LEN {
+        Err(TransientError::CorruptedValue)?
+    }
+    let id: [u8; KEY_ID_LEN] = stored[1..1 + KEY_ID_LEN]
+        .try_into()
+        .map_err(|_| TransientError::CorruptedValue)?;
+    Ok(u32::from_be_bytes(id))
+}
@@ -1,6 +1,8 @@
 //! This module defines the custom error types used throughout the TransientDB library.
 use std::{error::Error, fmt::Display};
 
+use crate::metadata::{MAX_TAG_LEN, MAX_TAGS};
+
 /// The primary error enum for the TransientDB library.
 #[derive(Debug)]
 pub enum TransientError {
@@ -19,6 +21,73 @@ pub enum TransientError {
     SledTransactionError,
     /// Error that occurs when parsing a byte slice to a u64 fails.
     ParsingToU64ByteFailed,
+    /// Error that occurs when decoding a stored or received byte slice fails.
+    ParsingFromByteError,
+    /// Wrapper for `std::io::Error`.
+    IOError {
+        /// The underlying `std::io` error.
+        error: std::io::Error,
+    },
+    /// Error that occurs when a replication peer sends an unexpected message.
+    ReplicationProtocolError,
+    /// Error that occurs when a namespace name is empty.
+    InvalidNamespaceName,
+    /// Error that occurs when using a namespace that was dropped.
+    NamespaceNotFound,
+    /// Error that occurs when a namespace is cleared or dropped during a transaction on it.
+    NamespaceChanged,
+    /// Error that occurs when tags are empty, too long or too many.
+    InvalidTags,
+    /// Error that occurs when a value was stored with a codec whose cargo feature isn't enabled.
+    UnsupportedCodec {
+        /// The marker byte of the codec.
+        codec: u8,
+    },
+    /// Error that occurs when a stored value cannot be decoded.
+    CorruptedValue,
+    /// Error that occurs when a value is encrypted with a key the key provider doesn't know.
+    UnknownEncryptionKey {
+        /// The id of the key.
+        id: u32,
+    },
+    /// Error that occurs when a value cannot be decrypted with the key it names,
+    /// because the key provider returned the wrong key or the value was tampered with.
+    WrongEncryptionKey {
+        /// The id of the key.
+        id: u32,
+    },
+    /// Error that occurs when a value cannot be encrypted.
+    EncryptionFailed,
+    /// Error that occurs when a record of an import cannot be parsed.
+    MalformedRecord {
+        /// The position of the record in the import, starting at 1.
+        record: u64,
+    },
+    /// Error that occurs when an import isn't a binary dump or was written by a newer version.
+    UnsupportedDump,
+    /// Error that occurs when writing to a database opened read-only.
+    ReadOnly,
+    /// Error that occurs when a Redis snapshot or command log cannot be parsed.
+    MalformedRedisFile {
+        /// What is wrong with the file.
+        reason: String,
+    },
+    /// Error that occurs when a conditional write finds the key at another version.
+    VersionConflict {
+        /// The version the write expected, 0 for a key that doesn't exist.
+        expected: u64,
+        /// The version the key was at, 0 if it doesn't exist.
+        actual: u64,
+    },
+    /// Error that occurs when renewing or releasing a lease that expired or was taken over.
+    LeaseLost,
+    /// Error that occurs when acquiring a lease on a key holding something else.
+    NotALease,
+    /// Error that occurs when a watcher fell too far behind and stopped receiving changes.
+    WatcherLagged,
+    /// Error that occurs when taking a snapshot of a database a replication follower applies
+    /// changes to, or following a primary while a snapshot is live.
+    Replicating,
 }
 
 impl Display for TransientError {
@@ -32,6 +101,63 @@ impl Display for TransientError {
             TransientError::ParsingToU64ByteFailed => {
                 writeln!(f, "Failed to parse a variable to a U64 byte [u8; 8]")
             }
+            TransientError::ParsingFromByteError => writeln!(f, "Parsing from byte failed"),
+            TransientError::IOError { error } => writeln!(f, "IO failed {}", error),
+            TransientError::ReplicationProtocolError => {
+                writeln!(f, "Replication peer sent an unexpected message")
+            }
+            TransientError::InvalidNamespaceName => writeln!(f, "Namespace name is empty"),
+            TransientError::NamespaceNotFound => writeln!(f, "Namespace does not exist"),
+            TransientError::NamespaceChanged => {
+                writeln!(f, "Namespace was cleared or dropped during the transaction")
+            }
+            TransientError::InvalidTags => writeln!(
+                f,
+                "Tags must be non-empty, at most {MAX_TAG_LEN} bytes long and at most {MAX_TAGS}"
+            ),
+            TransientError::UnsupportedCodec { codec } => writeln!(
+                f,
+                "Value was stored with codec {codec:#x}, enable its cargo feature to read it"
+            ),
+            TransientError::CorruptedValue => writeln!(f, "Stored value cannot be decoded"),
+            TransientError::UnknownEncryptionKey { id } => writeln!(
+                f,
+                "Value is encrypted with key {id}, which the key provider doesn't know"
+            ),
+            TransientError::WrongEncryptionKey { id } => writeln!(
+                f,
+                "Key {id} returned by the key provider doesn't decrypt the value, it is the wrong key"
+            ),
+            TransientError::EncryptionFailed => writeln!(f, "Encryption failed"),
+            TransientError::MalformedRecord { record } => {
+                writeln!(f, "Record {record} of the import cannot be parsed")
+            }
+            TransientError::UnsupportedDump => writeln!(
+                f,
+                "Input is not a binary dump or was written by a newer version"
+            ),
+            TransientError::ReadOnly => writeln!(f, "Database is opened read-only"),
+            TransientError::MalformedRedisFile { reason } => {
+                writeln!(f, "Redis file cannot be parsed: {reason}")
+            }
+            TransientError::VersionConflict { expected, actual } => writeln!(
+                f,
+                "Key is at version {actual}, the write expected version {expected}"
+            ),
+            TransientError::LeaseLost => {
+                writeln!(f, "Lease expired or was taken over by another owner")
+            }
+            TransientError::NotALease => writeln!(f, "Key holds a value that is not a lease"),
+            TransientError::Replicating => writeln!(
+                f,
+                "Database is replicating, snapshots and replication exclude each other"
+            ),
+            TransientError::WatcherLagged => {
+                writeln!(
+                    f,
+                    "Watcher fell too far behind, the changes after it were dropped"
+                )
+            }
         }
     }
 }
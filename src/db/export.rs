@@ -0,0 +1,586 @@
+//! This module exports the keys of a `DB` along with their values and
+//! metadata to portable formats, see [`DB::export`], and imports them back,
+//! see [`DB::import`].
+//!
+//! Every format holds the same [`ExportRecord`]s, so a database can be
+//! exported as JSON Lines or CSV to be inspected by other tools, or as a
+//! compact binary dump to be moved to another machine.
+
+use std::{
+    error::Error,
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    sync::{Arc, atomic::Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bincode::serde::{decode_from_slice, encode_to_vec};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    DB, Metadata,
+    db::{errors::TransientError, keyspace::Keyspace},
+    metadata::tag_set,
+};
+
+/// First bytes of a binary dump, followed by [`DUMP_VERSION`].
+const DUMP_MAGIC: &[u8; 8] = b"EPOCHDMP";
+
+/// The version of the layout of the binary dump.
+const DUMP_VERSION: u8 = 2;
+
+/// The format of an export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// One JSON object per line. Requires the `json` feature.
+    #[cfg(feature = "json")]
+    JsonLines,
+    /// Comma separated values with a header row. Requires the `csv` feature.
+    ///
+    /// Empty fields stand for the default keyspace and for keys without a TTL.
+    /// The tags of a key are held in a single field, itself written as a CSV row.
+    #[cfg(feature = "csv")]
+    Csv,
+    /// A header followed by the `bincode` encoding of every record, each
+    /// prefixed with its length.
+    Binary,
+}
+
+/// A key of an export, along with its value and metadata.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportRecord {
+    /// The namespace of the key, `None` for the default keyspace
+    pub namespace: Option<String>,
+    /// The key
+    pub key: String,
+    /// The value
+    pub value: String,
+    /// The number of times the key was accessed
+    pub freq: u64,
+    /// The creation time of the key, in seconds since the UNIX epoch
+    pub created_at: u64,
+    /// The expiration time of the key, in seconds since the UNIX epoch
+    pub expires_at: Option<u64>,
+    /// The seconds the key had left to live when it was exported
+    pub ttl_remaining: Option<u64>,
+    /// The tags of the key, in alphabetical order
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// The layout of [`ExportRecord`] in version 1 of the binary dump, before tags were exported.
+#[derive(Deserialize)]
+struct ExportRecordV1 {
+    namespace: Option<String>,
+    key: String,
+    value: String,
+    freq: u64,
+    created_at: u64,
+    expires_at: Option<u64>,
+    ttl_remaining: Option<u64>,
+}
+
+impl From<ExportRecordV1> for ExportRecord {
+    fn from(v1: ExportRecordV1) -> ExportRecord {
+        ExportRecord {
+            namespace: v1.namespace,
+            key: v1.key,
+            value: v1.value,
+            freq: v1.freq,
+            created_at: v1.created_at,
+            expires_at: v1.expires_at,
+            ttl_remaining: v1.ttl_remaining,
+            tags: Vec::new(),
+        }
+    }
+}
+
+/// The row of an [`ExportRecord`] in a CSV export, which can't hold a list in a field.
+#[cfg(feature = "csv")]
+#[derive(Serialize, Deserialize)]
+struct CsvRecord {
+    namespace: Option<String>,
+    key: String,
+    value: String,
+    freq: u64,
+    created_at: u64,
+    expires_at: Option<u64>,
+    ttl_remaining: Option<u64>,
+    #[serde(default)]
+    tags: String,
+}
+
+#[cfg(feature = "csv")]
+impl CsvRecord {
+    /// Returns the row of `record`, writing its tags as a CSV row of their own.
+    fn new(record: &ExportRecord) -> Result<CsvRecord, csv::Error> {
+        let mut tags = String::new();
+        if !record.tags.is_empty() {
+            let mut w = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(Vec::new());
+            w.write_record(&record.tags)?;
+            let row = w.into_inner().map_err(|e| e.into_error())?;
+            tags = String::from_utf8_lossy(row.strip_suffix(b"\n").unwrap_or(&row)).into_owned();
+        }
+
+        Ok(CsvRecord {
+            namespace: record.namespace.clone(),
+            key: record.key.clone(),
+            value: record.value.clone(),
+            freq: record.freq,
+            created_at: record.created_at,
+            expires_at: record.expires_at,
+            ttl_remaining: record.ttl_remaining,
+            tags,
+        })
+    }
+
+    /// Returns the record of the row, reading its tags back from their CSV row.
+    fn into_record(self) -> Result<ExportRecord, csv::Error> {
+        let tags = match csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(self.tags.as_bytes())
+            .records()
+            .next()
+        {
+            Some(row) => row?.iter().map(str::to_string).collect(),
+            None => Vec::new(),
+        };
+
+        Ok(ExportRecord {
+            namespace: self.namespace,
+            key: self.key,
+            value: self.value,
+            freq: self.freq,
+            created_at: self.created_at,
+            expires_at: self.expires_at,
+            ttl_remaining: self.ttl_remaining,
+            tags,
+        })
+    }
+}
+
+/// Settings of [`DB::export`].
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    pub(crate) format: ExportFormat,
+    pub(crate) prefix: Option<String>,
+}
+
+impl ExportOptions {
+    /// Exports every key in `format`.
+    pub fn new(format: ExportFormat) -> ExportOptions {
+        ExportOptions {
+            format,
+            prefix: None,
+        }
+    }
+
+    /// Only exports the keys starting with `prefix`, in every keyspace.
+    pub fn prefix(mut self, prefix: &str) -> ExportOptions {
+        self.prefix = Some(prefix.to_string());
+        self
+    }
+}
+
+/// Settings of [`DB::import`].
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    pub(crate) format: ExportFormat,
+    pub(crate) prefix: Option<String>,
+    pub(crate) reset_frequencies: bool,
+    pub(crate) rebase_ttl: bool,
+}
+
+impl ImportOptions {
+    /// Imports every record of an export in `format`, keeping their
+    /// frequencies and expiration times.
+    pub fn new(format: ExportFormat) -> ImportOptions {
+        ImportOptions {
+            format,
+            prefix: None,
+            reset_frequencies: false,
+            rebase_ttl: false,
+        }
+    }
+
+    /// Only imports the keys starting with `prefix`, in every keyspace.
+    pub fn prefix(mut self, prefix: &str) -> ImportOptions {
+        self.prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// Imports every key with a frequency of 0 instead of its exported one.
+    pub fn reset_frequencies(mut self, reset: bool) -> ImportOptions {
+        self.reset_frequencies = reset;
+        self
+    }
+
+    /// Gives every key the time it had left to live when it was exported,
+    /// counted from the import, instead of its exported expiration time.
+    ///
+    /// Keys then outlive the time spent between the export and the import,
+    /// and aren't skipped if their expiration time passed in the meantime.
+    pub fn rebase_ttl(mut self, rebase: bool) -> ImportOptions {
+        self.rebase_ttl = rebase;
+        self
+    }
+}
+
+/// The outcome of [`DB::import`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    /// The number of keys written
+    pub imported: u64,
+    /// The number of keys skipped because their expiration time has passed
+    pub expired: u64,
+}
+
+impl DB {
+    /// Writes every key along with its value and metadata to `writer`.
+    ///
+    /// The default keyspace is exported first, then every namespace. Keys
+    /// whose TTL has passed are skipped. Returns the number of keys exported.
+    ///
+    /// Values are read one at a time and written as they are read, so the
+    /// export never holds more than one value in memory. The keys are read
+    /// through a [`DB::snapshot`], so the export is consistent as of its
+    /// start, except on a replication follower, where it sees the changes
+    /// applied meanwhile. Exporting doesn't count as an access to the keys.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the keys cannot be read, a value is not valid
+    /// UTF-8 or `writer` fails.
+    pub fn export(
+        &self,
+        writer: impl Write,
+        options: &ExportOptions,
+    ) -> Result<u64, Box<dyn Error>> {
+        Ok(self.export_records(writer, options)?)
+    }
+
+    /// Writes the keys of an export read from `reader` to the database.
+    ///
+    /// Each key is written in its own transaction with its exported creation
+    /// time, frequency, expiration time and tags, replacing the key if it exists,
+    /// and namespaces are created as needed. Keys whose expiration time has
+    /// passed are skipped, unless [`ImportOptions::rebase_ttl`] is set.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a record cannot be read or carries invalid tags,
+    /// in which case the keys before it stay imported, or a key cannot be
+    /// written.
+    pub fn import(
+        &self,
+        reader: impl Read,
+        options: &ImportOptions,
+    ) -> Result<ImportReport, Box<dyn Error>> {
+        let report = self.import_records(reader, options)?;
+        self.persist(self.durability)?;
+        Ok(report)
+    }
+
+    /// Writes the records of every keyspace to `writer`, see [`DB::export`].
+    pub(crate) fn export_records(
+        &self,
+        writer: impl Write,
+        options: &ExportOptions,
+    ) -> Result<u64, TransientError> {
+        let now = now();
+        let prefix = options.prefix.as_deref().unwrap_or_default();
+        let mut out = RecordWriter::new(writer, options.format)?;
+
+        let mut exported = 0;
+        // NOTE: Keys written, removed or expired during the export don't tear it, except
+        // on a replication follower, which applies changes without keeping pre-images.
+        let snapshot = match self.take_snapshot() {
+            Err(TransientError::Replicating) => self.unregistered_snapshot()?,
+            snapshot => snapshot?,
+        };
+        for view in snapshot.keyspaces() {
+            let mut keys = view.scan_prefix(prefix);
+            while let Some((key, value, meta)) = keys.next_raw()? {
+                if let Some(record) = export_record(view.name(), key, value, &meta, now)? {
+                    out.write(&record)?;
+                    exported += 1;
+                }
+            }
+        }
+        out.finish()?;
+        Ok(exported)
+    }
+    /// Writes the records read from `reader`, see [`DB::import`].
+    pub(crate) fn import_records(
+        &self,
+        reader: impl Read,
+        options: &ImportOptions,
+    ) -> Result<ImportReport, TransientError> {
+        let now = now();
+        let mut report = ImportReport::default();
+
+        for record in RecordReader::new(reader, options.format)? {
+            let record = record?;
+            if !options
+                .prefix
+                .as_deref()
+                .is_none_or(|p| record.key.starts_with(p))
+            {
+                continue;
+            }
+
+            let ttl_sec = if options.rebase_ttl {
+                record.ttl_remaining.map(|t| now + t)
+            } else {
+                record.expires_at
+            };
+            if ttl_sec.is_some_and(|t| t <= now) {
+                report.expired += 1;
+                continue;
+            }
+
+            let tags = tag_set(&record.tags.iter().map(String::as_str).collect::<Vec<_>>())?;
+            let ks = match &record.namespace {
+                None => Arc::clone(&self.keyspace),
+                Some(name) => self.open_namespace(name, None, true)?,
+            };
+            let freq = if options.reset_frequencies {
+                0
+            } else {
+                record.freq
+            };
+            self.restore_in(&ks, &record.key, &record.value, ttl_sec, |meta| {
+                meta.freq = freq;
+                // NOTE: The exported frequency stands in for the decayed score, which isn't exported.
+                meta.score = freq as f64;
+                meta.created_at = record.created_at;
+                meta.tags = tags.clone();
+            })?;
+            report.imported += 1;
+        }
+        Ok(report)
+    }
+
+    /// Writes a key-value pair to `ks` expiring at the deadline `ttl_sec`,
+    /// letting `edit` change its metadata, see [`KeyspaceTx::write_with`].
+    ///
+    /// [`KeyspaceTx::write_with`]: crate::db::keyspace::KeyspaceTx::write_with
+    pub(crate) fn restore_in(
+        &self,
+        ks: &Keyspace,
+        key: &str,
+        value: &str,
+        ttl_sec: Option<u64>,
+        edit: impl Fn(&mut Metadata),
+    ) -> Result<(), TransientError> {
+        let key = key.as_bytes();
+        let stored = self.store_value(ks, key, value.as_bytes())?;
+
+        let res = self.transact(ks, |tx| {
+            tx.write_with(key, &stored.bytes, stored.len, ttl_sec, &edit)
+        });
+        if res.is_err() {
+            stored.discard(ks);
+        }
+        res?;
+
+        ks.stats.writes.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// Returns the current time, in seconds since the UNIX epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Cant get SystemTime")
+        .as_secs()
+}
+
+/// Returns the record of `key`, or `None` if it has expired.
+fn export_record(
+    namespace: Option<&str>,
+    key: Vec<u8>,
+    value: Vec<u8>,
+    meta: &Metadata,
+    now: u64,
+) -> Result<Option<ExportRecord>, TransientError> {
+    if meta.ttl.is_some_and(|t| t <= now) {
+        return Ok(None);
+    }
+
+    let utf8 = |b: Vec<u8>| String::from_utf8(b).map_err(|_| TransientError::ParsingToUTF8Error);
+    Ok(Some(ExportRecord {
+        namespace: namespace.map(str::to_string),
+        key: utf8(key)?,
+        value: utf8(value)?,
+        freq: meta.freq,
+        created_at: meta.created_at,
+        expires_at: meta.ttl,
+        ttl_remaining: meta.ttl.map(|t| t - now),
+        tags: meta.tags.iter().cloned().collect(),
+    }))
+}
+
+/// Writes records in one of the export formats.
+enum RecordWriter<W: Write> {
+    #[cfg(feature = "json")]
+    JsonLines(BufWriter<W>),
+    #[cfg(feature = "csv")]
+    Csv(Box<csv::Writer<W>>),
+    Binary(BufWriter<W>),
+}
+
+impl<W: Write> RecordWriter<W> {
+    /// Starts an export to `writer`, writing the header of the format if it has one.
+    fn new(writer: W, format: ExportFormat) -> Result<RecordWriter<W>, TransientError> {
+        Ok(match format {
+            #[cfg(feature = "json")]
+            ExportFormat::JsonLines => RecordWriter::JsonLines(BufWriter::new(writer)),
+            #[cfg(feature = "csv")]
+            ExportFormat::Csv => RecordWriter::Csv(Box::new(csv::Writer::from_writer(writer))),
+            ExportFormat::Binary => {
+                let mut writer = BufWriter::new(writer);
+                writer.write_all(DUMP_MAGIC).map_err(io_err)?;
+                writer.write_all(&[DUMP_VERSION]).map_err(io_err)?;
+                RecordWriter::Binary(writer)
+            }
+        })
+    }
+
+    fn write(&mut self, record: &ExportRecord) -> Result<(), TransientError> {
+        match self {
+            #[cfg(feature = "json")]
+            RecordWriter::JsonLines(w) => {
+                serde_json::to_writer(&mut *w, record)
+                    .map_err(|_| TransientError::ParsingToByteError)?;
+                w.write_all(b"\n").map_err(io_err)
+            }
+            #[cfg(feature = "csv")]
+            RecordWriter::Csv(w) => CsvRecord::new(record)
+                .and_then(|row| w.serialize(row))
+                .map_err(|_| TransientError::ParsingToByteError),
+            RecordWriter::Binary(w) => {
+                let bytes = encode_to_vec(record, bincode::config::standard())
+                    .map_err(|_| TransientError::ParsingToByteError)?;
+                w.write_all(&(bytes.len() as u32).to_be_bytes())
+                    .map_err(io_err)?;
+                w.write_all(&bytes).map_err(io_err)
+            }
+        }
+    }
+
+    fn finish(self) -> Result<(), TransientError> {
+        match self {
+            #[cfg(feature = "json")]
+            RecordWriter::JsonLines(mut w) => w.flush().map_err(io_err),
+            #[cfg(feature = "csv")]
+            RecordWriter::Csv(mut w) => w.flush().map_err(io_err),
+            RecordWriter::Binary(mut w) => w.flush().map_err(io_err),
+        }
+    }
+}
+
+/// Reads the records of an export in one of the export formats.
+enum RecordReader<R: Read> {
+    #[cfg(feature = "json")]
+    JsonLines(std::io::Lines<BufReader<R>>, u64),
+    #[cfg(feature = "csv")]
+    Csv(csv::DeserializeRecordsIntoIter<R, CsvRecord>, u64),
+    Binary(BufReader<R>, u64, u8),
+}
+
+impl<R: Read> RecordReader<R> {
+    /// Starts reading an export from `reader`, checking the header of the format if it has one.
+    fn new(reader: R, format: ExportFormat) -> Result<RecordReader<R>, TransientError> {
+        Ok(match format {
+            #[cfg(feature = "json")]
+            ExportFormat::JsonLines => RecordReader::JsonLines(BufReader::new(reader).lines(), 0),
+            #[cfg(feature = "csv")]
+            ExportFormat::Csv => {
+                RecordReader::Csv(csv::Reader::from_reader(reader).into_deserialize(), 0)
+            }
+            ExportFormat::Binary => {
+                let mut reader = BufReader::new(reader);
+                let mut header = [0; DUMP_MAGIC.len() + 1];
+                reader
+                    .read_exact(&mut header)
+                    .map_err(|_| TransientError::UnsupportedDump)?;
+                let version = header[DUMP_MAGIC.len()];
+                if header[..DUMP_MAGIC.len()] != DUMP_MAGIC[..]
+                    || !(1..=DUMP_VERSION).contains(&version)
+                {
+                    Err(TransientError::UnsupportedDump)?
+                }
+                RecordReader::Binary(reader, 0, version)
+            }
+        })
+    }
+}
+
+impl<R: Read> Iterator for RecordReader<R> {
+    type Item = Result<ExportRecord, TransientError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            #[cfg(feature = "json")]
+            RecordReader::JsonLines(lines, record) => loop {
+                let line = match lines.next()? {
+                    Ok(line) => line,
+                    Err(e) => return Some(Err(io_err(e))),
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                *record += 1;
+                let record = *record;
+                return Some(
+                    serde_json::from_str(&line)
+                        .map_err(|_| TransientError::MalformedRecord { record }),
+                );
+            },
+            #[cfg(feature = "csv")]
+            RecordReader::Csv(records, record) => {
+                let next = records.next()?;
+                *record += 1;
+                let record = *record;
+                Some(
+                    next.and_then(CsvRecord::into_record)
+                        .map_err(|_| TransientError::MalformedRecord { record }),
+                )
+            }
+            RecordReader::Binary(reader, record, version) => {
+                match reader.fill_buf() {
+                    Ok([]) => return None,
+                    Ok(_) => {}
+                    Err(e) => return Some(Err(io_err(e))),
+                }
+                *record += 1;
+                let record = *record;
+                let malformed = |_| TransientError::MalformedRecord { record };
+
+                let mut len = [0; 4];
+                if let Err(e) = reader.read_exact(&mut len) {
+                    return Some(Err(malformed(e)));
+                }
+                let mut bytes = vec![0; u32::from_be_bytes(len) as usize];
+                if let Err(e) = reader.read_exact(&mut bytes) {
+                    return Some(Err(malformed(e)));
+                }
+                let decoded = match version {
+                    1 => {
+                        decode_from_slice::<ExportRecordV1, _>(&bytes, bincode::config::standard())
+                            .map(|(r, _)| r.into())
+                    }
+                    _ => decode_from_slice(&bytes, bincode::config::standard()).map(|(r, _)| r),
+                };
+                Some(decoded.map_err(|_| TransientError::MalformedRecord { record }))
+            }
+        }
+    }
+}
+
+fn io_err(error: std::io::Error) -> TransientError {
+    TransientError::IOError { error }
+}
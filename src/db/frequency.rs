@@ -0,0 +1,206 @@
+//! This module implements the automatic frequency tracking of reads, see
+//! [`FrequencyTracking`], and the queries ranking keys by frequency.
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    mem,
+    str::from_utf8,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use sled::{
+    Tree,
+    transaction::{ConflictableTransactionError, TransactionError, Transactional},
+};
+
+use crate::{
+    DB, FrequencyTracking, Metadata,
+    db::{
+        errors::TransientError,
+        keyspace::{Keyspace, reindex, transaction_error},
+    },
+    oplog::{self, Mutation},
+};
+
+/// The reads of a keyspace that weren't added to the frequencies yet.
+#[derive(Debug, Default)]
+pub(crate) struct ReadTracker {
+    /// Number of reads since the keyspace was opened, used for sampling
+    count: AtomicU64,
+    /// Number of counted reads of each key since the last pass of the
+    /// background thread, 0 for keys whose reads only update the access time
+    pending: Mutex<HashMap<Vec<u8>, u64>>,
+}
+
+impl ReadTracker {
+    /// Takes every pending read out of the tracker.
+    pub(crate) fn drain(&self) -> HashMap<Vec<u8>, u64> {
+        mem::take(&mut *self.pending.lock().expect("Pending reads lock poisoned"))
+    }
+}
+
+impl DB {
+    /// Returns the `k` most frequently accessed keys of the default keyspace
+    /// with their frequency, hottest first.
+    ///
+    /// Keys are ranked by their raw [`Metadata::freq`], read from an index
+    /// kept in frequency order, so only `k` entries are read.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the index cannot be read.
+    pub fn top_k_by_frequency(&self, k: usize) -> Result<Vec<(String, u64)>, Box<dyn Error>> {
+        Ok(self.rank_in(&self.keyspace, k, true)?)
+    }
+
+    /// Returns the `k` least frequently accessed keys of the default keyspace
+    /// with their frequency, coldest first, see [`DB::top_k_by_frequency`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the index cannot be read.
+    pub fn coldest(&self, k: usize) -> Result<Vec<(String, u64)>, Box<dyn Error>> {
+        Ok(self.rank_in(&self.keyspace, k, false)?)
+    }
+
+    /// Returns `k` keys of `ks` from the hot or the cold end of the frequency index.
+    pub(crate) fn rank_in(
+        &self,
+        ks: &Keyspace,
+        k: usize,
+        hottest: bool,
+    ) -> Result<Vec<(String, u64)>, TransientError> {
+        let iter = ks.index_tree.iter();
+        let iter: Box<dyn Iterator<Item = _>> = if hottest {
+            Box::new(iter.rev())
+        } else {
+            Box::new(iter)
+        };
+
+        iter.take(k)
+            .map(|res| {
+                let (index_key, key) = res.map_err(|e| TransientError::SledError { error: e })?;
+                let freq_byte: [u8; 8] = index_key
+                    .get(..8)
+                    .and_then(|b| b.try_into().ok())
+                    .ok_or(TransientError::ParsingToU64ByteFailed)?;
+                let key = from_utf8(&key)
+                    .map_err(|_| TransientError::ParsingToUTF8Error)?
+                    .to_string();
+                Ok((key, u64::from_be_bytes(freq_byte)))
+            })
+            .collect()
+    }
+
+    /// Records that `key` of `ks` was read, according to the configured
+    /// [`FrequencyTracking`].
+    ///
+    /// Reads that don't change the frequency are buffered with a count of 0,
+    /// so the background thread still records their access time, without
+    /// logging a mutation. Nothing is recorded when the database is opened
+    /// read-only.
+    pub(crate) fn track_read(&self, ks: &Keyspace, key: &[u8]) -> Result<(), TransientError> {
+        let count = match self.frequency_tracking {
+            _ if self.read_only => return Ok(()),
+            FrequencyTracking::Manual => 0,
+            FrequencyTracking::Sampled { one_in } => {
+                if (ks.reads.count.fetch_add(1, Ordering::Relaxed) + 1).is_multiple_of(one_in) {
+                    let counts = HashMap::from([(key.to_vec(), one_in)]);
+                    return add_frequencies(
+                        ks,
+                        &self.oplog_tree,
+                        &self.state_tree,
+                        &counts,
+                        self.half_life,
+                    );
+                }
+                0
+            }
+            FrequencyTracking::Buffered => 1,
+        };
+
+        *ks.reads
+            .pending
+            .lock()
+            .expect("Pending reads lock poisoned")
+            .entry(key.to_vec())
+            .or_default() += count;
+        Ok(())
+    }
+}
+
+/// Adds `counts` to the frequencies of the keys of `ks` and records their
+/// access time in a single transaction, skipping the keys that no longer exist
+/// or whose metadata cannot be decoded.
+///
+/// Only the keys whose frequency changed are logged, an access time alone
+/// isn't replicated, audited or reported to watchers.
+///
+/// Does nothing if the namespace was cleared or dropped in the meantime.
+pub(crate) fn add_frequencies(
+    ks: &Keyspace,
+    oplog_tree: &Tree,
+    state_tree: &Tree,
+    counts: &HashMap<Vec<u8>, u64>,
+    half_life: Option<Duration>,
+) -> Result<(), TransientError> {
+    if counts.is_empty() {
+        return Ok(());
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Cant get SystemTime")
+        .as_secs();
+
+    let l: Result<(), TransactionError<TransientError>> =
+        (&*ks.meta_tree, &*ks.index_tree, oplog_tree, state_tree).transaction(
+            |(freq, index, oplog, state)| {
+                ks.check_generation(state)?;
+
+                for (key, count) in counts {
+                    let Some(meta) = freq.get(key)? else {
+                        continue;
+                    };
+                    // NOTE: A corrupted entry would fail the reads of every other key.
+                    let Ok(mut meta) = Metadata::from_u8(&meta) else {
+                        continue;
+                    };
+                    let old_freq = meta.freq;
+                    meta.record_access(*count, now, half_life);
+                    reindex(index, key, Some(old_freq), Some(meta.freq))?;
+
+                    freq.insert(
+                        key.as_slice(),
+                        meta.to_u8().map_err(|_| {
+                            ConflictableTransactionError::Abort(TransientError::ParsingToByteError)
+                        })?,
+                    )?;
+
+                    if *count > 0 {
+                        oplog::append(
+                            oplog,
+                            state,
+                            ks.name.as_deref(),
+                            Mutation::Meta {
+                                key: key.clone(),
+                                meta,
+                            },
+                            || TransientError::ParsingToByteError,
+                        )?;
+                    }
+                }
+
+                Ok(())
+            },
+        );
+
+    match l.map_err(transaction_error) {
+        Err(TransientError::NamespaceChanged) => Ok(()),
+        res => res,
+    }
+}
@@ -0,0 +1,650 @@
+//! This module defines `Keyspace`, the trees holding the keys of either the
+//! default keyspace of a `DB` or one of its namespaces, along with the
+//! operations both of them share.
+
+use std::{
+    collections::BTreeSet,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use sled::{
+    Tree,
+    transaction::{
+        ConflictableTransactionError, TransactionError, Transactional, TransactionalTree,
+        UnabortableTransactionError,
+    },
+};
+
+use crate::{
+    DB, Metadata,
+    db::{
+        blob::remove_chunks, errors::TransientError, frequency::ReadTracker, open_tree, snapshot,
+    },
+    namespace::{NamespaceConfig, NamespaceRecord, namespace_key},
+    oplog::{self, Mutation},
+};
+
+/// Counters describing the activity of a keyspace since the `DB` was opened.
+#[derive(Debug, Default)]
+pub(crate) struct KeyspaceStats {
+    pub(crate) hits: AtomicU64,
+    pub(crate) misses: AtomicU64,
+    pub(crate) writes: AtomicU64,
+    pub(crate) removes: AtomicU64,
+    pub(crate) expirations: AtomicU64,
+    pub(crate) evictions: AtomicU64,
+}
+
+/// The trees and settings of a single keyspace.
+#[derive(Debug)]
+pub(crate) struct Keyspace {
+    /// Name of the namespace, `None` for the default keyspace
+    pub(crate) name: Option<String>,
+    /// Bumped every time the namespace is cleared, so writes racing a clear are rejected
+    pub(crate) generation: u64,
+    /// Stores the key and value
+    pub(crate) data_tree: Arc<Tree>,
+    /// Stores the key and the metadata
+    pub(crate) meta_tree: Arc<Tree>,
+    /// Stores the ttl timestamp and the key
+    pub(crate) ttl_tree: Arc<Tree>,
+    /// Stores the frequency and the key, ordered by frequency
+    pub(crate) index_tree: Arc<Tree>,
+    /// Stores the tag and the key, grouped by tag
+    pub(crate) tag_tree: Arc<Tree>,
+    /// Stores the chunks of the values larger than the chunk size
+    pub(crate) blob_tree: Arc<Tree>,
+    /// Default TTL and eviction policy of the keyspace
+    pub(crate) config: NamespaceConfig,
+    /// Shared by every generation of the namespace
+    pub(crate) stats: Arc<KeyspaceStats>,
+    /// Reads not added to the frequencies yet
+    pub(crate) reads: ReadTracker,
+}
+
+impl Keyspace {
+    /// Returns the names of the data, metadata, ttl, frequency index, tag
+    /// index and blob trees of a keyspace.
+    pub(crate) fn tree_names(name: Option<&str>, generation: u64) -> [String; 6] {
+        match name {
+            None => [
+                "data_tree".to_string(),
+                "freq_tree".to_string(),
+                "ttl_tree".to_string(),
+                "freq_index_tree".to_string(),
+                "tag_tree".to_string(),
+                "blob_tree".to_string(),
+            ],
+            Some(name) => [
+                format!("ns:{name}:{generation}:data_tree"),
+                format!("ns:{name}:{generation}:freq_tree"),
+                format!("ns:{name}:{generation}:ttl_tree"),
+                format!("ns:{name}:{generation}:freq_index_tree"),
+                format!("ns:{name}:{generation}:tag_tree"),
+                format!("ns:{name}:{generation}:blob_tree"),
+            ],
+        }
+    }
+
+    /// Opens the trees of a keyspace, creating them if they don't exist unless
+    /// `read_only` is set.
+    ///
+    /// The frequency index is rebuilt if the keyspace was written by a version
+    /// that didn't maintain it, unless `read_only` is set.
+    pub(crate) fn open(
+        db: &sled::Db,
+        name: Option<&str>,
+        generation: u64,
+        config: NamespaceConfig,
+        read_only: bool,
+    ) -> Result<Keyspace, sled::Error> {
+        let [
+            data_name,
+            meta_name,
+            ttl_name,
+            index_name,
+            tag_name,
+            blob_name,
+        ] = Keyspace::tree_names(name, generation);
+
+        let ks = Keyspace {
+            name: name.map(str::to_string),
+            generation,
+            data_tree: Arc::new(open_tree(db, &data_name, read_only)?),
+            meta_tree: Arc::new(open_tree(db, &meta_name, read_only)?),
+            ttl_tree: Arc::new(open_tree(db, &ttl_name, read_only)?),
+            index_tree: Arc::new(open_tree(db, &index_name, read_only)?),
+            tag_tree: Arc::new(open_tree(db, &tag_name, read_only)?),
+            blob_tree: Arc::new(open_tree(db, &blob_name, read_only)?),
+            config,
+            stats: Arc::new(KeyspaceStats::default()),
+            reads: ReadTracker::default(),
+        };
+
+        if !read_only && ks.index_tree.is_empty() && !ks.meta_tree.is_empty() {
+            for i in ks.meta_tree.iter() {
+                let (key, meta) = i?;
+                let meta = Metadata::from_u8(&meta)
+                    .map_err(|_| sled::Error::Unsupported("Corrupted metadata".to_string()))?;
+                ks.index_tree.insert(index_key(meta.freq, &key), &*key)?;
+            }
+        }
+
+        Ok(ks)
+    }
+
+    /// Aborts the transaction if the namespace was cleared or dropped since
+    /// this keyspace was resolved.
+    pub(crate) fn check_generation(
+        &self,
+        state: &TransactionalTree,
+    ) -> Result<(), ConflictableTransactionError<TransientError>> {
+        let Some(name) = &self.name else {
+            return Ok(());
+        };
+
+        let current = state
+            .get(namespace_key(name))?
+            .and_then(|r| NamespaceRecord::from_u8(&r).ok())
+            .map(|r| r.generation);
+        if current != Some(self.generation) {
+            Err(ConflictableTransactionError::Abort(
+                TransientError::NamespaceChanged,
+            ))?
+        }
+        Ok(())
+    }
+}
+
+/// Returns the key of the frequency index entry of `key`.
+pub(crate) fn index_key(freq: u64, key: &[u8]) -> Vec<u8> {
+    [&freq.to_be_bytes()[..], key].concat()
+}
+
+/// Moves `key` in the frequency index from its `old` frequency to its `new`
+/// one, `None` meaning the key isn't stored.
+pub(crate) fn reindex(
+    index: &TransactionalTree,
+    key: &[u8],
+    old: Option<u64>,
+    new: Option<u64>,
+) -> Result<(), UnabortableTransactionError> {
+    if old == new {
+        return Ok(());
+    }
+    if let Some(old) = old {
+        index.remove(index_key(old, key))?;
+    }
+    if let Some(new) = new {
+        index.insert(index_key(new, key), key)?;
+    }
+    Ok(())
+}
+
+/// Returns the prefix shared by the tag index entries of `tag`.
+///
+/// The tag is prefixed with its length so no tag is a prefix of another.
+pub(crate) fn tag_prefix(tag: &str) -> Vec<u8> {
+    [&(tag.len() as u32).to_be_bytes()[..], tag.as_bytes()].concat()
+}
+
+/// Moves `key` in the tag index from its `old` tags to its `new` ones.
+pub(crate) fn retag(
+    tag_index: &TransactionalTree,
+    key: &[u8],
+    old: &BTreeSet<String>,
+    new: &BTreeSet<String>,
+) -> Result<(), UnabortableTransactionError> {
+    for tag in old.difference(new) {
+        tag_index.remove([tag_prefix(tag).as_slice(), key].concat())?;
+    }
+    for tag in new.difference(old) {
+        tag_index.insert([tag_prefix(tag).as_slice(), key].concat(), key)?;
+    }
+    Ok(())
+}
+
+/// Flattens the error of a transaction aborted with a `TransientError`.
+pub(crate) fn transaction_error(e: TransactionError<TransientError>) -> TransientError {
+    match e {
+        TransactionError::Abort(e) => e,
+        TransactionError::Storage(error) => TransientError::SledError { error },
+    }
+}
+
+/// The trees of a keyspace as seen from inside a transaction.
+pub(crate) struct KeyspaceTx<'a> {
+    pub(crate) ks: &'a Keyspace,
+    pub(crate) data: &'a TransactionalTree,
+    pub(crate) freq: &'a TransactionalTree,
+    pub(crate) ttl_tree: &'a TransactionalTree,
+    pub(crate) index: &'a TransactionalTree,
+    pub(crate) tags: &'a TransactionalTree,
+    pub(crate) blobs: &'a TransactionalTree,
+    pub(crate) oplog: &'a TransactionalTree,
+    pub(crate) state: &'a TransactionalTree,
+    pub(crate) snapshots: &'a TransactionalTree,
+}
+
+impl KeyspaceTx<'_> {
+    /// Writes a key-value pair, keeping the frequency, creation time and tags
+    /// of an existing key, replacing its TTL with `ttl_sec` and bumping its version.
+    ///
+    /// `stored` is the value as encoded by the `ValueCodec`, or a reference to
+    /// its chunks, and `value_len` the length of the value before that. The
+    /// chunks of the value it replaces are removed, unless a snapshot may read it.
+    pub(crate) fn write(
+        &self,
+        byte: &[u8],
+        stored: &[u8],
+        value_len: usize,
+        ttl_sec: Option<u64>,
+    ) -> Result<(), ConflictableTransactionError<TransientError>> {
+        self.write_with(byte, stored, value_len, ttl_sec, |_| {})
+    }
+
+    /// Writes a key-value pair like [`KeyspaceTx::write`], letting `edit`
+    /// change the metadata before it is stored.
+    ///
+    /// `edit` may change the frequency and the tags but not the TTL.
+    pub(crate) fn write_with(
+        &self,
+        byte: &[u8],
+        stored: &[u8],
+        value_len: usize,
+        ttl_sec: Option<u64>,
+        edit: impl FnOnce(&mut Metadata),
+    ) -> Result<(), ConflictableTransactionError<TransientError>> {
+        let abort = || ConflictableTransactionError::Abort(TransientError::SledTransactionError);
+
+        let old_meta = self.freq.get(byte)?;
+        let (mut meta, old_freq) = match &old_meta {
+            Some(m) => {
+                let mut meta = Metadata::from_u8(m).map_err(|_| abort())?;
+                if let Some(t) = meta.ttl {
+                    let _ = self.ttl_tree.remove([&t.to_be_bytes()[..], byte].concat());
+                }
+                meta.ttl = ttl_sec;
+                let freq = meta.freq;
+                (meta, Some(freq))
+            }
+            None => (Metadata::new(ttl_sec), None),
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Cant get SystemTime")
+            .as_secs();
+        meta.record_write(value_len, now.max(meta.created_at));
+        let old_tags = meta.tags.clone();
+        edit(&mut meta);
+        reindex(self.index, byte, old_freq, Some(meta.freq))?;
+        if meta.tags != old_tags {
+            retag(self.tags, byte, &old_tags, &meta.tags)?;
+        }
+
+        self.freq.insert(byte, meta.to_u8().map_err(|_| abort())?)?;
+
+        let old = self.data.insert(byte, stored)?;
+        let kept = snapshot::preserve(
+            self.snapshots,
+            self.state,
+            self.ks,
+            byte,
+            old.as_deref(),
+            old_meta.as_deref(),
+        )?;
+        if let Some(old) = old
+            && !kept
+        {
+            remove_chunks(self.blobs, &old)?;
+        }
+
+        if let Some(d) = ttl_sec {
+            self.ttl_tree
+                .insert([&d.to_be_bytes()[..], byte].concat(), byte)?;
+        }
+
+        oplog::append(
+            self.oplog,
+            self.state,
+            self.ks.name.as_deref(),
+            Mutation::Set {
+                key: byte.to_vec(),
+                value: stored.to_vec(),
+                meta,
+            },
+            || TransientError::SledTransactionError,
+        )?;
+
+        Ok(())
+    }
+
+    /// Replaces the tags of a key.
+    ///
+    /// Returns false, without changing anything, if the key has no metadata.
+    pub(crate) fn set_tags(
+        &self,
+        byte: &[u8],
+        tags: &BTreeSet<String>,
+    ) -> Result<bool, ConflictableTransactionError<TransientError>> {
+        let abort = || ConflictableTransactionError::Abort(TransientError::SledTransactionError);
+
+        let Some(old_meta) = self.freq.get(byte)? else {
+            return Ok(false);
+        };
+        let mut meta = Metadata::from_u8(&old_meta).map_err(|_| abort())?;
+        snapshot::preserve_metadata(
+            self.snapshots,
+            self.state,
+            self.ks,
+            byte,
+            self.data.get(byte)?.as_deref(),
+            &old_meta,
+        )?;
+        retag(self.tags, byte, &meta.tags, tags)?;
+        meta.tags = tags.clone();
+        self.freq.insert(byte, meta.to_u8().map_err(|_| abort())?)?;
+
+        oplog::append(
+            self.oplog,
+            self.state,
+            self.ks.name.as_deref(),
+            Mutation::Meta {
+                key: byte.to_vec(),
+                meta,
+            },
+            || TransientError::SledTransactionError,
+        )?;
+
+        Ok(true)
+    }
+
+    /// Replaces the TTL of a key with `ttl_sec`, leaving its value untouched.
+    ///
+    /// Returns false, without changing anything, if the key has no metadata.
+    pub(crate) fn set_ttl(
+        &self,
+        byte: &[u8],
+        ttl_sec: Option<u64>,
+    ) -> Result<bool, ConflictableTransactionError<TransientError>> {
+        let abort = || ConflictableTransactionError::Abort(TransientError::SledTransactionError);
+
+        let Some(old_meta) = self.freq.get(byte)? else {
+            return Ok(false);
+        };
+        let mut meta = Metadata::from_u8(&old_meta).map_err(|_| abort())?;
+        snapshot::preserve_metadata(
+            self.snapshots,
+            self.state,
+            self.ks,
+            byte,
+            self.data.get(byte)?.as_deref(),
+            &old_meta,
+        )?;
+        if let Some(t) = meta.ttl {
+            let _ = self.ttl_tree.remove([&t.to_be_bytes()[..], byte].concat());
+        }
+        if let Some(d) = ttl_sec {
+            self.ttl_tree
+                .insert([&d.to_be_bytes()[..], byte].concat(), byte)?;
+        }
+        meta.ttl = ttl_sec;
+        self.freq.insert(byte, meta.to_u8().map_err(|_| abort())?)?;
+
+        oplog::append(
+            self.oplog,
+            self.state,
+            self.ks.name.as_deref(),
+            Mutation::Meta {
+                key: byte.to_vec(),
+                meta,
+            },
+            || TransientError::SledTransactionError,
+        )?;
+
+        Ok(true)
+    }
+
+    /// Removes a key-value pair and its metadata.
+    ///
+    /// Returns false, without changing anything, if the key has no metadata.
+    pub(crate) fn delete(
+        &self,
+        byte: &[u8],
+    ) -> Result<bool, ConflictableTransactionError<TransientError>> {
+        let abort = || ConflictableTransactionError::Abort(TransientError::SledTransactionError);
+
+        let Some(old_meta) = self.freq.get(byte)? else {
+            return Ok(false);
+        };
+        let meta = Metadata::from_u8(&old_meta).map_err(|_| abort())?;
+        let old = self.data.remove(byte)?;
+        let kept = snapshot::preserve(
+            self.snapshots,
+            self.state,
+            self.ks,
+            byte,
+            old.as_deref(),
+            Some(&old_meta),
+        )?;
+        if let Some(old) = old
+            && !kept
+        {
+            remove_chunks(self.blobs, &old)?;
+        }
+        self.freq.remove(byte)?;
+        reindex(self.index, byte, Some(meta.freq), None)?;
+        retag(self.tags, byte, &meta.tags, &BTreeSet::new())?;
+
+        if let Some(t) = meta.ttl {
+            let _ = self.ttl_tree.remove([&t.to_be_bytes()[..], byte].concat());
+        }
+
+        oplog::append(
+            self.oplog,
+            self.state,
+            self.ks.name.as_deref(),
+            Mutation::Remove { key: byte.to_vec() },
+            || TransientError::SledTransactionError,
+        )?;
+
+        Ok(true)
+    }
+}
+
+impl Keyspace {
+    /// Turns a relative TTL into an absolute deadline, in seconds since the
+    /// UNIX epoch, falling back to the default TTL of the keyspace.
+    pub(crate) fn deadline(&self, ttl: Option<Duration>) -> Option<u64> {
+        ttl.or(self.config.default_ttl).map(|t| {
+            let systime = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("Cant get SystemTime");
+            (t + systime).as_secs()
+        })
+    }
+}
+
+impl DB {
+    /// Runs `f` in a transaction over the trees of `ks`, the mutation log, the
+    /// state tree and the snapshot tree, aborting if the namespace changed in the meantime.
+    pub(crate) fn transact<T>(
+        &self,
+        ks: &Keyspace,
+        f: impl Fn(&KeyspaceTx) -> Result<T, ConflictableTransactionError<TransientError>>,
+    ) -> Result<T, TransientError> {
+        self.check_writable()?;
+        let l: Result<T, TransactionError<TransientError>> = (
+            &*ks.data_tree,
+            &*ks.meta_tree,
+            &*ks.ttl_tree,
+            &*ks.index_tree,
+            &*ks.tag_tree,
+            &*ks.blob_tree,
+            &*self.oplog_tree,
+            &*self.state_tree,
+            &*self.snapshots.tree,
+        )
+            .transaction(
+                |(data, freq, ttl_tree, index, tags, blobs, oplog, state, snapshots)| {
+                    ks.check_generation(state)?;
+                    f(&KeyspaceTx {
+                        ks,
+                        data,
+                        freq,
+                        ttl_tree,
+                        index,
+                        tags,
+                        blobs,
+                        oplog,
+                        state,
+                        snapshots,
+                    })
+                },
+            );
+        l.map_err(transaction_error)
+    }
+
+    /// Sets a key-value pair in the given keyspace, see [`DB::set`].
+    pub(crate) fn set_in(
+        &self,
+        ks: &Keyspace,
+        key: &str,
+        val: &str,
+        ttl: Option<Duration>,
+    ) -> Result<(), TransientError> {
+        self.set_reader_in(ks, key, val.as_bytes(), ttl)
+    }
+
+    /// Retrieves the value for a given key of the given keyspace, see [`DB::get`].
+    pub(crate) fn get_in(
+        &self,
+        ks: &Keyspace,
+        key: &str,
+    ) -> Result<Option<String>, TransientError> {
+        let byte = key.as_bytes();
+        loop {
+            let stored = ks
+                .data_tree
+                .get(byte)
+                .map_err(|e| TransientError::SledError { error: e })?;
+            let Some(stored) = stored else {
+                ks.stats.misses.fetch_add(1, Ordering::Relaxed);
+                return Ok(None);
+            };
+            // NOTE: The chunks of a value overwritten in the meantime are gone, read it again.
+            let Some(val) = self.read_value(ks, byte, &stored)? else {
+                continue;
+            };
+
+            ks.stats.hits.fetch_add(1, Ordering::Relaxed);
+            self.track_read(ks, byte)?;
+            return Ok(Some(
+                String::from_utf8(val).map_err(|_| TransientError::ParsingToUTF8Error)?,
+            ));
+        }
+    }
+
+    /// Atomically increments the frequency counter for a given key of the
+    /// given keyspace, see [`DB::increment_frequency`].
+    pub(crate) fn increment_frequency_in(
+        &self,
+        ks: &Keyspace,
+        key: &str,
+    ) -> Result<(), TransientError> {
+        self.check_writable()?;
+        let byte = key.as_bytes();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Cant get SystemTime")
+            .as_secs();
+
+        let l: Result<(), TransactionError<TransientError>> = (
+            &*ks.meta_tree,
+            &*ks.index_tree,
+            &*self.oplog_tree,
+            &*self.state_tree,
+        )
+            .transaction(|(freq, index, oplog, state)| {
+                ks.check_generation(state)?;
+
+                let metadata = freq.get(byte)?.ok_or(ConflictableTransactionError::Abort(
+                    TransientError::IncretmentError,
+                ))?;
+                let mut meta = Metadata::from_u8(&metadata).map_err(|_| {
+                    ConflictableTransactionError::Abort(TransientError::IncretmentError)
+                })?;
+                let old_freq = meta.freq;
+                meta.record_access(1, now, self.half_life);
+                reindex(index, byte, Some(old_freq), Some(meta.freq))?;
+
+                freq.insert(
+                    byte,
+                    meta.to_u8().map_err(|_| {
+                        ConflictableTransactionError::Abort(TransientError::ParsingToByteError)
+                    })?,
+                )?;
+
+                oplog::append(
+                    oplog,
+                    state,
+                    ks.name.as_deref(),
+                    Mutation::Meta {
+                        key: byte.to_vec(),
+                        meta,
+                    },
+                    || TransientError::ParsingToByteError,
+                )?;
+
+                Ok(())
+            });
+        l.map_err(transaction_error)
+    }
+
+    /// Removes a key-value pair and its metadata from the given keyspace, see [`DB::remove`].
+    pub(crate) fn remove_in(&self, ks: &Keyspace, key: &str) -> Result<(), TransientError> {
+        self.transact(ks, |tx| {
+            if !tx.delete(key.as_bytes())? {
+                Err(ConflictableTransactionError::Abort(
+                    TransientError::SledTransactionError,
+                ))?
+            }
+            Ok(())
+        })?;
+
+        ks.stats.removes.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Brings the score and access windows of `meta` up to date with the current time.
+    pub(crate) fn current_metadata(&self, meta: Metadata) -> Metadata {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Cant get SystemTime")
+            .as_secs();
+        meta.at(now, self.half_life)
+    }
+
+    /// Retrieves the metadata for a given key of the given keyspace, see [`DB::get_metadata`].
+    pub(crate) fn get_metadata_in(
+        &self,
+        ks: &Keyspace,
+        key: &str,
+    ) -> Result<Option<Metadata>, TransientError> {
+        let byte = key.as_bytes();
+        let meta = ks
+            .meta_tree
+            .get(byte)
+            .map_err(|e| TransientError::SledError { error: e })?;
+        match meta {
+            Some(val) => Ok(Some(self.current_metadata(
+                Metadata::from_u8(&val).map_err(|_| TransientError::ParsingFromByteError)?,
+            ))),
+            None => Ok(None),
+        }
+    }
+}
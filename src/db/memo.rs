@@ -0,0 +1,137 @@
+//! This module implements read-through caching on the keys: a value missing
+//! from the database is computed by the caller and set with a TTL, so the
+//! reads that follow get it from the database until the TTL elapses.
+//!
+//! The async variants, which compute a missing value only once however many
+//! tasks miss it at the same time, are in [`AsyncDB`](crate::async_db::AsyncDB).
+
+use std::{
+    error::Error,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    DB,
+    db::{errors::TransientError, keyspace::Keyspace},
+    namespace::Namespace,
+};
+
+impl DB {
+    /// Retrieves the value for a given key, or computes it with `f` and sets
+    /// it with `ttl` if the key doesn't exist or its TTL elapsed.
+    ///
+    /// ```no_run
+    /// use epoch_db::DB;
+    /// use std::{path::Path, time::Duration};
+    ///
+    /// let db = DB::new(Path::new("./my_database")).unwrap();
+    /// let name = db
+    ///     .get_or_insert_with("user:1:name", Some(Duration::from_secs(60)), || {
+    ///         "Alice".to_string()
+    ///     })
+    ///     .unwrap();
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value cannot be retrieved, is not valid UTF-8
+    /// or cannot be set.
+    pub fn get_or_insert_with(
+        &self,
+        key: &str,
+        ttl: Option<Duration>,
+        f: impl FnOnce() -> String,
+    ) -> Result<String, Box<dyn Error>> {
+        self.try_get_or_insert_with(key, ttl, || Ok::<_, TransientError>(f()))
+    }
+
+    /// Retrieves the value for a given key like [`DB::get_or_insert_with`],
+    /// computing a missing value with the fallible `f`.
+    ///
+    /// Nothing is set if `f` fails, so the next call tries to compute the
+    /// value again.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of `f`, or an error if the value cannot be
+    /// retrieved, is not valid UTF-8 or cannot be set.
+    pub fn try_get_or_insert_with<E: Into<Box<dyn Error>>>(
+        &self,
+        key: &str,
+        ttl: Option<Duration>,
+        f: impl FnOnce() -> Result<String, E>,
+    ) -> Result<String, Box<dyn Error>> {
+        if let Some((val, _)) = self.get_cached_in(&self.keyspace, key)? {
+            return Ok(val);
+        }
+        let val = f().map_err(Into::into)?;
+        self.set_in(&self.keyspace, key, &val, ttl)?;
+        self.persist(self.durability)?;
+        Ok(val)
+    }
+
+    /// Retrieves the value of a key of the given keyspace along with its TTL,
+    /// `None` if the key doesn't exist or its TTL elapsed.
+    pub(crate) fn get_cached_in(
+        &self,
+        ks: &Keyspace,
+        key: &str,
+    ) -> Result<Option<(String, Option<u64>)>, TransientError> {
+        let Some((val, meta)) = self.get_with_metadata_in(ks, key)? else {
+            return Ok(None);
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Cant get SystemTime")
+            .as_secs();
+        // NOTE: The background thread may not have removed an expired value yet.
+        if meta.ttl.is_some_and(|t| t <= now) {
+            return Ok(None);
+        }
+        Ok(Some((val, meta.ttl)))
+    }
+}
+
+impl Namespace<'_> {
+    /// Retrieves the value for a given key, or computes it with `f` and sets
+    /// it with `ttl` if the key doesn't exist or its TTL elapsed, see
+    /// [`DB::get_or_insert_with`].
+    ///
+    /// If `ttl` is `None`, the default TTL of the namespace is used, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped, the value cannot be
+    /// retrieved, is not valid UTF-8 or cannot be set.
+    pub fn get_or_insert_with(
+        &self,
+        key: &str,
+        ttl: Option<Duration>,
+        f: impl FnOnce() -> String,
+    ) -> Result<String, Box<dyn Error>> {
+        self.try_get_or_insert_with(key, ttl, || Ok::<_, TransientError>(f()))
+    }
+
+    /// Retrieves the value for a given key like
+    /// [`Namespace::get_or_insert_with`], computing a missing value with the
+    /// fallible `f`, see [`DB::try_get_or_insert_with`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of `f`, or an error if the namespace was dropped, the
+    /// value cannot be retrieved, is not valid UTF-8 or cannot be set.
+    pub fn try_get_or_insert_with<E: Into<Box<dyn Error>>>(
+        &self,
+        key: &str,
+        ttl: Option<Duration>,
+        f: impl FnOnce() -> Result<String, E>,
+    ) -> Result<String, Box<dyn Error>> {
+        if let Some((val, _)) = self.with_keyspace(|ks| self.db.get_cached_in(ks, key))? {
+            return Ok(val);
+        }
+        let val = f().map_err(Into::into)?;
+        self.with_keyspace(|ks| self.db.set_in(ks, key, &val, ttl))?;
+        self.db.persist(self.db.durability)?;
+        Ok(val)
+    }
+}
@@ -0,0 +1,185 @@
+//! This module versions the on-disk format of the database and upgrades
+//! databases written by older versions when they are opened.
+//!
+//! The format version is stored in the `state_tree`. Databases created before
+//! it existed are at version 0. Every change of the format comes with a
+//! [`Migration`] from the previous version, and [`migrate`] runs the missing
+//! ones in order, recording the new version after each of them. Migrations
+//! must be safe to run again, in case the process stops before the version
+//! is recorded.
+
+use sled::{Batch, Db, Tree};
+
+use crate::{Metadata, db::keyspace::tag_prefix};
+
+/// The version of the on-disk format written by this version of the crate.
+pub const FORMAT_VERSION: u64 = 4;
+
+/// Key in the `state_tree` holding the on-disk format version.
+pub(crate) const FORMAT_VERSION_KEY: &[u8] = b"format_version";
+
+/// An upgrade of the on-disk format from `from` to `from + 1`.
+struct Migration {
+    /// The format version the migration upgrades from
+    from: u64,
+    /// What the migration does, reported when it fails
+    description: &'static str,
+    /// Applies the migration to the whole database
+    run: fn(&Db) -> Result<(), sled::Error>,
+}
+
+/// Every migration, ordered by the version they upgrade from.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: 0,
+        description: "versioned metadata envelope",
+        run: envelope_metadata,
+    },
+    Migration {
+        from: 1,
+        description: "metadata with update time, version, value length and tags",
+        run: upgrade_metadata,
+    },
+    Migration {
+        from: 2,
+        description: "tag index",
+        run: index_tags,
+    },
+    Migration {
+        from: 3,
+        description: "actor of the mutations in the mutation log",
+        run: clear_oplog,
+    },
+];
+
+/// Returns the format version of the database, 0 if none was recorded.
+pub(crate) fn format_version(state_tree: &Tree) -> Result<u64, sled::Error> {
+    let Some(version) = state_tree.get(FORMAT_VERSION_KEY)? else {
+        return Ok(0);
+    };
+    let version: [u8; 8] = version
+        .as_ref()
+        .try_into()
+        .map_err(|_| sled::Error::Unsupported("Corrupted format version".to_string()))?;
+    Ok(u64::from_be_bytes(version))
+}
+
+/// Brings the database up to [`FORMAT_VERSION`].
+///
+/// # Errors
+///
+/// Returns `sled::Error::Unsupported` if the database was written by a newer
+/// version of the crate, or if a migration fails.
+pub(crate) fn migrate(db: &Db, state_tree: &Tree) -> Result<(), sled::Error> {
+    let version = format_version(state_tree)?;
+    if version > FORMAT_VERSION {
+        return Err(sled::Error::Unsupported(format!(
+            "The database has on-disk format version {version}, but this version of epoch-db \
+             only supports up to version {FORMAT_VERSION}. Open it with a newer version."
+        )));
+    }
+
+    for migration in MIGRATIONS.iter().filter(|m| m.from >= version) {
+        (migration.run)(db).map_err(|e| {
+            sled::Error::Unsupported(format!(
+                "Migration from format version {} ({}) failed: {e}",
+                migration.from, migration.description
+            ))
+        })?;
+        state_tree.insert(FORMAT_VERSION_KEY, &(migration.from + 1).to_be_bytes())?;
+    }
+
+    db.flush()?;
+    Ok(())
+}
+
+/// Checks that the database is at [`FORMAT_VERSION`] without upgrading it.
+///
+/// # Errors
+///
+/// Returns `sled::Error::Unsupported` if the database was written in another
+/// on-disk format.
+pub(crate) fn check(state_tree: &Tree) -> Result<(), sled::Error> {
+    let version = format_version(state_tree)?;
+    if version != FORMAT_VERSION {
+        return Err(sled::Error::Unsupported(format!(
+            "The database has on-disk format version {version}, but this version of epoch-db \
+             reads version {FORMAT_VERSION} without upgrading it. Open it for writing first."
+        )));
+    }
+    Ok(())
+}
+
+/// Rewrites unversioned metadata into the versioned envelope.
+///
+/// The entries of the mutation log embed metadata in the old layout, so the
+/// log is cleared. Followers behind the primary bootstrap from a snapshot.
+fn envelope_metadata(db: &Db) -> Result<(), sled::Error> {
+    upgrade_metadata(db)?;
+    clear_oplog(db)
+}
+
+/// Clears the mutation log, whose entries are in the layout without the
+/// actor of the mutation. Followers behind the primary bootstrap from a snapshot.
+fn clear_oplog(db: &Db) -> Result<(), sled::Error> {
+    db.open_tree("oplog_tree")?.clear()
+}
+
+/// Rewrites the metadata of every keyspace that isn't in the current layout,
+/// filling in the length of the value from the data tree of the keyspace.
+fn upgrade_metadata(db: &Db) -> Result<(), sled::Error> {
+    for prefix in keyspace_prefixes(db) {
+        let meta_tree = db.open_tree([&prefix, b"freq_tree".as_slice()].concat())?;
+        let data_tree = db.open_tree([&prefix, b"data_tree".as_slice()].concat())?;
+
+        let mut batch = Batch::default();
+        for i in meta_tree.iter() {
+            let (key, meta) = i?;
+            if Metadata::is_current(&meta) {
+                continue;
+            }
+            let mut meta = Metadata::from_u8(&meta)
+                .map_err(|e| sled::Error::Unsupported(format!("Corrupted metadata: {e}")))?;
+            if let Some(val) = data_tree.get(&key)? {
+                meta.value_len = val.len() as u64;
+            }
+            let meta = meta
+                .to_u8()
+                .map_err(|e| sled::Error::Unsupported(format!("Metadata encoding failed: {e}")))?;
+            batch.insert(key, meta);
+        }
+        meta_tree.apply_batch(batch)?;
+    }
+    Ok(())
+}
+
+/// Fills the tag index of every keyspace from the tags in its metadata.
+fn index_tags(db: &Db) -> Result<(), sled::Error> {
+    for prefix in keyspace_prefixes(db) {
+        let meta_tree = db.open_tree([&prefix, b"freq_tree".as_slice()].concat())?;
+        let tag_tree = db.open_tree([&prefix, b"tag_tree".as_slice()].concat())?;
+
+        let mut batch = Batch::default();
+        for i in meta_tree.iter() {
+            let (key, meta) = i?;
+            let meta = Metadata::from_u8(&meta)
+                .map_err(|e| sled::Error::Unsupported(format!("Corrupted metadata: {e}")))?;
+            for tag in &meta.tags {
+                batch.insert([tag_prefix(tag).as_slice(), &key].concat(), &*key);
+            }
+        }
+        tag_tree.apply_batch(batch)?;
+    }
+    Ok(())
+}
+
+/// Returns the prefix of the tree names of every keyspace stored in `db`,
+/// including the stale generations of namespaces.
+fn keyspace_prefixes(db: &Db) -> Vec<Vec<u8>> {
+    db.tree_names()
+        .iter()
+        .filter_map(|name| name.strip_suffix(b"freq_tree"))
+        .filter(|prefix| prefix.is_empty() || prefix.ends_with(b":"))
+        .map(<[u8]>::to_vec)
+        .collect()
+}
@@ -2,113 +2,183 @@
 //! It includes the `DB` struct and its implementation, which provides the
 //! primary API for interacting with the database.
 
+pub mod batch;
+pub mod blob;
+pub mod codec;
+pub mod config;
+#[cfg(feature = "encryption")]
+pub mod encryption;
 pub mod errors;
-
+pub mod export;
+pub(crate) mod frequency;
+pub(crate) mod keyspace;
+pub(crate) mod memo;
+pub mod migration;
+pub mod snapshot;
+pub(crate) mod tags;
+pub(crate) mod ttl;
+pub mod verify;
+pub(crate) mod version;
+
+use codec::{CompressionStats, ValueCodec};
+use config::{DBConfig, Durability};
 use errors::TransientError;
-use sled::{
-    Config,
-    transaction::{ConflictableTransactionError, TransactionError, Transactional},
-};
+use frequency::add_frequencies;
+use keyspace::Keyspace;
+use sled::{Config, Tree};
+use snapshot::SnapshotRegistry;
 use std::{
+    collections::HashMap,
     error::Error,
+    fs, io,
     path::Path,
-    str::from_utf8,
-    sync::{Arc, atomic::AtomicBool},
-    thread::{self, JoinHandle},
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    sync::{Arc, Mutex, RwLock, atomic::AtomicBool},
+    time::Duration,
+};
+use ttl::TtlWorker;
+
+use crate::{
+    DB, Metadata,
+    audit::AuditLog,
+    namespace::{NAMESPACE_PREFIX, NamespaceConfig, NamespaceRecord},
+    oplog::{OPLOG_SEQ_KEY, OpLogEntry, read_seq},
+    watch::hub::WatchHub,
 };
-
-use crate::{DB, Metadata};
 
 impl DB {
     /// Creates a new `DB` instance or opens an existing one at the specified path.
     ///
     /// This function initializes the underlying `sled` database, opens the required
-    /// data trees (`data_tree`, `meta_tree`, `ttl_tree`), and spawns a background
-    /// thread to handle TTL expirations.
+    /// data trees (`data_tree`, `meta_tree`, `ttl_tree`, `freq_index_tree`, `tag_tree`,
+    /// `blob_tree`, `oplog_tree`, `state_tree`, `audit_tree`, `snapshot_tree`) along
+    /// with the trees of every namespace, and spawns a background thread to handle
+    /// TTL expirations.
     ///
     /// # Errors
     ///
     /// Returns a `sled::Error` if the database cannot be opened at the given path.
     pub fn new(path: &Path) -> Result<DB, sled::Error> {
+        DB::with_config(DBConfig::new(path))
+    }
+
+    /// Creates a new `DB` instance or opens an existing one using the given configuration.
+    ///
+    /// Databases written in an older on-disk format are migrated to
+    /// [`migration::FORMAT_VERSION`] first, unless they are opened with
+    /// [`DBConfig::read_only`].
+    ///
+    /// # Errors
+    ///
+    /// Returns a `sled::Error` if the database cannot be opened at the configured path,
+    /// or `sled::Error::Unsupported` if it was written in a newer on-disk format, or
+    /// in an older one when opened read-only.
+    pub fn with_config(config: DBConfig) -> Result<DB, sled::Error> {
+        // NOTE: sled would create an empty database.
+        if config.read_only && !config.path.exists() {
+            return Err(sled::Error::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No database at {}", config.path.display()),
+            )));
+        }
+
+        // NOTE: sled rewrites its recovery snapshot whenever it opens a database, so
+        // a read-only database is opened from a private copy of its files.
+        let copy = if config.read_only {
+            let copy = tempfile::tempdir()?;
+            copy_dir(&config.path, copy.path())?;
+            Some(copy)
+        } else {
+            None
+        };
+        let path = copy.as_ref().map_or(config.path.as_path(), |c| c.path());
+
+        let flush_interval = config.flush_interval.filter(|_| !config.read_only);
         let db = Config::new()
             .path(path)
-            .cache_capacity(512 * 1024 * 1024)
+            .cache_capacity(config.cache_capacity)
+            .flush_every_ms(flush_interval.map(|i| i.as_millis() as u64))
             .open()?;
 
-        let data_tree = Arc::new(db.open_tree("data_tree")?);
-        let meta_tree = Arc::new(db.open_tree("freq_tree")?);
-        let ttl_tree = Arc::new(db.open_tree("ttl_tree")?);
+        let state_tree = Arc::new(open_tree(&db, "state_tree", config.read_only)?);
+        if config.read_only {
+            migration::check(&state_tree)?;
+        } else {
+            migration::migrate(&db, &state_tree)?;
+        }
 
-        let ttl_tree_clone = Arc::clone(&ttl_tree);
-        let meta_tree_clone = Arc::clone(&meta_tree);
-        let data_tree_clone = Arc::clone(&data_tree);
+        let codec = ValueCodec::new(config.compression, config.compression_threshold);
+        #[cfg(feature = "encryption")]
+        let codec = codec.with_encryption(config.key_provider);
+        codec.check_key(&state_tree, !config.read_only)?;
+        let codec = Arc::new(codec);
+
+        let keyspace = Arc::new(Keyspace::open(
+            &db,
+            None,
+            0,
+            NamespaceConfig::default(),
+            config.read_only,
+        )?);
+        let oplog_tree = Arc::new(open_tree(&db, "oplog_tree", config.read_only)?);
+        let audit = config.audit.filter(|_| !config.read_only);
+        let audit_log = Arc::new(AuditLog::open(&db, &state_tree, audit, config.read_only)?);
+        let snapshots = Arc::new(SnapshotRegistry::open(&db, &state_tree, config.read_only)?);
+
+        let mut namespaces = HashMap::new();
+        for i in state_tree.scan_prefix(NAMESPACE_PREFIX) {
+            let (key, record) = i?;
+            let name = String::from_utf8_lossy(&key[NAMESPACE_PREFIX.len()..]).to_string();
+            let record = NamespaceRecord::from_u8(&record)
+                .map_err(|_| sled::Error::Unsupported(format!("Corrupted namespace {name}")))?;
+            let ks = Keyspace::open(
+                &db,
+                Some(&name),
+                record.generation,
+                record.config,
+                config.read_only,
+            )?;
+            namespaces.insert(name, Arc::new(ks));
+        }
+        let namespaces = Arc::new(RwLock::new(namespaces));
 
         let shutdown: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
-        let shutdown_clone = Arc::clone(&shutdown);
-
-        // TODO: Later have a clean up thread that checks if the following thread is fine and spawn
-        // it back and join the thread lol
-
-        let thread: JoinHandle<Result<(), TransientError>> = thread::spawn(move || {
-            loop {
-                thread::sleep(Duration::new(0, 100000000));
-
-                if shutdown_clone.load(std::sync::atomic::Ordering::SeqCst) {
-                    break;
-                }
-
-                let keys = ttl_tree_clone.iter();
-
-                for i in keys {
-                    let full_key = i.map_err(|e| TransientError::SledError { error: e })?;
-
-                    // NOTE: The reason time is 14 u8s long is because it is being stored like
-                    // this ([time,key], key) not ((time,key), key)
-                    let key = full_key.0;
-                    let key_byte = full_key.1;
-
-                    if key.len() < 8 {
-                        Err(TransientError::ParsingToU64ByteFailed)?
-                    }
-
-                    let time_byte: [u8; 8] = (&key[..8])
-                        .try_into()
-                        .map_err(|_| TransientError::ParsingToByteError)?;
-
-                    let time = u64::from_be_bytes(time_byte);
-                    let curr_time = SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .expect("Cant get SystemTime")
-                        .as_secs();
-
-                    if curr_time >= time {
-                        let l: Result<(), TransactionError<()>> =
-                            (&*data_tree_clone, &*meta_tree_clone, &*ttl_tree_clone).transaction(
-                                |(data, freq, ttl_tree_clone)| {
-                                    let byte = &key_byte;
-                                    data.remove(byte)?;
-                                    freq.remove(byte)?;
-
-                                    let _ = ttl_tree_clone.remove([&time_byte, &byte[..]].concat());
-
-                                    Ok(())
-                                },
-                            );
-                        l.map_err(|_| TransientError::SledTransactionError)?;
-                    } else {
-                        continue;
-                    }
-                }
+
+        let thread = (!config.read_only).then(|| {
+            TtlWorker {
+                default: Arc::clone(&keyspace),
+                namespaces: Arc::clone(&namespaces),
+                oplog_tree: Arc::clone(&oplog_tree),
+                state_tree: Arc::clone(&state_tree),
+                snapshot_tree: Arc::clone(&snapshots.tree),
+                oplog_retention: config.oplog_retention,
+                audit_log: Arc::clone(&audit_log),
+                half_life: config.frequency_half_life,
+                #[cfg(feature = "encryption")]
+                codec: Arc::clone(&codec),
+                shutdown: Arc::clone(&shutdown),
+                reported: Mutex::default(),
             }
-            Ok(())
+            .spawn()
         });
+
         Ok(DB {
-            data_tree,
-            meta_tree,
-            ttl_tree,
-            ttl_thread: Some(thread),
+            sled_db: db,
+            keyspace,
+            namespaces,
+            oplog_tree,
+            state_tree,
+            audit_log,
+            snapshots,
+            durability: config.durability,
+            frequency_tracking: config.frequency_tracking,
+            half_life: config.frequency_half_life,
+            codec,
+            chunk_size: config.chunk_size,
+            read_only: config.read_only,
+            ttl_thread: thread,
             shutdown,
+            watchers: WatchHub::default(),
+            _copy: copy,
         })
     }
 
@@ -117,64 +187,30 @@ impl DB {
     /// If the key already exists, its value and TTL will be updated.
     /// If `ttl` is `None`, the key will be persistent.
     ///
+    /// The write is made durable according to the [`Durability`] configured
+    /// with [`DBConfig::durability`].
+    ///
     /// # Errors
     ///
     /// This function can return an error if there's an issue with the underlying
     pub fn set(&self, key: &str, val: &str, ttl: Option<Duration>) -> Result<(), Box<dyn Error>> {
-        let data_tree = &self.data_tree;
-        let freq_tree = &self.meta_tree;
-        let ttl_tree = &self.ttl_tree;
-        let byte = key.as_bytes();
-        let ttl_sec = match ttl {
-            Some(t) => {
-                let systime = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .expect("Cant get SystemTime");
-                Some((t + systime).as_secs())
-            }
-            None => None,
-        };
-
-        let l: Result<(), TransactionError<()>> = (&**data_tree, &**freq_tree, &**ttl_tree)
-            .transaction(|(data, freq, ttl_tree)| {
-                match freq.get(byte)? {
-                    Some(m) => {
-                        let mut meta = Metadata::from_u8(&m.to_vec())
-                            .map_err(|_| ConflictableTransactionError::Abort(()))?;
-                        if let Some(t) = meta.ttl {
-                            let _ = ttl_tree.remove([&t.to_be_bytes()[..], &byte[..]].concat());
-                        }
-                        meta.ttl = ttl_sec;
-                        freq.insert(
-                            byte,
-                            meta.to_u8()
-                                .map_err(|_| ConflictableTransactionError::Abort(()))?,
-                        )?;
-                    }
-                    None => {
-                        freq.insert(
-                            byte,
-                            Metadata::new(ttl_sec)
-                                .to_u8()
-                                .map_err(|_| ConflictableTransactionError::Abort(()))?,
-                        )?;
-                    }
-                }
-
-                data.insert(byte, val.as_bytes())?;
-
-                match ttl_sec {
-                    Some(d) => {
-                        ttl_tree.insert([&d.to_be_bytes()[..], &byte[..]].concat(), byte)?;
-                    }
-                    None => (),
-                };
-
-                Ok(())
-            });
-        let _ = l.map_err(|_| TransientError::SledTransactionError)?;
+        self.set_with(key, val, ttl, self.durability)
+    }
 
-        Ok(())
+    /// Sets a key-value pair like [`DB::set`], made durable according to `durability`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transaction fails or the write cannot be flushed.
+    pub fn set_with(
+        &self,
+        key: &str,
+        val: &str,
+        ttl: Option<Duration>,
+        durability: Durability,
+    ) -> Result<(), Box<dyn Error>> {
+        self.set_in(&self.keyspace, key, val, ttl)?;
+        Ok(self.persist(durability)?)
     }
 
     /// Retrieves the value for a given key.
@@ -184,13 +220,7 @@ impl DB {
     /// Returns an error if the value cannot be retrieved from the database or if
     /// the value is not valid UTF-8.
     pub fn get(&self, key: &str) -> Result<Option<String>, Box<dyn Error>> {
-        let data_tree = &self.data_tree;
-        let byte = key.as_bytes();
-        let val = data_tree.get(byte)?;
-        match val {
-            Some(val) => Ok(Some(from_utf8(&val.to_vec())?.to_string())),
-            None => Ok(None),
-        }
+        Ok(self.get_in(&self.keyspace, key)?)
     }
 
     /// Atomically increments the frequency counter for a given key.
@@ -198,31 +228,10 @@ impl DB {
     /// # Errors
     ///
     /// This function can return an error if the key does not exist or if there
-    /// is an issue with the compare-and-swap operation.
+    /// is an issue with the transaction updating the metadata.
     pub fn increment_frequency(&self, key: &str) -> Result<(), Box<dyn Error>> {
-        let freq_tree = &self.meta_tree;
-        let byte = &key.as_bytes();
-
-        loop {
-            let metadata = freq_tree
-                .get(byte)?
-                .ok_or(TransientError::IncretmentError)?;
-            let meta = Metadata::from_u8(&metadata.to_vec())?;
-            let s = freq_tree.compare_and_swap(
-                byte,
-                Some(metadata),
-                Some(meta.freq_incretement().to_u8()?),
-            );
-            match s {
-                Ok(ss) => match ss {
-                    Ok(_) => break,
-                    Err(_) => (),
-                },
-                Err(_) => (),
-            }
-        }
-
-        Ok(())
+        self.increment_frequency_in(&self.keyspace, key)?;
+        Ok(self.persist(self.durability)?)
     }
 
     /// Removes a key-value pair and its associated metadata from the database.
@@ -231,62 +240,195 @@ impl DB {
     ///
     /// Can return an error if the transaction to remove the data fails.
     pub fn remove(&self, key: &str) -> Result<(), Box<dyn Error>> {
-        let data_tree = &self.data_tree;
-        let freq_tree = &self.meta_tree;
-        let ttl_tree = &self.ttl_tree;
-        let byte = &key.as_bytes();
-        let l: Result<(), TransactionError<()>> = (&**data_tree, &**freq_tree, &**ttl_tree)
-            .transaction(|(data, freq, ttl_tree)| {
-                data.remove(*byte)?;
-                let meta = freq
-                    .get(byte)?
-                    .ok_or(ConflictableTransactionError::Abort(()))?;
-                let time = Metadata::from_u8(&meta.to_vec())
-                    .map_err(|_| ConflictableTransactionError::Abort(()))?
-                    .ttl;
-                freq.remove(*byte)?;
-
-                match time {
-                    Some(t) => {
-                        let _ = ttl_tree.remove([&t.to_be_bytes()[..], &byte[..]].concat());
-                    }
-                    None => (),
-                }
-
-                Ok(())
-            });
-        l.map_err(|_| TransientError::SledTransactionError)?;
-        Ok(())
+        self.remove_with(key, self.durability)
+    }
+
+    /// Removes a key-value pair like [`DB::remove`], made durable according to `durability`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transaction fails or the removal cannot be flushed.
+    pub fn remove_with(&self, key: &str, durability: Durability) -> Result<(), Box<dyn Error>> {
+        self.remove_in(&self.keyspace, key)?;
+        Ok(self.persist(durability)?)
     }
 
     /// Retrieves the metadata for a given key.
     ///
+    /// The score and access windows are brought up to date with the current time.
+    ///
     /// # Errors
     ///
     /// Returns an error if the metadata cannot be retrieved or deserialized.
     pub fn get_metadata(&self, key: &str) -> Result<Option<Metadata>, Box<dyn Error>> {
-        let freq_tree = &self.meta_tree;
-        let byte = key.as_bytes();
-        let meta = freq_tree.get(byte)?;
-        match meta {
-            Some(val) => Ok(Some(Metadata::from_u8(&val.to_vec())?)),
-            None => Ok(None),
+        Ok(self.get_metadata_in(&self.keyspace, key)?)
+    }
+
+    /// Flushes every buffered write to disk, returning the number of bytes flushed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data cannot be written to disk.
+    pub fn flush(&self) -> Result<usize, Box<dyn Error>> {
+        Ok(self.sled_db.flush()?)
+    }
+
+    /// Returns the compression counters of the values written since the `DB`
+    /// was opened, see [`DBConfig::compression`].
+    pub fn compression_stats(&self) -> CompressionStats {
+        self.codec.stats()
+    }
+
+    /// Fails with [`TransientError::ReadOnly`] if the database was opened read-only.
+    pub(crate) fn check_writable(&self) -> Result<(), TransientError> {
+        if self.read_only {
+            Err(TransientError::ReadOnly)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Flushes the writes made so far if `durability` asks for it.
+    pub(crate) fn persist(&self, durability: Durability) -> Result<(), TransientError> {
+        if durability == Durability::Flushed {
+            self.sled_db
+                .flush()
+                .map_err(|e| TransientError::SledError { error: e })?;
+        }
+        Ok(())
+    }
+
+    /// Returns the sequence number of the most recent entry in the mutation log.
+    ///
+    /// Returns 0 if nothing has been written to the database yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the sequence counter cannot be read.
+    pub fn last_seq(&self) -> Result<u64, Box<dyn Error>> {
+        Ok(self.head_seq()?)
+    }
+
+    /// Returns the sequence number of the most recent entry in the mutation log.
+    pub(crate) fn head_seq(&self) -> Result<u64, TransientError> {
+        let seq = self
+            .state_tree
+            .get(OPLOG_SEQ_KEY)
+            .map_err(|e| TransientError::SledError { error: e })?;
+        Ok(read_seq(seq.as_deref()))
+    }
+
+    /// Returns the sequence number of the oldest entry still kept in the mutation log.
+    ///
+    /// Returns `None` if the log is empty.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the mutation log cannot be read.
+    pub fn first_seq(&self) -> Result<Option<u64>, Box<dyn Error>> {
+        Ok(self.tail_seq()?)
+    }
+
+    /// Returns the sequence number of the oldest entry still kept in the mutation log.
+    pub(crate) fn tail_seq(&self) -> Result<Option<u64>, TransientError> {
+        let first = self
+            .oplog_tree
+            .first()
+            .map_err(|e| TransientError::SledError { error: e })?;
+        Ok(first.map(|(seq_byte, _)| read_seq(Some(&seq_byte))))
+    }
+
+    /// Iterates over the entries of the mutation log with a sequence number
+    /// strictly greater than `seq`, in commit order.
+    ///
+    /// Values are returned decompressed.
+    pub fn changes_since(
+        &self,
+        seq: u64,
+    ) -> impl Iterator<Item = Result<OpLogEntry, TransientError>> + use<> {
+        let decoder = self.entry_decoder();
+        self.oplog_tree
+            .range(seq.saturating_add(1).to_be_bytes()..)
+            .map(move |res| {
+                let (_, entry) = res.map_err(|e| TransientError::SledError { error: e })?;
+                let entry = OpLogEntry::from_u8(&entry)
+                    .map_err(|_| TransientError::ParsingFromByteError)?;
+                decoder.decode(entry)
+            })
+    }
+}
+
+/// Copies the directory `from` and everything in it to `to`.
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
         }
     }
+    Ok(())
+}
+
+/// Opens the tree `name` of `db`.
+///
+/// Opening a tree that doesn't exist creates it, so when `read_only` is set
+/// an empty temporary tree stands for it instead.
+pub(crate) fn open_tree(db: &sled::Db, name: &str, read_only: bool) -> Result<Tree, sled::Error> {
+    if read_only && !db.tree_names().iter().any(|t| t == name.as_bytes()) {
+        return Config::new().temporary(true).open()?.open_tree(name);
+    }
+    db.open_tree(name)
 }
 
 impl Drop for DB {
-    /// Gracefully shuts down the TTL background thread when the `DB` instance
-    /// goes out of scope.
+    /// Writes the buffered reads, records the mutations in the audit log and
+    /// flushes every buffered write, then gracefully shuts down the TTL
+    /// background thread and closes the watchers when the `DB` instance goes
+    /// out of scope. A read-only `DB` only closes its watchers.
     fn drop(&mut self) {
+        if self.read_only {
+            self.watchers.close();
+            return;
+        }
+
+        // NOTE: Nothing can read the DB anymore, so the reads buffered since the
+        // last pass of the thread are final
+        let _ = add_frequencies(
+            &self.keyspace,
+            &self.oplog_tree,
+            &self.state_tree,
+            &self.keyspace.reads.drain(),
+            self.half_life,
+        );
+        for ks in self
+            .namespaces
+            .read()
+            .expect("Namespaces lock poisoned")
+            .values()
+        {
+            let _ = add_frequencies(
+                ks,
+                &self.oplog_tree,
+                &self.state_tree,
+                &ks.reads.drain(),
+                self.half_life,
+            );
+        }
+        let _ = self.audit_log.sync(&self.oplog_tree, &self.state_tree);
+
+        let _ = self.sled_db.flush();
+
         self.shutdown
             .store(true, std::sync::atomic::Ordering::SeqCst);
 
-        let _ = self
-            .ttl_thread
-            .take()
-            .expect("Fail to take ownership of ttl_thread")
-            .join()
-            .expect("Joining failed");
+        if let Some(thread) = self.ttl_thread.take() {
+            thread.join().expect("Joining failed");
+        }
+
+        self.watchers.close();
     }
 }
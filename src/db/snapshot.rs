@@ -0,0 +1,778 @@
+//! This module provides point-in-time snapshots of a `DB`, see [`DB::snapshot`].
+//!
+//! A snapshot is identified by the sequence number of the last mutation it
+//! sees. While a snapshot is live, every mutation that changes or removes a
+//! value, or changes its TTL or tags, first keeps the value and metadata the
+//! key had before it in the `snapshot_tree`, in the transaction that applies
+//! it, keyed by the sequence number of the mutation. A snapshot reads a key from the first
+//! of these pre-images after its sequence number, or from the current trees
+//! if the key didn't change since. The chunks of a kept value are only
+//! removed once no snapshot can read it anymore, and a value kept by a
+//! change of its metadata alone shares them with the key until the value
+//! changes.
+//!
+//! Only the first change of a key after the newest live snapshot needs a
+//! pre-image, so the `snapshot_tree` also records the sequence number of the
+//! last pre-image of every key. Clearing or dropping a namespace keeps its
+//! trees until no snapshot is live, and snapshots don't survive a restart.
+//!
+//! A replication follower applies the changes of its primary without
+//! pre-images, so no snapshot can be taken while it runs, and it only
+//! applies changes once no snapshot is live.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    ops::Bound,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use sled::{
+    IVec, Tree,
+    transaction::{ConflictableTransactionResult, TransactionalTree, UnabortableTransactionError},
+};
+
+use crate::{
+    DB, Metadata,
+    db::{
+        batch::Entry,
+        blob::BlobRef,
+        errors::TransientError,
+        keyspace::{Keyspace, transaction_error},
+        open_tree,
+    },
+    oplog::{OPLOG_SEQ_KEY, read_seq},
+};
+
+/// Key in the `state_tree` holding the sequence number of the newest live
+/// snapshot, absent when no snapshot is live.
+pub(crate) const SNAPSHOT_KEY: &[u8] = b"snapshot_seq";
+
+/// Prefix of the keys in the `state_tree` naming the trees of cleared or
+/// dropped namespaces, dropped once no snapshot is live.
+pub(crate) const PENDING_DROP_PREFIX: &[u8] = b"snapshot_drop:";
+
+/// Prefix of the pre-images in the `snapshot_tree`.
+const PRE_IMAGE: u8 = b'p';
+
+/// Prefix of the sequence number of the last pre-image of a key in the `snapshot_tree`.
+const LATEST: u8 = b'l';
+
+/// Marks a pre-image owning the chunks of its value.
+const OWNED: u8 = 0;
+
+/// Marks a pre-image whose value is still the one of the key, kept by a
+/// change of its metadata alone.
+const SHARED: u8 = 1;
+
+/// Identifies the trees of `ks` in the keys of the `snapshot_tree`.
+fn keyspace_id(ks: &Keyspace) -> Vec<u8> {
+    match &ks.name {
+        None => vec![0],
+        Some(name) => [
+            &[1][..],
+            &(name.len() as u32).to_be_bytes(),
+            name.as_bytes(),
+            &ks.generation.to_be_bytes(),
+        ]
+        .concat(),
+    }
+}
+
+/// Returns the name of the `blob_tree` of the keyspace identified by `id`,
+/// along with the rest of `entry` after the id.
+fn split_keyspace_id(entry: &[u8]) -> Option<(String, &[u8])> {
+    match entry.split_first()? {
+        (0, rest) => Some(("blob_tree".to_string(), rest)),
+        (1, rest) => {
+            let len = u32::from_be_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+            let name = std::str::from_utf8(rest.get(4..4 + len)?).ok()?;
+            let generation = u64::from_be_bytes(rest.get(4 + len..12 + len)?.try_into().ok()?);
+            let [.., blob_name] = Keyspace::tree_names(Some(name), generation);
+            Some((blob_name, &rest[12 + len..]))
+        }
+        _ => None,
+    }
+}
+
+/// Encodes `key` so the encodings sort like the keys, even when followed by
+/// a sequence number: every 0 byte is escaped as 0 255 and the key ends with 0 0.
+fn escape(key: &[u8], terminate: bool) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(key.len() + 2);
+    for &b in key {
+        escaped.push(b);
+        if b == 0 {
+            escaped.push(0xFF);
+        }
+    }
+    if terminate {
+        escaped.extend([0, 0]);
+    }
+    escaped
+}
+
+/// Decodes a key encoded with [`escape`], returning it and the bytes after it.
+fn unescape(escaped: &[u8]) -> Option<(Vec<u8>, &[u8])> {
+    let mut key = Vec::new();
+    let mut i = 0;
+    loop {
+        match (escaped.get(i)?, escaped.get(i + 1)) {
+            (0, Some(0)) => return Some((key, &escaped[i + 2..])),
+            (0, Some(0xFF)) => {
+                key.push(0);
+                i += 2;
+            }
+            (0, _) => return None,
+            (&b, _) => {
+                key.push(b);
+                i += 1;
+            }
+        }
+    }
+}
+
+/// Returns the key of an entry of `kind` about `key` of the keyspace `id`.
+fn entry_key(kind: u8, id: &[u8], key: &[u8]) -> Vec<u8> {
+    [&[kind][..], id, &escape(key, true)].concat()
+}
+
+/// Encodes the state of a key before a mutation, empty if it didn't exist.
+///
+/// `owner` is [`SHARED`] if the key still holds `stored`, whose chunks the
+/// image then doesn't own, [`OWNED`] otherwise.
+fn encode_image(owner: u8, stored: &[u8], meta: &[u8]) -> Vec<u8> {
+    [
+        &[owner][..],
+        &(stored.len() as u32).to_be_bytes(),
+        stored,
+        meta,
+    ]
+    .concat()
+}
+
+/// Decodes a state encoded with [`encode_image`].
+fn decode_image(image: &[u8]) -> Result<Option<(IVec, IVec)>, TransientError> {
+    if image.is_empty() {
+        return Ok(None);
+    }
+    let len = image
+        .get(1..5)
+        .and_then(|l| l.try_into().ok())
+        .map(u32::from_be_bytes)
+        .ok_or(TransientError::ParsingFromByteError)? as usize;
+    let stored = image
+        .get(5..5 + len)
+        .ok_or(TransientError::ParsingFromByteError)?;
+    Ok(Some((stored.into(), image[5 + len..].into())))
+}
+
+/// Keeps the value and metadata `key` of `ks` has before the mutation about
+/// to be appended to the mutation log, if a live snapshot may read them.
+///
+/// Must be called before the mutation is appended. Returns `true` if
+/// `stored` was kept, in which case its chunks must not be removed.
+pub(crate) fn preserve(
+    snapshots: &TransactionalTree,
+    state: &TransactionalTree,
+    ks: &Keyspace,
+    key: &[u8],
+    stored: Option<&[u8]>,
+    meta: Option<&[u8]>,
+) -> Result<bool, UnabortableTransactionError> {
+    keep(snapshots, state, ks, key, stored, meta, OWNED)
+}
+
+/// Keeps the metadata `key` of `ks` has before a mutation changing it but
+/// not the value `stored`, like [`preserve`].
+///
+/// The pre-image shares the chunks of the value with the key, until the
+/// value changes and the pre-image takes them over.
+pub(crate) fn preserve_metadata(
+    snapshots: &TransactionalTree,
+    state: &TransactionalTree,
+    ks: &Keyspace,
+    key: &[u8],
+    stored: Option<&[u8]>,
+    meta: &[u8],
+) -> Result<(), UnabortableTransactionError> {
+    keep(snapshots, state, ks, key, stored, Some(meta), SHARED)?;
+    Ok(())
+}
+
+/// Keeps the state of `key` before a mutation, see [`preserve`].
+fn keep(
+    snapshots: &TransactionalTree,
+    state: &TransactionalTree,
+    ks: &Keyspace,
+    key: &[u8],
+    stored: Option<&[u8]>,
+    meta: Option<&[u8]>,
+    owner: u8,
+) -> Result<bool, UnabortableTransactionError> {
+    // NOTE: Reading the key makes the transaction conflict with the creation of a snapshot.
+    let Some(newest) = state.get(SNAPSHOT_KEY)? else {
+        return Ok(false);
+    };
+    let id = keyspace_id(ks);
+    let latest = entry_key(LATEST, &id, key);
+    let latest_seq = snapshots.get(&latest)?;
+    if read_seq(latest_seq.as_deref()) > read_seq(Some(&newest)) {
+        // NOTE: The value kept by a change of the metadata is changing now, take its chunks over.
+        let entry = [
+            entry_key(PRE_IMAGE, &id, key),
+            latest_seq.unwrap_or_default().to_vec(),
+        ]
+        .concat();
+        if owner == OWNED
+            && let Some(image) = snapshots.get(&entry)?
+            && image.first() == Some(&SHARED)
+            && decode_image(&image).is_ok_and(|i| i.map(|(s, _)| s).as_deref() == stored)
+        {
+            let mut image = image.to_vec();
+            image[0] = OWNED;
+            snapshots.insert(entry, image)?;
+            return Ok(true);
+        }
+        return Ok(false);
+    }
+
+    let seq = read_seq(state.get(OPLOG_SEQ_KEY)?.as_deref()) + 1;
+    let image = match (stored, meta) {
+        (Some(stored), Some(meta)) => encode_image(owner, stored, meta),
+        _ => Vec::new(),
+    };
+    snapshots.insert(
+        [entry_key(PRE_IMAGE, &id, key), seq.to_be_bytes().to_vec()].concat(),
+        image,
+    )?;
+    snapshots.insert(latest, &seq.to_be_bytes())?;
+    Ok(stored.is_some() && meta.is_some())
+}
+
+/// Returns the generation a new namespace `name` starts at, past the
+/// generations of a namespace of the same name whose trees aren't dropped yet.
+pub(crate) fn first_generation(state_tree: &Tree, name: &str) -> Result<u64, sled::Error> {
+    let prefix = [PENDING_DROP_PREFIX, format!("ns:{name}:").as_bytes()].concat();
+    let mut first = 0;
+    for i in state_tree.scan_prefix(&prefix).keys() {
+        let key = i?;
+        let tree_name = String::from_utf8_lossy(&key[PENDING_DROP_PREFIX.len()..]);
+        let generation = tree_name[prefix.len() - PENDING_DROP_PREFIX.len()..]
+            .split(':')
+            .next()
+            .and_then(|g| g.parse::<u64>().ok());
+        // NOTE: The prefix also matches the trees of the namespaces named `{name}:...`.
+        if let Some(generation) = generation
+            && Keyspace::tree_names(Some(name), generation).contains(&tree_name.to_string())
+        {
+            first = first.max(generation + 1);
+        }
+    }
+    Ok(first)
+}
+
+/// The `snapshot_tree` and the live snapshots.
+#[derive(Debug)]
+pub(crate) struct SnapshotRegistry {
+    pub(crate) tree: Arc<Tree>,
+    /// The sequence numbers of the live snapshots, with how many share each
+    live: Mutex<BTreeMap<u64, usize>>,
+    /// How many replication followers apply changes to the database
+    followers: AtomicUsize,
+}
+
+impl SnapshotRegistry {
+    /// Opens the `snapshot_tree` of `db`, discarding what the snapshots of
+    /// the previous run kept unless `read_only` is set.
+    pub(crate) fn open(
+        db: &sled::Db,
+        state_tree: &Tree,
+        read_only: bool,
+    ) -> Result<SnapshotRegistry, sled::Error> {
+        let registry = SnapshotRegistry {
+            tree: Arc::new(open_tree(db, "snapshot_tree", read_only)?),
+            live: Mutex::new(BTreeMap::new()),
+            followers: AtomicUsize::new(0),
+        };
+        if !read_only {
+            state_tree.remove(SNAPSHOT_KEY)?;
+            registry.collect(db, state_tree, u64::MAX, true)?;
+        }
+        Ok(registry)
+    }
+
+    /// Removes the pre-images with a sequence number up to `up_to` and the
+    /// chunks of their values, then drops the trees of the retired
+    /// namespaces if `drop_trees` is set.
+    fn collect(
+        &self,
+        db: &sled::Db,
+        state_tree: &Tree,
+        up_to: u64,
+        drop_trees: bool,
+    ) -> Result<(), sled::Error> {
+        let trees = db.tree_names();
+        for i in self.tree.iter() {
+            let (entry, value) = i?;
+            let Some((&kind, rest)) = entry.split_first() else {
+                continue;
+            };
+            let Some((blob_name, rest)) = split_keyspace_id(rest) else {
+                self.tree.remove(&entry)?;
+                continue;
+            };
+            let seq = match kind {
+                PRE_IMAGE => unescape(rest).map(|(_, seq)| read_seq(Some(seq))),
+                _ => Some(read_seq(Some(&value))),
+            };
+            if seq.is_some_and(|seq| seq > up_to) {
+                continue;
+            }
+
+            if kind == PRE_IMAGE
+                && value.first() == Some(&OWNED)
+                && let Ok(Some((stored, _))) = decode_image(&value)
+                && let Some(blob) = BlobRef::parse(&stored)
+                // NOTE: Opening the blob_tree of a dropped namespace would create it again.
+                && trees.iter().any(|t| t == blob_name.as_bytes())
+            {
+                blob.discard(&db.open_tree(blob_name)?);
+            }
+            self.tree.remove(&entry)?;
+        }
+
+        if drop_trees {
+            for i in state_tree.scan_prefix(PENDING_DROP_PREFIX).keys() {
+                let key = i?;
+                db.drop_tree(&key[PENDING_DROP_PREFIX.len()..])?;
+                state_tree.remove(key)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl DB {
+    /// Returns a read-only view of the database as of now.
+    ///
+    /// Gets and scans through the snapshot see the keys, values, TTLs and
+    /// tags as they were when it was created, whatever is written, removed
+    /// or expired in the meantime, including namespaces cleared or dropped
+    /// since. The frequency of a key that didn't change since is read as it
+    /// is now, so it may be newer. Reads through a snapshot don't update
+    /// frequencies.
+    ///
+    /// While a snapshot is live, the first change of each key keeps the
+    /// previous value on disk, until the snapshot is dropped. Snapshots
+    /// cannot be taken while a replication follower applies changes to the
+    /// database.
+    ///
+    /// ```no_run
+    /// use epoch_db::DB;
+    /// use std::path::Path;
+    ///
+    /// let db = DB::new(Path::new("./my_database")).unwrap();
+    /// let snapshot = db.snapshot().unwrap();
+    /// db.set("key", "new", None).unwrap();
+    /// for entry in snapshot.iter() {
+    ///     let (key, (value, meta)) = entry.unwrap();
+    ///     println!("{key} = {value}, read {} times", meta.freq);
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the snapshot cannot be registered, or
+    /// [`TransientError::Replicating`] if a replication follower applies
+    /// changes to the database.
+    pub fn snapshot(&self) -> Result<Snapshot<'_>, Box<dyn Error>> {
+        Ok(self.take_snapshot()?)
+    }
+
+    /// Registers a snapshot of the current keyspaces, see [`DB::snapshot`].
+    pub(crate) fn take_snapshot(&self) -> Result<Snapshot<'_>, TransientError> {
+        self.pin_snapshot(!self.read_only)
+    }
+
+    /// Returns a view of the current keyspaces that isn't registered as a
+    /// snapshot, so it reads the keys as they are when it reads them.
+    ///
+    /// Unlike a snapshot, it can be taken while a replication follower runs.
+    pub(crate) fn unregistered_snapshot(&self) -> Result<Snapshot<'_>, TransientError> {
+        self.pin_snapshot(false)
+    }
+
+    /// Returns a view of the current keyspaces, registered as a snapshot if
+    /// `register` is true.
+    fn pin_snapshot(&self, register: bool) -> Result<Snapshot<'_>, TransientError> {
+        let sled_err = |e| TransientError::SledError { error: e };
+        // NOTE: Holding the lock keeps namespaces from being cleared or dropped meanwhile.
+        let namespaces = self.namespaces.read().expect("Namespaces lock poisoned");
+
+        let seq = if !register {
+            read_seq(
+                self.state_tree
+                    .get(OPLOG_SEQ_KEY)
+                    .map_err(sled_err)?
+                    .as_deref(),
+            )
+        } else {
+            let mut live = self.snapshots.live.lock().expect("Snapshots lock poisoned");
+            if self.snapshots.followers.load(Ordering::SeqCst) > 0 {
+                return Err(TransientError::Replicating);
+            }
+            let seq = self
+                .state_tree
+                .transaction(
+                    |state| -> ConflictableTransactionResult<u64, TransientError> {
+                        let seq = read_seq(state.get(OPLOG_SEQ_KEY)?.as_deref());
+                        state.insert(SNAPSHOT_KEY, &seq.to_be_bytes())?;
+                        Ok(seq)
+                    },
+                )
+                .map_err(transaction_error)?;
+            *live.entry(seq).or_default() += 1;
+            seq
+        };
+
+        let pin = Arc::new(SnapshotPin {
+            db: self,
+            seq,
+            registered: register,
+            namespaces: namespaces.clone(),
+        });
+        Ok(Snapshot {
+            pin,
+            ks: Arc::clone(&self.keyspace),
+        })
+    }
+
+    /// Registers a replication follower, which keeps new snapshots from being taken.
+    pub(crate) fn add_follower(&self) {
+        let _live = self.snapshots.live.lock().expect("Snapshots lock poisoned");
+        self.snapshots.followers.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Unregisters a replication follower.
+    pub(crate) fn remove_follower(&self) {
+        self.snapshots.followers.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Returns true while a snapshot is live.
+    pub(crate) fn has_live_snapshots(&self) -> bool {
+        !self
+            .snapshots
+            .live
+            .lock()
+            .expect("Snapshots lock poisoned")
+            .is_empty()
+    }
+
+    /// Unregisters the snapshot at `seq`, then removes what no live snapshot
+    /// can read anymore.
+    fn release_snapshot(&self, seq: u64) -> Result<(), TransientError> {
+        let sled_err = |e| TransientError::SledError { error: e };
+        let mut live = self.snapshots.live.lock().expect("Snapshots lock poisoned");
+        if let Some(count) = live.get_mut(&seq) {
+            *count -= 1;
+            if *count == 0 {
+                live.remove(&seq);
+            }
+        }
+
+        let up_to = match (live.first_key_value(), live.last_key_value()) {
+            (Some((&oldest, _)), Some((newest, _))) => {
+                self.state_tree
+                    .insert(SNAPSHOT_KEY, &newest.to_be_bytes())
+                    .map_err(sled_err)?;
+                oldest
+            }
+            _ => {
+                self.state_tree.remove(SNAPSHOT_KEY).map_err(sled_err)?;
+                read_seq(
+                    self.state_tree
+                        .get(OPLOG_SEQ_KEY)
+                        .map_err(sled_err)?
+                        .as_deref(),
+                )
+            }
+        };
+        // NOTE: Holding the lock keeps a new snapshot from reading trees about to be dropped.
+        self.snapshots
+            .collect(&self.sled_db, &self.state_tree, up_to, live.is_empty())
+            .map_err(sled_err)
+    }
+
+    /// Returns the chunked values kept for the snapshots from the keyspace `ks`.
+    pub(crate) fn preserved_blobs(&self, ks: &Keyspace) -> Result<Vec<BlobRef>, TransientError> {
+        let prefix = [&[PRE_IMAGE][..], &keyspace_id(ks)].concat();
+        let mut blobs = Vec::new();
+        for i in self.snapshots.tree.scan_prefix(prefix).values() {
+            let image = i.map_err(|e| TransientError::SledError { error: e })?;
+            if let Some((stored, _)) = decode_image(&image)?
+                && let Some(blob) = BlobRef::parse(&stored)
+            {
+                blobs.push(blob);
+            }
+        }
+        Ok(blobs)
+    }
+}
+
+/// Keeps a snapshot registered until every view of it is dropped.
+struct SnapshotPin<'a> {
+    db: &'a DB,
+    seq: u64,
+    /// `false` when the database is read-only, since nothing changes, or for
+    /// a view that reads the keys as they are
+    registered: bool,
+    /// The keyspaces of the namespaces when the snapshot was taken
+    namespaces: HashMap<String, Arc<Keyspace>>,
+}
+
+impl Drop for SnapshotPin<'_> {
+    fn drop(&mut self) {
+        if self.registered {
+            let _ = self.db.release_snapshot(self.seq);
+        }
+    }
+}
+
+/// A read-only view of a keyspace as of the creation of a snapshot,
+/// returned by [`DB::snapshot`].
+///
+/// Views of the namespaces, returned by [`Snapshot::namespace`], share the
+/// snapshot, which stays registered until every view of it is dropped.
+pub struct Snapshot<'a> {
+    pin: Arc<SnapshotPin<'a>>,
+    ks: Arc<Keyspace>,
+}
+
+impl<'a> Snapshot<'a> {
+    /// Returns the sequence number of the last mutation the snapshot sees,
+    /// see [`DB::changes_since`].
+    pub fn seq(&self) -> u64 {
+        self.pin.seq
+    }
+
+    /// Returns the view of the namespace `name` as of the snapshot.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TransientError::NamespaceNotFound`] if the namespace didn't
+    /// exist when the snapshot was taken.
+    pub fn namespace(&self, name: &str) -> Result<Snapshot<'a>, Box<dyn Error>> {
+        let ks = self
+            .pin
+            .namespaces
+            .get(name)
+            .ok_or(TransientError::NamespaceNotFound)?;
+        Ok(Snapshot {
+            pin: Arc::clone(&self.pin),
+            ks: Arc::clone(ks),
+        })
+    }
+
+    /// Returns the names of the namespaces as of the snapshot, sorted.
+    pub fn namespaces(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.pin.namespaces.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Returns the views of the default keyspace and of every namespace.
+    pub(crate) fn keyspaces(&self) -> Vec<Snapshot<'a>> {
+        let mut views = vec![Snapshot {
+            pin: Arc::clone(&self.pin),
+            ks: Arc::clone(&self.pin.db.keyspace),
+        }];
+        views.extend(self.pin.namespaces.values().map(|ks| Snapshot {
+            pin: Arc::clone(&self.pin),
+            ks: Arc::clone(ks),
+        }));
+        views
+    }
+
+    /// Returns the name of the namespace of the view, `None` for the default keyspace.
+    pub(crate) fn name(&self) -> Option<&str> {
+        self.ks.name.as_deref()
+    }
+
+    /// Retrieves the value of `key` as of the snapshot.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value cannot be read or decoded.
+    pub fn get(&self, key: &str) -> Result<Option<String>, Box<dyn Error>> {
+        Ok(self
+            .read(key.as_bytes())?
+            .map(|(value, _)| String::from_utf8(value))
+            .transpose()
+            .map_err(|_| TransientError::ParsingToUTF8Error)?)
+    }
+
+    /// Retrieves the metadata of `key` as of the snapshot, see [`DB::snapshot`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the metadata cannot be read or decoded.
+    pub fn get_metadata(&self, key: &str) -> Result<Option<Metadata>, Box<dyn Error>> {
+        Ok(self.read(key.as_bytes())?.map(|(_, meta)| meta))
+    }
+
+    /// Iterates over every key of the view with its value and metadata, in
+    /// key order.
+    pub fn iter(&self) -> SnapshotIter<'a> {
+        self.scan_prefix("")
+    }
+
+    /// Iterates over the keys starting with `prefix` with their value and
+    /// metadata, in key order.
+    pub fn scan_prefix(&self, prefix: &str) -> SnapshotIter<'a> {
+        SnapshotIter {
+            view: Snapshot {
+                pin: Arc::clone(&self.pin),
+                ks: Arc::clone(&self.ks),
+            },
+            prefix: prefix.as_bytes().to_vec(),
+            last: None,
+        }
+    }
+
+    /// Returns the stored value and metadata of `key` as of the snapshot.
+    fn resolve(&self, key: &[u8]) -> Result<Option<(IVec, IVec)>, TransientError> {
+        let sled_err = |e| TransientError::SledError { error: e };
+        // NOTE: A mutation committed after these reads keeps the state they return.
+        let stored = self.ks.data_tree.get(key).map_err(sled_err)?;
+        let meta = self.ks.meta_tree.get(key).map_err(sled_err)?;
+
+        if self.pin.registered {
+            let prefix = entry_key(PRE_IMAGE, &keyspace_id(&self.ks), key);
+            let from = [prefix.as_slice(), &(self.pin.seq + 1).to_be_bytes()].concat();
+            let image = self.pin.db.snapshots.tree.range(from..).next();
+            if let Some((entry, image)) = image.transpose().map_err(sled_err)?
+                && entry.starts_with(&prefix)
+            {
+                return decode_image(&image);
+            }
+        }
+        Ok(stored.zip(meta))
+    }
+
+    /// Returns the value and metadata of `key` as of the snapshot.
+    fn read(&self, key: &[u8]) -> Result<Option<(Vec<u8>, Metadata)>, TransientError> {
+        loop {
+            let Some((stored, meta)) = self.resolve(key)? else {
+                return Ok(None);
+            };
+            // NOTE: The chunks of a value overwritten in the meantime are gone, read it again.
+            if let Some(value) = self.pin.db.read_value(&self.ks, key, &stored)? {
+                let meta =
+                    Metadata::from_u8(&meta).map_err(|_| TransientError::ParsingFromByteError)?;
+                return Ok(Some((value, self.pin.db.current_metadata(meta))));
+            }
+        }
+    }
+
+    /// Returns the first key after `last` starting with `prefix` that is
+    /// either stored now or was changed since the snapshot.
+    fn next_key(
+        &self,
+        prefix: &[u8],
+        last: Option<&[u8]>,
+    ) -> Result<Option<Vec<u8>>, TransientError> {
+        let sled_err = |e| TransientError::SledError { error: e };
+        let after = |start: Vec<u8>| match last {
+            Some(_) => Bound::Excluded(start),
+            None => Bound::Included(start),
+        };
+
+        // NOTE: A key removed after this read has a pre-image before the next one.
+        let current = self
+            .ks
+            .meta_tree
+            .range::<Vec<u8>, _>((after(last.unwrap_or(prefix).to_vec()), Bound::Unbounded))
+            .keys()
+            .next()
+            .transpose()
+            .map_err(sled_err)?
+            .filter(|key| key.starts_with(prefix))
+            .map(|key| key.to_vec());
+        if !self.pin.registered {
+            return Ok(current);
+        }
+
+        let id = keyspace_id(&self.ks);
+        let start = match last {
+            Some(last) => [entry_key(PRE_IMAGE, &id, last), vec![0xFF; 8]].concat(),
+            None => [&[PRE_IMAGE][..], &id, &escape(prefix, false)].concat(),
+        };
+        let changed = self
+            .pin
+            .db
+            .snapshots
+            .tree
+            .range::<Vec<u8>, _>((after(start), Bound::Unbounded))
+            .keys()
+            .next()
+            .transpose()
+            .map_err(sled_err)?
+            .and_then(|entry| {
+                let rest = entry.strip_prefix([&[PRE_IMAGE][..], &id].concat().as_slice())?;
+                unescape(rest).map(|(key, _)| key)
+            })
+            .filter(|key| key.starts_with(prefix));
+
+        Ok(match (current, changed) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        })
+    }
+}
+
+/// A key with its decoded value and metadata, as bytes.
+pub(crate) type RawEntry = (Vec<u8>, Vec<u8>, Metadata);
+
+/// The keys of a snapshot with their value and metadata, in key order,
+/// returned by [`Snapshot::iter`] and [`Snapshot::scan_prefix`].
+pub struct SnapshotIter<'a> {
+    view: Snapshot<'a>,
+    prefix: Vec<u8>,
+    /// The last key returned or skipped
+    last: Option<Vec<u8>>,
+}
+
+impl SnapshotIter<'_> {
+    /// Returns the next key with its value and metadata, before UTF-8 decoding.
+    pub(crate) fn next_raw(&mut self) -> Result<Option<RawEntry>, TransientError> {
+        loop {
+            let Some(key) = self.view.next_key(&self.prefix, self.last.as_deref())? else {
+                return Ok(None);
+            };
+            let found = self.view.read(&key)?;
+            self.last = Some(key.clone());
+            if let Some((value, meta)) = found {
+                return Ok(Some((key, value, meta)));
+            }
+        }
+    }
+}
+
+impl Iterator for SnapshotIter<'_> {
+    type Item = Result<(String, Entry), TransientError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let utf8 =
+            |b: Vec<u8>| String::from_utf8(b).map_err(|_| TransientError::ParsingToUTF8Error);
+        match self.next_raw() {
+            Ok(Some((key, value, meta))) => {
+                Some(utf8(key).and_then(|k| Ok((k, (utf8(value)?, meta)))))
+            }
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
@@ -0,0 +1,115 @@
+//! This module implements the operations on the tags of the keys, see
+//! [`Metadata::tags`](crate::Metadata::tags). Every keyspace keeps an index
+//! from each tag to the keys carrying it, updated in the same transaction as
+//! the metadata, so looking up a tag only reads the keys that carry it.
+
+use std::{error::Error, str::from_utf8, sync::atomic::Ordering};
+
+use crate::{
+    DB,
+    db::{
+        errors::TransientError,
+        keyspace::{Keyspace, tag_prefix},
+    },
+    metadata::tag_set,
+};
+
+impl DB {
+    /// Replaces the tags of a key, returning false if the key doesn't exist.
+    ///
+    /// Tags are kept when the value is overwritten. A key carries at most
+    /// [`MAX_TAGS`](crate::metadata::MAX_TAGS) non-empty tags of at most
+    /// [`MAX_TAG_LEN`](crate::metadata::MAX_TAG_LEN) bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the tags are invalid or the transaction fails.
+    pub fn set_tags(&self, key: &str, tags: &[&str]) -> Result<bool, Box<dyn Error>> {
+        let found = self.set_tags_in(&self.keyspace, key, tags)?;
+        self.persist(self.durability)?;
+        Ok(found)
+    }
+
+    /// Returns the keys carrying `tag`, in key order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the tag index cannot be read.
+    pub fn keys_with_tag(&self, tag: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(self.keys_with_tag_in(&self.keyspace, tag)?)
+    }
+
+    /// Removes every key carrying `tag` in a single transaction, returning
+    /// the number of keys removed.
+    ///
+    /// Keys tagged while the removal runs may be kept.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the tag index cannot be read or the transaction fails.
+    pub fn remove_by_tag(&self, tag: &str) -> Result<usize, Box<dyn Error>> {
+        let removed = self.remove_by_tag_in(&self.keyspace, tag)?;
+        self.persist(self.durability)?;
+        Ok(removed)
+    }
+
+    /// Replaces the tags of a key of the given keyspace, see [`DB::set_tags`].
+    pub(crate) fn set_tags_in(
+        &self,
+        ks: &Keyspace,
+        key: &str,
+        tags: &[&str],
+    ) -> Result<bool, TransientError> {
+        let tags = tag_set(tags)?;
+        self.transact(ks, |tx| tx.set_tags(key.as_bytes(), &tags))
+    }
+
+    /// Returns the keys of the given keyspace carrying `tag`, see [`DB::keys_with_tag`].
+    pub(crate) fn keys_with_tag_in(
+        &self,
+        ks: &Keyspace,
+        tag: &str,
+    ) -> Result<Vec<String>, TransientError> {
+        ks.tag_tree
+            .scan_prefix(tag_prefix(tag))
+            .values()
+            .map(|key| {
+                let key = key.map_err(|e| TransientError::SledError { error: e })?;
+                Ok(from_utf8(&key)
+                    .map_err(|_| TransientError::ParsingToUTF8Error)?
+                    .to_string())
+            })
+            .collect()
+    }
+
+    /// Removes the keys of the given keyspace carrying `tag`, see [`DB::remove_by_tag`].
+    pub(crate) fn remove_by_tag_in(
+        &self,
+        ks: &Keyspace,
+        tag: &str,
+    ) -> Result<usize, TransientError> {
+        let prefix = tag_prefix(tag);
+        let keys = ks
+            .tag_tree
+            .scan_prefix(&prefix)
+            .values()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| TransientError::SledError { error: e })?;
+
+        let removed = self.transact(ks, |tx| {
+            let mut removed = 0;
+            for key in &keys {
+                // NOTE: The key may have been retagged since the scan.
+                if tx.tags.get([prefix.as_slice(), key].concat())?.is_some() && tx.delete(key)? {
+                    removed += 1;
+                }
+            }
+            Ok(removed)
+        })?;
+
+        ks.stats
+            .removes
+            .fetch_add(removed as u64, Ordering::Relaxed);
+        Ok(removed)
+    }
+}
@@ -1 +1,421 @@
-         
+//! This module contains the background thread of the `DB`. It expires keys
+//! whose TTL elapsed, enforces the eviction policy of every namespace, trims
+//! the mutation log, copies it to the audit log and re-encrypts the values
+//! after a key rotation.
+
+use std::{
+    collections::{BTreeSet, HashMap, hash_map::Entry},
+    sync::{
+        Arc, Mutex, RwLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use sled::{
+    Tree,
+    transaction::{TransactionError, Transactional},
+};
+
+#[cfg(feature = "encryption")]
+use crate::db::{codec::ValueCodec, encryption::reencrypt_keyspace};
+use crate::{
+    Metadata,
+    audit::AuditLog,
+    db::{
+        blob::remove_chunks,
+        errors::TransientError,
+        frequency::add_frequencies,
+        keyspace::{Keyspace, reindex, retag, transaction_error},
+        snapshot,
+    },
+    namespace::EvictionPolicy,
+    oplog::{self, Mutation, OPLOG_SEQ_KEY, read_seq},
+};
+
+/// How long the thread sleeps between two passes.
+const TICK: Duration = Duration::new(0, 100000000);
+
+/// How long the same failure of the background thread isn't reported again.
+const REPORT_EVERY: Duration = Duration::from_secs(60);
+
+/// Eviction policies are enforced every this many passes, since they scan the namespace.
+const EVICTION_EVERY: u64 = 10;
+
+/// How many values of a keyspace are re-encrypted with a new key per pass.
+#[cfg(feature = "encryption")]
+const REENCRYPT_BATCH: usize = 256;
+
+/// The trees and settings the background thread works on.
+pub(crate) struct TtlWorker {
+    pub(crate) default: Arc<Keyspace>,
+    pub(crate) namespaces: Arc<RwLock<HashMap<String, Arc<Keyspace>>>>,
+    pub(crate) oplog_tree: Arc<Tree>,
+    pub(crate) state_tree: Arc<Tree>,
+    pub(crate) snapshot_tree: Arc<Tree>,
+    pub(crate) oplog_retention: u64,
+    pub(crate) audit_log: Arc<AuditLog>,
+    pub(crate) half_life: Option<Duration>,
+    #[cfg(feature = "encryption")]
+    pub(crate) codec: Arc<ValueCodec>,
+    pub(crate) shutdown: Arc<AtomicBool>,
+    /// When each failure was last reported, see [`TtlWorker::report`]
+    pub(crate) reported: Mutex<HashMap<String, Instant>>,
+}
+
+impl TtlWorker {
+    /// Spawns the background thread, which runs until `shutdown` is set.
+    pub(crate) fn spawn(self) -> JoinHandle<()> {
+        // TODO: Later have a clean up thread that checks if the following thread is fine and spawn
+        // it back and join the thread lol
+        thread::spawn(move || {
+            let mut pass: u64 = 0;
+            loop {
+                thread::sleep(TICK);
+
+                if self.shutdown.load(Ordering::SeqCst) {
+                    break;
+                }
+
+                let mut keyspaces = vec![Arc::clone(&self.default)];
+                keyspaces.extend(
+                    self.namespaces
+                        .read()
+                        .expect("Namespaces lock poisoned")
+                        .values()
+                        .cloned(),
+                );
+
+                for ks in &keyspaces {
+                    self.report("frequency tracking", self.track_reads(ks));
+                    self.report("expire", self.expire(ks));
+                    if pass.is_multiple_of(EVICTION_EVERY) {
+                        self.report("evict", self.evict(ks));
+                    }
+                }
+
+                #[cfg(feature = "encryption")]
+                self.reencrypt(&keyspaces);
+
+                self.report(
+                    "audit",
+                    self.audit_log.sync(&self.oplog_tree, &self.state_tree),
+                );
+                self.report("audit rotation", self.audit_log.rotate());
+                self.report("oplog trimming", self.trim_oplog());
+                pass += 1;
+            }
+        })
+    }
+
+    /// Re-encrypts a batch of the values of each keyspace that aren't encrypted
+    /// with the current key, until a pass finds none left.
+    ///
+    /// Values that cannot be re-encrypted are reported and skipped, so a
+    /// single corrupted value doesn't hold the rotation back.
+    #[cfg(feature = "encryption")]
+    fn reencrypt(&self, keyspaces: &[Arc<Keyspace>]) {
+        if !self.codec.rotating() {
+            return;
+        }
+
+        let mut done = true;
+        for ks in keyspaces {
+            let res = reencrypt_keyspace(ks, &self.codec, REENCRYPT_BATCH, &mut |e| {
+                self.report("re-encryption", Err(e));
+            });
+            match res {
+                Ok(0) => (),
+                Ok(_) => done = false,
+                Err(e) => {
+                    done = false;
+                    self.report("re-encryption", Err(e));
+                }
+            }
+        }
+        if done {
+            self.codec.set_rotated();
+        }
+    }
+
+    /// Adds the reads buffered by `ks` to the frequencies of its keys.
+    fn track_reads(&self, ks: &Keyspace) -> Result<(), TransientError> {
+        add_frequencies(
+            ks,
+            &self.oplog_tree,
+            &self.state_tree,
+            &ks.reads.drain(),
+            self.half_life,
+        )
+    }
+
+    /// Removes every key of `ks` whose TTL elapsed.
+    ///
+    /// A key that cannot be expired is reported and skipped, so it doesn't hold
+    /// back the ones after it.
+    fn expire(&self, ks: &Keyspace) -> Result<(), TransientError> {
+        let keys = ks.ttl_tree.iter();
+
+        for i in keys {
+            let full_key = i.map_err(|e| TransientError::SledError { error: e })?;
+
+            // NOTE: The reason time is 14 u8s long is because it is being stored like
+            // this ([time,key], key) not ((time,key), key)
+            let key = full_key.0;
+            let key_byte = full_key.1;
+
+            let Some(time_byte) = key.get(..8).and_then(|t| <[u8; 8]>::try_from(t).ok()) else {
+                self.report("expire", Err(TransientError::ParsingToU64ByteFailed));
+                continue;
+            };
+
+            let time = u64::from_be_bytes(time_byte);
+            let curr_time = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("Cant get SystemTime")
+                .as_secs();
+
+            // NOTE: The ttl_tree is ordered by time, so nothing after this key has expired yet.
+            if curr_time < time {
+                break;
+            }
+
+            let l: Result<(), TransactionError<TransientError>> = (
+                &*ks.data_tree,
+                &*ks.meta_tree,
+                &*ks.ttl_tree,
+                &*ks.index_tree,
+                &*ks.tag_tree,
+                &*ks.blob_tree,
+                &*self.oplog_tree,
+                &*self.state_tree,
+                &*self.snapshot_tree,
+            )
+                .transaction(
+                    |(data, freq, ttl_tree, index, tags, blobs, oplog, state, snapshots)| {
+                        ks.check_generation(state)?;
+
+                        let byte = &key_byte;
+                        let old = data.remove(byte)?;
+                        let old_meta = freq.remove(byte)?;
+                        let kept = snapshot::preserve(
+                            snapshots,
+                            state,
+                            ks,
+                            byte,
+                            old.as_deref(),
+                            old_meta.as_deref(),
+                        )?;
+                        if let Some(old) = old
+                            && !kept
+                        {
+                            remove_chunks(blobs, &old)?;
+                        }
+                        // NOTE: Undecodable metadata doesn't keep the key alive, only its
+                        // index and tag entries are left for `DB::repair`.
+                        if let Some(meta) = old_meta.and_then(|m| Metadata::from_u8(&m).ok()) {
+                            reindex(index, byte, Some(meta.freq), None)?;
+                            retag(tags, byte, &meta.tags, &BTreeSet::new())?;
+                        }
+
+                        let _ = ttl_tree.remove([&time_byte, &byte[..]].concat());
+
+                        oplog::append(
+                            oplog,
+                            state,
+                            ks.name.as_deref(),
+                            Mutation::Expire { key: byte.to_vec() },
+                            || TransientError::SledTransactionError,
+                        )?;
+
+                        Ok(())
+                    },
+                );
+
+            match l.map_err(transaction_error) {
+                Ok(()) => {
+                    ks.stats.expirations.fetch_add(1, Ordering::Relaxed);
+                }
+                // NOTE: The namespace was cleared or dropped under us, its old trees are gone.
+                Err(TransientError::NamespaceChanged) => return Ok(()),
+                Err(e) => self.report("expire", Err(e)),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Enforces the eviction policy of `ks`.
+    ///
+    /// Keys with undecodable metadata are left out of the policy.
+    fn evict(&self, ks: &Keyspace) -> Result<(), TransientError> {
+        let curr_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Cant get SystemTime")
+            .as_secs();
+
+        let victims: Vec<Vec<u8>> = match ks.config.eviction {
+            EvictionPolicy::None => return Ok(()),
+            EvictionPolicy::LeastFrequentlyUsed { max_keys } => {
+                let len = ks.meta_tree.len() as u64;
+                if len <= max_keys {
+                    return Ok(());
+                }
+
+                let mut candidates = Vec::with_capacity(len as usize);
+                for i in ks.meta_tree.iter() {
+                    let (key, meta) = i.map_err(|e| TransientError::SledError { error: e })?;
+                    let Ok(meta) = Metadata::from_u8(&meta) else {
+                        continue;
+                    };
+                    let score = meta.at(curr_time, self.half_life).score;
+                    candidates.push((score, meta.created_at, key.to_vec()));
+                }
+                candidates.sort_by(|a, b| {
+                    a.0.total_cmp(&b.0)
+                        .then_with(|| (a.1, &a.2).cmp(&(b.1, &b.2)))
+                });
+                candidates
+                    .into_iter()
+                    .take((len - max_keys) as usize)
+                    .map(|(_, _, key)| key)
+                    .collect()
+            }
+            EvictionPolicy::Lifecycle {
+                grace_period,
+                min_freq,
+            } => {
+                let mut victims = Vec::new();
+                for i in ks.meta_tree.iter() {
+                    let (key, meta) = i.map_err(|e| TransientError::SledError { error: e })?;
+                    let Ok(meta) = Metadata::from_u8(&meta) else {
+                        continue;
+                    };
+                    if curr_time.saturating_sub(meta.created_at) >= grace_period.as_secs()
+                        && meta.at(curr_time, self.half_life).score < min_freq as f64
+                    {
+                        victims.push(key.to_vec());
+                    }
+                }
+                victims
+            }
+        };
+
+        for key in victims {
+            let l: Result<bool, TransactionError<TransientError>> = (
+                &*ks.data_tree,
+                &*ks.meta_tree,
+                &*ks.ttl_tree,
+                &*ks.index_tree,
+                &*ks.tag_tree,
+                &*ks.blob_tree,
+                &*self.oplog_tree,
+                &*self.state_tree,
+                &*self.snapshot_tree,
+            )
+                .transaction(
+                    |(data, freq, ttl_tree, index, tags, blobs, oplog, state, snapshots)| {
+                        ks.check_generation(state)?;
+
+                        let Some(old_meta) = freq.get(&key)? else {
+                            return Ok(false);
+                        };
+                        let meta = Metadata::from_u8(&old_meta).ok();
+
+                        let old = data.remove(key.as_slice())?;
+                        let kept = snapshot::preserve(
+                            snapshots,
+                            state,
+                            ks,
+                            &key,
+                            old.as_deref(),
+                            Some(&old_meta),
+                        )?;
+                        if let Some(old) = old
+                            && !kept
+                        {
+                            remove_chunks(blobs, &old)?;
+                        }
+                        freq.remove(key.as_slice())?;
+                        if let Some(meta) = meta {
+                            reindex(index, &key, Some(meta.freq), None)?;
+                            retag(tags, &key, &meta.tags, &BTreeSet::new())?;
+                            if let Some(t) = meta.ttl {
+                                ttl_tree.remove([&t.to_be_bytes()[..], &key].concat())?;
+                            }
+                        }
+
+                        oplog::append(
+                            oplog,
+                            state,
+                            ks.name.as_deref(),
+                            Mutation::Evict { key: key.clone() },
+                            || TransientError::SledTransactionError,
+                        )?;
+
+                        Ok(true)
+                    },
+                );
+
+            match l.map_err(transaction_error) {
+                Ok(true) => {
+                    ks.stats.evictions.fetch_add(1, Ordering::Relaxed);
+                }
+                Ok(false) => (),
+                Err(TransientError::NamespaceChanged) => return Ok(()),
+                Err(e) => self.report("evict", Err(e)),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Drops the entries of the mutation log older than the retention window
+    /// and already recorded in the audit log.
+    ///
+    /// Followers that still need them get re-bootstrapped from a snapshot instead.
+    fn trim_oplog(&self) -> Result<(), TransientError> {
+        let head = read_seq(
+            self.state_tree
+                .get(OPLOG_SEQ_KEY)
+                .map_err(|e| TransientError::SledError { error: e })?
+                .as_deref(),
+        );
+        let audited = self.audit_log.audited_seq(&self.state_tree)?;
+        while let Some((seq_byte, _)) = self
+            .oplog_tree
+            .first()
+            .map_err(|e| TransientError::SledError { error: e })?
+        {
+            let seq = read_seq(Some(&seq_byte));
+            if head - seq < self.oplog_retention || audited.is_some_and(|a| seq > a) {
+                break;
+            }
+            self.oplog_tree
+                .remove(seq_byte)
+                .map_err(|e| TransientError::SledError { error: e })?;
+        }
+        Ok(())
+    }
+
+    /// Reports a failure of the background thread, which carries on with its
+    /// next task.
+    ///
+    /// The same failure is reported at most once every [`REPORT_EVERY`], so a
+    /// key failing on every pass doesn't flood the standard error.
+    fn report(&self, task: &str, res: Result<(), TransientError>) {
+        let Err(e) = res else {
+            return;
+        };
+        let message = format!("epoch-db: {task} failed: {}", e.to_string().trim_end());
+
+        let mut reported = self.reported.lock().expect("Reports lock poisoned");
+        let now = Instant::now();
+        reported.retain(|_, at| now.duration_since(*at) < REPORT_EVERY);
+        if let Entry::Vacant(entry) = reported.entry(message) {
+            eprintln!("{}", entry.key());
+            entry.insert(now);
+        }
+    }
+}
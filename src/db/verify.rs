@@ -0,0 +1,538 @@
+//! This module checks that the trees of every keyspace agree with each other,
+//! see [`DB::verify`], and repairs them, see [`DB::repair`].
+//!
+//! The `data_tree` and `meta_tree` of a keyspace hold the keys. The TTL,
+//! frequency and tag indexes are derived from the metadata, and the
+//! `blob_tree` holds the chunks the values refer to. Writes keep them in sync
+//! in a single transaction, but a crash during a write made by an older
+//! version, a bug or an edit of the raw trees may leave them out of sync.
+
+use std::{
+    collections::HashSet,
+    error::Error,
+    fmt::{self, Display, Formatter},
+    str::from_utf8,
+    sync::Arc,
+};
+
+use sled::{Tree, transaction::ConflictableTransactionError};
+
+use crate::{
+    DB, Metadata,
+    db::{
+        blob::{BlobRef, blob_id, remove_chunks},
+        errors::TransientError,
+        keyspace::{Keyspace, KeyspaceTx, index_key, reindex, tag_prefix},
+    },
+    oplog::{self, Mutation},
+};
+
+/// A way the trees of a keyspace disagree about a key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inconsistency {
+    /// The key has a value but no metadata, so it cannot be removed.
+    OrphanData,
+    /// The key has metadata but no value.
+    OrphanMetadata,
+    /// The metadata of the key cannot be decoded.
+    UndecodableMetadata,
+    /// Chunks of the value of the key are missing.
+    MissingChunks,
+    /// The `ttl_tree` expires the key at `deadline`, which isn't its TTL.
+    OrphanTtl {
+        /// The deadline of the entry, in seconds since the UNIX epoch.
+        deadline: u64,
+    },
+    /// The TTL of the key is missing from the `ttl_tree`, so the key never expires.
+    MissingTtl {
+        /// The TTL of the key, in seconds since the UNIX epoch.
+        deadline: u64,
+    },
+    /// The frequency index holds the key at `freq`, which isn't its frequency.
+    StaleFrequencyIndex {
+        /// The frequency of the entry.
+        freq: u64,
+    },
+    /// The frequency index doesn't hold the key.
+    MissingFrequencyIndex,
+    /// The tag index lists the key under `tag`, which the key doesn't carry.
+    StaleTag {
+        /// The tag of the entry.
+        tag: String,
+    },
+    /// The tag index doesn't list the key under `tag`.
+    MissingTag {
+        /// The tag the key carries.
+        tag: String,
+    },
+    /// An entry of the given tree cannot be parsed, the key of the issue
+    /// being the raw key of the entry.
+    MalformedEntry {
+        /// The name of the tree, such as `ttl_tree`.
+        tree: &'static str,
+    },
+    /// Chunks of a value that no key refers to.
+    OrphanChunks {
+        /// The id shared by the chunks.
+        blob: u64,
+    },
+}
+
+/// An inconsistency found by [`DB::verify`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    /// The namespace of the key, `None` for the default keyspace.
+    pub namespace: Option<String>,
+    /// The key concerned, empty for orphan chunks.
+    pub key: Vec<u8>,
+    /// What is wrong.
+    pub kind: Inconsistency,
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let namespace = self.namespace.as_deref().unwrap_or("default");
+        let key = String::from_utf8_lossy(&self.key);
+        match &self.kind {
+            Inconsistency::OrphanData => write!(f, "{namespace}: {key:?} has no metadata"),
+            Inconsistency::OrphanMetadata => write!(f, "{namespace}: {key:?} has no value"),
+            Inconsistency::UndecodableMetadata => {
+                write!(f, "{namespace}: {key:?} has undecodable metadata")
+            }
+            Inconsistency::MissingChunks => {
+                write!(f, "{namespace}: {key:?} is missing chunks of its value")
+            }
+            Inconsistency::OrphanTtl { deadline } => {
+                write!(
+                    f,
+                    "{namespace}: {key:?} has a stale TTL entry at {deadline}"
+                )
+            }
+            Inconsistency::MissingTtl { deadline } => {
+                write!(f, "{namespace}: {key:?} has no TTL entry for {deadline}")
+            }
+            Inconsistency::StaleFrequencyIndex { freq } => {
+                write!(
+                    f,
+                    "{namespace}: {key:?} has a stale frequency entry at {freq}"
+                )
+            }
+            Inconsistency::MissingFrequencyIndex => {
+                write!(f, "{namespace}: {key:?} has no frequency entry")
+            }
+            Inconsistency::StaleTag { tag } => {
+                write!(f, "{namespace}: {key:?} has a stale entry for tag {tag:?}")
+            }
+            Inconsistency::MissingTag { tag } => {
+                write!(f, "{namespace}: {key:?} has no entry for tag {tag:?}")
+            }
+            Inconsistency::MalformedEntry { tree } => {
+                write!(f, "{namespace}: malformed entry {:?} in {tree}", self.key)
+            }
+            Inconsistency::OrphanChunks { blob } => {
+                write!(f, "{namespace}: chunks of blob {blob} belong to no key")
+            }
+        }
+    }
+}
+
+/// The result of [`DB::verify`] and [`DB::repair`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// The number of keys checked, across every keyspace.
+    pub keys: u64,
+    /// The inconsistencies found.
+    pub issues: Vec<Issue>,
+}
+
+impl VerifyReport {
+    /// Returns `true` if no inconsistency was found.
+    pub fn is_consistent(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+impl DB {
+    /// Checks that the trees of every keyspace agree with each other.
+    ///
+    /// The trees are read without locking them, so writes made while the
+    /// check runs may be reported as inconsistencies. Run it while the
+    /// database is idle for an exact report.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a tree cannot be read.
+    pub fn verify(&self) -> Result<VerifyReport, Box<dyn Error>> {
+        Ok(self.verify_trees()?)
+    }
+
+    /// Checks the trees of every keyspace, see [`DB::verify`].
+    pub(crate) fn verify_trees(&self) -> Result<VerifyReport, TransientError> {
+        let mut report = VerifyReport::default();
+        for ks in self.all_keyspaces() {
+            report.keys += ks.meta_tree.len() as u64;
+            report.issues.extend(self.verify_keyspace(&ks)?);
+        }
+        Ok(report)
+    }
+
+    /// Checks the trees like [`DB::verify`] and fixes the inconsistencies
+    /// found, returning them. The fixes are flushed to disk.
+    ///
+    /// Values without metadata, metadata without a value and values missing
+    /// chunks are removed along with their key. Undecodable metadata is
+    /// replaced with new metadata without a TTL nor tags. The indexes are
+    /// brought in line with the metadata, and orphan chunks are removed.
+    ///
+    /// Each fix of a key checks the inconsistency again in a transaction, so
+    /// keys written while the repair runs are left alone.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a tree cannot be read or a fix fails.
+    pub fn repair(&self) -> Result<VerifyReport, Box<dyn Error>> {
+        self.check_writable()?;
+        let mut report = VerifyReport::default();
+        for ks in self.all_keyspaces() {
+            report.keys += ks.meta_tree.len() as u64;
+            let mut issues = self.verify_keyspace(&ks)?;
+            // NOTE: Fixing a key may leave index entries behind, which the later fixes remove.
+            issues.sort_by_key(|issue| repair_order(&issue.kind));
+            for issue in &issues {
+                self.repair_issue(&ks, issue)?;
+            }
+            report.issues.extend(issues);
+        }
+        self.sled_db.flush()?;
+        Ok(report)
+    }
+
+    /// Returns the default keyspace and the keyspace of every namespace.
+    pub(crate) fn all_keyspaces(&self) -> Vec<Arc<Keyspace>> {
+        let mut keyspaces = vec![Arc::clone(&self.keyspace)];
+        keyspaces.extend(
+            self.namespaces
+                .read()
+                .expect("Namespaces lock poisoned")
+                .values()
+                .cloned(),
+        );
+        keyspaces
+    }
+
+    /// Returns the inconsistencies between the trees of `ks`.
+    fn verify_keyspace(&self, ks: &Keyspace) -> Result<Vec<Issue>, TransientError> {
+        let sled_err = |e| TransientError::SledError { error: e };
+        let issue = |key: &[u8], kind| Issue {
+            namespace: ks.name.clone(),
+            key: key.to_vec(),
+            kind,
+        };
+        let meta_of = |key: &[u8]| -> Result<Option<Metadata>, TransientError> {
+            Ok(ks
+                .meta_tree
+                .get(key)
+                .map_err(sled_err)?
+                .and_then(|m| Metadata::from_u8(&m).ok()))
+        };
+        // NOTE: Chunks of values being written have a newer id and no key yet.
+        let first_unseen_blob = self.sled_db.generate_id().map_err(sled_err)?;
+
+        let mut issues = Vec::new();
+        let mut referenced = HashSet::new();
+        for i in ks.data_tree.iter() {
+            let (key, stored) = i.map_err(sled_err)?;
+            if !ks.meta_tree.contains_key(&key).map_err(sled_err)? {
+                issues.push(issue(&key, Inconsistency::OrphanData));
+            }
+            if let Some(blob) = BlobRef::parse(&stored) {
+                referenced.insert(blob.id);
+                if !blob.is_complete(&ks.blob_tree).map_err(sled_err)? {
+                    issues.push(issue(&key, Inconsistency::MissingChunks));
+                }
+            }
+        }
+        // NOTE: Read after the data_tree, since values move from there to the snapshot_tree.
+        referenced.extend(self.preserved_blobs(ks)?.iter().map(|blob| blob.id));
+
+        for i in ks.meta_tree.iter() {
+            let (key, meta) = i.map_err(sled_err)?;
+            let Ok(meta) = Metadata::from_u8(&meta) else {
+                issues.push(issue(&key, Inconsistency::UndecodableMetadata));
+                continue;
+            };
+            if !ks.data_tree.contains_key(&key).map_err(sled_err)? {
+                issues.push(issue(&key, Inconsistency::OrphanMetadata));
+            }
+            if let Some(deadline) = meta.ttl
+                && !ks
+                    .ttl_tree
+                    .contains_key(ttl_key(deadline, &key))
+                    .map_err(sled_err)?
+            {
+                issues.push(issue(&key, Inconsistency::MissingTtl { deadline }));
+            }
+            if !ks
+                .index_tree
+                .contains_key(index_key(meta.freq, &key))
+                .map_err(sled_err)?
+            {
+                issues.push(issue(&key, Inconsistency::MissingFrequencyIndex));
+            }
+            for tag in &meta.tags {
+                let entry = [tag_prefix(tag).as_slice(), &key].concat();
+                if !ks.tag_tree.contains_key(entry).map_err(sled_err)? {
+                    let tag = tag.clone();
+                    issues.push(issue(&key, Inconsistency::MissingTag { tag }));
+                }
+            }
+        }
+
+        for i in ks.ttl_tree.iter() {
+            let (entry, _) = i.map_err(sled_err)?;
+            let Some((deadline, key)) = split_u64(&entry) else {
+                let tree = "ttl_tree";
+                issues.push(issue(&entry, Inconsistency::MalformedEntry { tree }));
+                continue;
+            };
+            if meta_of(key)?.and_then(|m| m.ttl) != Some(deadline) {
+                issues.push(issue(key, Inconsistency::OrphanTtl { deadline }));
+            }
+        }
+
+        for i in ks.index_tree.iter() {
+            let (entry, _) = i.map_err(sled_err)?;
+            let Some((freq, key)) = split_u64(&entry) else {
+                let tree = "freq_index_tree";
+                issues.push(issue(&entry, Inconsistency::MalformedEntry { tree }));
+                continue;
+            };
+            if meta_of(key)?.map(|m| m.freq) != Some(freq) {
+                issues.push(issue(key, Inconsistency::StaleFrequencyIndex { freq }));
+            }
+        }
+
+        for i in ks.tag_tree.iter() {
+            let (entry, _) = i.map_err(sled_err)?;
+            let Some((tag, key)) = split_tag(&entry) else {
+                let tree = "tag_tree";
+                issues.push(issue(&entry, Inconsistency::MalformedEntry { tree }));
+                continue;
+            };
+            if !meta_of(key)?.is_some_and(|m| m.tags.contains(tag)) {
+                let tag = tag.to_string();
+                issues.push(issue(key, Inconsistency::StaleTag { tag }));
+            }
+        }
+
+        let mut orphans = HashSet::new();
+        for i in ks.blob_tree.iter() {
+            let (entry, _) = i.map_err(sled_err)?;
+            let Some(blob) = blob_id(&entry) else {
+                let tree = "blob_tree";
+                issues.push(issue(&entry, Inconsistency::MalformedEntry { tree }));
+                continue;
+            };
+            if blob < first_unseen_blob && !referenced.contains(&blob) && orphans.insert(blob) {
+                issues.push(issue(&[], Inconsistency::OrphanChunks { blob }));
+            }
+        }
+
+        Ok(issues)
+    }
+
+    /// Fixes a single inconsistency of `ks`, if it is still there.
+    fn repair_issue(&self, ks: &Keyspace, issue: &Issue) -> Result<(), TransientError> {
+        let sled_err = |e| TransientError::SledError { error: e };
+        let key = issue.key.as_slice();
+        match &issue.kind {
+            Inconsistency::MalformedEntry { tree } => {
+                let tree: &Tree = match *tree {
+                    "ttl_tree" => &ks.ttl_tree,
+                    "freq_index_tree" => &ks.index_tree,
+                    "tag_tree" => &ks.tag_tree,
+                    _ => &ks.blob_tree,
+                };
+                tree.remove(key).map_err(sled_err)?;
+                return Ok(());
+            }
+            Inconsistency::OrphanChunks { blob } => {
+                for i in ks.blob_tree.scan_prefix(blob.to_be_bytes()) {
+                    let (chunk, _) = i.map_err(sled_err)?;
+                    ks.blob_tree.remove(chunk).map_err(sled_err)?;
+                }
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        // NOTE: Computed outside of the transaction, since it may read chunks.
+        let value_len = match ks.data_tree.get(key).map_err(sled_err)? {
+            Some(stored) => match BlobRef::parse(&stored) {
+                Some(blob) => blob.len,
+                None => self
+                    .codec
+                    .decode(key, &stored)
+                    .map_or(0, |v| v.len() as u64),
+            },
+            None => 0,
+        };
+
+        self.transact(ks, |tx| {
+            let meta = tx.freq.get(key)?.map(|m| Metadata::from_u8(&m).ok());
+            match &issue.kind {
+                Inconsistency::OrphanData
+                | Inconsistency::OrphanMetadata
+                | Inconsistency::MissingChunks => repair_value(tx, key),
+                Inconsistency::UndecodableMetadata => {
+                    if let Some(None) = meta {
+                        repair_metadata(tx, key, value_len)?;
+                    }
+                    Ok(())
+                }
+                Inconsistency::OrphanTtl { deadline } => {
+                    if meta.flatten().and_then(|m| m.ttl) != Some(*deadline) {
+                        tx.ttl_tree.remove(ttl_key(*deadline, key))?;
+                    }
+                    Ok(())
+                }
+                Inconsistency::MissingTtl { deadline } => {
+                    if meta.flatten().and_then(|m| m.ttl) == Some(*deadline) {
+                        tx.ttl_tree.insert(ttl_key(*deadline, key), key)?;
+                    }
+                    Ok(())
+                }
+                Inconsistency::StaleFrequencyIndex { freq } => {
+                    if meta.flatten().map(|m| m.freq) != Some(*freq) {
+                        tx.index.remove(index_key(*freq, key))?;
+                    }
+                    Ok(())
+                }
+                Inconsistency::MissingFrequencyIndex => {
+                    if let Some(meta) = meta.flatten() {
+                        tx.index.insert(index_key(meta.freq, key), key)?;
+                    }
+                    Ok(())
+                }
+                Inconsistency::StaleTag { tag } => {
+                    if !meta.flatten().is_some_and(|m| m.tags.contains(tag)) {
+                        tx.tags.remove([tag_prefix(tag).as_slice(), key].concat())?;
+                    }
+                    Ok(())
+                }
+                Inconsistency::MissingTag { tag } => {
+                    if meta.flatten().is_some_and(|m| m.tags.contains(tag)) {
+                        tx.tags
+                            .insert([tag_prefix(tag).as_slice(), key].concat(), key)?;
+                    }
+                    Ok(())
+                }
+                Inconsistency::MalformedEntry { .. } | Inconsistency::OrphanChunks { .. } => Ok(()),
+            }
+        })
+    }
+}
+
+/// Returns when an inconsistency is fixed: metadata is made decodable first,
+/// so keys can then be removed, and the indexes are fixed last.
+fn repair_order(kind: &Inconsistency) -> u8 {
+    match kind {
+        Inconsistency::UndecodableMetadata => 0,
+        Inconsistency::OrphanData
+        | Inconsistency::OrphanMetadata
+        | Inconsistency::MissingChunks => 1,
+        _ => 2,
+    }
+}
+
+/// Removes a key whose value or metadata is missing, or whose value is
+/// missing chunks.
+fn repair_value(
+    tx: &KeyspaceTx,
+    key: &[u8],
+) -> Result<(), ConflictableTransactionError<TransientError>> {
+    let stored = tx.data.get(key)?;
+    let complete = match stored.as_deref().and_then(BlobRef::parse) {
+        Some(blob) => blob.is_complete_in(tx.blobs)?,
+        None => true,
+    };
+    let has_meta = tx.freq.get(key)?.is_some();
+
+    match stored {
+        Some(_) if has_meta && complete => Ok(()),
+        Some(stored) if !has_meta => {
+            tx.data.remove(key)?;
+            remove_chunks(tx.blobs, &stored)?;
+            append_remove(tx, key)
+        }
+        _ if has_meta => tx.delete(key).map(|_| ()),
+        _ => Ok(()),
+    }
+}
+
+/// Replaces undecodable metadata with new metadata, or removes it along with
+/// the key if the key has no value.
+fn repair_metadata(
+    tx: &KeyspaceTx,
+    key: &[u8],
+    value_len: u64,
+) -> Result<(), ConflictableTransactionError<TransientError>> {
+    if tx.data.get(key)?.is_none() {
+        tx.freq.remove(key)?;
+        return append_remove(tx, key);
+    }
+
+    let mut meta = Metadata::new(None);
+    meta.value_len = value_len;
+    let bytes = meta
+        .to_u8()
+        .map_err(|_| ConflictableTransactionError::Abort(TransientError::ParsingToByteError))?;
+    tx.freq.insert(key, bytes)?;
+    reindex(tx.index, key, None, Some(meta.freq))?;
+    append(
+        tx,
+        Mutation::Meta {
+            key: key.to_vec(),
+            meta,
+        },
+    )
+}
+
+fn append_remove(
+    tx: &KeyspaceTx,
+    key: &[u8],
+) -> Result<(), ConflictableTransactionError<TransientError>> {
+    append(tx, Mutation::Remove { key: key.to_vec() })
+}
+
+fn append(
+    tx: &KeyspaceTx,
+    mutation: Mutation,
+) -> Result<(), ConflictableTransactionError<TransientError>> {
+    oplog::append(tx.oplog, tx.state, tx.ks.name.as_deref(), mutation, || {
+        TransientError::SledTransactionError
+    })?;
+    Ok(())
+}
+
+/// Returns the key of the `ttl_tree` entry expiring `key` at `deadline`.
+fn ttl_key(deadline: u64, key: &[u8]) -> Vec<u8> {
+    [&deadline.to_be_bytes()[..], key].concat()
+}
+
+/// Splits an index entry into the number it starts with and the key.
+fn split_u64(entry: &[u8]) -> Option<(u64, &[u8])> {
+    let (number, key) = entry.split_first_chunk::<8>()?;
+    Some((u64::from_be_bytes(*number), key))
+}
+
+/// Splits a tag index entry into the tag and the key.
+fn split_tag(entry: &[u8]) -> Option<(&str, &[u8])> {
+    let (len, rest) = entry.split_first_chunk::<4>()?;
+    let len = u32::from_be_bytes(*len) as usize;
+    if rest.len() < len {
+        return None;
+    }
+    let (tag, key) = rest.split_at(len);
+    Some((from_utf8(tag).ok()?, key))
+}
@@ -0,0 +1,143 @@
+//! This module implements optimistic concurrency on the keys, built on
+//! [`Metadata::version`](crate::Metadata::version). A client reads a value
+//! along with its version, then writes it back only if the version didn't
+//! change in the meantime, like an HTTP `ETag` with `If-Match`.
+
+use std::{error::Error, sync::atomic::Ordering, time::Duration};
+
+use sled::transaction::{ConflictableTransactionError, TransactionError, Transactional};
+
+use crate::{
+    DB, Metadata,
+    db::{
+        errors::TransientError,
+        keyspace::{Keyspace, transaction_error},
+    },
+};
+
+impl DB {
+    /// Retrieves the value for a given key along with its version.
+    ///
+    /// The version is bumped by every write of the value, see
+    /// [`Metadata::version`], and is the one to pass to [`DB::set_if_version`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value cannot be retrieved from the database or if
+    /// the value is not valid UTF-8.
+    pub fn get_with_version(&self, key: &str) -> Result<Option<(String, u64)>, Box<dyn Error>> {
+        Ok(self.get_with_version_in(&self.keyspace, key)?)
+    }
+
+    /// Sets a key-value pair like [`DB::set`] if the version of the key is
+    /// still `expected_version`, returning its new version.
+    ///
+    /// An `expected_version` of 0 only sets a key that doesn't exist. A key
+    /// written again after being removed or expired starts over at version 1.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TransientError::VersionConflict`] if the key was written,
+    /// removed or created since its version was read, or an error if the
+    /// transaction fails.
+    pub fn set_if_version(
+        &self,
+        key: &str,
+        val: &str,
+        ttl: Option<Duration>,
+        expected_version: u64,
+    ) -> Result<u64, Box<dyn Error>> {
+        let version = self.set_if_version_in(&self.keyspace, key, val, ttl, expected_version)?;
+        self.persist(self.durability)?;
+        Ok(version)
+    }
+
+    /// Retrieves the value and version of a key of the given keyspace, see
+    /// [`DB::get_with_version`].
+    pub(crate) fn get_with_version_in(
+        &self,
+        ks: &Keyspace,
+        key: &str,
+    ) -> Result<Option<(String, u64)>, TransientError> {
+        Ok(self
+            .get_with_metadata_in(ks, key)?
+            .map(|(val, meta)| (val, meta.version)))
+    }
+
+    /// Retrieves the value of a key of the given keyspace along with the
+    /// metadata written with it.
+    pub(crate) fn get_with_metadata_in(
+        &self,
+        ks: &Keyspace,
+        key: &str,
+    ) -> Result<Option<(String, Metadata)>, TransientError> {
+        let byte = key.as_bytes();
+        loop {
+            // NOTE: Both are read in a transaction so the metadata is the one of the value.
+            let l: Result<_, TransactionError<TransientError>> = (&*ks.data_tree, &*ks.meta_tree)
+                .transaction(|(data, meta)| Ok((data.get(byte)?, meta.get(byte)?)));
+            let (Some(stored), Some(meta)) = l.map_err(transaction_error)? else {
+                ks.stats.misses.fetch_add(1, Ordering::Relaxed);
+                return Ok(None);
+            };
+            // NOTE: The chunks of a value overwritten in the meantime are gone, read it again.
+            let Some(val) = self.read_value(ks, byte, &stored)? else {
+                continue;
+            };
+            let meta =
+                Metadata::from_u8(&meta).map_err(|_| TransientError::ParsingFromByteError)?;
+
+            ks.stats.hits.fetch_add(1, Ordering::Relaxed);
+            self.track_read(ks, byte)?;
+            let val = String::from_utf8(val).map_err(|_| TransientError::ParsingToUTF8Error)?;
+            return Ok(Some((val, meta)));
+        }
+    }
+
+    /// Sets a key-value pair of the given keyspace if its version is still
+    /// `expected_version`, see [`DB::set_if_version`].
+    pub(crate) fn set_if_version_in(
+        &self,
+        ks: &Keyspace,
+        key: &str,
+        val: &str,
+        ttl: Option<Duration>,
+        expected_version: u64,
+    ) -> Result<u64, TransientError> {
+        let byte = key.as_bytes();
+        let ttl_sec = ks.deadline(ttl);
+        let stored = self.store_value(ks, byte, val.as_bytes())?;
+
+        let res = self.transact(ks, |tx| {
+            let actual = match tx.freq.get(byte)? {
+                Some(meta) => {
+                    Metadata::from_u8(&meta)
+                        .map_err(|_| {
+                            ConflictableTransactionError::Abort(
+                                TransientError::ParsingFromByteError,
+                            )
+                        })?
+                        .version
+                }
+                None => 0,
+            };
+            if actual != expected_version {
+                Err(ConflictableTransactionError::Abort(
+                    TransientError::VersionConflict {
+                        expected: expected_version,
+                        actual,
+                    },
+                ))?
+            }
+            tx.write(byte, &stored.bytes, stored.len, ttl_sec)?;
+            Ok(actual + 1)
+        });
+        if res.is_err() {
+            stored.discard(ks);
+        }
+        let version = res?;
+
+        ks.stats.writes.fetch_add(1, Ordering::Relaxed);
+        Ok(version)
+    }
+}
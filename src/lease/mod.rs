@@ -0,0 +1,350 @@
+//! The `lease` module implements leases on top of TTL keys, for leader
+//! election and mutual exclusion between processes sharing a `DB`, see
+//! [`DB::acquire_lease`].
+//!
+//! A lease is a key whose value holds its owner and fencing token, written
+//! with the TTL of the lease. Acquiring, renewing and releasing it read and
+//! write the key in a single transaction, and the background thread removes
+//! it once its TTL elapses like any other key, so a lease whose owner stops
+//! renewing it is eventually free again.
+//!
+//! The fencing token of a lease is the sequence number of the write that
+//! acquired it, see [`DB::last_seq`], so every acquisition gets a larger
+//! token than the ones before it. Passing it along with the writes made
+//! under the lease lets the systems receiving them reject the writes of an
+//! owner who lost the lease without knowing it.
+
+use std::{
+    error::Error,
+    sync::atomic::Ordering,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use sled::transaction::ConflictableTransactionError;
+
+use crate::{
+    DB, Metadata,
+    db::{
+        errors::TransientError,
+        keyspace::{Keyspace, KeyspaceTx},
+    },
+    namespace::Namespace,
+    oplog::{OPLOG_SEQ_KEY, read_seq},
+};
+
+/// A lease held until it is released, dropped or its TTL elapses, returned
+/// by [`DB::acquire_lease`].
+///
+/// Dropping the lease releases it, unless it was lost in the meantime.
+#[derive(Debug)]
+pub struct Lease<'a> {
+    db: &'a DB,
+    /// The namespace of the lease, `None` for the default keyspace
+    namespace: Option<Namespace<'a>>,
+    name: String,
+    owner: String,
+    token: u64,
+    released: bool,
+}
+
+/// The holder of a lease, as stored in its value.
+struct Holder {
+    token: u64,
+    owner: String,
+}
+
+impl Holder {
+    /// Parses the value of a lease, `{token}:{owner}`.
+    fn parse(value: &[u8]) -> Option<Holder> {
+        let (token, owner) = std::str::from_utf8(value).ok()?.split_once(':')?;
+        Some(Holder {
+            token: token.parse().ok()?,
+            owner: owner.to_string(),
+        })
+    }
+
+    fn to_value(&self) -> String {
+        format!("{}:{}", self.token, self.owner)
+    }
+}
+
+impl DB {
+    /// Acquires the lease `name` for `owner` until `ttl` elapses, returning
+    /// `None` if another owner holds it.
+    ///
+    /// The lease is the key `name` of the default keyspace, so it shouldn't
+    /// be used for anything else. Acquiring a lease `owner` already holds
+    /// takes it over with a new fencing token, so a process restarted under
+    /// the same owner doesn't wait for its previous lease to expire, and the
+    /// guard of the previous lease fails to renew it.
+    ///
+    /// TTLs are counted in whole seconds, so a lease may last up to a
+    /// second longer than `ttl`, but never less.
+    ///
+    /// ```no_run
+    /// use epoch_db::DB;
+    /// use std::{path::Path, time::Duration};
+    ///
+    /// let db = DB::new(Path::new("./my_database")).unwrap();
+    /// if let Some(lease) = db.acquire_lease("leader", "node-1", Duration::from_secs(10)).unwrap() {
+    ///     println!("Leading with token {}", lease.token());
+    ///     lease.renew(Duration::from_secs(10)).unwrap();
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key `name` holds something else than a lease
+    /// or the transaction fails.
+    pub fn acquire_lease(
+        &self,
+        name: &str,
+        owner: &str,
+        ttl: Duration,
+    ) -> Result<Option<Lease<'_>>, Box<dyn Error>> {
+        let token = self.acquire_lease_in(&self.keyspace, name, owner, ttl)?;
+        self.persist(self.durability)?;
+        Ok(token.map(|token| Lease::new(self, None, name, owner, token)))
+    }
+
+    /// Acquires a lease in the given keyspace, see [`DB::acquire_lease`].
+    ///
+    /// Returns the fencing token of the lease, `None` if another owner holds it.
+    pub(crate) fn acquire_lease_in(
+        &self,
+        ks: &Keyspace,
+        name: &str,
+        owner: &str,
+        ttl: Duration,
+    ) -> Result<Option<u64>, TransientError> {
+        let byte = name.as_bytes();
+        let ttl_sec = lease_deadline(ks, ttl);
+        let token = self.transact(ks, |tx| {
+            if let Some(holder) = self.lease_holder(tx, byte)?
+                && holder.owner != owner
+            {
+                return Ok(None);
+            }
+
+            // NOTE: The write below is logged with the next sequence number.
+            let holder = Holder {
+                token: read_seq(tx.state.get(OPLOG_SEQ_KEY)?.as_deref()) + 1,
+                owner: owner.to_string(),
+            };
+            self.write_lease(tx, byte, &holder, ttl_sec)?;
+            Ok(Some(holder.token))
+        })?;
+
+        if token.is_some() {
+            ks.stats.writes.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(token)
+    }
+
+    /// Extends the lease of the given keyspace acquired with `token` until
+    /// `ttl` elapses, see [`Lease::renew`].
+    pub(crate) fn renew_lease_in(
+        &self,
+        ks: &Keyspace,
+        name: &str,
+        token: u64,
+        ttl: Duration,
+    ) -> Result<(), TransientError> {
+        let byte = name.as_bytes();
+        let ttl_sec = lease_deadline(ks, ttl);
+        self.transact(ks, |tx| {
+            let holder = self.held_lease(tx, byte, token)?;
+            self.write_lease(tx, byte, &holder, ttl_sec)
+        })?;
+        ks.stats.writes.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Releases the lease of the given keyspace acquired with `token`, see
+    /// [`Lease::release`].
+    pub(crate) fn release_lease_in(
+        &self,
+        ks: &Keyspace,
+        name: &str,
+        token: u64,
+    ) -> Result<(), TransientError> {
+        let byte = name.as_bytes();
+        self.transact(ks, |tx| {
+            self.held_lease(tx, byte, token)?;
+            tx.delete(byte)
+        })?;
+        ks.stats.removes.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Returns the holder of the lease `byte`, `None` if it is free or its
+    /// TTL elapsed.
+    fn lease_holder(
+        &self,
+        tx: &KeyspaceTx,
+        byte: &[u8],
+    ) -> Result<Option<Holder>, ConflictableTransactionError<TransientError>> {
+        let abort = ConflictableTransactionError::Abort;
+
+        let Some(meta) = tx.freq.get(byte)? else {
+            return Ok(None);
+        };
+        let meta =
+            Metadata::from_u8(&meta).map_err(|_| abort(TransientError::ParsingFromByteError))?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Cant get SystemTime")
+            .as_secs();
+        // NOTE: The background thread may not have removed an expired lease yet.
+        if meta.ttl.is_some_and(|t| t <= now) {
+            return Ok(None);
+        }
+
+        let Some(stored) = tx.data.get(byte)? else {
+            return Ok(None);
+        };
+        let value = self.codec.decode(byte, &stored).map_err(abort)?;
+        Holder::parse(&value)
+            .map(Some)
+            .ok_or(abort(TransientError::NotALease))
+    }
+
+    /// Returns the holder of the lease `byte`, aborting with
+    /// [`TransientError::LeaseLost`] unless it was acquired with `token`.
+    fn held_lease(
+        &self,
+        tx: &KeyspaceTx,
+        byte: &[u8],
+        token: u64,
+    ) -> Result<Holder, ConflictableTransactionError<TransientError>> {
+        match self.lease_holder(tx, byte)? {
+            Some(holder) if holder.token == token => Ok(holder),
+            _ => Err(ConflictableTransactionError::Abort(
+                TransientError::LeaseLost,
+            )),
+        }
+    }
+
+    /// Writes the lease `byte` held by `holder` until `ttl_sec`.
+    fn write_lease(
+        &self,
+        tx: &KeyspaceTx,
+        byte: &[u8],
+        holder: &Holder,
+        ttl_sec: Option<u64>,
+    ) -> Result<(), ConflictableTransactionError<TransientError>> {
+        let value = holder.to_value();
+        let stored = self
+            .codec
+            .encode(byte, value.as_bytes())
+            .map_err(ConflictableTransactionError::Abort)?;
+        tx.write(byte, &stored, value.len(), ttl_sec)
+    }
+}
+
+/// Returns the deadline of a lease held for `ttl`.
+fn lease_deadline(ks: &Keyspace, ttl: Duration) -> Option<u64> {
+    // NOTE: Deadlines are truncated to whole seconds, round up so the lease
+    // isn't free before `ttl` elapses.
+    ks.deadline(Some(ttl + Duration::from_secs(1)))
+}
+
+impl<'a> Namespace<'a> {
+    /// Acquires the lease `name` for `owner` until `ttl` elapses, returning
+    /// `None` if another owner holds it, see [`DB::acquire_lease`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped, the key `name` holds
+    /// something else than a lease or the transaction fails.
+    pub fn acquire_lease(
+        &self,
+        name: &str,
+        owner: &str,
+        ttl: Duration,
+    ) -> Result<Option<Lease<'a>>, Box<dyn Error>> {
+        let token = self.with_keyspace(|ks| self.db.acquire_lease_in(ks, name, owner, ttl))?;
+        self.db.persist(self.db.durability)?;
+        Ok(token.map(|token| Lease::new(self.db, Some(self.clone()), name, owner, token)))
+    }
+}
+
+impl<'a> Lease<'a> {
+    fn new(
+        db: &'a DB,
+        namespace: Option<Namespace<'a>>,
+        name: &str,
+        owner: &str,
+        token: u64,
+    ) -> Lease<'a> {
+        Lease {
+            db,
+            namespace,
+            name: name.to_string(),
+            owner: owner.to_string(),
+            token,
+            released: false,
+        }
+    }
+
+    /// Returns the name of the lease.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the owner of the lease.
+    pub fn owner(&self) -> &str {
+        &self.owner
+    }
+
+    /// Returns the fencing token of the lease, larger than the token of any
+    /// lease acquired before it.
+    pub fn token(&self) -> u64 {
+        self.token
+    }
+
+    /// Extends the lease until `ttl` elapses from now.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TransientError::LeaseLost`] if the TTL of the lease elapsed
+    /// or it was taken over since it was acquired, or an error if the
+    /// transaction fails.
+    pub fn renew(&self, ttl: Duration) -> Result<(), Box<dyn Error>> {
+        self.with_keyspace(|ks| self.db.renew_lease_in(ks, &self.name, self.token, ttl))?;
+        Ok(self.db.persist(self.db.durability)?)
+    }
+
+    /// Releases the lease so another owner can acquire it.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TransientError::LeaseLost`] if the TTL of the lease elapsed
+    /// or it was taken over since it was acquired, or an error if the
+    /// transaction fails.
+    pub fn release(mut self) -> Result<(), Box<dyn Error>> {
+        self.released = true;
+        self.with_keyspace(|ks| self.db.release_lease_in(ks, &self.name, self.token))?;
+        Ok(self.db.persist(self.db.durability)?)
+    }
+
+    /// Runs `f` against the keyspace of the lease.
+    fn with_keyspace<T>(
+        &self,
+        f: impl Fn(&Keyspace) -> Result<T, TransientError>,
+    ) -> Result<T, TransientError> {
+        match &self.namespace {
+            Some(namespace) => namespace.with_keyspace(f),
+            None => f(&self.db.keyspace),
+        }
+    }
+}
+
+impl Drop for Lease<'_> {
+    fn drop(&mut self) {
+        if !self.released {
+            let _ = self.with_keyspace(|ks| self.db.release_lease_in(ks, &self.name, self.token));
+            let _ = self.db.persist(self.db.durability);
+        }
+    }
+}
@@ -10,16 +10,34 @@
 //! It provides a high-level, ergonomic API by treating data's **access frequency**
 //! and **age** as first-class citizens.
 
-use db::errors::TransientError;
+use audit::AuditLog;
+use db::{codec::ValueCodec, keyspace::Keyspace, snapshot::SnapshotRegistry};
+use metadata::AccessWindows;
 use serde::{Deserialize, Serialize};
 use sled::Tree;
 use std::{
-    sync::{Arc, atomic::AtomicBool},
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, RwLock, atomic::AtomicBool},
     thread::JoinHandle,
+    time::Duration,
 };
+use watch::hub::WatchHub;
 
+#[cfg(feature = "async")]
+pub mod async_db;
+pub mod audit;
 pub mod db;
+pub mod lease;
 pub mod metadata;
+pub mod namespace;
+pub mod oplog;
+pub mod ratelimit;
+pub mod redis;
+pub mod replication;
+pub mod session;
+pub mod watch;
+
+pub use db::config::{Compression, DBConfig, Durability, FrequencyTracking};
 
 /// This is the main struct which represents the database.
 ///
@@ -30,28 +48,52 @@ pub mod metadata;
 /// When this struct is dropped, it will signal the background thread to shut down
 /// and wait for it to finish gracefully.
 ///
-/// This struct also holds 2 Arc<sled::Tree> directly instead of a single sled::Db,
-/// since almost all of the functions uses the tree directly which requires the sled::Db to
-/// constantly open each trees.
+/// This struct also holds the trees of every keyspace directly instead of only the
+/// sled::Db, since almost all of the functions uses the tree directly which requires the
+/// sled::Db to constantly open each trees.
 /// Passing trees from the struct deletes the constant need to open the trees
 #[derive(Debug)]
 pub struct DB {
-    /// Stores the key and value
-    data_tree: Arc<Tree>,
-    /// Stores the key and the metadata
-    meta_tree: Arc<Tree>,
-    /// Stores the ttl timestamp and the key
-    ttl_tree: Arc<Tree>,
-    /// Manage the background thread which checks for expired keys
-    ttl_thread: Option<JoinHandle<Result<(), TransientError>>>,
+    /// The underlying database, used to open and drop the trees of namespaces
+    sled_db: sled::Db,
+    /// Stores the trees of the default keyspace
+    keyspace: Arc<Keyspace>,
+    /// Stores the trees of every namespace, by name
+    namespaces: Arc<RwLock<HashMap<String, Arc<Keyspace>>>>,
+    /// Stores the sequence number and the mutation, in commit order
+    oplog_tree: Arc<Tree>,
+    /// Stores internal bookkeeping such as the mutation log sequence counter
+    state_tree: Arc<Tree>,
+    /// Stores the audited mutations, copied from the mutation log
+    audit_log: Arc<AuditLog>,
+    /// Stores what the live snapshots read instead of the current values
+    snapshots: Arc<SnapshotRegistry>,
+    /// Durability of the writes that don't ask for one explicitly
+    durability: Durability,
+    /// How reads update the frequency of the keys they find
+    frequency_tracking: FrequencyTracking,
+    /// Half-life of the decayed frequency of the keys, `None` disables the decay
+    half_life: Option<Duration>,
+    /// Compresses and decompresses the values
+    codec: Arc<ValueCodec>,
+    /// Values larger than this are stored in chunks of this size
+    chunk_size: usize,
+    /// Whether writes are refused, see [`DBConfig::read_only`]
+    read_only: bool,
+    /// Manage the background thread which checks for expired keys, `None` when read-only
+    ttl_thread: Option<JoinHandle<()>>,
     /// Signals the ttl_thread to gracefully shutdown, when the DB is dropped
     shutdown: Arc<AtomicBool>,
+    /// Forwards the changes of the mutation log to the watchers
+    watchers: WatchHub,
+    /// The copy a read-only database was opened from, removed after the trees are dropped
+    _copy: Option<tempfile::TempDir>,
 }
 
 /// Contains additional information about a key, such as its access frequency and lifecycle.
 ///
 /// NOTE: This struct derives Serialize and Deserialize to be stored as raw bytes (&[u8]) in the underlying sled tree.
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Metadata {
     /// The number of time the key has been accessed
     pub freq: u64,
@@ -59,4 +101,22 @@ pub struct Metadata {
     pub created_at: u64,
     /// The key's time-to-live in seconds. If None, the key is persistent and never expires.
     pub ttl: Option<u64>,
+    /// The access frequency with exponential decay applied, see
+    /// [`DBConfig::frequency_half_life`]. Equal to `freq` when there is no half-life.
+    pub score: f64,
+    /// Timestamp of the last read or frequency increment, in seconds since the
+    /// UNIX epoch. Equal to `created_at` until the key is accessed. Reads that
+    /// don't update the frequency right away are recorded by the background
+    /// thread on its next pass.
+    pub last_accessed_at: u64,
+    /// The number of accesses in the current minute, hour and day
+    pub windows: AccessWindows,
+    /// Timestamp of the last write of the value, in seconds since the UNIX epoch
+    pub updated_at: u64,
+    /// The number of times the value was written, starting at 1 for the first write
+    pub version: u64,
+    /// The length of the value, in bytes
+    pub value_len: u64,
+    /// The labels attached to the key, see [`DB::set_tags`]
+    pub tags: BTreeSet<String>,
 }
@@ -2,13 +2,102 @@
 //! methods. `Metadata` is used to track information about each key-value
 //! pair, such as its creation time, access frequency, and TTL.
 
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::{
+    collections::BTreeSet,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use crate::Metadata;
+use crate::{Metadata, db::errors::TransientError};
 use bincode::{
     error::{DecodeError, EncodeError},
     serde::{decode_from_slice, encode_to_vec},
 };
+use serde::{Deserialize, Serialize};
+
+/// Access counters of a key over fixed windows of time.
+///
+/// The windows are aligned on the UNIX epoch, so `minute` counts the accesses
+/// since the start of the current minute, `hour` since the start of the
+/// current hour and `day` since the start of the current UTC day.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct AccessWindows {
+    /// The number of accesses in the current minute
+    pub minute: u64,
+    /// The number of accesses in the current hour
+    pub hour: u64,
+    /// The number of accesses in the current day
+    pub day: u64,
+}
+
+impl AccessWindows {
+    /// Resets the windows that ended between `last` and `now`.
+    fn roll(&mut self, last: u64, now: u64) {
+        if now / 60 != last / 60 {
+            self.minute = 0;
+        }
+        if now / 3600 != last / 3600 {
+            self.hour = 0;
+        }
+        if now / 86400 != last / 86400 {
+            self.day = 0;
+        }
+    }
+}
+
+/// First byte of a versioned metadata envelope.
+///
+/// The envelope is this tag, the version of the layout and the `bincode`
+/// encoding of the metadata in that layout. Unversioned metadata written by
+/// older databases starts with a varint, which never begins with `0xFF`, so
+/// both can be told apart.
+const ENVELOPE_TAG: u8 = 0xFF;
+
+/// The version of the layout `Metadata::to_u8` writes.
+pub const METADATA_VERSION: u8 = 2;
+
+/// The largest number of tags a key can carry.
+pub const MAX_TAGS: usize = 32;
+
+/// The largest length of a tag, in bytes.
+pub const MAX_TAG_LEN: usize = 128;
+
+/// The layout of version 1 of the envelope, also used by unversioned
+/// metadata once the decayed frequency and the access windows were added.
+#[derive(Deserialize)]
+struct MetadataV1 {
+    freq: u64,
+    created_at: u64,
+    ttl: Option<u64>,
+    score: f64,
+    last_accessed_at: u64,
+    windows: AccessWindows,
+}
+
+impl From<MetadataV1> for Metadata {
+    fn from(v1: MetadataV1) -> Metadata {
+        Metadata {
+            freq: v1.freq,
+            created_at: v1.created_at,
+            ttl: v1.ttl,
+            score: v1.score,
+            last_accessed_at: v1.last_accessed_at,
+            windows: v1.windows,
+            updated_at: v1.created_at,
+            version: 1,
+            value_len: 0,
+            tags: BTreeSet::new(),
+        }
+    }
+}
+
+/// The layout of `Metadata` before the decayed frequency and the access
+/// windows were added, still found in older databases.
+#[derive(Deserialize)]
+struct LegacyMetadata {
+    freq: u64,
+    created_at: u64,
+    ttl: Option<u64>,
+}
 
 impl Metadata {
     /// Creates a new `Metadata` instance with an optional TTL.
@@ -23,6 +112,13 @@ impl Metadata {
             freq: 0,
             created_at: currtime,
             ttl,
+            score: 0.0,
+            last_accessed_at: currtime,
+            windows: AccessWindows::default(),
+            updated_at: currtime,
+            version: 0,
+            value_len: 0,
+            tags: BTreeSet::new(),
         }
     }
 
@@ -38,21 +134,139 @@ impl Metadata {
         self
     }
 
-    /// Serializes the `Metadata` instance into a byte vector using `bincode`.
+    /// Records `count` accesses at `now`, in seconds since the UNIX epoch.
+    ///
+    /// The score is decayed with `half_life` since the last access before
+    /// `count` is added to it.
+    pub(crate) fn record_access(&mut self, count: u64, now: u64, half_life: Option<Duration>) {
+        *self = self.at(now, half_life);
+        self.freq = self.freq.saturating_add(count);
+        self.score += count as f64;
+        self.windows.minute = self.windows.minute.saturating_add(count);
+        self.windows.hour = self.windows.hour.saturating_add(count);
+        self.windows.day = self.windows.day.saturating_add(count);
+        self.last_accessed_at = self.last_accessed_at.max(now);
+    }
+
+    /// Records a write of a `value_len` bytes long value at `now`, in seconds
+    /// since the UNIX epoch, bumping the version.
+    pub(crate) fn record_write(&mut self, value_len: usize, now: u64) {
+        self.updated_at = now;
+        self.version = self.version.saturating_add(1);
+        self.value_len = value_len as u64;
+    }
+
+    /// Returns the metadata as seen at `now`, in seconds since the UNIX epoch:
+    /// the score decayed with `half_life` and the windows that ended since the
+    /// last access reset.
+    pub fn at(&self, now: u64, half_life: Option<Duration>) -> Metadata {
+        let mut meta = self.clone();
+        let elapsed = now.saturating_sub(self.last_accessed_at);
+
+        if let Some(half_life) = half_life.filter(|h| !h.is_zero()) {
+            meta.score *= (-(elapsed as f64) / half_life.as_secs_f64()).exp2();
+        }
+        meta.windows.roll(self.last_accessed_at, now);
+        meta
+    }
+
+    /// Serializes the `Metadata` instance into a versioned envelope using
+    /// `bincode`.
     ///
     /// # Errors
     ///
     /// Returns an `EncodeError` if serialization fails.
     pub fn to_u8(&self) -> Result<Vec<u8>, EncodeError> {
-        encode_to_vec(self, bincode::config::standard())
+        let mut bytes = vec![ENVELOPE_TAG, METADATA_VERSION];
+        bytes.extend(encode_to_vec(self, bincode::config::standard())?);
+        Ok(bytes)
     }
 
     /// Deserializes a `Metadata` instance from a byte slice using `bincode`.
     ///
+    /// Metadata in an older layout is upgraded: its `updated_at` is its
+    /// creation time, its `version` is 1, its `value_len` is 0 and it has no
+    /// tags. Unversioned metadata written by older databases is still
+    /// accepted.
+    ///
     /// # Errors
     ///
-    /// Returns a `DecodeError` if deserialization fails.
+    /// Returns a `DecodeError` if deserialization fails or if the metadata was
+    /// written in a newer layout than [`METADATA_VERSION`].
     pub fn from_u8(slice: &[u8]) -> Result<Metadata, DecodeError> {
-        Ok(decode_from_slice(slice, bincode::config::standard())?.0)
+        match slice {
+            [ENVELOPE_TAG, METADATA_VERSION, payload @ ..] => {
+                Ok(decode_from_slice(payload, bincode::config::standard())?.0)
+            }
+            [ENVELOPE_TAG, 1, payload @ ..] => Ok(decode_from_slice::<MetadataV1, _>(
+                payload,
+                bincode::config::standard(),
+            )?
+            .0
+            .into()),
+            [ENVELOPE_TAG, version, ..] => Err(DecodeError::OtherString(format!(
+                "unsupported metadata version {version}"
+            ))),
+            [ENVELOPE_TAG] => Err(DecodeError::UnexpectedEnd { additional: 1 }),
+            _ => Metadata::from_unversioned(slice),
+        }
+    }
+
+    /// Returns `true` if `slice` holds metadata in the layout of [`METADATA_VERSION`].
+    pub(crate) fn is_current(slice: &[u8]) -> bool {
+        slice.starts_with(&[ENVELOPE_TAG, METADATA_VERSION])
+    }
+
+    /// Deserializes metadata written before the version envelope existed.
+    ///
+    /// Metadata without a score or access windows is upgraded with a score
+    /// equal to its frequency. Fields added since are filled in as for
+    /// version 1, see [`Metadata::from_u8`].
+    fn from_unversioned(slice: &[u8]) -> Result<Metadata, DecodeError> {
+        match decode_from_slice::<MetadataV1, _>(slice, bincode::config::standard()) {
+            Ok((meta, _)) => Ok(meta.into()),
+            Err(e) => {
+                let Ok((legacy, _)) =
+                    decode_from_slice::<LegacyMetadata, _>(slice, bincode::config::standard())
+                else {
+                    return Err(e);
+                };
+                Ok(MetadataV1 {
+                    freq: legacy.freq,
+                    created_at: legacy.created_at,
+                    ttl: legacy.ttl,
+                    score: legacy.freq as f64,
+                    last_accessed_at: legacy.created_at,
+                    windows: AccessWindows::default(),
+                }
+                .into())
+            }
+        }
+    }
+}
+
+/// Checks that `tags` fit in [`MAX_TAGS`] non-empty tags of at most
+/// [`MAX_TAG_LEN`] bytes.
+pub(crate) fn tag_set(tags: &[&str]) -> Result<BTreeSet<String>, TransientError> {
+    let tags: BTreeSet<String> = tags.iter().map(|t| t.to_string()).collect();
+    if tags.len() > MAX_TAGS || tags.iter().any(|t| t.is_empty() || t.len() > MAX_TAG_LEN) {
+        Err(TransientError::InvalidTags)?
+    }
+    Ok(tags)
+}
+
+/// Serializes `Metadata` embedded in other structures through its versioned
+/// envelope, for use with `#[serde(with = "...")]`.
+pub(crate) mod envelope {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer, de, ser};
+
+    use crate::Metadata;
+
+    pub(crate) fn serialize<S: Serializer>(meta: &Metadata, s: S) -> Result<S::Ok, S::Error> {
+        meta.to_u8().map_err(ser::Error::custom)?.serialize(s)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Metadata, D::Error> {
+        Metadata::from_u8(&Vec::<u8>::deserialize(d)?).map_err(de::Error::custom)
     }
 }
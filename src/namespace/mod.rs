@@ -0,0 +1,651 @@
+//! The `namespace` module lets a `DB` hold several independent keyspaces.
+//!
+//! Each namespace gets its own set of sled trees, a default TTL, an eviction
+//! policy and its own statistics. Because nothing is shared with the rest of
+//! the database, a namespace can be cleared or dropped atomically without
+//! scanning any other key.
+
+use std::{
+    error::Error,
+    io::Read,
+    sync::{Arc, atomic::Ordering},
+    time::Duration,
+};
+
+use bincode::{
+    error::{DecodeError, EncodeError},
+    serde::{decode_from_slice, encode_to_vec},
+};
+use serde::{Deserialize, Serialize};
+use sled::transaction::{TransactionError, Transactional};
+
+use crate::{
+    DB, Metadata,
+    db::{
+        batch::Entry,
+        blob::ValueReader,
+        errors::TransientError,
+        keyspace::{Keyspace, KeyspaceStats, transaction_error},
+        snapshot::{PENDING_DROP_PREFIX, SNAPSHOT_KEY, first_generation},
+    },
+    oplog::{self, Mutation},
+};
+
+/// Prefix of the `state_tree` keys holding the record of each namespace.
+pub(crate) const NAMESPACE_PREFIX: &[u8] = b"namespace:";
+
+/// Returns the `state_tree` key holding the record of the namespace `name`.
+pub(crate) fn namespace_key(name: &str) -> Vec<u8> {
+    [NAMESPACE_PREFIX, name.as_bytes()].concat()
+}
+
+/// Decides which keys the background thread removes from a namespace on its own.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum EvictionPolicy {
+    /// Keys are only removed by the caller or when their TTL elapses.
+    #[default]
+    None,
+    /// Keeps at most `max_keys` keys, evicting the ones with the lowest
+    /// [`Metadata::score`] first.
+    LeastFrequentlyUsed {
+        /// The number of keys the namespace may hold.
+        max_keys: u64,
+    },
+    /// Evicts keys older than `grace_period` whose [`Metadata::score`] is below `min_freq`.
+    Lifecycle {
+        /// How long a key is kept regardless of how often it is accessed.
+        grace_period: Duration,
+        /// The score a key needs to keep to survive the grace period.
+        min_freq: u64,
+    },
+}
+
+/// Settings of a namespace.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NamespaceConfig {
+    pub(crate) default_ttl: Option<Duration>,
+    pub(crate) eviction: EvictionPolicy,
+}
+
+impl NamespaceConfig {
+    /// Creates a configuration without a default TTL nor an eviction policy.
+    pub fn new() -> NamespaceConfig {
+        NamespaceConfig::default()
+    }
+
+    /// Sets the TTL applied by `set` when it is called without one.
+    pub fn default_ttl(mut self, ttl: Duration) -> NamespaceConfig {
+        self.default_ttl = Some(ttl);
+        self
+    }
+
+    /// Sets the eviction policy enforced by the background thread.
+    pub fn eviction(mut self, policy: EvictionPolicy) -> NamespaceConfig {
+        self.eviction = policy;
+        self
+    }
+}
+
+/// The persisted state of a namespace, stored in the `state_tree`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct NamespaceRecord {
+    /// Part of the tree names, bumped every time the namespace is cleared
+    pub(crate) generation: u64,
+    pub(crate) config: NamespaceConfig,
+}
+
+impl NamespaceRecord {
+    /// Serializes the `NamespaceRecord` instance into a byte vector using `bincode`.
+    pub(crate) fn to_u8(&self) -> Result<Vec<u8>, EncodeError> {
+        encode_to_vec(self, bincode::config::standard())
+    }
+
+    /// Deserializes a `NamespaceRecord` instance from a byte slice using `bincode`.
+    pub(crate) fn from_u8(slice: &[u8]) -> Result<NamespaceRecord, DecodeError> {
+        Ok(decode_from_slice(slice, bincode::config::standard())?.0)
+    }
+}
+
+/// A snapshot of the statistics of a keyspace.
+///
+/// Apart from `keys`, the counters start at 0 every time the `DB` is opened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NamespaceStats {
+    /// The number of keys currently stored.
+    pub keys: u64,
+    /// The number of `get` calls that found a value.
+    pub hits: u64,
+    /// The number of `get` calls that found nothing.
+    pub misses: u64,
+    /// The number of `set` calls.
+    pub writes: u64,
+    /// The number of `remove` calls.
+    pub removes: u64,
+    /// The number of keys removed because their TTL elapsed.
+    pub expirations: u64,
+    /// The number of keys removed by the eviction policy.
+    pub evictions: u64,
+}
+
+impl NamespaceStats {
+    pub(crate) fn collect(ks: &Keyspace) -> NamespaceStats {
+        let stats: &KeyspaceStats = &ks.stats;
+        NamespaceStats {
+            keys: ks.meta_tree.len() as u64,
+            hits: stats.hits.load(Ordering::Relaxed),
+            misses: stats.misses.load(Ordering::Relaxed),
+            writes: stats.writes.load(Ordering::Relaxed),
+            removes: stats.removes.load(Ordering::Relaxed),
+            expirations: stats.expirations.load(Ordering::Relaxed),
+            evictions: stats.evictions.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A handle to a namespace of a `DB`, returned by [`DB::namespace`].
+///
+/// It offers the same operations as the `DB` itself, scoped to the keys of
+/// the namespace.
+#[derive(Debug, Clone)]
+pub struct Namespace<'a> {
+    pub(crate) db: &'a DB,
+    pub(crate) name: String,
+}
+
+impl DB {
+    /// Returns a handle to the namespace `name`, creating it with the default
+    /// [`NamespaceConfig`] if it doesn't exist yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the name is empty or the namespace trees cannot be opened.
+    pub fn namespace(&self, name: &str) -> Result<Namespace<'_>, Box<dyn Error>> {
+        if !self
+            .namespaces
+            .read()
+            .expect("Namespaces lock poisoned")
+            .contains_key(name)
+        {
+            self.open_namespace(name, None, true)?;
+        }
+
+        Ok(Namespace {
+            db: self,
+            name: name.to_string(),
+        })
+    }
+
+    /// Returns a handle to the namespace `name`, creating it or updating its
+    /// settings with `config`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the name is empty or the namespace trees cannot be opened.
+    pub fn namespace_with(
+        &self,
+        name: &str,
+        config: NamespaceConfig,
+    ) -> Result<Namespace<'_>, Box<dyn Error>> {
+        self.open_namespace(name, Some(config), true)?;
+
+        Ok(Namespace {
+            db: self,
+            name: name.to_string(),
+        })
+    }
+
+    /// Returns the names of every namespace, in alphabetical order.
+    pub fn namespaces(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .namespaces
+            .read()
+            .expect("Namespaces lock poisoned")
+            .keys()
+            .cloned()
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Drops the namespace `name` and every key it holds.
+    ///
+    /// Returns false if the namespace doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace trees cannot be dropped.
+    pub fn drop_namespace(&self, name: &str) -> Result<bool, Box<dyn Error>> {
+        Ok(self.retire_namespace(name, true, true)?)
+    }
+
+    /// Returns the statistics of the default keyspace.
+    pub fn stats(&self) -> NamespaceStats {
+        NamespaceStats::collect(&self.keyspace)
+    }
+
+    /// Opens or creates the namespace `name`, replacing its settings if `config` is given.
+    ///
+    /// When `log` is false a change of settings isn't recorded in the mutation
+    /// log, which is used by followers applying a change of their primary.
+    pub(crate) fn open_namespace(
+        &self,
+        name: &str,
+        config: Option<NamespaceConfig>,
+        log: bool,
+    ) -> Result<Arc<Keyspace>, TransientError> {
+        if name.is_empty() {
+            Err(TransientError::InvalidNamespaceName)?
+        }
+
+        let mut namespaces = self.namespaces.write().expect("Namespaces lock poisoned");
+        let existing = namespaces.get(name).cloned();
+
+        let (generation, stats) = match &existing {
+            Some(ks) if config.is_none() => return Ok(Arc::clone(ks)),
+            Some(ks) => (ks.generation, Arc::clone(&ks.stats)),
+            None => {
+                let generation = first_generation(&self.state_tree, name)
+                    .map_err(|e| TransientError::SledError { error: e })?;
+                (generation, Arc::new(KeyspaceStats::default()))
+            }
+        };
+        self.check_writable()?;
+
+        let logged = config.clone().filter(|_| log);
+        let record = NamespaceRecord {
+            generation,
+            config: config.unwrap_or_default(),
+        };
+        let record_byte = record
+            .to_u8()
+            .map_err(|_| TransientError::ParsingToByteError)?;
+
+        match logged {
+            Some(config) => {
+                let l: Result<(), TransactionError<TransientError>> =
+                    (&*self.oplog_tree, &*self.state_tree).transaction(|(oplog, state)| {
+                        state.insert(namespace_key(name), record_byte.as_slice())?;
+                        oplog::append(
+                            oplog,
+                            state,
+                            Some(name),
+                            Mutation::Configure {
+                                config: config.clone(),
+                            },
+                            || TransientError::SledTransactionError,
+                        )?;
+                        Ok(())
+                    });
+                l.map_err(transaction_error)?;
+            }
+            None => {
+                self.state_tree
+                    .insert(namespace_key(name), record_byte)
+                    .map_err(|e| TransientError::SledError { error: e })?;
+            }
+        }
+
+        let mut ks = Keyspace::open(&self.sled_db, Some(name), generation, record.config, false)
+            .map_err(|e| TransientError::SledError { error: e })?;
+        ks.stats = stats;
+
+        let ks = Arc::new(ks);
+        namespaces.insert(name.to_string(), Arc::clone(&ks));
+        Ok(ks)
+    }
+
+    /// Returns the current keyspace of the namespace `name`.
+    pub(crate) fn resolve_namespace(&self, name: &str) -> Result<Arc<Keyspace>, TransientError> {
+        self.namespaces
+            .read()
+            .expect("Namespaces lock poisoned")
+            .get(name)
+            .cloned()
+            .ok_or(TransientError::NamespaceNotFound)
+    }
+
+    /// Clears or drops the namespace `name` by swapping its trees out in a
+    /// single transaction, then dropping the old ones, or leaving that to the
+    /// release of the last live snapshot.
+    ///
+    /// When `log` is false the change isn't recorded in the mutation log, which
+    /// is used by followers applying a change of their primary.
+    pub(crate) fn retire_namespace(
+        &self,
+        name: &str,
+        drop: bool,
+        log: bool,
+    ) -> Result<bool, TransientError> {
+        self.check_writable()?;
+        let mut namespaces = self.namespaces.write().expect("Namespaces lock poisoned");
+        let Some(old) = namespaces.get(name).cloned() else {
+            return Ok(false);
+        };
+
+        let generation = old.generation + 1;
+        let record = NamespaceRecord {
+            generation,
+            config: old.config.clone(),
+        }
+        .to_u8()
+        .map_err(|_| TransientError::ParsingToByteError)?;
+
+        let old_trees = Keyspace::tree_names(Some(name), old.generation);
+        let l: Result<bool, TransactionError<TransientError>> =
+            (&*self.oplog_tree, &*self.state_tree).transaction(|(oplog, state)| {
+                // NOTE: Live snapshots still read the old trees, they are dropped once released.
+                let deferred = state.get(SNAPSHOT_KEY)?.is_some();
+                if deferred {
+                    for tree_name in &old_trees {
+                        state.insert([PENDING_DROP_PREFIX, tree_name.as_bytes()].concat(), &[])?;
+                    }
+                }
+
+                if drop {
+                    state.remove(namespace_key(name))?;
+                } else {
+                    state.insert(namespace_key(name), record.as_slice())?;
+                }
+
+                if log {
+                    let mutation = if drop {
+                        Mutation::DropNamespace
+                    } else {
+                        Mutation::Clear
+                    };
+                    oplog::append(oplog, state, Some(name), mutation, || {
+                        TransientError::SledTransactionError
+                    })?;
+                }
+
+                Ok(deferred)
+            });
+        let deferred = l.map_err(transaction_error)?;
+
+        if drop {
+            namespaces.remove(name);
+        } else {
+            let mut ks = Keyspace::open(
+                &self.sled_db,
+                Some(name),
+                generation,
+                old.config.clone(),
+                false,
+            )
+            .map_err(|e| TransientError::SledError { error: e })?;
+            ks.stats = Arc::clone(&old.stats);
+            namespaces.insert(name.to_string(), Arc::new(ks));
+        }
+
+        for tree_name in old_trees.iter().filter(|_| !deferred) {
+            self.sled_db
+                .drop_tree(tree_name)
+                .map_err(|e| TransientError::SledError { error: e })?;
+        }
+
+        Ok(true)
+    }
+}
+
+impl Namespace<'_> {
+    /// Runs `f` against the current keyspace of the namespace, retrying if
+    /// the namespace gets cleared while it runs.
+    pub(crate) fn with_keyspace<T>(
+        &self,
+        f: impl Fn(&Keyspace) -> Result<T, TransientError>,
+    ) -> Result<T, TransientError> {
+        loop {
+            let ks = self.db.resolve_namespace(&self.name)?;
+            match f(&ks) {
+                Err(TransientError::NamespaceChanged) => continue,
+                res => return res,
+            }
+        }
+    }
+
+    /// Runs the write `f` like [`Namespace::with_keyspace`], then makes it
+    /// durable according to the default durability of the `DB`.
+    fn write<T>(
+        &self,
+        f: impl Fn(&Keyspace) -> Result<T, TransientError>,
+    ) -> Result<T, Box<dyn Error>> {
+        let res = self.with_keyspace(f)?;
+        self.db.persist(self.db.durability)?;
+        Ok(res)
+    }
+
+    /// Returns the name of the namespace.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the settings of the namespace.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped.
+    pub fn config(&self) -> Result<NamespaceConfig, Box<dyn Error>> {
+        Ok(self.db.resolve_namespace(&self.name)?.config.clone())
+    }
+
+    /// Sets a key-value pair with an optional Time-To-Live (TTL).
+    ///
+    /// If `ttl` is `None`, the default TTL of the namespace is used, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped or the transaction fails.
+    pub fn set(&self, key: &str, val: &str, ttl: Option<Duration>) -> Result<(), Box<dyn Error>> {
+        self.write(|ks| self.db.set_in(ks, key, val, ttl))
+    }
+
+    /// Retrieves the value for a given key.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped, the value cannot be
+    /// retrieved or it is not valid UTF-8.
+    pub fn get(&self, key: &str) -> Result<Option<String>, Box<dyn Error>> {
+        let ks = self.db.resolve_namespace(&self.name)?;
+        Ok(self.db.get_in(&ks, key)?)
+    }
+
+    /// Retrieves the value for a given key along with its version, see
+    /// [`DB::get_with_version`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped, the value cannot be
+    /// retrieved or it is not valid UTF-8.
+    pub fn get_with_version(&self, key: &str) -> Result<Option<(String, u64)>, Box<dyn Error>> {
+        let ks = self.db.resolve_namespace(&self.name)?;
+        Ok(self.db.get_with_version_in(&ks, key)?)
+    }
+
+    /// Sets a key-value pair if the version of the key is still
+    /// `expected_version`, returning its new version, see [`DB::set_if_version`].
+    ///
+    /// If `ttl` is `None`, the default TTL of the namespace is used, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TransientError::VersionConflict`] if the key was written,
+    /// removed or created since its version was read, or an error if the
+    /// namespace was dropped or the transaction fails.
+    pub fn set_if_version(
+        &self,
+        key: &str,
+        val: &str,
+        ttl: Option<Duration>,
+        expected_version: u64,
+    ) -> Result<u64, Box<dyn Error>> {
+        self.write(|ks| {
+            self.db
+                .set_if_version_in(ks, key, val, ttl, expected_version)
+        })
+    }
+
+    /// Sets a key to the content of `reader`, see [`DB::set_reader`].
+    ///
+    /// If `ttl` is `None`, the default TTL of the namespace is used, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped or cleared while the
+    /// content was read, `reader` fails, the content is not valid UTF-8 or
+    /// the transaction fails.
+    pub fn set_reader(
+        &self,
+        key: &str,
+        reader: impl Read,
+        ttl: Option<Duration>,
+    ) -> Result<(), Box<dyn Error>> {
+        // NOTE: The content can only be read once, so a clear racing the write isn't retried.
+        let ks = self.db.resolve_namespace(&self.name)?;
+        self.db.set_reader_in(&ks, key, reader, ttl)?;
+        Ok(self.db.persist(self.db.durability)?)
+    }
+
+    /// Returns a reader streaming the value of a key, see [`DB::get_reader`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped or the value cannot be retrieved.
+    pub fn get_reader(&self, key: &str) -> Result<Option<ValueReader>, Box<dyn Error>> {
+        let ks = self.db.resolve_namespace(&self.name)?;
+        Ok(self.db.get_reader_in(&ks, key)?)
+    }
+
+    /// Atomically increments the frequency counter for a given key.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped or the key does not exist.
+    pub fn increment_frequency(&self, key: &str) -> Result<(), Box<dyn Error>> {
+        self.write(|ks| self.db.increment_frequency_in(ks, key))
+    }
+
+    /// Removes a key-value pair and its associated metadata from the namespace.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped or the transaction fails.
+    pub fn remove(&self, key: &str) -> Result<(), Box<dyn Error>> {
+        self.write(|ks| self.db.remove_in(ks, key))
+    }
+
+    /// Retrieves the metadata for a given key.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped or the metadata cannot be
+    /// retrieved or deserialized.
+    pub fn get_metadata(&self, key: &str) -> Result<Option<Metadata>, Box<dyn Error>> {
+        let ks = self.db.resolve_namespace(&self.name)?;
+        Ok(self.db.get_metadata_in(&ks, key)?)
+    }
+
+    /// Replaces the tags of a key, returning false if the key doesn't exist,
+    /// see [`DB::set_tags`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped, the tags are invalid or
+    /// the transaction fails.
+    pub fn set_tags(&self, key: &str, tags: &[&str]) -> Result<bool, Box<dyn Error>> {
+        self.write(|ks| self.db.set_tags_in(ks, key, tags))
+    }
+
+    /// Returns the keys carrying `tag`, see [`DB::keys_with_tag`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped or the tag index cannot be read.
+    pub fn keys_with_tag(&self, tag: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(self.with_keyspace(|ks| self.db.keys_with_tag_in(ks, tag))?)
+    }
+
+    /// Removes every key carrying `tag` in a single transaction, see [`DB::remove_by_tag`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped, the tag index cannot be
+    /// read or the transaction fails.
+    pub fn remove_by_tag(&self, tag: &str) -> Result<usize, Box<dyn Error>> {
+        self.write(|ks| self.db.remove_by_tag_in(ks, tag))
+    }
+
+    /// Sets many key-value pairs in a single transaction, see [`DB::mset`].
+    ///
+    /// Pairs without a TTL get the default TTL of the namespace, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped or the transaction fails.
+    pub fn mset(&self, items: &[(&str, &str, Option<Duration>)]) -> Result<(), Box<dyn Error>> {
+        self.write(|ks| self.db.mset_in(ks, items))
+    }
+
+    /// Retrieves the values and metadata of many keys, see [`DB::mget`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped or the values cannot be retrieved.
+    pub fn mget(&self, keys: &[&str]) -> Result<Vec<Option<Entry>>, Box<dyn Error>> {
+        let ks = self.db.resolve_namespace(&self.name)?;
+        Ok(self.db.mget_in(&ks, keys)?)
+    }
+
+    /// Removes many keys in a single transaction, see [`DB::mremove`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped or the transaction fails.
+    pub fn mremove(&self, keys: &[&str]) -> Result<usize, Box<dyn Error>> {
+        self.write(|ks| self.db.mremove_in(ks, keys))
+    }
+
+    /// Atomically removes every key of the namespace, keeping its settings.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped or its trees cannot be replaced.
+    pub fn clear(&self) -> Result<(), Box<dyn Error>> {
+        if !self.db.retire_namespace(&self.name, false, true)? {
+            Err(TransientError::NamespaceNotFound)?
+        }
+        Ok(self.db.persist(self.db.durability)?)
+    }
+
+    /// Returns the `k` most frequently accessed keys of the namespace, see
+    /// [`DB::top_k_by_frequency`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped or its index cannot be read.
+    pub fn top_k_by_frequency(&self, k: usize) -> Result<Vec<(String, u64)>, Box<dyn Error>> {
+        let ks = self.db.resolve_namespace(&self.name)?;
+        Ok(self.db.rank_in(&ks, k, true)?)
+    }
+
+    /// Returns the `k` least frequently accessed keys of the namespace, see
+    /// [`DB::coldest`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped or its index cannot be read.
+    pub fn coldest(&self, k: usize) -> Result<Vec<(String, u64)>, Box<dyn Error>> {
+        let ks = self.db.resolve_namespace(&self.name)?;
+        Ok(self.db.rank_in(&ks, k, false)?)
+    }
+
+    /// Returns the statistics of the namespace.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped.
+    pub fn stats(&self) -> Result<NamespaceStats, Box<dyn Error>> {
+        let ks = self.db.resolve_namespace(&self.name)?;
+        Ok(NamespaceStats::collect(&ks))
+    }
+}
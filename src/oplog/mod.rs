@@ -0,0 +1,155 @@
+//! The `oplog` module defines the ordered mutation log of the database.
+//!
+//! Every mutation applied to a `DB` (`set`, `remove`, frequency updates, TTL
+//! expirations and namespace changes) is appended to the `oplog_tree` inside the same sled
+//! transaction that performs it. Entries are keyed by a monotonically
+//! increasing sequence number, so iterating the tree yields the exact order in
+//! which the mutations were committed.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bincode::{
+    error::{DecodeError, EncodeError},
+    serde::{decode_from_slice, encode_to_vec},
+};
+use serde::{Deserialize, Serialize};
+use sled::transaction::{ConflictableTransactionError, TransactionalTree};
+
+use crate::{Metadata, audit, namespace::NamespaceConfig};
+
+/// Key in the `state_tree` holding the sequence number of the last appended entry.
+pub(crate) const OPLOG_SEQ_KEY: &[u8] = b"oplog_seq";
+
+/// Key in the `state_tree` holding the last sequence number applied from a primary.
+pub(crate) const REPLICATED_SEQ_KEY: &[u8] = b"replicated_seq";
+
+/// A single change applied to the database.
+///
+/// Mutations carry the full resulting state of the key instead of a delta, so
+/// applying the same mutation twice is harmless.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum Mutation {
+    /// The key was written with the given value and metadata.
+    Set {
+        /// The key that was written.
+        key: Vec<u8>,
+        /// The new value of the key.
+        value: Vec<u8>,
+        /// The metadata of the key after the write.
+        #[serde(with = "crate::metadata::envelope")]
+        meta: Metadata,
+    },
+    /// The metadata of the key changed while its value stayed the same.
+    Meta {
+        /// The key whose metadata changed.
+        key: Vec<u8>,
+        /// The metadata of the key after the change.
+        #[serde(with = "crate::metadata::envelope")]
+        meta: Metadata,
+    },
+    /// The key was removed by the caller.
+    Remove {
+        /// The key that was removed.
+        key: Vec<u8>,
+    },
+    /// The key was removed by the TTL thread because its TTL elapsed.
+    Expire {
+        /// The key that expired.
+        key: Vec<u8>,
+    },
+    /// The key was removed by the eviction policy of its namespace.
+    Evict {
+        /// The key that was evicted.
+        key: Vec<u8>,
+    },
+    /// Every key of the namespace was removed.
+    Clear,
+    /// The namespace was dropped.
+    DropNamespace,
+    /// The settings of the namespace were replaced.
+    Configure {
+        /// The new settings of the namespace.
+        config: NamespaceConfig,
+    },
+}
+
+/// An entry of the mutation log.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct OpLogEntry {
+    /// The position of the entry in the log, starting at 1.
+    pub seq: u64,
+    /// Timestamp of the mutation, in seconds since the UNIX epoch
+    pub timestamp: u64,
+    /// The namespace the mutation applies to, `None` for the default keyspace.
+    pub namespace: Option<String>,
+    /// The actor set with [`act_as`](crate::audit::act_as) by the thread
+    /// that made the mutation.
+    pub actor: Option<String>,
+    /// The mutation itself.
+    pub mutation: Mutation,
+}
+
+impl OpLogEntry {
+    /// Serializes the `OpLogEntry` instance into a byte vector using `bincode`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `EncodeError` if serialization fails.
+    pub fn to_u8(&self) -> Result<Vec<u8>, EncodeError> {
+        encode_to_vec(self, bincode::config::standard())
+    }
+
+    /// Deserializes an `OpLogEntry` instance from a byte slice using `bincode`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `DecodeError` if deserialization fails.
+    pub fn from_u8(slice: &[u8]) -> Result<OpLogEntry, DecodeError> {
+        Ok(decode_from_slice(slice, bincode::config::standard())?.0)
+    }
+}
+
+/// Decodes a sequence number stored as big endian bytes, defaulting to 0 when absent.
+pub(crate) fn read_seq(bytes: Option<&[u8]>) -> u64 {
+    match bytes.and_then(|b| <[u8; 8]>::try_from(b).ok()) {
+        Some(b) => u64::from_be_bytes(b),
+        None => 0,
+    }
+}
+
+/// Appends `mutation` to the log as part of an ongoing transaction.
+///
+/// The sequence counter lives in the `state_tree`, so every transaction that
+/// appends to the log conflicts with every other one and sled serializes them.
+/// This is what guarantees that sequence order matches commit order.
+pub(crate) fn append<E>(
+    oplog: &TransactionalTree,
+    state: &TransactionalTree,
+    namespace: Option<&str>,
+    mutation: Mutation,
+    abort: impl Fn() -> E,
+) -> Result<u64, ConflictableTransactionError<E>> {
+    let seq = read_seq(state.get(OPLOG_SEQ_KEY)?.as_deref()) + 1;
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Cant get SystemTime")
+        .as_secs();
+
+    let entry = OpLogEntry {
+        seq,
+        timestamp,
+        namespace: namespace.map(str::to_string),
+        actor: audit::current_actor(),
+        mutation,
+    };
+
+    oplog.insert(
+        &seq.to_be_bytes(),
+        entry
+            .to_u8()
+            .map_err(|_| ConflictableTransactionError::Abort(abort()))?,
+    )?;
+    state.insert(OPLOG_SEQ_KEY, &seq.to_be_bytes())?;
+
+    Ok(seq)
+}
@@ -0,0 +1,330 @@
+//! The `ratelimit` module implements rate limiters keeping their state in a
+//! namespace of a `DB`, so the limits hold across restarts, see [`RateLimiter`].
+//!
+//! Every limited key has its state stored under the same key in the
+//! namespace, read, checked and written back in a single transaction, so
+//! concurrent checks of the same key never consume more than the limit. The
+//! state is written with the TTL after which it is the same as no state at
+//! all, so the background thread removes the state of the keys that stopped
+//! being used.
+
+use std::{
+    cmp::Reverse,
+    error::Error,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use sled::transaction::ConflictableTransactionError;
+
+use crate::{
+    DB, Metadata,
+    db::{
+        errors::TransientError,
+        keyspace::{Keyspace, KeyspaceTx},
+    },
+    namespace::Namespace,
+};
+
+/// The limit a [`RateLimiter`] enforces on a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    /// Allows bursts of up to `capacity` requests, refilled by one every
+    /// `refill_every`.
+    TokenBucket {
+        /// The largest number of requests allowed at once
+        capacity: u64,
+        /// How long it takes for one more request to be allowed
+        refill_every: Duration,
+    },
+    /// Allows at most `max` requests in any `window`, keeping the time of
+    /// every request made in the last `window`.
+    SlidingWindow {
+        /// The largest number of requests allowed in a window
+        max: u64,
+        /// The length of the window
+        window: Duration,
+    },
+}
+
+/// The outcome of a check, returned by [`RateLimiter::check`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decision {
+    /// Whether the requests are allowed, in which case they were counted
+    pub allowed: bool,
+    /// How many more requests are allowed right now
+    pub remaining: u64,
+    /// How long until the requests would be allowed, `None` if they are
+    /// allowed or never will be, because they exceed the limit on their own
+    pub retry_after: Option<Duration>,
+}
+
+/// Limits the rate of requests per key, with limits configured per key prefix.
+///
+/// ```no_run
+/// use epoch_db::{DB, ratelimit::{Limit, RateLimiter}};
+/// use std::{path::Path, time::Duration};
+///
+/// let db = DB::new(Path::new("./my_database")).unwrap();
+/// let limiter = RateLimiter::new(
+///     db.namespace("ratelimit").unwrap(),
+///     Limit::TokenBucket { capacity: 10, refill_every: Duration::from_secs(6) },
+/// )
+/// .limit_prefix("login:", Limit::SlidingWindow { max: 5, window: Duration::from_secs(60) });
+///
+/// if !limiter.check("login:alice").unwrap().allowed {
+///     println!("Too many attempts");
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct RateLimiter<'a> {
+    namespace: Namespace<'a>,
+    default: Limit,
+    /// The limits of the keys starting with a prefix, the longest prefix first
+    prefixes: Vec<(String, Limit)>,
+}
+
+impl<'a> RateLimiter<'a> {
+    /// Creates a limiter applying `default` to every key, keeping its state
+    /// in `namespace`.
+    ///
+    /// The namespace should only hold the state of rate limiters, since the
+    /// state of a key is stored under the key itself.
+    pub fn new(namespace: Namespace<'a>, default: Limit) -> RateLimiter<'a> {
+        RateLimiter {
+            namespace,
+            default,
+            prefixes: Vec::new(),
+        }
+    }
+
+    /// Applies `limit` to the keys starting with `prefix` instead of the
+    /// default one. Keys matching several prefixes get the limit of the longest.
+    pub fn limit_prefix(mut self, prefix: &str, limit: Limit) -> RateLimiter<'a> {
+        self.prefixes.retain(|(p, _)| p != prefix);
+        self.prefixes.push((prefix.to_string(), limit));
+        self.prefixes.sort_by_key(|(prefix, _)| Reverse(prefix.len()));
+        self
+    }
+
+    /// Returns the limit applied to `key`.
+    pub fn limit_of(&self, key: &str) -> Limit {
+        self.prefixes
+            .iter()
+            .find(|(prefix, _)| key.starts_with(prefix.as_str()))
+            .map_or(self.default, |(_, limit)| *limit)
+    }
+
+    /// Counts one request of `key` if its limit allows it, see [`RateLimiter::check_n`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped or the transaction fails.
+    pub fn check(&self, key: &str) -> Result<Decision, Box<dyn Error>> {
+        self.check_n(key, 1)
+    }
+
+    /// Counts `n` requests of `key` if its limit allows all of them at once,
+    /// leaving its state untouched otherwise.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped or the transaction fails.
+    pub fn check_n(&self, key: &str, n: u64) -> Result<Decision, Box<dyn Error>> {
+        let limit = self.limit_of(key);
+        let db = self.namespace.db;
+        let decision = self
+            .namespace
+            .with_keyspace(|ks| db.consume_in(ks, key, limit, n))?;
+        if decision.allowed {
+            db.persist(db.durability)?;
+        }
+        Ok(decision)
+    }
+
+    /// Forgets the requests of `key`, so its whole limit is available again.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace was dropped or the transaction fails.
+    pub fn reset(&self, key: &str) -> Result<(), Box<dyn Error>> {
+        let db = self.namespace.db;
+        self.namespace
+            .with_keyspace(|ks| db.transact(ks, |tx| tx.delete(key.as_bytes())))?;
+        Ok(db.persist(db.durability)?)
+    }
+}
+
+/// The state of a limited key, as stored in its value.
+#[derive(Debug)]
+enum State {
+    /// `tb {tokens} {refilled_at}`, the tokens left when last refilled, in
+    /// milliseconds since the UNIX epoch
+    TokenBucket { tokens: u64, refilled_at: u64 },
+    /// `sw {at}...`, the time of every request of the window, oldest first
+    SlidingWindow { log: Vec<u64> },
+}
+
+impl State {
+    fn parse(value: &[u8]) -> Option<State> {
+        let mut fields = std::str::from_utf8(value).ok()?.split(' ');
+        match fields.next()? {
+            "tb" => Some(State::TokenBucket {
+                tokens: fields.next()?.parse().ok()?,
+                refilled_at: fields.next()?.parse().ok()?,
+            }),
+            "sw" => Some(State::SlidingWindow {
+                log: fields.map(str::parse).collect::<Result<_, _>>().ok()?,
+            }),
+            _ => None,
+        }
+    }
+
+    fn to_value(&self) -> String {
+        match self {
+            State::TokenBucket {
+                tokens,
+                refilled_at,
+            } => format!("tb {tokens} {refilled_at}"),
+            State::SlidingWindow { log } => log.iter().fold("sw".to_string(), |mut value, at| {
+                value.push(' ');
+                value.push_str(&at.to_string());
+                value
+            }),
+        }
+    }
+}
+
+/// Applies `n` requests at `now`, in milliseconds since the UNIX epoch, to
+/// `state`, returning the decision and the state to write back if they are
+/// allowed, along with how long until it is the same as no state at all.
+fn apply(limit: Limit, state: Option<State>, now: u64, n: u64) -> (Decision, Option<(State, u64)>) {
+    let denied = |remaining, retry_after: Option<u64>| Decision {
+        allowed: false,
+        remaining,
+        retry_after: retry_after.map(Duration::from_millis),
+    };
+
+    match limit {
+        Limit::TokenBucket {
+            capacity,
+            refill_every,
+        } => {
+            let every = (refill_every.as_millis() as u64).max(1);
+            let (mut tokens, mut refilled_at) = match state {
+                Some(State::TokenBucket {
+                    tokens,
+                    refilled_at,
+                }) => (tokens.min(capacity), refilled_at.min(now)),
+                _ => (capacity, now),
+            };
+            let refills = (now - refilled_at) / every;
+            tokens = tokens.saturating_add(refills).min(capacity);
+            // NOTE: The time since the last refill counts towards the next one.
+            refilled_at = if tokens == capacity {
+                now
+            } else {
+                refilled_at + refills * every
+            };
+
+            if tokens < n {
+                let wait = (n <= capacity).then(|| (n - tokens) * every - (now - refilled_at));
+                return (denied(tokens, wait), None);
+            }
+            tokens -= n;
+            let full_in = (capacity - tokens) * every - (now - refilled_at);
+            let decision = Decision {
+                allowed: true,
+                remaining: tokens,
+                retry_after: None,
+            };
+            let state = State::TokenBucket {
+                tokens,
+                refilled_at,
+            };
+            (decision, Some((state, full_in)))
+        }
+        Limit::SlidingWindow { max, window } => {
+            let window = window.as_millis() as u64;
+            let mut log = match state {
+                Some(State::SlidingWindow { log }) => log,
+                _ => Vec::new(),
+            };
+            log.retain(|at| at + window > now);
+
+            let used = log.len() as u64;
+            if used + n > max {
+                // NOTE: The requests fit once the oldest ones in excess leave the window.
+                let wait = (n <= max).then(|| log[(used + n - max - 1) as usize] + window - now);
+                return (denied(max.saturating_sub(used), wait), None);
+            }
+            log.extend((0..n).map(|_| now));
+            let decision = Decision {
+                allowed: true,
+                remaining: max - used - n,
+                retry_after: None,
+            };
+            (decision, Some((State::SlidingWindow { log }, window)))
+        }
+    }
+}
+
+impl DB {
+    /// Counts `n` requests of `key` of the given keyspace if `limit` allows
+    /// them, see [`RateLimiter::check_n`].
+    pub(crate) fn consume_in(
+        &self,
+        ks: &Keyspace,
+        key: &str,
+        limit: Limit,
+        n: u64,
+    ) -> Result<Decision, TransientError> {
+        let byte = key.as_bytes();
+        self.transact(ks, |tx| {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("Cant get SystemTime")
+                .as_millis() as u64;
+            let state = self.limiter_state(tx, byte, now)?;
+            let (decision, state) = apply(limit, state, now, n);
+
+            if let Some((state, expires_in)) = state {
+                let value = state.to_value();
+                let stored = self
+                    .codec
+                    .encode(byte, value.as_bytes())
+                    .map_err(ConflictableTransactionError::Abort)?;
+                // NOTE: TTLs are in whole seconds, keep the state until the next one.
+                let ttl = Duration::from_millis(expires_in) + Duration::from_secs(1);
+                tx.write(byte, &stored, value.len(), ks.deadline(Some(ttl)))?;
+            }
+            Ok(decision)
+        })
+    }
+
+    /// Returns the state of the limited key `byte`, `None` if it has none,
+    /// its TTL elapsed or it cannot be parsed.
+    fn limiter_state(
+        &self,
+        tx: &KeyspaceTx,
+        byte: &[u8],
+        now: u64,
+    ) -> Result<Option<State>, ConflictableTransactionError<TransientError>> {
+        let abort = ConflictableTransactionError::Abort;
+
+        let Some(meta) = tx.freq.get(byte)? else {
+            return Ok(None);
+        };
+        let meta =
+            Metadata::from_u8(&meta).map_err(|_| abort(TransientError::ParsingFromByteError))?;
+        // NOTE: The background thread may not have removed an expired state yet.
+        if meta.ttl.is_some_and(|t| t * 1000 <= now) {
+            return Ok(None);
+        }
+
+        let Some(stored) = tx.data.get(byte)? else {
+            return Ok(None);
+        };
+        let value = self.codec.decode(byte, &stored).map_err(abort)?;
+        Ok(State::parse(&value))
+    }
+}
@@ -0,0 +1,367 @@
+//! This module replays Redis AOF command logs.
+//!
+//! Commands are replayed in memory, the way Redis loads the log, and only
+//! the final state of every key is written to the database. The commands
+//! acting on strings and on keys in general are replayed. A key touched by
+//! any other command holds a type the database cannot store, so it is
+//! reported with the name of the command instead.
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, Read},
+};
+
+use crate::{
+    db::errors::TransientError,
+    redis::{
+        SkipReason,
+        rdb::{RdbParser, malformed},
+    },
+};
+
+/// The value of a key once the log is replayed.
+#[derive(Debug, Clone)]
+pub(crate) struct AofKey {
+    /// The value, `Err` holding why it cannot be imported
+    pub(crate) value: Result<Vec<u8>, SkipReason>,
+    /// The expiration time of the key, in milliseconds since the UNIX epoch
+    pub(crate) expires_at: Option<u64>,
+}
+
+/// The keys of every Redis database, by database.
+pub(crate) type Databases = HashMap<u64, HashMap<Vec<u8>, AofKey>>;
+
+/// Replays a log in memory.
+pub(crate) struct AofReplay {
+    pub(crate) databases: Databases,
+    database: u64,
+    /// The current time, in milliseconds since the UNIX epoch, which
+    /// relative expirations are counted from
+    now: u64,
+}
+
+impl AofReplay {
+    pub(crate) fn new(now: u64) -> AofReplay {
+        AofReplay {
+            databases: HashMap::new(),
+            database: 0,
+            now,
+        }
+    }
+
+    /// Replays the log read from `reader`, which may start with an RDB
+    /// preamble holding the state the commands apply to.
+    pub(crate) fn replay(&mut self, mut reader: impl BufRead) -> Result<(), TransientError> {
+        if reader.fill_buf().map_err(io_err)?.starts_with(b"REDIS") {
+            let mut parser = RdbParser::new(reader)?;
+            while let Some(entry) = parser.next_entry()? {
+                self.db(entry.database).insert(
+                    entry.key,
+                    AofKey {
+                        value: entry.value.map_err(SkipReason::UnsupportedType),
+                        expires_at: entry.expires_at,
+                    },
+                );
+            }
+            reader = parser.into_inner();
+        }
+
+        while let Some(args) = read_command(&mut reader)? {
+            self.apply(args)?;
+        }
+        Ok(())
+    }
+
+    fn db(&mut self, database: u64) -> &mut HashMap<Vec<u8>, AofKey> {
+        self.databases.entry(database).or_default()
+    }
+
+    /// Applies a single command.
+    fn apply(&mut self, args: Vec<Vec<u8>>) -> Result<(), TransientError> {
+        let name = String::from_utf8_lossy(&args[0]).to_uppercase();
+        let arity = |n: usize| {
+            if args.len() < n {
+                Err(malformed(&format!("{name} is missing arguments")))
+            } else {
+                Ok(())
+            }
+        };
+        let now = self.now;
+        let database = self.database;
+
+        match name.as_str() {
+            "SELECT" => {
+                arity(2)?;
+                self.database = parse_u64(&args[1])?;
+            }
+            "MULTI" | "EXEC" | "PING" | "SCRIPT" | "FUNCTION" => {}
+            "SET" => {
+                arity(3)?;
+                self.set(&args[1], &args[2], &args[3..])?;
+            }
+            "SETNX" | "MSETNX" => {
+                arity(3)?;
+                let db = self.db(database);
+                if args[1..].chunks(2).all(|kv| !db.contains_key(&kv[0])) {
+                    for kv in args[1..].chunks_exact(2) {
+                        db.insert(kv[0].clone(), string(kv[1].clone(), None));
+                    }
+                }
+            }
+            "MSET" => {
+                arity(3)?;
+                let db = self.db(database);
+                for kv in args[1..].chunks_exact(2) {
+                    db.insert(kv[0].clone(), string(kv[1].clone(), None));
+                }
+            }
+            "SETEX" | "PSETEX" => {
+                arity(4)?;
+                let ttl = parse_u64(&args[2])?;
+                let ttl = if name == "SETEX" { ttl * 1000 } else { ttl };
+                let value = string(args[3].clone(), Some(now + ttl));
+                self.db(database).insert(args[1].clone(), value);
+            }
+            "GETSET" => {
+                arity(3)?;
+                let value = string(args[2].clone(), None);
+                self.db(database).insert(args[1].clone(), value);
+            }
+            "APPEND" => {
+                arity(3)?;
+                let entry = self
+                    .db(database)
+                    .entry(args[1].clone())
+                    .or_insert_with(|| string(Vec::new(), None));
+                if let Ok(value) = &mut entry.value {
+                    value.extend_from_slice(&args[2]);
+                }
+            }
+            "INCR" | "DECR" | "INCRBY" | "DECRBY" => {
+                arity(if name.ends_with("BY") { 3 } else { 2 })?;
+                let by = match args.get(2) {
+                    Some(by) => parse_i64(by)?,
+                    None => 1,
+                };
+                let by = if name.starts_with("DECR") { -by } else { by };
+                let entry = self
+                    .db(database)
+                    .entry(args[1].clone())
+                    .or_insert_with(|| string(b"0".to_vec(), None));
+                if let Ok(value) = &mut entry.value {
+                    *value = (parse_i64(value)? + by).to_string().into_bytes();
+                }
+            }
+            "GETDEL" | "DEL" | "UNLINK" => {
+                arity(2)?;
+                let db = self.db(database);
+                for key in &args[1..] {
+                    db.remove(key);
+                }
+            }
+            "EXPIRE" | "PEXPIRE" | "EXPIREAT" | "PEXPIREAT" => {
+                arity(3)?;
+                let t = parse_u64(&args[2])?;
+                let expires_at = match name.as_str() {
+                    "EXPIRE" => now + t * 1000,
+                    "PEXPIRE" => now + t,
+                    "EXPIREAT" => t * 1000,
+                    _ => t,
+                };
+                if let Some(key) = self.db(database).get_mut(&args[1]) {
+                    key.expires_at = Some(expires_at);
+                }
+            }
+            "PERSIST" => {
+                arity(2)?;
+                if let Some(key) = self.db(database).get_mut(&args[1]) {
+                    key.expires_at = None;
+                }
+            }
+            "RENAME" | "RENAMENX" => {
+                arity(3)?;
+                let db = self.db(database);
+                if name == "RENAMENX" && db.contains_key(&args[2]) {
+                    return Ok(());
+                }
+                if let Some(key) = db.remove(&args[1]) {
+                    db.insert(args[2].clone(), key);
+                }
+            }
+            "MOVE" => {
+                arity(3)?;
+                let target = parse_u64(&args[2])?;
+                if self.db(target).contains_key(&args[1]) {
+                    return Ok(());
+                }
+                if let Some(key) = self.db(database).remove(&args[1]) {
+                    self.db(target).insert(args[1].clone(), key);
+                }
+            }
+            "COPY" => {
+                arity(3)?;
+                let mut target = database;
+                let mut replace = false;
+                let mut options = args[3..].iter();
+                while let Some(option) = options.next() {
+                    match option.to_ascii_uppercase().as_slice() {
+                        b"DB" => {
+                            target = parse_u64(
+                                options
+                                    .next()
+                                    .ok_or_else(|| malformed("COPY DB without a database"))?,
+                            )?
+                        }
+                        b"REPLACE" => replace = true,
+                        _ => Err(malformed("unknown COPY option"))?,
+                    }
+                }
+                let Some(key) = self.db(database).get(&args[1]).cloned() else {
+                    return Ok(());
+                };
+                let target = self.db(target);
+                if replace || !target.contains_key(&args[2]) {
+                    target.insert(args[2].clone(), key);
+                }
+            }
+            "SWAPDB" => {
+                arity(3)?;
+                let (a, b) = (parse_u64(&args[1])?, parse_u64(&args[2])?);
+                let first = self.databases.remove(&a);
+                let second = self.databases.remove(&b);
+                if let Some(first) = first {
+                    self.databases.insert(b, first);
+                }
+                if let Some(second) = second {
+                    self.databases.insert(a, second);
+                }
+            }
+            "FLUSHDB" => {
+                self.databases.remove(&database);
+            }
+            "FLUSHALL" => self.databases.clear(),
+            _ if args.len() >= 2 => {
+                self.db(database)
+                    .entry(args[1].clone())
+                    .or_insert_with(|| AofKey {
+                        value: Err(SkipReason::UnsupportedCommand(name)),
+                        expires_at: None,
+                    });
+            }
+            _ => Err(malformed(&format!("unsupported command {name}")))?,
+        }
+        Ok(())
+    }
+
+    /// Applies `SET key value [options]`.
+    fn set(&mut self, key: &[u8], value: &[u8], options: &[Vec<u8>]) -> Result<(), TransientError> {
+        let now = self.now;
+        let database = self.database;
+        let existing = self.db(database).get(key).and_then(|k| k.expires_at);
+        let exists = self.db(database).contains_key(key);
+
+        let mut expires_at = None;
+        let mut options = options.iter();
+        while let Some(option) = options.next() {
+            let mut arg = || -> Result<u64, TransientError> {
+                parse_u64(
+                    options
+                        .next()
+                        .ok_or_else(|| malformed("SET option without a value"))?,
+                )
+            };
+            match option.to_ascii_uppercase().as_slice() {
+                b"NX" if exists => return Ok(()),
+                b"XX" if !exists => return Ok(()),
+                b"NX" | b"XX" | b"GET" => {}
+                b"EX" => expires_at = Some(now + arg()? * 1000),
+                b"PX" => expires_at = Some(now + arg()?),
+                b"EXAT" => expires_at = Some(arg()? * 1000),
+                b"PXAT" => expires_at = Some(arg()?),
+                b"KEEPTTL" => expires_at = existing,
+                _ => Err(malformed("unknown SET option"))?,
+            }
+        }
+
+        self.db(database)
+            .insert(key.to_vec(), string(value.to_vec(), expires_at));
+        Ok(())
+    }
+}
+
+fn string(value: Vec<u8>, expires_at: Option<u64>) -> AofKey {
+    AofKey {
+        value: Ok(value),
+        expires_at,
+    }
+}
+
+/// Reads the next command of the log, skipping the annotations Redis adds to
+/// it, or returns `None` at its end.
+fn read_command(reader: &mut impl BufRead) -> Result<Option<Vec<Vec<u8>>>, TransientError> {
+    let count = loop {
+        let Some(line) = read_line(reader)? else {
+            return Ok(None);
+        };
+        match line.first() {
+            Some(b'#') => continue,
+            Some(b'*') => break parse_u64(&line[1..])?,
+            _ => Err(malformed("expected a command"))?,
+        }
+    };
+    if count == 0 {
+        Err(malformed("empty command"))?
+    }
+
+    let mut args = Vec::new();
+    for _ in 0..count {
+        let line = read_line(reader)?.ok_or_else(|| malformed("truncated command"))?;
+        let Some(len) = line.strip_prefix(b"$") else {
+            Err(malformed("expected a bulk string"))?
+        };
+        let len = parse_u64(len)? as usize;
+
+        let mut arg = Vec::new();
+        reader
+            .take(len as u64 + 2)
+            .read_to_end(&mut arg)
+            .map_err(io_err)?;
+        if arg.len() != len + 2 || !arg.ends_with(b"\r\n") {
+            Err(malformed("truncated command"))?
+        }
+        arg.truncate(len);
+        args.push(arg);
+    }
+    Ok(Some(args))
+}
+
+/// Reads a line ending with `\r\n`, without it.
+fn read_line(reader: &mut impl BufRead) -> Result<Option<Vec<u8>>, TransientError> {
+    let mut line = Vec::new();
+    if reader.read_until(b'\n', &mut line).map_err(io_err)? == 0 {
+        return Ok(None);
+    }
+    if !line.ends_with(b"\r\n") {
+        Err(malformed("truncated command"))?
+    }
+    line.truncate(line.len() - 2);
+    Ok(Some(line))
+}
+
+fn parse_u64(bytes: &[u8]) -> Result<u64, TransientError> {
+    std::str::from_utf8(bytes)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| malformed("expected an integer"))
+}
+
+fn parse_i64(bytes: &[u8]) -> Result<i64, TransientError> {
+    std::str::from_utf8(bytes)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| malformed("expected an integer"))
+}
+
+fn io_err(error: std::io::Error) -> TransientError {
+    TransientError::IOError { error }
+}
@@ -0,0 +1,260 @@
+//! The `redis` module imports the keys of a Redis server from its RDB
+//! snapshot, see [`DB::import_redis_rdb`], or its AOF command log, see
+//! [`DB::import_redis_aof`], to migrate a Redis cache to EpochDB.
+//!
+//! Only strings can be stored in the database. Keys holding lists, sets,
+//! sorted sets, hashes, streams or module types are skipped and reported
+//! in the [`RedisImportReport`], as are keys or values that aren't valid
+//! UTF-8. Redis expirations become the TTL of the keys.
+
+pub(crate) mod aof;
+pub(crate) mod rdb;
+
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::BufReader,
+    path::Path,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use aof::AofReplay;
+use rdb::{RdbParser, malformed};
+
+use crate::{
+    DB,
+    db::{errors::TransientError, keyspace::Keyspace},
+};
+
+/// Settings of [`DB::import_redis_rdb`] and [`DB::import_redis_aof`].
+#[derive(Debug, Clone, Default)]
+pub struct RedisImportOptions {
+    pub(crate) database: u64,
+    pub(crate) namespace: Option<String>,
+}
+
+impl RedisImportOptions {
+    /// Imports the keys of Redis database 0 into the default keyspace.
+    pub fn new() -> RedisImportOptions {
+        RedisImportOptions::default()
+    }
+
+    /// Imports the keys of the Redis database `index`, as chosen with `SELECT`.
+    pub fn database(mut self, index: u64) -> RedisImportOptions {
+        self.database = index;
+        self
+    }
+
+    /// Imports the keys into the namespace `name`, creating it if it doesn't exist.
+    pub fn namespace(mut self, name: &str) -> RedisImportOptions {
+        self.namespace = Some(name.to_string());
+        self
+    }
+}
+
+/// Why a Redis key wasn't imported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SkipReason {
+    /// The key holds a type other than a string, such as `hash`.
+    UnsupportedType(&'static str),
+    /// The key was written by a command that doesn't act on strings, such as `HSET`.
+    UnsupportedCommand(String),
+    /// The key or its value is not valid UTF-8.
+    NotUtf8,
+}
+
+/// A Redis key that wasn't imported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedKey {
+    /// The key, with the bytes that aren't valid UTF-8 replaced
+    pub key: String,
+    /// Why the key was skipped
+    pub reason: SkipReason,
+}
+
+/// The outcome of a Redis import.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RedisImportReport {
+    /// The number of keys written
+    pub imported: u64,
+    /// The number of keys skipped because their expiration time has passed
+    pub expired: u64,
+    /// The keys skipped because the database cannot store them
+    pub skipped: Vec<SkippedKey>,
+}
+
+impl DB {
+    /// Imports the string keys of a Redis RDB snapshot, such as `dump.rdb`.
+    ///
+    /// The snapshot is read one key at a time, and each key is written in
+    /// its own transaction as it is read, replacing the key if it exists.
+    /// Keys whose expiration time has passed are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read, is not a snapshot, was
+    /// written by a newer version of Redis or is corrupted, in which case the
+    /// keys before the error stay imported, or a key cannot be written.
+    pub fn import_redis_rdb(
+        &self,
+        path: &Path,
+        options: &RedisImportOptions,
+    ) -> Result<RedisImportReport, Box<dyn Error>> {
+        let file = File::open(path)?;
+        let ks = self.redis_keyspace(options)?;
+        let now = now_ms();
+
+        let mut report = RedisImportReport::default();
+        let mut parser = RdbParser::new(BufReader::new(file))?;
+        while let Some(entry) = parser.next_entry()? {
+            if entry.database == options.database {
+                let value = entry.value.map_err(SkipReason::UnsupportedType);
+                self.import_redis_key(&ks, entry.key, value, entry.expires_at, now, &mut report)?;
+            }
+        }
+
+        self.persist(self.durability)?;
+        Ok(report)
+    }
+
+    /// Imports the string keys of a Redis AOF command log.
+    ///
+    /// `path` is either a single log, such as the `appendonly.aof` written by
+    /// Redis before 7.0, which may start with an RDB preamble, or the
+    /// `appendonlydir` directory of Redis 7.0 and later, whose manifest lists
+    /// the base and incremental files to replay.
+    ///
+    /// The commands are replayed in memory and the resulting keys are then
+    /// written one at a time, replacing the keys that exist. Expirations set
+    /// with a relative time are counted from the import. Keys whose
+    /// expiration time has passed are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a file cannot be read, the manifest lists no
+    /// file, or a command or the preamble is malformed, in which case nothing
+    /// is imported, or a key cannot be written.
+    pub fn import_redis_aof(
+        &self,
+        path: &Path,
+        options: &RedisImportOptions,
+    ) -> Result<RedisImportReport, Box<dyn Error>> {
+        let ks = self.redis_keyspace(options)?;
+        let now = now_ms();
+
+        let mut replay = AofReplay::new(now);
+        for file in aof_files(path)? {
+            replay.replay(BufReader::new(File::open(file)?))?;
+        }
+
+        let mut report = RedisImportReport::default();
+        let keys = replay
+            .databases
+            .remove(&options.database)
+            .unwrap_or_default();
+        for (key, entry) in keys {
+            self.import_redis_key(&ks, key, entry.value, entry.expires_at, now, &mut report)?;
+        }
+
+        self.persist(self.durability)?;
+        Ok(report)
+    }
+
+    /// Returns the keyspace the keys are imported into.
+    fn redis_keyspace(
+        &self,
+        options: &RedisImportOptions,
+    ) -> Result<Arc<Keyspace>, TransientError> {
+        match &options.namespace {
+            None => Ok(Arc::clone(&self.keyspace)),
+            Some(name) => self.open_namespace(name, None, true),
+        }
+    }
+
+    /// Writes a Redis key to `ks`, or records in `report` why it is skipped.
+    fn import_redis_key(
+        &self,
+        ks: &Keyspace,
+        key: Vec<u8>,
+        value: Result<Vec<u8>, SkipReason>,
+        expires_at: Option<u64>,
+        now: u64,
+        report: &mut RedisImportReport,
+    ) -> Result<(), TransientError> {
+        if expires_at.is_some_and(|t| t <= now) {
+            report.expired += 1;
+            return Ok(());
+        }
+
+        let skip = |key: &[u8], reason| SkippedKey {
+            key: String::from_utf8_lossy(key).into_owned(),
+            reason,
+        };
+        let value = match value {
+            Ok(value) => value,
+            Err(reason) => {
+                report.skipped.push(skip(&key, reason));
+                return Ok(());
+            }
+        };
+        let (Ok(key_str), Ok(value)) = (std::str::from_utf8(&key), String::from_utf8(value)) else {
+            report.skipped.push(skip(&key, SkipReason::NotUtf8));
+            return Ok(());
+        };
+
+        // NOTE: Redis expires keys to the millisecond, the database to the second.
+        let ttl_sec = expires_at.map(|t| t.div_ceil(1000));
+        self.restore_in(ks, key_str, &value, ttl_sec, |_| {})?;
+        report.imported += 1;
+        Ok(())
+    }
+}
+
+/// Returns the logs to replay, in order, for `path`.
+fn aof_files(path: &Path) -> Result<Vec<std::path::PathBuf>, TransientError> {
+    let io_err = |error| TransientError::IOError { error };
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut manifest = None;
+    for entry in fs::read_dir(path).map_err(io_err)? {
+        let entry = entry.map_err(io_err)?.path();
+        if entry.extension().is_some_and(|e| e == "manifest") {
+            manifest = Some(entry);
+        }
+    }
+    let manifest = manifest.ok_or_else(|| malformed("no manifest in the AOF directory"))?;
+
+    // NOTE: Each line is `file <name> seq <n> type <b|h|i>`, the base file first and the
+    // incremental ones in order. History files are already merged into the base file.
+    let mut files = Vec::new();
+    for line in fs::read_to_string(&manifest).map_err(io_err)?.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let field = |name| {
+            fields
+                .chunks_exact(2)
+                .find(|f| f[0] == name)
+                .map(|f| f[1].trim_matches('"'))
+        };
+        match (field("file"), field("type")) {
+            (Some(_), Some("h")) => {}
+            (Some(file), Some(_)) => files.push(path.join(file)),
+            _ if line.trim().is_empty() => {}
+            _ => Err(malformed("malformed AOF manifest"))?,
+        }
+    }
+    if files.is_empty() {
+        Err(malformed("AOF manifest lists no file"))?
+    }
+    Ok(files)
+}
+
+/// Returns the current time, in milliseconds since the UNIX epoch.
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Cant get SystemTime")
+        .as_millis() as u64
+}
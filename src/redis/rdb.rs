@@ -0,0 +1,424 @@
+//! This module parses Redis RDB snapshots.
+//!
+//! Only string values are decoded. Every other type is skipped so the
+//! snapshot can be read to the end, and reported by the name of its type.
+
+use std::io::Read;
+
+use crate::db::errors::TransientError;
+
+/// The newest RDB version the parser knows the layout of.
+const MAX_RDB_VERSION: u32 = 12;
+
+const OPCODE_SLOT_INFO: u8 = 0xF4;
+const OPCODE_FUNCTION2: u8 = 0xF5;
+const OPCODE_FUNCTION_PRE_GA: u8 = 0xF6;
+const OPCODE_MODULE_AUX: u8 = 0xF7;
+const OPCODE_IDLE: u8 = 0xF8;
+const OPCODE_FREQ: u8 = 0xF9;
+const OPCODE_AUX: u8 = 0xFA;
+const OPCODE_RESIZEDB: u8 = 0xFB;
+const OPCODE_EXPIRETIME_MS: u8 = 0xFC;
+const OPCODE_EXPIRETIME: u8 = 0xFD;
+const OPCODE_SELECTDB: u8 = 0xFE;
+const OPCODE_EOF: u8 = 0xFF;
+
+/// A key of a snapshot.
+#[derive(Debug)]
+pub(crate) struct RdbEntry {
+    /// The Redis database holding the key
+    pub(crate) database: u64,
+    pub(crate) key: Vec<u8>,
+    /// The value, `Err` holding the name of its type if it isn't a string
+    pub(crate) value: Result<Vec<u8>, &'static str>,
+    /// The expiration time of the key, in milliseconds since the UNIX epoch
+    pub(crate) expires_at: Option<u64>,
+}
+
+/// A length, or a string stored as an integer.
+enum Length {
+    Len(u64),
+    Encoded(u8),
+}
+
+/// Reads the keys of a snapshot one at a time.
+pub(crate) struct RdbParser<R: Read> {
+    reader: R,
+    version: u32,
+    database: u64,
+    /// CRC-64 of the bytes read so far
+    crc: u64,
+    done: bool,
+}
+
+impl<R: Read> RdbParser<R> {
+    /// Starts reading a snapshot from `reader`, checking its header.
+    pub(crate) fn new(reader: R) -> Result<RdbParser<R>, TransientError> {
+        let mut parser = RdbParser {
+            reader,
+            version: 0,
+            database: 0,
+            crc: 0,
+            done: false,
+        };
+        let header = parser.read_bytes(9)?;
+        let version = std::str::from_utf8(&header[5..])
+            .ok()
+            .and_then(|v| v.parse().ok());
+        match (&header[..5], version) {
+            (b"REDIS", Some(version)) if version <= MAX_RDB_VERSION => parser.version = version,
+            (b"REDIS", _) => Err(malformed("unsupported RDB version"))?,
+            _ => Err(malformed("not an RDB file"))?,
+        }
+        Ok(parser)
+    }
+
+    /// Returns the reader, positioned after the end of the snapshot once
+    /// [`RdbParser::next_entry`] returned `None`.
+    pub(crate) fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Returns the next key of the snapshot, or `None` at its end.
+    pub(crate) fn next_entry(&mut self) -> Result<Option<RdbEntry>, TransientError> {
+        if self.done {
+            return Ok(None);
+        }
+
+        let mut expires_at = None;
+        loop {
+            let opcode = self.read_u8()?;
+            match opcode {
+                OPCODE_EOF => {
+                    self.check_crc()?;
+                    self.done = true;
+                    return Ok(None);
+                }
+                OPCODE_SELECTDB => self.database = self.read_len()?,
+                OPCODE_RESIZEDB => {
+                    self.read_len()?;
+                    self.read_len()?;
+                }
+                OPCODE_AUX => {
+                    self.read_string()?;
+                    self.read_string()?;
+                }
+                OPCODE_EXPIRETIME_MS => expires_at = Some(self.read_u64_le()?),
+                OPCODE_EXPIRETIME => expires_at = Some(self.read_u32_le()? as u64 * 1000),
+                OPCODE_IDLE => {
+                    self.read_len()?;
+                }
+                OPCODE_FREQ => {
+                    self.read_u8()?;
+                }
+                OPCODE_MODULE_AUX => {
+                    self.read_len()?;
+                    self.read_len()?;
+                    self.read_len()?;
+                    self.skip_module_value()?;
+                }
+                OPCODE_FUNCTION2 => {
+                    self.read_string()?;
+                }
+                OPCODE_SLOT_INFO => {
+                    self.read_len()?;
+                    self.read_len()?;
+                    self.read_len()?;
+                }
+                OPCODE_FUNCTION_PRE_GA => Err(malformed("unsupported function opcode"))?,
+                kind => {
+                    let key = self.read_string()?;
+                    let value = match kind {
+                        0 => Ok(self.read_string()?),
+                        _ => Err(self.skip_value(kind)?),
+                    };
+                    return Ok(Some(RdbEntry {
+                        database: self.database,
+                        key,
+                        value,
+                        expires_at,
+                    }));
+                }
+            }
+        }
+    }
+
+    /// Skips a value of type `kind`, returning the name of the type.
+    fn skip_value(&mut self, kind: u8) -> Result<&'static str, TransientError> {
+        match kind {
+            // Lists and sets of strings
+            1 | 2 => {
+                for _ in 0..self.read_len()? {
+                    self.read_string()?;
+                }
+            }
+            // Sorted sets with scores as strings
+            3 => {
+                for _ in 0..self.read_len()? {
+                    self.read_string()?;
+                    let len = self.read_u8()?;
+                    if len < 253 {
+                        self.read_bytes(len as usize)?;
+                    }
+                }
+            }
+            // Hashes
+            4 => {
+                for _ in 0..self.read_len()? * 2 {
+                    self.read_string()?;
+                }
+            }
+            // Sorted sets with binary scores
+            5 => {
+                for _ in 0..self.read_len()? {
+                    self.read_string()?;
+                    self.read_bytes(8)?;
+                }
+            }
+            7 => {
+                self.read_len()?;
+                self.skip_module_value()?;
+            }
+            // Zipmaps, ziplists, intsets and listpacks are stored as a single string
+            9..=13 | 16 | 17 | 20 => {
+                self.read_string()?;
+            }
+            // Quicklists of ziplists
+            14 => {
+                for _ in 0..self.read_len()? {
+                    self.read_string()?;
+                }
+            }
+            // Quicklists of listpacks
+            18 => {
+                for _ in 0..self.read_len()? {
+                    self.read_len()?;
+                    self.read_string()?;
+                }
+            }
+            15 | 19 | 21 => self.skip_stream(kind)?,
+            // Hashes with field expirations
+            24 => {
+                self.read_bytes(8)?;
+                for _ in 0..self.read_len()? {
+                    self.read_len()?;
+                    self.read_string()?;
+                    self.read_string()?;
+                }
+            }
+            25 => {
+                self.read_bytes(8)?;
+                self.read_string()?;
+            }
+            _ => Err(malformed("value type without a known layout"))?,
+        }
+
+        Ok(match kind {
+            1 | 10 | 14 | 18 => "list",
+            2 | 11 | 20 => "set",
+            3 | 5 | 12 | 17 => "zset",
+            4 | 9 | 13 | 16 | 24 | 25 => "hash",
+            15 | 19 | 21 => "stream",
+            _ => "module",
+        })
+    }
+
+    /// Skips a stream, its consumer groups and their pending entries.
+    fn skip_stream(&mut self, kind: u8) -> Result<(), TransientError> {
+        for _ in 0..self.read_len()? {
+            self.read_string()?;
+            self.read_string()?;
+        }
+        // Length and last id, then the first id, the max deleted id and the
+        // number of entries added since version 2
+        let lens = if kind >= 19 { 8 } else { 3 };
+        for _ in 0..lens {
+            self.read_len()?;
+        }
+
+        for _ in 0..self.read_len()? {
+            self.read_string()?;
+            self.read_len()?;
+            self.read_len()?;
+            if kind >= 19 {
+                self.read_len()?;
+            }
+            for _ in 0..self.read_len()? {
+                self.read_bytes(16 + 8)?;
+                self.read_len()?;
+            }
+            for _ in 0..self.read_len()? {
+                self.read_string()?;
+                self.read_bytes(if kind >= 21 { 16 } else { 8 })?;
+                for _ in 0..self.read_len()? {
+                    self.read_bytes(16)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Skips the opcodes of a module value up to its end.
+    fn skip_module_value(&mut self) -> Result<(), TransientError> {
+        loop {
+            match self.read_len()? {
+                0 => return Ok(()),
+                1 | 2 => {
+                    self.read_len()?;
+                }
+                3 => {
+                    self.read_bytes(4)?;
+                }
+                4 => {
+                    self.read_bytes(8)?;
+                }
+                5 => {
+                    self.read_string()?;
+                }
+                _ => Err(malformed("unknown module opcode"))?,
+            }
+        }
+    }
+
+    /// Checks the checksum at the end of the snapshot, which is 0 if Redis
+    /// was configured not to compute it.
+    fn check_crc(&mut self) -> Result<(), TransientError> {
+        if self.version < 5 {
+            return Ok(());
+        }
+        let expected = self.crc;
+        let crc = self.read_u64_le()?;
+        if crc != 0 && crc != expected {
+            Err(malformed("checksum mismatch"))?
+        }
+        Ok(())
+    }
+
+    fn read_length(&mut self) -> Result<Length, TransientError> {
+        let first = self.read_u8()?;
+        Ok(match first >> 6 {
+            0 => Length::Len((first & 0x3F) as u64),
+            1 => Length::Len((((first & 0x3F) as u64) << 8) | self.read_u8()? as u64),
+            2 if first == 0x80 => {
+                let bytes = self.read_bytes(4)?;
+                Length::Len(u32::from_be_bytes(bytes.try_into().expect("4 bytes")) as u64)
+            }
+            2 if first == 0x81 => {
+                let bytes = self.read_bytes(8)?;
+                Length::Len(u64::from_be_bytes(bytes.try_into().expect("8 bytes")))
+            }
+            2 => Err(malformed("unknown length encoding"))?,
+            _ => Length::Encoded(first & 0x3F),
+        })
+    }
+
+    fn read_len(&mut self) -> Result<u64, TransientError> {
+        match self.read_length()? {
+            Length::Len(len) => Ok(len),
+            Length::Encoded(_) => Err(malformed("expected a length")),
+        }
+    }
+
+    /// Reads a string, which may be stored as an integer or compressed with LZF.
+    fn read_string(&mut self) -> Result<Vec<u8>, TransientError> {
+        match self.read_length()? {
+            Length::Len(len) => self.read_bytes(len as usize),
+            Length::Encoded(0) => Ok((self.read_u8()? as i8).to_string().into_bytes()),
+            Length::Encoded(1) => {
+                let bytes = self.read_bytes(2)?;
+                let n = i16::from_le_bytes(bytes.try_into().expect("2 bytes"));
+                Ok(n.to_string().into_bytes())
+            }
+            Length::Encoded(2) => Ok((self.read_u32_le()? as i32).to_string().into_bytes()),
+            Length::Encoded(3) => {
+                let compressed = self.read_len()? as usize;
+                let len = self.read_len()? as usize;
+                let data = self.read_bytes(compressed)?;
+                lzf_decompress(&data, len)
+            }
+            Length::Encoded(_) => Err(malformed("unknown string encoding")),
+        }
+    }
+
+    fn read_u8(&mut self) -> Result<u8, TransientError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u32_le(&mut self) -> Result<u32, TransientError> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().expect("4 bytes")))
+    }
+
+    fn read_u64_le(&mut self) -> Result<u64, TransientError> {
+        let bytes = self.read_bytes(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().expect("8 bytes")))
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<Vec<u8>, TransientError> {
+        let mut buf = Vec::new();
+        (&mut self.reader)
+            .take(len as u64)
+            .read_to_end(&mut buf)
+            .map_err(|error| TransientError::IOError { error })?;
+        if buf.len() != len {
+            Err(malformed("unexpected end of file"))?
+        }
+        self.crc = crc64(self.crc, &buf);
+        Ok(buf)
+    }
+}
+
+/// Decompresses LZF `data` into `len` bytes.
+fn lzf_decompress(data: &[u8], len: usize) -> Result<Vec<u8>, TransientError> {
+    let corrupted = || malformed("corrupted LZF string");
+    let mut out = Vec::with_capacity(len);
+    let mut i = 0;
+    while i < data.len() {
+        let ctrl = data[i] as usize;
+        i += 1;
+        if ctrl < 32 {
+            let run = data.get(i..i + ctrl + 1).ok_or_else(corrupted)?;
+            out.extend_from_slice(run);
+            i += ctrl + 1;
+        } else {
+            let mut run = ctrl >> 5;
+            if run == 7 {
+                run += *data.get(i).ok_or_else(corrupted)? as usize;
+                i += 1;
+            }
+            let offset = ((ctrl & 0x1F) << 8) + *data.get(i).ok_or_else(corrupted)? as usize + 1;
+            i += 1;
+            let start = out.len().checked_sub(offset).ok_or_else(corrupted)?;
+            // NOTE: The reference may overlap the bytes it produces, so it is copied byte by byte.
+            for j in start..start + run + 2 {
+                out.push(out[j]);
+            }
+        }
+    }
+    if out.len() != len {
+        Err(corrupted())?
+    }
+    Ok(out)
+}
+
+/// Updates `crc` with `data`, using the CRC-64/Jones variant Redis checksums snapshots with.
+pub(crate) fn crc64(mut crc: u64, data: &[u8]) -> u64 {
+    const POLY: u64 = 0x95AC_9329_AC4B_C9B5;
+    for &byte in data {
+        crc ^= byte as u64;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+pub(crate) fn malformed(reason: &str) -> TransientError {
+    TransientError::MalformedRedisFile {
+        reason: reason.to_string(),
+    }
+}
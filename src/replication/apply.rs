@@ -0,0 +1,245 @@
+//! Applies snapshots and mutation log entries received from a primary to a
+//! follower `DB`.
+
+use std::{collections::BTreeSet, error::Error, sync::Arc};
+
+use sled::transaction::{ConflictableTransactionError, TransactionError, Transactional};
+
+use crate::{
+    DB, Metadata,
+    db::{
+        blob::remove_chunks,
+        errors::TransientError,
+        keyspace::{Keyspace, reindex, retag},
+    },
+    namespace::NamespaceConfig,
+    oplog::{Mutation, OpLogEntry, REPLICATED_SEQ_KEY, read_seq},
+};
+
+impl DB {
+    /// Returns the last sequence number of the primary's mutation log that
+    /// was applied to this database.
+    ///
+    /// Returns `None` if this database has never completed a bootstrap from a primary.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the replication state cannot be read.
+    pub fn replicated_seq(&self) -> Result<Option<u64>, Box<dyn Error>> {
+        Ok(self.read_replicated_seq()?)
+    }
+
+    /// Reads the last sequence number applied from a primary.
+    pub(crate) fn read_replicated_seq(&self) -> Result<Option<u64>, TransientError> {
+        Ok(self
+            .state_tree
+            .get(REPLICATED_SEQ_KEY)
+            .map_err(|e| TransientError::SledError { error: e })?
+            .map(|seq_byte| read_seq(Some(&seq_byte))))
+    }
+
+    /// Returns the keyspace a replicated change applies to, creating the
+    /// namespace with default settings if this follower doesn't have it yet.
+    fn replica_keyspace(&self, namespace: Option<&str>) -> Result<Arc<Keyspace>, TransientError> {
+        match namespace {
+            None => Ok(Arc::clone(&self.keyspace)),
+            Some(name) => self.open_namespace(name, None, false),
+        }
+    }
+
+    /// Discards every key and namespace so a snapshot can be loaded.
+    pub(crate) fn clear_replica(&self) -> Result<(), TransientError> {
+        self.check_writable()?;
+        let sled_err = |e| TransientError::SledError { error: e };
+
+        self.state_tree
+            .remove(REPLICATED_SEQ_KEY)
+            .map_err(sled_err)?;
+        self.keyspace.data_tree.clear().map_err(sled_err)?;
+        self.keyspace.meta_tree.clear().map_err(sled_err)?;
+        self.keyspace.ttl_tree.clear().map_err(sled_err)?;
+        self.keyspace.index_tree.clear().map_err(sled_err)?;
+        self.keyspace.tag_tree.clear().map_err(sled_err)?;
+        self.keyspace.blob_tree.clear().map_err(sled_err)?;
+
+        for name in self.namespaces() {
+            self.retire_namespace(&name, true, false)?;
+        }
+        Ok(())
+    }
+
+    /// Creates the namespace `name` of a snapshot with the settings it has on the primary.
+    pub(crate) fn apply_snapshot_namespace(
+        &self,
+        name: &str,
+        config: NamespaceConfig,
+    ) -> Result<(), TransientError> {
+        self.open_namespace(name, Some(config), false)?;
+        Ok(())
+    }
+
+    /// Loads a single key of a snapshot.
+    pub(crate) fn apply_snapshot_entry(
+        &self,
+        namespace: Option<&str>,
+        key: &[u8],
+        value: &[u8],
+        meta: &Metadata,
+    ) -> Result<(), TransientError> {
+        let ks = self.replica_keyspace(namespace)?;
+        let meta_byte = meta
+            .to_u8()
+            .map_err(|_| TransientError::ParsingToByteError)?;
+        let value = self.store_value(&ks, key, value)?;
+
+        let l: Result<(), TransactionError<()>> = (
+            &*ks.data_tree,
+            &*ks.meta_tree,
+            &*ks.ttl_tree,
+            &*ks.index_tree,
+            &*ks.tag_tree,
+            &*ks.blob_tree,
+        )
+            .transaction(|(data, freq, ttl_tree, index, tags, blobs)| {
+                if let Some(old) = data.insert(key, value.bytes.as_slice())? {
+                    remove_chunks(blobs, &old)?;
+                }
+                let old = match freq.insert(key, meta_byte.as_slice())? {
+                    Some(old) => Some(
+                        Metadata::from_u8(&old)
+                            .map_err(|_| ConflictableTransactionError::Abort(()))?,
+                    ),
+                    None => None,
+                };
+                reindex(index, key, old.as_ref().map(|m| m.freq), Some(meta.freq))?;
+                retag(
+                    tags,
+                    key,
+                    old.as_ref().map_or(&BTreeSet::new(), |m| &m.tags),
+                    &meta.tags,
+                )?;
+                if let Some(t) = meta.ttl {
+                    ttl_tree.insert([&t.to_be_bytes()[..], key].concat(), key)?;
+                }
+                Ok(())
+            });
+        if l.is_err() {
+            value.discard(&ks);
+        }
+        l.map_err(|_| TransientError::SledTransactionError)
+    }
+
+    /// Records that everything up to `seq` of the primary's log was applied.
+    pub(crate) fn set_replicated_seq(&self, seq: u64) -> Result<(), TransientError> {
+        self.check_writable()?;
+        self.state_tree
+            .insert(REPLICATED_SEQ_KEY, &seq.to_be_bytes())
+            .map_err(|e| TransientError::SledError { error: e })?;
+        Ok(())
+    }
+
+    /// Applies a single mutation log entry and records its sequence number in
+    /// the same transaction.
+    pub(crate) fn apply_entry(&self, entry: &OpLogEntry) -> Result<(), TransientError> {
+        self.check_writable()?;
+        let namespace = entry.namespace.as_deref();
+
+        let (key, value, meta) = match &entry.mutation {
+            Mutation::Set { key, value, meta } => (key, Some(value), Some(meta)),
+            Mutation::Meta { key, meta } => (key, None, Some(meta)),
+            Mutation::Remove { key } | Mutation::Expire { key } | Mutation::Evict { key } => {
+                (key, None, None)
+            }
+            Mutation::Clear | Mutation::DropNamespace => {
+                if let Some(name) = namespace {
+                    let drop = entry.mutation == Mutation::DropNamespace;
+                    self.retire_namespace(name, drop, false)?;
+                }
+                return self.set_replicated_seq(entry.seq);
+            }
+            Mutation::Configure { config } => {
+                if let Some(name) = namespace {
+                    self.open_namespace(name, Some(config.clone()), false)?;
+                }
+                return self.set_replicated_seq(entry.seq);
+            }
+        };
+        let ks = self.replica_keyspace(namespace)?;
+        // NOTE: Values are sent decoded, they are stored with the codec and chunk size of
+        // this database.
+        let value = value
+            .map(|v| self.store_value(&ks, key, v.as_slice()))
+            .transpose()?;
+
+        let l: Result<(), TransactionError<()>> = (
+            &*ks.data_tree,
+            &*ks.meta_tree,
+            &*ks.ttl_tree,
+            &*ks.index_tree,
+            &*ks.tag_tree,
+            &*ks.blob_tree,
+            &*self.state_tree,
+        )
+            .transaction(|(data, freq, ttl_tree, index, tags, blobs, state)| {
+                // NOTE: A metadata change for a key this follower doesn't hold can only come
+                // from the overlap between a snapshot and the log, a later entry settles it.
+                let skip = value.is_none() && meta.is_some() && data.get(key)?.is_none();
+
+                if !skip {
+                    let mut old_freq = None;
+                    let mut old_tags = BTreeSet::new();
+                    if let Some(old) = freq.get(key)? {
+                        let old = Metadata::from_u8(&old)
+                            .map_err(|_| ConflictableTransactionError::Abort(()))?;
+                        if let Some(t) = old.ttl {
+                            ttl_tree.remove([&t.to_be_bytes()[..], key].concat())?;
+                        }
+                        old_freq = Some(old.freq);
+                        old_tags = old.tags;
+                    }
+                    reindex(index, key, old_freq, meta.map(|m| m.freq))?;
+                    retag(
+                        tags,
+                        key,
+                        &old_tags,
+                        meta.map_or(&BTreeSet::new(), |m| &m.tags),
+                    )?;
+
+                    match meta {
+                        Some(meta) => {
+                            freq.insert(
+                                key.as_slice(),
+                                meta.to_u8()
+                                    .map_err(|_| ConflictableTransactionError::Abort(()))?,
+                            )?;
+                            if let Some(t) = meta.ttl {
+                                ttl_tree
+                                    .insert([&t.to_be_bytes()[..], key].concat(), key.as_slice())?;
+                            }
+                        }
+                        None => {
+                            freq.remove(key.as_slice())?;
+                            if let Some(old) = data.remove(key.as_slice())? {
+                                remove_chunks(blobs, &old)?;
+                            }
+                        }
+                    }
+
+                    if let Some(value) = &value
+                        && let Some(old) = data.insert(key.as_slice(), value.bytes.as_slice())?
+                    {
+                        remove_chunks(blobs, &old)?;
+                    }
+                }
+
+                state.insert(REPLICATED_SEQ_KEY, &entry.seq.to_be_bytes())?;
+                Ok(())
+            });
+        if l.is_err()
+            && let Some(value) = &value
+        {
+            value.discard(&ks);
+        }
+        l.map_err(|_| TransientError::SledTransactionError)
+    }
+}
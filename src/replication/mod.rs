@@ -0,0 +1,521 @@
+//! The `replication` module ships the mutation log of a primary `DB` to
+//! read-only followers over TCP.
+//!
+//! A [`ReplicationPrimary`] listens for followers and streams every entry of
+//! its mutation log to them in commit order. A [`ReplicationFollower`]
+//! connects to a primary and applies those entries to its own `DB`, keeping the
+//! exact TTL deadlines and frequencies of the primary. A follower that has never
+//! been bootstrapped, or that fell further behind than the primary's
+//! `oplog_retention`, is sent a full snapshot first.
+//!
+//! Followers should be treated as read-only, writing to them directly makes
+//! them diverge from the primary until the next bootstrap.
+
+mod apply;
+pub(crate) mod protocol;
+
+use std::{
+    collections::HashMap,
+    io::BufReader,
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use protocol::{Message, PROTOCOL_VERSION, read_message, write_message};
+
+use crate::{DB, Metadata, db::errors::TransientError};
+
+/// How long the primary waits for new log entries before checking for shutdown.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long the primary waits before polling the mutation log for new entries.
+const TAIL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long a follower waits before reconnecting to its primary.
+const RECONNECT_DELAY: Duration = Duration::from_millis(200);
+
+/// The replication state of a follower connected to a [`ReplicationPrimary`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct FollowerStatus {
+    /// The address the follower connected from.
+    pub addr: SocketAddr,
+    /// The last sequence number the follower acknowledged.
+    pub acked_seq: u64,
+    /// How many log entries the follower is behind the primary.
+    pub lag: u64,
+    /// How many snapshots were sent to the follower on this connection.
+    pub bootstraps: u64,
+}
+
+/// Bookkeeping for a single follower connection.
+#[derive(Debug)]
+struct Session {
+    addr: SocketAddr,
+    /// Kept to unblock the session threads when the primary shuts down
+    stream: TcpStream,
+    acked_seq: Arc<AtomicU64>,
+    bootstraps: Arc<AtomicU64>,
+}
+
+/// Serves the mutation log of a `DB` to followers.
+///
+/// When this struct is dropped, every follower connection is closed and the
+/// background threads are joined.
+#[derive(Debug)]
+pub struct ReplicationPrimary {
+    db: Arc<DB>,
+    local_addr: SocketAddr,
+    sessions: Arc<Mutex<HashMap<u64, Session>>>,
+    workers: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    acceptor: Option<JoinHandle<()>>,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl ReplicationPrimary {
+    /// Starts listening for followers on `addr`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the listener cannot be bound to `addr`.
+    pub fn start(
+        db: Arc<DB>,
+        addr: impl ToSocketAddrs,
+    ) -> Result<ReplicationPrimary, TransientError> {
+        let io_err = |e| TransientError::IOError { error: e };
+
+        let listener = TcpListener::bind(addr).map_err(io_err)?;
+        listener.set_nonblocking(true).map_err(io_err)?;
+        let local_addr = listener.local_addr().map_err(io_err)?;
+
+        let sessions: Arc<Mutex<HashMap<u64, Session>>> = Arc::new(Mutex::new(HashMap::new()));
+        let workers: Arc<Mutex<Vec<JoinHandle<()>>>> = Arc::new(Mutex::new(Vec::new()));
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let db_clone = Arc::clone(&db);
+        let sessions_clone = Arc::clone(&sessions);
+        let workers_clone = Arc::clone(&workers);
+        let shutdown_clone = Arc::clone(&shutdown);
+
+        let acceptor = thread::spawn(move || {
+            let mut next_id = 0;
+            while !shutdown_clone.load(Ordering::SeqCst) {
+                let (stream, addr) = match listener.accept() {
+                    Ok(conn) => conn,
+                    Err(_) => {
+                        thread::sleep(POLL_INTERVAL);
+                        continue;
+                    }
+                };
+
+                let Ok(stream_clone) = stream.try_clone() else {
+                    continue;
+                };
+                let _ = stream.set_nonblocking(false);
+                let _ = stream.set_nodelay(true);
+
+                next_id += 1;
+                let session = Session {
+                    addr,
+                    stream: stream_clone,
+                    acked_seq: Arc::new(AtomicU64::new(0)),
+                    bootstraps: Arc::new(AtomicU64::new(0)),
+                };
+                let acked_seq = Arc::clone(&session.acked_seq);
+                let bootstraps = Arc::clone(&session.bootstraps);
+                sessions_clone
+                    .lock()
+                    .expect("Sessions lock poisoned")
+                    .insert(next_id, session);
+
+                let id = next_id;
+                let db = Arc::clone(&db_clone);
+                let sessions = Arc::clone(&sessions_clone);
+                let shutdown = Arc::clone(&shutdown_clone);
+                let worker = thread::spawn(move || {
+                    let _ = serve_follower(&db, stream, &acked_seq, &bootstraps, &shutdown);
+                    sessions.lock().expect("Sessions lock poisoned").remove(&id);
+                });
+
+                let mut workers = workers_clone.lock().expect("Workers lock poisoned");
+                workers.retain(|w| !w.is_finished());
+                workers.push(worker);
+            }
+        });
+
+        Ok(ReplicationPrimary {
+            db,
+            local_addr,
+            sessions,
+            workers,
+            acceptor: Some(acceptor),
+            shutdown,
+        })
+    }
+
+    /// Returns the address the primary is listening on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Returns the replication state of every connected follower.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the head of the mutation log cannot be read.
+    pub fn followers(&self) -> Result<Vec<FollowerStatus>, TransientError> {
+        let head = self.db.head_seq()?;
+
+        Ok(self
+            .sessions
+            .lock()
+            .expect("Sessions lock poisoned")
+            .values()
+            .map(|s| {
+                let acked_seq = s.acked_seq.load(Ordering::SeqCst);
+                FollowerStatus {
+                    addr: s.addr,
+                    acked_seq,
+                    lag: head.saturating_sub(acked_seq),
+                    bootstraps: s.bootstraps.load(Ordering::SeqCst),
+                }
+            })
+            .collect())
+    }
+}
+
+impl Drop for ReplicationPrimary {
+    /// Closes every follower connection and joins the background threads.
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+
+        if let Some(acceptor) = self.acceptor.take() {
+            let _ = acceptor.join();
+        }
+
+        for session in self
+            .sessions
+            .lock()
+            .expect("Sessions lock poisoned")
+            .values()
+        {
+            let _ = session.stream.shutdown(Shutdown::Both);
+        }
+
+        for worker in self
+            .workers
+            .lock()
+            .expect("Workers lock poisoned")
+            .drain(..)
+        {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Returns true if a follower at `seq` can't catch up from the retained log.
+fn needs_snapshot(db: &DB, seq: u64) -> Result<bool, TransientError> {
+    let head = db.head_seq()?;
+    if seq == head {
+        return Ok(false);
+    }
+    if seq > head {
+        // NOTE: The follower has seen entries this primary never wrote, so it followed
+        // another primary or this one was reset.
+        return Ok(true);
+    }
+
+    let first = db.tail_seq()?;
+    Ok(first.is_none_or(|f| f > seq + 1))
+}
+
+/// Sends a full snapshot and returns the sequence number it is consistent with.
+fn send_snapshot(db: &DB, stream: &mut TcpStream) -> Result<u64, TransientError> {
+    let sled_err = |e| TransientError::SledError { error: e };
+
+    // NOTE: The head is read before scanning, so every entry committed while the scan
+    // runs is streamed afterwards. Entries carry full state so replaying them is safe.
+    let seq = db.head_seq()?;
+
+    write_message(stream, &Message::SnapshotStart)?;
+
+    let mut keyspaces = vec![Arc::clone(&db.keyspace)];
+    keyspaces.extend(
+        db.namespaces
+            .read()
+            .expect("Namespaces lock poisoned")
+            .values()
+            .cloned(),
+    );
+
+    for ks in keyspaces {
+        if let Some(name) = &ks.name {
+            write_message(
+                stream,
+                &Message::SnapshotNamespace {
+                    name: name.clone(),
+                    config: ks.config.clone(),
+                },
+            )?;
+        }
+        for i in ks.meta_tree.iter() {
+            let (key, meta_byte) = i.map_err(sled_err)?;
+            let Some(value) = ks.data_tree.get(&key).map_err(sled_err)? else {
+                continue;
+            };
+            // NOTE: A value removed while the snapshot is sent is removed by a later entry.
+            let Some(value) = db.read_value(&ks, &key, &value)? else {
+                continue;
+            };
+            let meta =
+                Metadata::from_u8(&meta_byte).map_err(|_| TransientError::ParsingFromByteError)?;
+
+            write_message(
+                stream,
+                &Message::SnapshotEntry {
+                    namespace: ks.name.clone(),
+                    key: key.to_vec(),
+                    value,
+                    meta,
+                },
+            )?;
+        }
+    }
+    write_message(stream, &Message::SnapshotEnd { seq })?;
+
+    Ok(seq)
+}
+
+/// Runs a single follower connection until it fails or the primary shuts down.
+fn serve_follower(
+    db: &DB,
+    mut stream: TcpStream,
+    acked_seq: &AtomicU64,
+    bootstraps: &AtomicU64,
+    shutdown: &AtomicBool,
+) -> Result<(), TransientError> {
+    let io_err = |e| TransientError::IOError { error: e };
+
+    let applied_seq = match read_message(&mut stream)? {
+        Message::Hello {
+            version: PROTOCOL_VERSION,
+            applied_seq,
+        } => applied_seq,
+        _ => Err(TransientError::ReplicationProtocolError)?,
+    };
+
+    let mut cursor = match applied_seq {
+        Some(seq) if !needs_snapshot(db, seq)? => seq,
+        _ => {
+            bootstraps.fetch_add(1, Ordering::SeqCst);
+            send_snapshot(db, &mut stream)?
+        }
+    };
+    acked_seq.store(cursor, Ordering::SeqCst);
+
+    let mut ack_stream = stream.try_clone().map_err(io_err)?;
+    thread::scope(|scope| {
+        let ack_thread = scope.spawn(move || {
+            let mut reader = BufReader::new(&mut ack_stream);
+            while let Ok(Message::Ack { seq }) = read_message(&mut reader) {
+                acked_seq.store(seq, Ordering::SeqCst);
+            }
+        });
+
+        let res = stream_log(db, &mut stream, &mut cursor, bootstraps, shutdown);
+        let _ = stream.shutdown(Shutdown::Both);
+        let _ = ack_thread.join();
+        res
+    })
+}
+
+/// Streams new log entries to the follower, re-bootstrapping it when it falls behind.
+fn stream_log(
+    db: &DB,
+    stream: &mut TcpStream,
+    cursor: &mut u64,
+    bootstraps: &AtomicU64,
+    shutdown: &AtomicBool,
+) -> Result<(), TransientError> {
+    // NOTE: The log is polled rather than watched, since a sled subscriber that isn't
+    // drained fast enough blocks every writer once its channel is full.
+    while !shutdown.load(Ordering::SeqCst) {
+        if needs_snapshot(db, *cursor)? {
+            bootstraps.fetch_add(1, Ordering::SeqCst);
+            *cursor = send_snapshot(db, stream)?;
+        }
+
+        let mut sent = false;
+        for entry in db.changes_since(*cursor) {
+            let entry = entry?;
+            if entry.seq != *cursor + 1 {
+                // NOTE: The entries in between were trimmed while we were reading.
+                break;
+            }
+            *cursor = entry.seq;
+            write_message(stream, &Message::Entry(entry))?;
+            sent = true;
+        }
+
+        if !sent {
+            thread::sleep(TAIL_INTERVAL);
+        }
+    }
+
+    Ok(())
+}
+
+/// Keeps a `DB` in sync with a [`ReplicationPrimary`].
+///
+/// The follower reconnects automatically when the connection drops, resuming
+/// from the last entry it applied. When this struct is dropped, the connection
+/// is closed and the background thread is joined.
+#[derive(Debug)]
+pub struct ReplicationFollower {
+    db: Arc<DB>,
+    stream: Arc<Mutex<Option<TcpStream>>>,
+    connected: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl ReplicationFollower {
+    /// Starts replicating from the primary listening on `primary` into `db`.
+    ///
+    /// No snapshot of `db` can be taken until the follower is dropped, and
+    /// the follower waits for the live ones to be dropped before applying
+    /// anything.
+    pub fn start(db: Arc<DB>, primary: SocketAddr) -> ReplicationFollower {
+        db.add_follower();
+        let stream: Arc<Mutex<Option<TcpStream>>> = Arc::new(Mutex::new(None));
+        let connected = Arc::new(AtomicBool::new(false));
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let db_clone = Arc::clone(&db);
+        let stream_clone = Arc::clone(&stream);
+        let connected_clone = Arc::clone(&connected);
+        let shutdown_clone = Arc::clone(&shutdown);
+
+        let worker = thread::spawn(move || {
+            while !shutdown_clone.load(Ordering::SeqCst) {
+                if let Ok(conn) = TcpStream::connect_timeout(&primary, Duration::from_secs(1)) {
+                    let _ = follow(
+                        &db_clone,
+                        conn,
+                        &stream_clone,
+                        &connected_clone,
+                        &shutdown_clone,
+                    );
+                    connected_clone.store(false, Ordering::SeqCst);
+                }
+
+                if !shutdown_clone.load(Ordering::SeqCst) {
+                    thread::sleep(RECONNECT_DELAY);
+                }
+            }
+        });
+
+        ReplicationFollower {
+            db,
+            stream,
+            connected,
+            worker: Some(worker),
+            shutdown,
+        }
+    }
+
+    /// Returns true while the follower is connected to its primary.
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    /// Returns the last sequence number of the primary's log applied locally.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the replication state cannot be read.
+    pub fn applied_seq(&self) -> Result<Option<u64>, TransientError> {
+        self.db.read_replicated_seq()
+    }
+}
+
+impl Drop for ReplicationFollower {
+    /// Closes the connection to the primary and joins the background thread.
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+
+        if let Some(stream) = self.stream.lock().expect("Stream lock poisoned").take() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+        self.db.remove_follower();
+    }
+}
+
+/// Applies everything the primary sends until the connection fails.
+fn follow(
+    db: &DB,
+    mut stream: TcpStream,
+    shared_stream: &Mutex<Option<TcpStream>>,
+    connected: &AtomicBool,
+    shutdown: &AtomicBool,
+) -> Result<(), TransientError> {
+    let io_err = |e| TransientError::IOError { error: e };
+
+    let _ = stream.set_nodelay(true);
+    *shared_stream.lock().expect("Stream lock poisoned") =
+        Some(stream.try_clone().map_err(io_err)?);
+
+    // NOTE: Checked after publishing the stream, so a concurrent drop either sees the
+    // stream and shuts it down or we see the flag here.
+    if shutdown.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+    // NOTE: Applied changes keep no pre-images and a bootstrap drops trees, so they
+    // wait for the snapshots taken before this follower started to be dropped.
+    if db.has_live_snapshots() {
+        Err(TransientError::Replicating)?
+    }
+
+    let applied_seq = db.read_replicated_seq()?;
+    write_message(
+        &mut stream,
+        &Message::Hello {
+            version: PROTOCOL_VERSION,
+            applied_seq,
+        },
+    )?;
+    connected.store(true, Ordering::SeqCst);
+
+    let mut reader = BufReader::new(stream.try_clone().map_err(io_err)?);
+    loop {
+        match read_message(&mut reader)? {
+            Message::SnapshotStart => db.clear_replica()?,
+            Message::SnapshotNamespace { name, config } => {
+                db.apply_snapshot_namespace(&name, config)?
+            }
+            Message::SnapshotEntry {
+                namespace,
+                key,
+                value,
+                meta,
+            } => db.apply_snapshot_entry(namespace.as_deref(), &key, &value, &meta)?,
+            Message::SnapshotEnd { seq } => {
+                db.set_replicated_seq(seq)?;
+                write_message(&mut stream, &Message::Ack { seq })?;
+            }
+            Message::Entry(entry) => {
+                db.apply_entry(&entry)?;
+                write_message(&mut stream, &Message::Ack { seq: entry.seq })?;
+            }
+            _ => Err(TransientError::ReplicationProtocolError)?,
+        }
+    }
+}
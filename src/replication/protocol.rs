@@ -0,0 +1,83 @@
+//! The wire protocol spoken between a replication primary and its followers.
+//!
+//! Every message is sent as a frame made of a 4 byte big endian length
+//! followed by the `bincode` encoding of a [`Message`].
+
+use std::io::{Read, Write};
+
+use bincode::serde::{decode_from_slice, encode_to_vec};
+use serde::{Deserialize, Serialize};
+
+use crate::{Metadata, db::errors::TransientError, namespace::NamespaceConfig, oplog::OpLogEntry};
+
+/// The largest frame accepted from a peer, in bytes.
+const MAX_FRAME_LEN: usize = 256 * 1024 * 1024;
+
+/// The version of the protocol, bumped on every incompatible change of [`Message`].
+pub(crate) const PROTOCOL_VERSION: u32 = 2;
+
+/// A message exchanged over a replication connection.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub(crate) enum Message {
+    /// Sent by the follower when it connects, carrying the version of the
+    /// protocol it speaks and the last sequence number it applied. `None`
+    /// asks for a full snapshot.
+    Hello {
+        version: u32,
+        applied_seq: Option<u64>,
+    },
+    /// Sent by the follower once it has applied everything up to `seq`.
+    Ack { seq: u64 },
+    /// Tells the follower to discard its data and load a snapshot.
+    SnapshotStart,
+    /// A namespace of the snapshot with its settings, sent before its keys.
+    SnapshotNamespace {
+        name: String,
+        config: NamespaceConfig,
+    },
+    /// A single key of the snapshot.
+    SnapshotEntry {
+        namespace: Option<String>,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        #[serde(with = "crate::metadata::envelope")]
+        meta: Metadata,
+    },
+    /// Ends the snapshot, which is consistent with the log up to `seq`.
+    SnapshotEnd { seq: u64 },
+    /// A mutation log entry to apply.
+    Entry(OpLogEntry),
+}
+
+/// Writes `message` as a single frame.
+pub(crate) fn write_message<W: Write>(w: &mut W, message: &Message) -> Result<(), TransientError> {
+    let payload = encode_to_vec(message, bincode::config::standard())
+        .map_err(|_| TransientError::ParsingToByteError)?;
+    let len = u32::try_from(payload.len()).map_err(|_| TransientError::ParsingToByteError)?;
+
+    // NOTE: The length and payload go out in a single write so a frame never gets split
+    // into two tiny TCP segments.
+    w.write_all(&[&len.to_be_bytes()[..], &payload].concat())
+        .and_then(|_| w.flush())
+        .map_err(|e| TransientError::IOError { error: e })
+}
+
+/// Reads a single frame and decodes it.
+pub(crate) fn read_message<R: Read>(r: &mut R) -> Result<Message, TransientError> {
+    let mut len_byte = [0u8; 4];
+    r.read_exact(&mut len_byte)
+        .map_err(|e| TransientError::IOError { error: e })?;
+
+    let len = u32::from_be_bytes(len_byte) as usize;
+    if len > MAX_FRAME_LEN {
+        Err(TransientError::ReplicationProtocolError)?
+    }
+
+    let mut payload = vec![0u8; len];
+    r.read_exact(&mut payload)
+        .map_err(|e| TransientError::IOError { error: e })?;
+
+    Ok(decode_from_slice(&payload, bincode::config::standard())
+        .map_err(|_| TransientError::ParsingFromByteError)?
+        .0)
+}
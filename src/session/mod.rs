@@ -0,0 +1,438 @@
+//! The `session` module implements a store of web sessions kept in a
+//! namespace of a `DB`, see [`SessionStore`].
+//!
+//! A session is a key named after its ID whose value holds the session data,
+//! and whose TTL is the deadline of the session. Loading or saving a session
+//! pushes its deadline back to the end of the idle timeout, but never past the
+//! absolute timeout counted from its creation, so the background thread
+//! removes the sessions that timed out like any other key.
+//!
+//! With the `tower-sessions` feature, [`SessionStore`] also implements the
+//! `SessionStore` trait of `tower-sessions`, storing its records as JSON.
+
+#[cfg(feature = "tower-sessions")]
+mod tower;
+
+use std::{
+    error::Error,
+    fmt::Write as _,
+    sync::{Arc, atomic::Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bincode::serde::{decode_from_slice, encode_to_vec};
+use serde::{Serialize, de::DeserializeOwned};
+use sled::transaction::ConflictableTransactionError;
+
+use crate::{
+    DB, Metadata,
+    db::{
+        errors::TransientError,
+        keyspace::{Keyspace, KeyspaceTx},
+    },
+    namespace::Namespace,
+};
+
+/// Settings of a [`SessionStore`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionConfig {
+    namespace: String,
+    idle_timeout: Duration,
+    absolute_timeout: Option<Duration>,
+}
+
+impl Default for SessionConfig {
+    fn default() -> SessionConfig {
+        SessionConfig {
+            namespace: "sessions".to_string(),
+            idle_timeout: Duration::from_secs(30 * 60),
+            absolute_timeout: None,
+        }
+    }
+}
+
+impl SessionConfig {
+    /// Creates a configuration storing the sessions in the `sessions`
+    /// namespace, with an idle timeout of 30 minutes and no absolute timeout.
+    pub fn new() -> SessionConfig {
+        SessionConfig::default()
+    }
+
+    /// Sets the namespace holding the sessions, which shouldn't be used for
+    /// anything else.
+    pub fn namespace(mut self, name: &str) -> SessionConfig {
+        self.namespace = name.to_string();
+        self
+    }
+
+    /// Sets how long a session lasts without being loaded or saved.
+    pub fn idle_timeout(mut self, timeout: Duration) -> SessionConfig {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Sets how long a session lasts after its creation, however often it is used.
+    pub fn absolute_timeout(mut self, timeout: Duration) -> SessionConfig {
+        self.absolute_timeout = Some(timeout);
+        self
+    }
+
+    /// Returns the deadline of a session created at `created_at` and used at
+    /// `now`, capped by `expires_at`, in seconds since the UNIX epoch.
+    fn deadline(&self, created_at: u64, now: u64, expires_at: Option<u64>) -> u64 {
+        let absolute = self
+            .absolute_timeout
+            .map(|t| created_at.saturating_add(t.as_secs()));
+        [absolute, expires_at]
+            .into_iter()
+            .flatten()
+            .fold(now.saturating_add(self.idle_timeout.as_secs()), u64::min)
+    }
+}
+
+/// A store of sessions with idle and absolute timeouts.
+///
+/// It is cheap to clone, every clone shares the same `DB`.
+///
+/// ```no_run
+/// use epoch_db::{DB, session::{SessionConfig, SessionStore}};
+/// use std::{path::Path, sync::Arc, time::Duration};
+///
+/// let db = Arc::new(DB::new(Path::new("./my_database")).unwrap());
+/// let store = SessionStore::new(
+///     db,
+///     SessionConfig::new().absolute_timeout(Duration::from_secs(12 * 3600)),
+/// )
+/// .unwrap();
+///
+/// let id = store.create(&vec!["cart:42".to_string()]).unwrap();
+/// let cart: Option<Vec<String>> = store.load(&id).unwrap();
+/// assert_eq!(Some(vec!["cart:42".to_string()]), cart);
+/// ```
+#[derive(Debug, Clone)]
+pub struct SessionStore {
+    db: Arc<DB>,
+    config: SessionConfig,
+}
+
+/// How a session is written, see [`DB::write_session_in`].
+#[derive(Clone, Copy)]
+enum Write<'a> {
+    /// Writes a new session, unless a live one has the same ID
+    Create(&'a str),
+    /// Replaces the data of a live session
+    Replace(&'a str),
+    /// Writes the data of a session, whether it exists or not
+    #[cfg_attr(not(feature = "tower-sessions"), allow(dead_code))]
+    Upsert(&'a str),
+    /// Pushes back the deadline of a live session, keeping its data
+    Touch,
+}
+
+impl Write<'_> {
+    fn payload(&self) -> Option<&str> {
+        match self {
+            Write::Create(p) | Write::Replace(p) | Write::Upsert(p) => Some(p),
+            Write::Touch => None,
+        }
+    }
+}
+
+impl SessionStore {
+    /// Creates a store keeping its sessions in `db`, creating the namespace
+    /// of the sessions if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the namespace name is invalid or cannot be created.
+    pub fn new(db: Arc<DB>, config: SessionConfig) -> Result<SessionStore, Box<dyn Error>> {
+        db.namespace(&config.namespace)?;
+        Ok(SessionStore { db, config })
+    }
+
+    /// Returns the settings of the store.
+    pub fn config(&self) -> &SessionConfig {
+        &self.config
+    }
+
+    /// Creates a session holding `data`, returning its ID.
+    ///
+    /// IDs are 128 random bits from the operating system, written in hex.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `data` cannot be serialized, no random bits are
+    /// available or the transaction fails.
+    pub fn create<T: Serialize + ?Sized>(&self, data: &T) -> Result<String, Box<dyn Error>> {
+        let payload = encode_data(data)?;
+        loop {
+            let mut bits = [0u8; 16];
+            getrandom::getrandom(&mut bits).map_err(|e| e.to_string())?;
+            let id = to_hex(&bits);
+            if self.write(&id, Write::Create(&payload), None)? {
+                return Ok(id);
+            }
+        }
+    }
+
+    /// Retrieves the data of the session `id`, pushing back its deadline.
+    ///
+    /// Returns `None` if the session doesn't exist or timed out.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data cannot be deserialized into `T` or the
+    /// transaction fails.
+    pub fn load<T: DeserializeOwned>(&self, id: &str) -> Result<Option<T>, Box<dyn Error>> {
+        match self.read(id, true)? {
+            Some(payload) => Ok(Some(decode_data(&payload)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Replaces the data of the session `id`, pushing back its deadline.
+    ///
+    /// Returns false, without changing anything, if the session doesn't
+    /// exist or timed out.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `data` cannot be serialized or the transaction fails.
+    pub fn save<T: Serialize + ?Sized>(&self, id: &str, data: &T) -> Result<bool, Box<dyn Error>> {
+        let payload = encode_data(data)?;
+        Ok(self.write(id, Write::Replace(&payload), None)?)
+    }
+
+    /// Pushes back the deadline of the session `id` without reading nor
+    /// writing its data.
+    ///
+    /// Returns false if the session doesn't exist or timed out.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transaction fails.
+    pub fn touch(&self, id: &str) -> Result<bool, Box<dyn Error>> {
+        Ok(self.write(id, Write::Touch, None)?)
+    }
+
+    /// Removes the session `id`, returning false if it didn't exist or timed out.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transaction fails.
+    pub fn destroy(&self, id: &str) -> Result<bool, Box<dyn Error>> {
+        Ok(self.remove(id)?)
+    }
+
+    fn namespace(&self) -> Namespace<'_> {
+        Namespace {
+            db: &self.db,
+            name: self.config.namespace.clone(),
+        }
+    }
+
+    /// Writes the session `id`, see [`DB::write_session_in`].
+    fn write(
+        &self,
+        id: &str,
+        write: Write,
+        expires_at: Option<u64>,
+    ) -> Result<bool, TransientError> {
+        let written = self.namespace().with_keyspace(|ks| {
+            self.db
+                .write_session_in(ks, &self.config, id, write, expires_at)
+        })?;
+        self.db.persist(self.db.durability)?;
+        Ok(written)
+    }
+
+    /// Reads the session `id`, see [`DB::read_session_in`].
+    fn read(&self, id: &str, slide: bool) -> Result<Option<String>, TransientError> {
+        let payload = self
+            .namespace()
+            .with_keyspace(|ks| self.db.read_session_in(ks, &self.config, id, slide))?;
+        if slide && payload.is_some() {
+            self.db.persist(self.db.durability)?;
+        }
+        Ok(payload)
+    }
+
+    /// Removes the session `id`, see [`DB::destroy_session_in`].
+    fn remove(&self, id: &str) -> Result<bool, TransientError> {
+        let found = self
+            .namespace()
+            .with_keyspace(|ks| self.db.destroy_session_in(ks, id))?;
+        self.db.persist(self.db.durability)?;
+        Ok(found)
+    }
+}
+
+impl DB {
+    /// Writes the session `id` of the given keyspace as `write` says, with a
+    /// deadline capped by `expires_at`.
+    ///
+    /// Returns false, without changing anything, if the session exists and
+    /// shouldn't, or doesn't and should. A session whose deadline already
+    /// passed is removed instead.
+    fn write_session_in(
+        &self,
+        ks: &Keyspace,
+        config: &SessionConfig,
+        id: &str,
+        write: Write,
+        expires_at: Option<u64>,
+    ) -> Result<bool, TransientError> {
+        let byte = id.as_bytes();
+        let stored = write
+            .payload()
+            .map(|p| self.store_value(ks, byte, p.as_bytes()))
+            .transpose()?;
+
+        // NOTE: Whether the session was accepted, and whether `stored` was written.
+        let res = self.transact(ks, |tx| {
+            let now = now_secs();
+            let live = live_session(tx, byte, now)?;
+            match (write, &live) {
+                (Write::Create(_), Some(_)) | (Write::Replace(_) | Write::Touch, None) => {
+                    return Ok((false, false));
+                }
+                // NOTE: A session that timed out may not have been removed yet, start over.
+                (_, None) => {
+                    tx.delete(byte)?;
+                }
+                _ => {}
+            }
+
+            let created_at = live.map_or(now, |meta| meta.created_at);
+            let deadline = config.deadline(created_at, now, expires_at);
+            if deadline <= now {
+                tx.delete(byte)?;
+                return Ok((true, false));
+            }
+            match &stored {
+                Some(stored) => tx.write(byte, &stored.bytes, stored.len, Some(deadline))?,
+                None => {
+                    tx.set_ttl(byte, Some(deadline))?;
+                }
+            }
+            Ok((true, true))
+        });
+
+        if let Some(stored) = &stored
+            && !matches!(res, Ok((_, true)))
+        {
+            stored.discard(ks);
+        }
+        let (accepted, _) = res?;
+        if accepted {
+            ks.stats.writes.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(accepted)
+    }
+
+    /// Reads the data of the session `id` of the given keyspace, pushing back
+    /// its deadline if `slide` is true.
+    fn read_session_in(
+        &self,
+        ks: &Keyspace,
+        config: &SessionConfig,
+        id: &str,
+        slide: bool,
+    ) -> Result<Option<String>, TransientError> {
+        let byte = id.as_bytes();
+        loop {
+            let stored = self.transact(ks, |tx| {
+                let now = now_secs();
+                let Some(meta) = live_session(tx, byte, now)? else {
+                    return Ok(None);
+                };
+                let deadline = config.deadline(meta.created_at, now, None);
+                if slide && meta.ttl != Some(deadline) {
+                    tx.set_ttl(byte, Some(deadline))?;
+                }
+                Ok(tx.data.get(byte)?)
+            })?;
+            let Some(stored) = stored else {
+                ks.stats.misses.fetch_add(1, Ordering::Relaxed);
+                return Ok(None);
+            };
+            // NOTE: The chunks of a session saved in the meantime are gone, read it again.
+            let Some(payload) = self.read_value(ks, byte, &stored)? else {
+                continue;
+            };
+
+            ks.stats.hits.fetch_add(1, Ordering::Relaxed);
+            self.track_read(ks, byte)?;
+            let payload =
+                String::from_utf8(payload).map_err(|_| TransientError::ParsingToUTF8Error)?;
+            return Ok(Some(payload));
+        }
+    }
+
+    /// Removes the session `id` of the given keyspace, returning false if it
+    /// didn't exist or timed out.
+    fn destroy_session_in(&self, ks: &Keyspace, id: &str) -> Result<bool, TransientError> {
+        let byte = id.as_bytes();
+        let found = self.transact(ks, |tx| {
+            let live = live_session(tx, byte, now_secs())?.is_some();
+            tx.delete(byte)?;
+            Ok(live)
+        })?;
+        if found {
+            ks.stats.removes.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(found)
+    }
+}
+
+/// Returns the metadata of the session `byte`, `None` if it doesn't exist or
+/// its deadline passed.
+fn live_session(
+    tx: &KeyspaceTx,
+    byte: &[u8],
+    now: u64,
+) -> Result<Option<Metadata>, ConflictableTransactionError<TransientError>> {
+    let Some(meta) = tx.freq.get(byte)? else {
+        return Ok(None);
+    };
+    let meta = Metadata::from_u8(&meta)
+        .map_err(|_| ConflictableTransactionError::Abort(TransientError::ParsingFromByteError))?;
+    // NOTE: The background thread may not have removed a session that timed out yet.
+    Ok(meta.ttl.is_none_or(|t| t > now).then_some(meta))
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Cant get SystemTime")
+        .as_secs()
+}
+
+/// Serializes session data, as hex since values must be valid UTF-8.
+fn encode_data<T: Serialize + ?Sized>(data: &T) -> Result<String, TransientError> {
+    let bytes = encode_to_vec(data, bincode::config::standard())
+        .map_err(|_| TransientError::ParsingToByteError)?;
+    Ok(to_hex(&bytes))
+}
+
+fn decode_data<T: DeserializeOwned>(payload: &str) -> Result<T, TransientError> {
+    let bytes = (0..payload.len())
+        .step_by(2)
+        .map(|i| {
+            payload
+                .get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+        })
+        .collect::<Option<Vec<u8>>>()
+        .ok_or(TransientError::ParsingFromByteError)?;
+    Ok(decode_from_slice(&bytes, bincode::config::standard())
+        .map_err(|_| TransientError::ParsingFromByteError)?
+        .0)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, b| {
+        let _ = write!(hex, "{b:02x}");
+        hex
+    })
+}
@@ -0,0 +1,74 @@
+//! The implementation of the `SessionStore` trait of `tower-sessions` for
+//! [`SessionStore`], available with the `tower-sessions` feature.
+//!
+//! Records are stored as JSON under the string form of their ID, and their
+//! expiry date caps the deadline given by the idle and absolute timeouts.
+//! Loading a record doesn't push its deadline back, since `tower-sessions`
+//! saves the sessions whose expiry date moves.
+
+use async_trait::async_trait;
+use tower_sessions_core::{
+    SessionStore as TowerSessionStore,
+    session::{Id, Record},
+    session_store::{Error, Result},
+};
+
+use super::{SessionStore, Write};
+use crate::{async_db::blocking, db::errors::TransientError};
+
+#[async_trait]
+impl TowerSessionStore for SessionStore {
+    async fn create(&self, record: &mut Record) -> Result<()> {
+        loop {
+            let payload = encode_record(record)?;
+            let (store, id, expires_at) = (self.clone(), record.id.to_string(), expiry(record));
+            let created =
+                blocking(move || store.write(&id, Write::Create(&payload), Some(expires_at)))
+                    .await
+                    .map_err(backend)?;
+            if created {
+                return Ok(());
+            }
+            // NOTE: Another live session has this ID, draw a new one.
+            record.id = Id::default();
+        }
+    }
+
+    async fn save(&self, record: &Record) -> Result<()> {
+        let payload = encode_record(record)?;
+        let (store, id, expires_at) = (self.clone(), record.id.to_string(), expiry(record));
+        blocking(move || store.write(&id, Write::Upsert(&payload), Some(expires_at)))
+            .await
+            .map_err(backend)?;
+        Ok(())
+    }
+
+    async fn load(&self, session_id: &Id) -> Result<Option<Record>> {
+        let (store, id) = (self.clone(), session_id.to_string());
+        let payload = blocking(move || store.read(&id, false))
+            .await
+            .map_err(backend)?;
+        payload
+            .map(|p| serde_json::from_str(&p).map_err(|e| Error::Decode(e.to_string())))
+            .transpose()
+    }
+
+    async fn delete(&self, session_id: &Id) -> Result<()> {
+        let (store, id) = (self.clone(), session_id.to_string());
+        blocking(move || store.remove(&id)).await.map_err(backend)?;
+        Ok(())
+    }
+}
+
+fn encode_record(record: &Record) -> Result<String> {
+    serde_json::to_string(record).map_err(|e| Error::Encode(e.to_string()))
+}
+
+/// Returns the expiry date of `record`, in seconds since the UNIX epoch.
+fn expiry(record: &Record) -> u64 {
+    record.expiry_date.unix_timestamp().max(0) as u64
+}
+
+fn backend(e: TransientError) -> Error {
+    Error::Backend(e.to_string().trim_end().to_string())
+}
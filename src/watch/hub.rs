@@ -0,0 +1,245 @@
+//! Forwards the changes of the mutation log to the watchers interested in them.
+//!
+//! A single sled subscriber is drained by a background thread, which copies
+//! every change into the bounded queue of each watcher whose namespace and
+//! prefix it matches. A watcher that isn't read fills its queue and is marked
+//! lagged instead of slowing the writers down.
+
+use std::{
+    collections::VecDeque,
+    fmt::{self, Debug, Formatter},
+    sync::{
+        Arc, Condvar, Mutex, MutexGuard, Weak,
+        atomic::{AtomicBool, Ordering},
+        mpsc::RecvTimeoutError,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use sled::{Event, Subscriber, Tree};
+
+use crate::{
+    db::errors::TransientError,
+    oplog::{Mutation, OpLogEntry},
+};
+
+/// How many changes a watcher buffers before it is marked lagged.
+pub(crate) const WATCHER_CAPACITY: usize = 1024;
+
+/// How long the dispatcher waits for new changes before checking for shutdown.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The watchers of a `DB` and the thread feeding them.
+#[derive(Default)]
+pub(crate) struct WatchHub {
+    queues: Arc<Mutex<Vec<Weak<Queue>>>>,
+    /// Started with the first watcher
+    dispatcher: Mutex<Option<JoinHandle<()>>>,
+    shutdown: Arc<AtomicBool>,
+}
+
+/// The changes waiting to be read by a single watcher.
+pub(crate) struct Queue {
+    /// The namespace being watched, `None` for the default keyspace
+    pub(crate) namespace: Option<String>,
+    /// Only keys starting with this prefix are reported
+    pub(crate) prefix: Vec<u8>,
+    /// Only expirations are reported
+    pub(crate) expirations_only: bool,
+    /// The head of the log when the watcher was created, older changes aren't reported
+    after: u64,
+    state: Mutex<QueueState>,
+    ready: Condvar,
+}
+
+#[derive(Default)]
+pub(crate) struct QueueState {
+    pub(crate) entries: VecDeque<Result<OpLogEntry, TransientError>>,
+    /// Set when the queue overflowed, the changes after it were dropped
+    pub(crate) lagged: bool,
+    /// Set when no more changes will be added
+    pub(crate) closed: bool,
+    #[cfg(feature = "async")]
+    pub(crate) waker: Option<std::task::Waker>,
+}
+
+impl WatchHub {
+    /// Registers a watcher, starting the dispatcher on the first one.
+    pub(crate) fn subscribe(
+        &self,
+        oplog_tree: &Tree,
+        namespace: Option<&str>,
+        prefix: &[u8],
+        expirations_only: bool,
+        after: u64,
+    ) -> Arc<Queue> {
+        let queue = Arc::new(Queue {
+            namespace: namespace.map(str::to_string),
+            prefix: prefix.to_vec(),
+            expirations_only,
+            after,
+            state: Mutex::default(),
+            ready: Condvar::new(),
+        });
+
+        let mut dispatcher = self.dispatcher.lock().expect("Dispatcher lock poisoned");
+        if dispatcher.is_none() {
+            // NOTE: Subscribed before returning, so no change committed after the
+            // watcher is created is missed.
+            let subscriber = oplog_tree.watch_prefix(vec![]);
+            let queues = Arc::clone(&self.queues);
+            let shutdown = Arc::clone(&self.shutdown);
+            *dispatcher = Some(thread::spawn(move || {
+                dispatch(subscriber, &queues, &shutdown)
+            }));
+        }
+        self.queues
+            .lock()
+            .expect("Queues lock poisoned")
+            .push(Arc::downgrade(&queue));
+
+        queue
+    }
+
+    /// Stops the dispatcher and closes every watcher.
+    pub(crate) fn close(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        if let Some(dispatcher) = self
+            .dispatcher
+            .lock()
+            .expect("Dispatcher lock poisoned")
+            .take()
+        {
+            let _ = dispatcher.join();
+        }
+    }
+}
+
+impl Debug for WatchHub {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WatchHub").finish_non_exhaustive()
+    }
+}
+
+/// Drains `subscriber` until shutdown, forwarding every change to the queues interested in it.
+fn dispatch(mut subscriber: Subscriber, queues: &Mutex<Vec<Weak<Queue>>>, shutdown: &AtomicBool) {
+    while !shutdown.load(Ordering::SeqCst) {
+        let event = match subscriber.next_timeout(POLL_INTERVAL) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        // NOTE: Removals only come from the TTL thread trimming old entries
+        let Event::Insert { value, .. } = event else {
+            continue;
+        };
+        let entry = OpLogEntry::from_u8(&value).ok();
+
+        queues
+            .lock()
+            .expect("Queues lock poisoned")
+            .retain(|queue| match queue.upgrade() {
+                Some(queue) => queue.offer(entry.as_ref()),
+                None => false,
+            });
+    }
+
+    for queue in queues.lock().expect("Queues lock poisoned").drain(..) {
+        if let Some(queue) = queue.upgrade() {
+            queue.push(|state| state.closed = true);
+        }
+    }
+}
+
+impl Queue {
+    /// Adds `entry` if it is watched, `None` standing for an entry that cannot
+    /// be decoded. Returns false once the queue lagged and needs no more changes.
+    fn offer(&self, entry: Option<&OpLogEntry>) -> bool {
+        let Some(entry) = entry else {
+            return self.push(|state| {
+                state
+                    .entries
+                    .push_back(Err(TransientError::ParsingFromByteError))
+            });
+        };
+        if !self.watches(entry) {
+            return true;
+        }
+        self.push(|state| state.entries.push_back(Ok(entry.clone())))
+    }
+
+    fn watches(&self, entry: &OpLogEntry) -> bool {
+        if entry.seq <= self.after || entry.namespace != self.namespace {
+            return false;
+        }
+        match &entry.mutation {
+            Mutation::Expire { key } => key.starts_with(&self.prefix),
+            _ if self.expirations_only => false,
+            Mutation::Set { key, .. }
+            | Mutation::Meta { key, .. }
+            | Mutation::Remove { key }
+            | Mutation::Evict { key } => key.starts_with(&self.prefix),
+            Mutation::Clear | Mutation::DropNamespace => true,
+            Mutation::Configure { .. } => false,
+        }
+    }
+
+    /// Applies `f` to the state and wakes the reader, marking the queue lagged
+    /// if it overflows.
+    fn push(&self, f: impl FnOnce(&mut QueueState)) -> bool {
+        let mut state = self.lock();
+        f(&mut state);
+        if state.entries.len() > WATCHER_CAPACITY {
+            state.entries.pop_back();
+            state.lagged = true;
+        }
+        let open = !state.lagged && !state.closed;
+        #[cfg(feature = "async")]
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        drop(state);
+
+        self.ready.notify_all();
+        open
+    }
+
+    pub(crate) fn lock(&self) -> MutexGuard<'_, QueueState> {
+        self.state.lock().expect("Queue lock poisoned")
+    }
+
+    /// Waits until the state changes or `timeout` elapses.
+    pub(crate) fn wait<'a>(
+        &self,
+        state: MutexGuard<'a, QueueState>,
+        timeout: Option<Duration>,
+    ) -> MutexGuard<'a, QueueState> {
+        match timeout {
+            Some(timeout) => {
+                self.ready
+                    .wait_timeout(state, timeout)
+                    .expect("Queue lock poisoned")
+                    .0
+            }
+            None => self.ready.wait(state).expect("Queue lock poisoned"),
+        }
+    }
+}
+
+impl QueueState {
+    /// Takes the next change, `Some(Err(WatcherLagged))` once after the last
+    /// change kept before the queue overflowed, `None` if there is nothing to
+    /// read yet.
+    pub(crate) fn pop(&mut self) -> Option<Result<OpLogEntry, TransientError>> {
+        if let Some(entry) = self.entries.pop_front() {
+            return Some(entry);
+        }
+        if self.lagged {
+            self.lagged = false;
+            self.closed = true;
+            return Some(Err(TransientError::WatcherLagged));
+        }
+        None
+    }
+}
@@ -0,0 +1,178 @@
+//! The `watch` module lets callers subscribe to the changes made to the keys
+//! of a `DB`.
+//!
+//! A [`Watcher`] is fed by the mutation log, so it sees every write, removal,
+//! expiration and eviction of the keys it watches, in commit order, as they
+//! are committed. Changes committed before the watcher was created are not
+//! replayed, use [`DB::changes_since`] for that.
+
+pub(crate) mod hub;
+
+use std::{
+    fmt::{self, Debug, Formatter},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use hub::Queue;
+
+use crate::{
+    DB,
+    db::{blob::EntryDecoder, errors::TransientError},
+    namespace::Namespace,
+    oplog::OpLogEntry,
+};
+
+/// A subscription to the changes made to the keys of a keyspace, returned by
+/// [`DB::watch`] and [`DB::watch_expirations`].
+///
+/// It is an `Iterator` that blocks until the next change, and with the
+/// `async` feature also a `Stream` that doesn't.
+///
+/// NOTE: Up to 1024 changes are buffered until they are read. A watcher that
+/// falls further behind stops receiving changes and reports
+/// [`TransientError::WatcherLagged`] after the ones it buffered, writers are
+/// never slowed down by it.
+pub struct Watcher {
+    queue: Arc<Queue>,
+    /// Decodes the values of the changes
+    decoder: EntryDecoder,
+}
+
+impl Watcher {
+    pub(crate) fn new(
+        db: &DB,
+        namespace: Option<&str>,
+        prefix: &[u8],
+        expirations_only: bool,
+    ) -> Watcher {
+        // NOTE: A head that cannot be read only lets older changes through.
+        let after = db.head_seq().unwrap_or(0);
+        Watcher {
+            queue: db.watchers.subscribe(
+                &db.oplog_tree,
+                namespace,
+                prefix,
+                expirations_only,
+                after,
+            ),
+            decoder: db.entry_decoder(),
+        }
+    }
+
+    /// Waits up to `timeout` for the next change.
+    ///
+    /// Returns `None` if nothing changed in time or the `DB` was closed.
+    pub fn next_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Option<Result<OpLogEntry, TransientError>> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.queue.lock();
+        loop {
+            if let Some(res) = state.pop() {
+                drop(state);
+                return Some(res.and_then(|entry| self.decoder.decode(entry)));
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if state.closed || left.is_zero() {
+                return None;
+            }
+            state = self.queue.wait(state, Some(left));
+        }
+    }
+}
+
+impl Debug for Watcher {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Watcher")
+            .field("namespace", &self.queue.namespace)
+            .field("prefix", &self.queue.prefix)
+            .field("expirations_only", &self.queue.expirations_only)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Iterator for Watcher {
+    type Item = Result<OpLogEntry, TransientError>;
+
+    /// Blocks until the next change, returning `None` once the `DB` is closed.
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut state = self.queue.lock();
+        loop {
+            if let Some(res) = state.pop() {
+                drop(state);
+                return Some(res.and_then(|entry| self.decoder.decode(entry)));
+            }
+            if state.closed {
+                return None;
+            }
+            state = self.queue.wait(state, None);
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl futures_core::Stream for Watcher {
+    type Item = Result<OpLogEntry, TransientError>;
+
+    fn poll_next(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        use std::task::Poll;
+
+        let mut state = self.queue.lock();
+        if let Some(res) = state.pop() {
+            drop(state);
+            return Poll::Ready(Some(res.and_then(|entry| self.decoder.decode(entry))));
+        }
+        if state.closed {
+            return Poll::Ready(None);
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+#[cfg(feature = "async")]
+impl Watcher {
+    /// Waits for the next change without blocking the thread, returning
+    /// `None` once the `DB` is closed.
+    pub async fn recv(&mut self) -> Option<Result<OpLogEntry, TransientError>> {
+        std::future::poll_fn(|cx| {
+            futures_core::Stream::poll_next(std::pin::Pin::new(&mut *self), cx)
+        })
+        .await
+    }
+}
+
+impl DB {
+    /// Watches the changes made to the keys of the default keyspace starting
+    /// with `prefix`. An empty prefix watches every key.
+    pub fn watch(&self, prefix: &str) -> Watcher {
+        Watcher::new(self, None, prefix.as_bytes(), false)
+    }
+
+    /// Watches the keys of the default keyspace starting with `prefix` that
+    /// expire.
+    pub fn watch_expirations(&self, prefix: &str) -> Watcher {
+        Watcher::new(self, None, prefix.as_bytes(), true)
+    }
+}
+
+impl Namespace<'_> {
+    /// Watches the changes made to the keys of the namespace starting with
+    /// `prefix`, see [`DB::watch`].
+    ///
+    /// Clearing or dropping the namespace is reported as well.
+    pub fn watch(&self, prefix: &str) -> Watcher {
+        Watcher::new(self.db, Some(&self.name), prefix.as_bytes(), false)
+    }
+
+    /// Watches the keys of the namespace starting with `prefix` that expire,
+    /// see [`DB::watch_expirations`].
+    pub fn watch_expirations(&self, prefix: &str) -> Watcher {
+        Watcher::new(self.db, Some(&self.name), prefix.as_bytes(), true)
+    }
+}
@@ -0,0 +1,222 @@
+#![cfg(feature = "async")]
+
+use std::{
+    io::{Cursor, ErrorKind, Read},
+    path::Path,
+    time::Duration,
+};
+
+use epoch_db::{
+    async_db::AsyncDB,
+    db::{
+        errors::TransientError,
+        export::{ExportFormat, ExportOptions, ImportOptions},
+    },
+    namespace::NamespaceConfig,
+    oplog::Mutation,
+};
+use tempfile::tempdir;
+use tokio::time::{sleep, timeout};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Opens the database at `path` once the one dropped just before is closed.
+async fn reopen(path: &Path) -> AsyncDB {
+    for _ in 0..100 {
+        match AsyncDB::open(path).await {
+            Err(TransientError::SledError {
+                error: sled::Error::Io(e),
+            }) if e.kind() == ErrorKind::Other => sleep(Duration::from_millis(10)).await,
+            res => return res.unwrap(),
+        }
+    }
+    AsyncDB::open(path).await.unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_async_operations() {
+    let temp_dir = tempdir().unwrap();
+    let db = AsyncDB::open(temp_dir.path()).await.unwrap();
+
+    db.set("user:1", "Alice", None).await.unwrap();
+    db.increment_frequency("user:1").await.unwrap();
+
+    assert_eq!("Alice", db.get("user:1").await.unwrap().unwrap());
+    assert_eq!(1, db.get_metadata("user:1").await.unwrap().unwrap().freq);
+
+    db.remove("user:1").await.unwrap();
+    assert!(db.get("user:1").await.unwrap().is_none());
+    assert!(db.remove("user:1").await.is_err());
+    assert_eq!(3, db.last_seq().await.unwrap());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_async_batch() {
+    let temp_dir = tempdir().unwrap();
+    let db = AsyncDB::open(temp_dir.path()).await.unwrap();
+
+    db.mset(&[("a", "1", None), ("b", "2", None)])
+        .await
+        .unwrap();
+
+    let res = db.mget(&["a", "missing", "b"]).await.unwrap();
+    assert_eq!("1", res[0].as_ref().unwrap().0);
+    assert!(res[1].is_none());
+    assert_eq!("2", res[2].as_ref().unwrap().0);
+
+    assert_eq!(1, db.mremove(&["a", "missing"]).await.unwrap());
+    assert_eq!(1, db.stats().await.keys);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_async_versions() {
+    let temp_dir = tempdir().unwrap();
+    let db = AsyncDB::open(temp_dir.path()).await.unwrap();
+
+    assert_eq!(1, db.set_if_version("k", "a", None, 0).await.unwrap());
+    assert!(matches!(
+        db.set_if_version("k", "b", None, 0).await,
+        Err(TransientError::VersionConflict {
+            expected: 0,
+            actual: 1
+        })
+    ));
+    assert_eq!(2, db.set_if_version("k", "b", None, 1).await.unwrap());
+    assert_eq!(
+        ("b".to_string(), 2),
+        db.get_with_version("k").await.unwrap().unwrap()
+    );
+
+    let sessions = db.namespace("sessions").await.unwrap();
+    assert!(sessions.get_with_version("k").await.unwrap().is_none());
+    assert_eq!(1, sessions.set_if_version("k", "c", None, 0).await.unwrap());
+    assert_eq!(
+        ("c".to_string(), 1),
+        sessions.get_with_version("k").await.unwrap().unwrap()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_async_streams_and_exports() {
+    let temp_dir = tempdir().unwrap();
+    let db = AsyncDB::open(temp_dir.path()).await.unwrap();
+    let doc = "0123456789abcdef".repeat(256);
+
+    db.set_reader("doc", Cursor::new(doc.clone()), None)
+        .await
+        .unwrap();
+    let sessions = db.namespace("sessions").await.unwrap();
+    sessions
+        .set_reader("user:1", Cursor::new("session"), None)
+        .await
+        .unwrap();
+
+    let mut reader = db.get_reader("doc").await.unwrap().unwrap();
+    let read = tokio::task::spawn_blocking(move || {
+        let mut read = String::new();
+        reader.read_to_string(&mut read).unwrap();
+        read
+    })
+    .await
+    .unwrap();
+    assert_eq!(doc, read);
+    assert!(sessions.get_reader("missing").await.unwrap().is_none());
+    assert!(db.verify().await.unwrap().is_consistent());
+
+    let dump = temp_dir.path().join("dump");
+    let file = std::fs::File::create(&dump).unwrap();
+    let options = ExportOptions::new(ExportFormat::Binary);
+    assert_eq!(2, db.export(file, &options).await.unwrap());
+
+    let other_dir = tempdir().unwrap();
+    let other = AsyncDB::open(other_dir.path()).await.unwrap();
+    let file = std::fs::File::open(&dump).unwrap();
+    let report = other
+        .import(file, &ImportOptions::new(ExportFormat::Binary))
+        .await
+        .unwrap();
+    assert_eq!(2, report.imported);
+    assert_eq!(doc, other.get("doc").await.unwrap().unwrap());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_async_namespace() {
+    let temp_dir = tempdir().unwrap();
+    let db = AsyncDB::open(temp_dir.path()).await.unwrap();
+
+    let sessions = db
+        .namespace_with(
+            "sessions",
+            NamespaceConfig::new().default_ttl(Duration::from_secs(60)),
+        )
+        .await
+        .unwrap();
+
+    sessions.set("user:1", "session", None).await.unwrap();
+    assert!(db.get("user:1").await.unwrap().is_none());
+    assert_eq!("session", sessions.get("user:1").await.unwrap().unwrap());
+    assert!(
+        sessions
+            .get_metadata("user:1")
+            .await
+            .unwrap()
+            .unwrap()
+            .ttl
+            .is_some()
+    );
+
+    sessions.clear().await.unwrap();
+    assert_eq!(0, sessions.stats().await.unwrap().keys);
+
+    assert!(db.drop_namespace("sessions").await.unwrap());
+    assert!(db.namespaces().is_empty());
+    assert!(sessions.get("user:1").await.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_async_watch() {
+    let temp_dir = tempdir().unwrap();
+    let db = AsyncDB::open(temp_dir.path()).await.unwrap();
+
+    let mut watcher = db.watch("user:");
+    let mut expirations = db.watch_expirations("session:");
+
+    db.set("user:1", "Alice", None).await.unwrap();
+    db.set("session:1", "token", Some(Duration::from_secs(1)))
+        .await
+        .unwrap();
+
+    let entry = timeout(TIMEOUT, watcher.recv())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert!(matches!(entry.mutation, Mutation::Set { key, .. } if key == b"user:1"));
+
+    let entry = timeout(TIMEOUT, expirations.recv())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        Mutation::Expire {
+            key: b"session:1".to_vec()
+        },
+        entry.mutation
+    );
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn test_async_flush_on_current_thread() {
+    let temp_dir = tempdir().unwrap();
+
+    {
+        let db = AsyncDB::open(temp_dir.path()).await.unwrap();
+        db.set("user:1", "Alice", None).await.unwrap();
+        db.flush().await.unwrap();
+    }
+
+    // NOTE: The dropped `DB` is closed on the blocking thread pool.
+    let db = reopen(temp_dir.path()).await;
+    assert_eq!("Alice", db.get("user:1").await.unwrap().unwrap());
+}
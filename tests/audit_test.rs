@@ -0,0 +1,219 @@
+use std::{
+    process::Command,
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use epoch_db::{
+    DB, DBConfig,
+    audit::{self, AuditConfig, AuditEvent, AuditQuery, AuditRecord},
+};
+use tempfile::tempdir;
+
+mod common;
+
+use common::reopen;
+
+fn records(db: &DB, query: &AuditQuery) -> Vec<AuditRecord> {
+    db.audit(query).unwrap().map(Result::unwrap).collect()
+}
+
+/// Returns the key of every record, `*` for the events on a whole namespace.
+fn keys(records: &[AuditRecord]) -> Vec<String> {
+    records
+        .iter()
+        .map(|r| String::from_utf8_lossy(r.event.key().unwrap_or(b"*")).into_owned())
+        .collect()
+}
+
+#[test]
+fn test_mutations_are_recorded_with_their_actor() {
+    let temp_dir = tempdir().unwrap();
+    let db = DBConfig::new(temp_dir.path())
+        .audit(AuditConfig::new())
+        .open()
+        .unwrap();
+
+    {
+        let _alice = audit::act_as("alice");
+        db.set("a", "1", None).unwrap();
+        {
+            let _bob = audit::act_as("bob");
+            db.set("b", "2", Some(Duration::from_secs(1))).unwrap();
+        }
+        db.increment_frequency("a").unwrap();
+        db.remove("a").unwrap();
+    }
+    db.set("c", "3", None).unwrap();
+    thread::sleep(Duration::from_millis(2500));
+
+    let all = records(&db, &AuditQuery::new());
+    assert_eq!(keys(&all), vec!["a", "b", "a", "c", "b"]);
+    assert!(matches!(all[0].event, AuditEvent::Set { .. }));
+    assert!(matches!(all[2].event, AuditEvent::Remove { .. }));
+    assert!(
+        matches!(all[4].event, AuditEvent::Expire { .. }),
+        "Expirations should be recorded."
+    );
+    let actors: Vec<_> = all.iter().map(|r| r.actor.as_deref()).collect();
+    assert_eq!(
+        actors,
+        vec![Some("alice"), Some("bob"), Some("alice"), None, None]
+    );
+    assert!(all.windows(2).all(|w| w[0].seq < w[1].seq));
+
+    let line = all[1].to_string();
+    assert!(line.contains(" bob - set b version="), "{line}");
+    assert!(line.contains(" ttl="), "{line}");
+}
+
+#[test]
+fn test_query_filters() {
+    let temp_dir = tempdir().unwrap();
+    let db = DBConfig::new(temp_dir.path())
+        .audit(AuditConfig::new())
+        .open()
+        .unwrap();
+    let users = db.namespace("users").unwrap();
+
+    {
+        let _alice = audit::act_as("alice");
+        db.set("k", "1", None).unwrap();
+        users.set("k", "1", None).unwrap();
+    }
+    {
+        let _bob = audit::act_as("bob");
+        db.set("other", "1", None).unwrap();
+        users.clear().unwrap();
+    }
+
+    let by_key = records(&db, &AuditQuery::new().key("k"));
+    assert_eq!(
+        keys(&by_key),
+        vec!["k", "k", "*"],
+        "Clearing a namespace should match every key."
+    );
+
+    let in_users = records(&db, &AuditQuery::new().key("k").namespace("users"));
+    assert_eq!(keys(&in_users), vec!["k", "*"]);
+    assert!(
+        in_users
+            .iter()
+            .all(|r| r.namespace.as_deref() == Some("users"))
+    );
+
+    let by_bob = records(&db, &AuditQuery::new().actor("bob"));
+    assert_eq!(keys(&by_bob), vec!["other", "*"]);
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    assert_eq!(4, records(&db, &AuditQuery::new().since(now - 60)).len());
+    assert!(records(&db, &AuditQuery::new().until(now - 60)).is_empty());
+    assert!(records(&db, &AuditQuery::new().since(now + 60)).is_empty());
+}
+
+#[test]
+fn test_rotation_and_metadata() {
+    let temp_dir = tempdir().unwrap();
+    let db = DBConfig::new(temp_dir.path())
+        .audit(AuditConfig::new().max_entries(3).include_metadata(true))
+        .open()
+        .unwrap();
+
+    for i in 0..5 {
+        db.set(&format!("key:{i}"), "value", None).unwrap();
+    }
+    db.increment_frequency("key:4").unwrap();
+    thread::sleep(Duration::from_millis(300));
+
+    let kept = records(&db, &AuditQuery::new());
+    assert_eq!(
+        keys(&kept),
+        vec!["key:3", "key:4", "key:4"],
+        "Only the last records should be kept."
+    );
+    match &kept[2].event {
+        AuditEvent::Meta { meta, .. } => assert_eq!(1, meta.freq),
+        event => panic!("Expected a metadata change, got {event:?}"),
+    }
+}
+
+#[test]
+fn test_undecodable_records_expire() {
+    let temp_dir = tempdir().unwrap();
+    let config = || {
+        DBConfig::new(temp_dir.path()).audit(AuditConfig::new().max_age(Duration::from_secs(3600)))
+    };
+    {
+        let db = config().open().unwrap();
+        db.set("key:0", "value", None).unwrap();
+        db.set("key:1", "value", None).unwrap();
+        assert_eq!(2, records(&db, &AuditQuery::new()).len());
+    }
+    {
+        let sled_db = reopen(|| sled::open(temp_dir.path())).unwrap();
+        let audit = sled_db.open_tree("audit_tree").unwrap();
+        audit.insert(1u64.to_be_bytes(), &[0xFF, 0xEE, 1]).unwrap();
+        sled_db.flush().unwrap();
+    }
+
+    let db = reopen(|| config().open()).unwrap();
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(vec!["key:1"], keys(&records(&db, &AuditQuery::new())));
+}
+
+#[test]
+fn test_audit_outlives_the_mutation_log() {
+    let temp_dir = tempdir().unwrap();
+    {
+        let db = DBConfig::new(temp_dir.path())
+            .oplog_retention(1)
+            .audit(AuditConfig::new())
+            .open()
+            .unwrap();
+        for i in 0..5 {
+            db.set(&format!("key:{i}"), "value", None).unwrap();
+        }
+        thread::sleep(Duration::from_millis(300));
+        assert_eq!(5, records(&db, &AuditQuery::new()).len());
+        db.remove("key:0").unwrap();
+    }
+
+    let db = DB::new(temp_dir.path()).unwrap();
+    let all = records(&db, &AuditQuery::new());
+    assert_eq!(6, all.len(), "Records should survive a reopen.");
+    assert!(matches!(all[5].event, AuditEvent::Remove { .. }));
+}
+
+#[test]
+fn test_cli() {
+    let temp_dir = tempdir().unwrap();
+    {
+        let db = DBConfig::new(temp_dir.path())
+            .audit(AuditConfig::new())
+            .open()
+            .unwrap();
+        let _alice = audit::act_as("alice");
+        db.set("a", "1", None).unwrap();
+        db.set("b", "2", None).unwrap();
+    }
+    let epoch = env!("CARGO_BIN_EXE_epoch");
+    let path = temp_dir.path().to_str().unwrap();
+
+    let output = Command::new(epoch)
+        .args(["audit", path, "--key", "b", "--actor", "alice"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(1, stdout.lines().count(), "{stdout}");
+    assert!(stdout.contains(" alice - set b "), "{stdout}");
+
+    let usage = Command::new(epoch)
+        .args(["audit", path, "--since", "yesterday"])
+        .output()
+        .unwrap();
+    assert_eq!(Some(2), usage.status.code());
+}
@@ -0,0 +1,117 @@
+use std::{thread::sleep, time::Duration};
+
+use epoch_db::DB;
+use tempfile::tempdir;
+
+#[test]
+fn test_mset_mget() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+
+    db.mset(&[
+        ("user:1", "Alice", None),
+        ("user:2", "Bob", Some(Duration::from_secs(60))),
+        ("user:3", "Charlie", None),
+    ])
+    .unwrap();
+    db.increment_frequency("user:3").unwrap();
+
+    let res = db
+        .mget(&["user:3", "user:missing", "user:1", "user:2"])
+        .unwrap();
+
+    assert_eq!(4, res.len());
+    let (val, meta) = res[0].as_ref().unwrap();
+    assert_eq!("Charlie", val);
+    assert_eq!(1, meta.freq);
+    assert!(res[1].is_none());
+    assert_eq!("Alice", res[2].as_ref().unwrap().0);
+    let (val, meta) = res[3].as_ref().unwrap();
+    assert_eq!("Bob", val);
+    assert!(meta.ttl.is_some());
+}
+
+#[test]
+fn test_mset_keeps_existing_metadata() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+
+    db.set("user:1", "Version 1", None).unwrap();
+    db.increment_frequency("user:1").unwrap();
+    let initial_meta = db.get_metadata("user:1").unwrap().unwrap();
+
+    db.mset(&[("user:1", "Version 2", None), ("user:2", "Bob", None)])
+        .unwrap();
+
+    let final_meta = db.get_metadata("user:1").unwrap().unwrap();
+    assert_eq!("Version 2", db.get("user:1").unwrap().unwrap());
+    assert_eq!(
+        (initial_meta.freq, initial_meta.created_at),
+        (final_meta.freq, final_meta.created_at),
+        "Frequency and created_at should not change on a batch update."
+    );
+    assert_eq!(initial_meta.version + 1, final_meta.version);
+}
+
+#[test]
+fn test_mset_ttl() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+
+    db.mset(&[
+        ("session:1", "token", Some(Duration::from_secs(2))),
+        ("session:2", "token", None),
+    ])
+    .unwrap();
+
+    sleep(Duration::from_secs(3));
+
+    assert!(db.get("session:1").unwrap().is_none());
+    assert!(db.get("session:2").unwrap().is_some());
+}
+
+#[test]
+fn test_mremove() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+
+    db.mset(&[
+        ("user:1", "Alice", Some(Duration::from_secs(60))),
+        ("user:2", "Bob", None),
+        ("user:3", "Charlie", None),
+    ])
+    .unwrap();
+
+    let removed = db.mremove(&["user:1", "user:2", "user:missing"]).unwrap();
+
+    assert_eq!(2, removed);
+    assert!(db.get("user:1").unwrap().is_none());
+    assert!(db.get_metadata("user:1").unwrap().is_none());
+    assert!(db.get("user:2").unwrap().is_none());
+    assert_eq!("Charlie", db.get("user:3").unwrap().unwrap());
+}
+
+#[test]
+fn test_namespace_batch() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+
+    let cache = db.namespace("cache").unwrap();
+    cache
+        .mset(&[("a", "1", None), ("b", "2", None), ("c", "3", None)])
+        .unwrap();
+
+    assert!(
+        db.mget(&["a", "b", "c"])
+            .unwrap()
+            .iter()
+            .all(Option::is_none)
+    );
+
+    assert_eq!(1, cache.mremove(&["b"]).unwrap());
+    let res = cache.mget(&["a", "b", "c"]).unwrap();
+    assert_eq!("1", res[0].as_ref().unwrap().0);
+    assert!(res[1].is_none());
+    assert_eq!("3", res[2].as_ref().unwrap().0);
+    assert_eq!(3, cache.stats().unwrap().writes);
+}
@@ -0,0 +1,157 @@
+use std::{
+    io::{Cursor, Read},
+    path::Path,
+    thread,
+    time::Duration,
+};
+
+use epoch_db::{DBConfig, oplog::Mutation};
+use tempfile::tempdir;
+
+fn blob_chunks(path: &Path, tree: &str) -> usize {
+    let sled_db = sled::open(path).unwrap();
+    sled_db.open_tree(tree).unwrap().len()
+}
+
+fn document(len: usize) -> String {
+    "0123456789abcdef".repeat(len / 16)
+}
+
+#[test]
+fn test_large_values_are_chunked() {
+    let temp_dir = tempdir().unwrap();
+    let doc = document(4096);
+
+    {
+        let db = DBConfig::new(temp_dir.path())
+            .chunk_size(1024)
+            .open()
+            .unwrap();
+        db.set("doc", &doc, None).unwrap();
+        db.set("small", "value", None).unwrap();
+
+        assert_eq!(doc, db.get("doc").unwrap().unwrap());
+        assert_eq!("value", db.get("small").unwrap().unwrap());
+        assert_eq!(4096, db.get_metadata("doc").unwrap().unwrap().value_len);
+
+        let entries = db.mget(&["doc", "small", "missing"]).unwrap();
+        assert_eq!(doc, entries[0].as_ref().unwrap().0);
+        assert!(entries[2].is_none());
+
+        let mut reader = db.get_reader("doc").unwrap().unwrap();
+        assert_eq!(4096, reader.len());
+        let mut read = String::new();
+        reader.read_to_string(&mut read).unwrap();
+        assert_eq!(doc, read);
+    }
+
+    assert_eq!(4, blob_chunks(temp_dir.path(), "blob_tree"));
+}
+
+#[test]
+fn test_set_reader() {
+    let temp_dir = tempdir().unwrap();
+    let db = DBConfig::new(temp_dir.path())
+        .chunk_size(1000)
+        .open()
+        .unwrap();
+
+    // NOTE: Multi-byte characters end up split between two chunks.
+    let text = "épée ".repeat(1000);
+    db.set_reader("text", Cursor::new(text.as_bytes()), None)
+        .unwrap();
+    assert_eq!(text, db.get("text").unwrap().unwrap());
+
+    db.set_reader("short", "tiny".as_bytes(), None).unwrap();
+    let mut read = String::new();
+    db.get_reader("short")
+        .unwrap()
+        .unwrap()
+        .read_to_string(&mut read)
+        .unwrap();
+    assert_eq!("tiny", read);
+
+    let mut invalid = vec![b'a'; 2500];
+    invalid[2200] = 0xFF;
+    assert!(db.set_reader("invalid", invalid.as_slice(), None).is_err());
+    assert!(db.get("invalid").unwrap().is_none());
+    assert!(db.get_reader("missing").unwrap().is_none());
+
+    let ns = db.namespace("files").unwrap();
+    ns.set_reader("text", text.as_bytes(), None).unwrap();
+    assert_eq!(text, ns.get("text").unwrap().unwrap());
+    assert_eq!(
+        text.len() as u64,
+        ns.get_reader("text").unwrap().unwrap().len()
+    );
+}
+
+#[test]
+fn test_chunks_are_removed_with_the_value() {
+    let temp_dir = tempdir().unwrap();
+
+    {
+        let db = DBConfig::new(temp_dir.path())
+            .chunk_size(1024)
+            .open()
+            .unwrap();
+        db.set("overwritten", &document(4096), None).unwrap();
+        db.set("overwritten", &document(2048), None).unwrap();
+        db.set("removed", &document(4096), None).unwrap();
+        db.remove("removed").unwrap();
+        db.set("expired", &document(4096), Some(Duration::from_secs(1)))
+            .unwrap();
+        db.mset(&[("batch", &document(3072), None), ("batch", "small", None)])
+            .unwrap();
+
+        thread::sleep(Duration::from_millis(2500));
+        assert!(db.get("expired").unwrap().is_none());
+        assert_eq!("small", db.get("batch").unwrap().unwrap());
+    }
+
+    assert_eq!(2, blob_chunks(temp_dir.path(), "blob_tree"));
+}
+
+#[test]
+fn test_reader_fails_once_the_value_is_replaced() {
+    let temp_dir = tempdir().unwrap();
+    let db = DBConfig::new(temp_dir.path())
+        .chunk_size(1024)
+        .open()
+        .unwrap();
+    db.set("doc", &document(4096), None).unwrap();
+
+    let mut reader = db.get_reader("doc").unwrap().unwrap();
+    let mut first = [0; 1024];
+    reader.read_exact(&mut first).unwrap();
+
+    db.set("doc", "replaced", None).unwrap();
+    let mut rest = Vec::new();
+    let err = reader.read_to_end(&mut rest).unwrap_err();
+    assert_eq!(std::io::ErrorKind::NotFound, err.kind());
+}
+
+#[test]
+fn test_changes_carry_chunked_values() {
+    let temp_dir = tempdir().unwrap();
+    let db = DBConfig::new(temp_dir.path())
+        .chunk_size(1024)
+        .open()
+        .unwrap();
+    let doc = document(4096);
+
+    db.set("doc", &doc, None).unwrap();
+    let entries: Vec<_> = db.changes_since(0).map(Result::unwrap).collect();
+    match &entries[0].mutation {
+        Mutation::Set { value, .. } => assert_eq!(doc.as_bytes(), value.as_slice()),
+        other => panic!("Unexpected mutation {other:?}"),
+    }
+
+    db.set("doc", &document(2048), None).unwrap();
+    let entries: Vec<_> = db.changes_since(0).map(Result::unwrap).collect();
+    assert!(
+        matches!(entries[0].mutation, Mutation::Meta { .. }),
+        "The overwritten value should not be reported."
+    );
+    assert!(matches!(entries[1].mutation, Mutation::Set { .. }));
+}
@@ -0,0 +1,20 @@
+//! Helpers shared by the integration tests.
+
+use std::{io::ErrorKind, thread, time::Duration};
+
+/// Runs `open` until the file lock of the database closed just before is released.
+///
+/// sled releases the lock from its background threads, so the database may
+/// still be locked for a moment after being dropped. sled reports a held lock
+/// as an I/O error of kind `Other`.
+pub fn reopen<T>(open: impl Fn() -> Result<T, sled::Error>) -> Result<T, sled::Error> {
+    for _ in 0..100 {
+        match open() {
+            Err(sled::Error::Io(e)) if e.kind() == ErrorKind::Other => {
+                thread::sleep(Duration::from_millis(10));
+            }
+            res => return res,
+        }
+    }
+    open()
+}
@@ -0,0 +1,115 @@
+#![cfg(all(feature = "zstd", feature = "lz4"))]
+
+use std::time::Duration;
+
+use epoch_db::{Compression, DBConfig, oplog::Mutation};
+use tempfile::tempdir;
+
+fn document(i: usize) -> String {
+    let items: Vec<String> = (0..100)
+        .map(|n| format!(r#"{{"id":{n},"owner":"user:{i}","tags":["a","b"]}}"#))
+        .collect();
+    format!("[{}]", items.join(","))
+}
+
+#[test]
+fn test_zstd_compression() {
+    let temp_dir = tempdir().unwrap();
+    let doc = document(1);
+
+    {
+        let db = DBConfig::new(temp_dir.path())
+            .compression(Compression::Zstd { level: 3 })
+            .open()
+            .unwrap();
+        db.set("doc:1", &doc, None).unwrap();
+
+        assert_eq!(doc, db.get("doc:1").unwrap().unwrap());
+        assert_eq!(
+            doc.len() as u64,
+            db.get_metadata("doc:1").unwrap().unwrap().value_len
+        );
+        let stats = db.compression_stats();
+        assert_eq!(1, stats.compressed);
+        assert_eq!(doc.len() as u64, stats.input_bytes);
+        assert!(stats.ratio() < 0.5);
+    }
+
+    let sled_db = sled::open(temp_dir.path()).unwrap();
+    let stored = sled_db
+        .open_tree("data_tree")
+        .unwrap()
+        .get("doc:1")
+        .unwrap()
+        .unwrap();
+    assert!(stored.len() < doc.len());
+}
+
+#[test]
+fn test_threshold() {
+    let temp_dir = tempdir().unwrap();
+    let db = DBConfig::new(temp_dir.path())
+        .compression(Compression::Lz4)
+        .compression_threshold(4096)
+        .open()
+        .unwrap();
+
+    db.set("small", &"a".repeat(1000), None).unwrap();
+    db.set("large", &"a".repeat(5000), None).unwrap();
+
+    let stats = db.compression_stats();
+    assert_eq!(1, stats.compressed);
+    assert_eq!(1, stats.uncompressed);
+    assert_eq!("a".repeat(5000), db.get("large").unwrap().unwrap());
+}
+
+#[test]
+fn test_mixed_codecs() {
+    let temp_dir = tempdir().unwrap();
+
+    {
+        let db = DBConfig::new(temp_dir.path()).open().unwrap();
+        db.set("plain", &document(0), None).unwrap();
+    }
+    {
+        let db = DBConfig::new(temp_dir.path())
+            .compression(Compression::Lz4)
+            .open()
+            .unwrap();
+        db.set("lz4", &document(1), None).unwrap();
+    }
+
+    let db = DBConfig::new(temp_dir.path())
+        .compression(Compression::Zstd { level: 1 })
+        .open()
+        .unwrap();
+    db.mset(&[("zstd", &document(2), Some(Duration::from_secs(60)))])
+        .unwrap();
+
+    let values = db.mget(&["plain", "lz4", "zstd"]).unwrap();
+    for (i, value) in values.into_iter().enumerate() {
+        assert_eq!(document(i), value.unwrap().0);
+    }
+}
+
+#[test]
+fn test_changes_are_decompressed() {
+    let temp_dir = tempdir().unwrap();
+    let db = DBConfig::new(temp_dir.path())
+        .compression(Compression::Zstd { level: 3 })
+        .open()
+        .unwrap();
+    let mut watcher = db.watch("doc:");
+
+    db.set("doc:1", &document(1), None).unwrap();
+
+    let expected = document(1).into_bytes();
+    let entry = watcher
+        .next_timeout(Duration::from_secs(5))
+        .unwrap()
+        .unwrap();
+    assert!(matches!(entry.mutation, Mutation::Set { ref value, .. } if *value == expected));
+
+    let entry = db.changes_since(0).next().unwrap().unwrap();
+    assert!(matches!(entry.mutation, Mutation::Set { ref value, .. } if *value == expected));
+}
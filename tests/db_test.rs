@@ -1,3 +1,5 @@
+#![allow(clippy::needless_borrow)]
+
 use std::{
     sync::Arc,
     thread::{self, sleep},
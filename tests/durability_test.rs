@@ -0,0 +1,83 @@
+use epoch_db::{DBConfig, Durability};
+use tempfile::tempdir;
+
+#[test]
+fn test_flush() {
+    let temp_dir = tempdir().unwrap();
+    let db = DBConfig::new(temp_dir.path())
+        .flush_interval(None)
+        .open()
+        .unwrap();
+
+    db.set("user:1", "Alice", None).unwrap();
+
+    assert!(db.flush().unwrap() > 0);
+    assert_eq!(
+        0,
+        db.flush().unwrap(),
+        "Nothing should be left to flush after a flush."
+    );
+}
+
+#[test]
+fn test_set_with_flushed() {
+    let temp_dir = tempdir().unwrap();
+    let db = DBConfig::new(temp_dir.path())
+        .flush_interval(None)
+        .open()
+        .unwrap();
+
+    db.set_with("user:1", "Alice", None, Durability::Flushed)
+        .unwrap();
+    assert_eq!(0, db.flush().unwrap());
+
+    db.set_with("user:2", "Bob", None, Durability::Buffered)
+        .unwrap();
+    assert!(db.flush().unwrap() > 0);
+
+    db.remove_with("user:1", Durability::Flushed).unwrap();
+    assert_eq!(0, db.flush().unwrap());
+
+    db.mset_with(&[("a", "1", None), ("b", "2", None)], Durability::Flushed)
+        .unwrap();
+    assert_eq!(0, db.flush().unwrap());
+}
+
+#[test]
+fn test_default_durability() {
+    let temp_dir = tempdir().unwrap();
+    let db = DBConfig::new(temp_dir.path())
+        .flush_interval(None)
+        .durability(Durability::Flushed)
+        .open()
+        .unwrap();
+
+    db.set("user:1", "Alice", None).unwrap();
+    assert_eq!(0, db.flush().unwrap());
+
+    db.namespace("sessions")
+        .unwrap()
+        .set("user:1", "token", None)
+        .unwrap();
+    assert_eq!(0, db.flush().unwrap());
+
+    db.set_with("user:2", "Bob", None, Durability::Buffered)
+        .unwrap();
+    assert!(db.flush().unwrap() > 0);
+}
+
+#[test]
+fn test_drop_flushes() {
+    let temp_dir = tempdir().unwrap();
+
+    {
+        let db = DBConfig::new(temp_dir.path())
+            .flush_interval(None)
+            .open()
+            .unwrap();
+        db.set("user:1", "Alice", None).unwrap();
+    }
+
+    let db = DBConfig::new(temp_dir.path()).open().unwrap();
+    assert_eq!("Alice", db.get("user:1").unwrap().unwrap());
+}
@@ -0,0 +1,193 @@
+#![cfg(feature = "encryption")]
+
+use std::{thread, time::Duration};
+
+use epoch_db::{DB, DBConfig, db::encryption::StaticKeys};
+use tempfile::tempdir;
+
+mod common;
+
+use common::reopen;
+
+const KEY_1: [u8; 32] = [1; 32];
+const KEY_2: [u8; 32] = [2; 32];
+
+fn raw_value(path: &std::path::Path, key: &str) -> Vec<u8> {
+    let sled_db = sled::open(path).unwrap();
+    sled_db
+        .open_tree("data_tree")
+        .unwrap()
+        .get(key)
+        .unwrap()
+        .unwrap()
+        .to_vec()
+}
+
+#[test]
+fn test_values_are_encrypted() {
+    let temp_dir = tempdir().unwrap();
+    {
+        let db = DBConfig::new(temp_dir.path())
+            .encryption(StaticKeys::new(1, KEY_1))
+            .chunk_size(64)
+            .open()
+            .unwrap();
+        db.set("secret", "correct horse battery staple", None)
+            .unwrap();
+        db.set("large", &"horse".repeat(100), None).unwrap();
+        assert_eq!("horse".repeat(100), db.get("large").unwrap().unwrap());
+        db.mset(&[("other", "tr0ub4dor", None)]).unwrap();
+
+        assert_eq!(
+            "correct horse battery staple",
+            db.get("secret").unwrap().unwrap()
+        );
+        let found = db.mget(&["other"]).unwrap();
+        assert_eq!("tr0ub4dor", found[0].as_ref().unwrap().0);
+    }
+
+    let sled_db = sled::open(temp_dir.path()).unwrap();
+    let chunks = sled_db.open_tree("blob_tree").unwrap();
+    assert_eq!(8, chunks.len());
+    assert!(chunks.iter().values().all(|c| c.unwrap()[0] == 0xFD));
+    drop((chunks, sled_db));
+
+    let stored = raw_value(temp_dir.path(), "secret");
+    assert!(
+        !stored.windows(b"horse".len()).any(|w| w == b"horse"),
+        "The plaintext should not be stored."
+    );
+}
+
+#[test]
+fn test_wrong_key_is_refused() {
+    let temp_dir = tempdir().unwrap();
+    {
+        let db = DBConfig::new(temp_dir.path())
+            .encryption(StaticKeys::new(1, KEY_1))
+            .open()
+            .unwrap();
+        db.set("secret", "value", None).unwrap();
+    }
+
+    let err = DBConfig::new(temp_dir.path())
+        .encryption(StaticKeys::new(1, KEY_2))
+        .open()
+        .unwrap_err();
+    assert!(matches!(err, sled::Error::Unsupported(_)));
+
+    let err = DB::new(temp_dir.path()).unwrap_err();
+    assert!(
+        err.to_string().contains("encrypted"),
+        "Opening without a key should explain why: {err}"
+    );
+}
+
+#[test]
+fn test_key_rotation() {
+    let temp_dir = tempdir().unwrap();
+    {
+        let db = DBConfig::new(temp_dir.path())
+            .encryption(StaticKeys::new(1, KEY_1))
+            .open()
+            .unwrap();
+        for i in 0..10 {
+            db.set(&format!("key:{i}"), &format!("value:{i}"), None)
+                .unwrap();
+        }
+    }
+
+    {
+        let db = DBConfig::new(temp_dir.path())
+            .encryption(StaticKeys::new(2, KEY_2).with_retired_key(1, KEY_1))
+            .open()
+            .unwrap();
+        assert_eq!("value:3", db.get("key:3").unwrap().unwrap());
+        db.set("key:0", "new", None).unwrap();
+
+        // NOTE: The background thread may have rewritten some of them already.
+        assert!(db.reencrypt().unwrap() <= 9);
+        assert_eq!(0, db.reencrypt().unwrap());
+    }
+
+    let db = DBConfig::new(temp_dir.path())
+        .encryption(StaticKeys::new(2, KEY_2))
+        .open()
+        .unwrap();
+    assert_eq!("new", db.get("key:0").unwrap().unwrap());
+    assert_eq!("value:9", db.get("key:9").unwrap().unwrap());
+}
+
+#[test]
+fn test_corrupted_values_dont_stop_the_rotation() {
+    let temp_dir = tempdir().unwrap();
+    {
+        let db = DBConfig::new(temp_dir.path())
+            .encryption(StaticKeys::new(1, KEY_1))
+            .open()
+            .unwrap();
+        db.set("broken", "value", None).unwrap();
+        db.set("other", "value", None).unwrap();
+    }
+    {
+        let sled_db = reopen(|| sled::open(temp_dir.path())).unwrap();
+        let data = sled_db.open_tree("data_tree").unwrap();
+        let mut stored = data.get("broken").unwrap().unwrap().to_vec();
+        *stored.last_mut().unwrap() ^= 0xFF;
+        data.insert("broken", stored).unwrap();
+        sled_db.flush().unwrap();
+    }
+
+    let db = reopen(|| {
+        DBConfig::new(temp_dir.path())
+            .encryption(StaticKeys::new(2, KEY_2).with_retired_key(1, KEY_1))
+            .open()
+    })
+    .unwrap();
+    db.set("later", "value", Some(Duration::from_secs(1)))
+        .unwrap();
+    thread::sleep(Duration::from_secs(3));
+
+    assert!(
+        db.get_metadata("later").unwrap().is_none(),
+        "The background thread should keep expiring keys."
+    );
+    assert!(db.reencrypt().is_err());
+    assert_eq!("value", db.get("other").unwrap().unwrap());
+    drop(db);
+
+    assert_eq!(
+        2,
+        u32::from_be_bytes(
+            raw_value(temp_dir.path(), "other")[1..5]
+                .try_into()
+                .unwrap()
+        )
+    );
+}
+
+#[test]
+fn test_plaintext_values_are_encrypted() {
+    let temp_dir = tempdir().unwrap();
+    {
+        let db = DB::new(temp_dir.path()).unwrap();
+        db.set("old", "plaintext", None).unwrap();
+        let ns = db.namespace("ns").unwrap();
+        ns.set("old", "in a namespace", None).unwrap();
+    }
+
+    let db = DBConfig::new(temp_dir.path())
+        .encryption(StaticKeys::new(1, KEY_1))
+        .open()
+        .unwrap();
+    assert_eq!("plaintext", db.get("old").unwrap().unwrap());
+    db.reencrypt().unwrap();
+    assert_eq!(0, db.reencrypt().unwrap());
+
+    let ns = db.namespace("ns").unwrap();
+    assert_eq!("in a namespace", ns.get("old").unwrap().unwrap());
+    drop(ns);
+    drop(db);
+
+    assert_eq!(0xFD, raw_value(temp_dir.path(), "old")[0]);
+}
@@ -0,0 +1,265 @@
+use std::time::Duration;
+
+use epoch_db::{
+    DB, DBConfig,
+    db::{
+        errors::TransientError,
+        export::{ExportFormat, ExportOptions, ImportOptions},
+    },
+};
+use tempfile::tempdir;
+
+#[test]
+fn test_binary_dump_round_trip() {
+    let source_dir = tempdir().unwrap();
+    let target_dir = tempdir().unwrap();
+    let large = "0123456789abcdef".repeat(256);
+
+    let source = DBConfig::new(source_dir.path())
+        .chunk_size(1024)
+        .open()
+        .unwrap();
+    source.set("user:1", "alice", None).unwrap();
+    source
+        .set("user:2", "bob", Some(Duration::from_secs(3600)))
+        .unwrap();
+    source.set("doc", &large, None).unwrap();
+    source.increment_frequency("user:1").unwrap();
+    source.increment_frequency("user:1").unwrap();
+    let sessions = source.namespace("sessions").unwrap();
+    sessions.set("abc", "token", None).unwrap();
+
+    let mut dump = Vec::new();
+    let options = ExportOptions::new(ExportFormat::Binary);
+    assert_eq!(source.export(&mut dump, &options).unwrap(), 4);
+
+    let target = DB::new(target_dir.path()).unwrap();
+    let report = target
+        .import(dump.as_slice(), &ImportOptions::new(ExportFormat::Binary))
+        .unwrap();
+    assert_eq!(report.imported, 4);
+    assert_eq!(report.expired, 0);
+
+    assert_eq!(target.get("user:1").unwrap().as_deref(), Some("alice"));
+    assert_eq!(target.get("doc").unwrap(), Some(large));
+    assert_eq!(
+        target.namespace("sessions").unwrap().get("abc").unwrap(),
+        Some("token".to_string())
+    );
+
+    let before = source.get_metadata("user:2").unwrap().unwrap();
+    let after = target.get_metadata("user:2").unwrap().unwrap();
+    assert_eq!(after.ttl, before.ttl);
+    assert_eq!(after.created_at, before.created_at);
+    assert_eq!(target.get_metadata("user:1").unwrap().unwrap().freq, 2);
+}
+
+#[test]
+fn test_tags_round_trip() {
+    let formats = [
+        ExportFormat::Binary,
+        #[cfg(feature = "json")]
+        ExportFormat::JsonLines,
+        #[cfg(feature = "csv")]
+        ExportFormat::Csv,
+    ];
+
+    let source_dir = tempdir().unwrap();
+    let source = DB::new(source_dir.path()).unwrap();
+    source.set("user:1", "alice", None).unwrap();
+    source
+        .set_tags("user:1", &["tenant:1", "a, \"quoted\" tag "])
+        .unwrap();
+    source.set("user:2", "bob", None).unwrap();
+    let sessions = source.namespace("sessions").unwrap();
+    sessions.set("abc", "token", None).unwrap();
+    sessions.set_tags("abc", &["tenant:1"]).unwrap();
+
+    for format in formats {
+        let mut out = Vec::new();
+        source
+            .export(&mut out, &ExportOptions::new(format))
+            .unwrap();
+
+        let target_dir = tempdir().unwrap();
+        let target = DB::new(target_dir.path()).unwrap();
+        target.set("user:2", "stale", None).unwrap();
+        target.set_tags("user:2", &["stale"]).unwrap();
+        let report = target
+            .import(out.as_slice(), &ImportOptions::new(format))
+            .unwrap();
+        assert_eq!(report.imported, 3, "{format:?}");
+
+        assert_eq!(
+            source.get_metadata("user:1").unwrap().unwrap().tags,
+            target.get_metadata("user:1").unwrap().unwrap().tags,
+            "{format:?}"
+        );
+        assert_eq!(
+            vec!["user:1"],
+            target.keys_with_tag("a, \"quoted\" tag ").unwrap(),
+            "{format:?}"
+        );
+        assert!(
+            target.keys_with_tag("stale").unwrap().is_empty(),
+            "Importing a key should replace its tags, {format:?}"
+        );
+        assert!(
+            target
+                .get_metadata("user:2")
+                .unwrap()
+                .unwrap()
+                .tags
+                .is_empty()
+        );
+        assert_eq!(
+            vec!["abc"],
+            target
+                .namespace("sessions")
+                .unwrap()
+                .keys_with_tag("tenant:1")
+                .unwrap(),
+            "{format:?}"
+        );
+    }
+}
+
+#[test]
+fn test_prefix_filters_and_reset_frequencies() {
+    let source_dir = tempdir().unwrap();
+    let target_dir = tempdir().unwrap();
+
+    let source = DB::new(source_dir.path()).unwrap();
+    for key in ["user:1", "user:2", "order:1"] {
+        source.set(key, "value", None).unwrap();
+        source.increment_frequency(key).unwrap();
+    }
+
+    let mut dump = Vec::new();
+    let options = ExportOptions::new(ExportFormat::Binary).prefix("user:");
+    assert_eq!(source.export(&mut dump, &options).unwrap(), 2);
+
+    let target = DB::new(target_dir.path()).unwrap();
+    let options = ImportOptions::new(ExportFormat::Binary)
+        .prefix("user:2")
+        .reset_frequencies(true);
+    let report = target.import(dump.as_slice(), &options).unwrap();
+    assert_eq!(report.imported, 1);
+
+    assert!(target.get("user:1").unwrap().is_none());
+    assert!(target.get("order:1").unwrap().is_none());
+    assert_eq!(target.get_metadata("user:2").unwrap().unwrap().freq, 0);
+}
+
+#[test]
+fn test_import_rejects_malformed_dumps() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+    let options = ImportOptions::new(ExportFormat::Binary);
+
+    let err = db.import(&b"not a dump"[..], &options).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<TransientError>(),
+        Some(TransientError::UnsupportedDump)
+    ));
+
+    db.set("a", "1", None).unwrap();
+    db.set("b", "2", None).unwrap();
+    let mut dump = Vec::new();
+    db.export(&mut dump, &ExportOptions::new(ExportFormat::Binary))
+        .unwrap();
+    dump.truncate(dump.len() - 1);
+
+    let other_dir = tempdir().unwrap();
+    let other = DB::new(other_dir.path()).unwrap();
+    let err = other.import(dump.as_slice(), &options).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<TransientError>(),
+        Some(TransientError::MalformedRecord { record: 2 })
+    ));
+    assert_eq!(other.get("a").unwrap().as_deref(), Some("1"));
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_json_lines_expired_and_rebased_ttls() {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let now = || {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    };
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+    let past = now() - 60;
+    let lines = format!(
+        "{{\"namespace\":null,\"key\":\"gone\",\"value\":\"1\",\"freq\":3,\"created_at\":{past},\"expires_at\":{past},\"ttl_remaining\":600}}\n\
+         \n\
+         {{\"namespace\":\"cache\",\"key\":\"kept\",\"value\":\"2\",\"freq\":5,\"created_at\":{past},\"expires_at\":null,\"ttl_remaining\":null}}\n"
+    );
+
+    let options = ImportOptions::new(ExportFormat::JsonLines);
+    let report = db.import(lines.as_bytes(), &options).unwrap();
+    assert_eq!((report.imported, report.expired), (1, 1));
+    assert!(db.get("gone").unwrap().is_none());
+    let meta = db
+        .namespace("cache")
+        .unwrap()
+        .get_metadata("kept")
+        .unwrap()
+        .unwrap();
+    assert_eq!((meta.freq, meta.created_at, meta.ttl), (5, past, None));
+
+    let report = db
+        .import(lines.as_bytes(), &options.clone().rebase_ttl(true))
+        .unwrap();
+    assert_eq!((report.imported, report.expired), (2, 0));
+    let ttl = db.get_metadata("gone").unwrap().unwrap().ttl.unwrap();
+    assert!(ttl >= now() + 590 && ttl <= now() + 600);
+
+    let mut out = Vec::new();
+    db.export(&mut out, &ExportOptions::new(ExportFormat::JsonLines))
+        .unwrap();
+    assert_eq!(String::from_utf8(out).unwrap().lines().count(), 2);
+}
+
+#[cfg(feature = "csv")]
+#[test]
+fn test_csv_round_trip() {
+    let source_dir = tempdir().unwrap();
+    let target_dir = tempdir().unwrap();
+
+    let source = DB::new(source_dir.path()).unwrap();
+    source.set("quoted", "a, \"b\"\nc", None).unwrap();
+    source
+        .namespace("ns")
+        .unwrap()
+        .set("k", "v", Some(Duration::from_secs(60)))
+        .unwrap();
+
+    let mut out = Vec::new();
+    source
+        .export(&mut out, &ExportOptions::new(ExportFormat::Csv))
+        .unwrap();
+    let csv = String::from_utf8(out).unwrap();
+    assert!(csv.starts_with("namespace,key,value,freq,created_at,expires_at,ttl_remaining,tags\n"));
+
+    let target = DB::new(target_dir.path()).unwrap();
+    let report = target
+        .import(csv.as_bytes(), &ImportOptions::new(ExportFormat::Csv))
+        .unwrap();
+    assert_eq!(report.imported, 2);
+    assert_eq!(
+        target.get("quoted").unwrap().as_deref(),
+        Some("a, \"b\"\nc")
+    );
+    let meta = target
+        .namespace("ns")
+        .unwrap()
+        .get_metadata("k")
+        .unwrap()
+        .unwrap();
+    assert!(meta.ttl.is_some());
+}
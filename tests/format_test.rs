@@ -0,0 +1,117 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use epoch_db::{DB, Metadata, db::migration::FORMAT_VERSION};
+use tempfile::tempdir;
+
+mod common;
+
+use common::reopen;
+
+#[test]
+fn test_unversioned_metadata_is_migrated() {
+    let dir = tempdir().unwrap();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    {
+        let sled_db = sled::open(dir.path()).unwrap();
+        // NOTE: The original layout of `Metadata`: frequency, creation time and TTL.
+        let legacy =
+            bincode::serde::encode_to_vec((3u64, now, None::<u64>), bincode::config::standard())
+                .unwrap();
+        sled_db
+            .open_tree("data_tree")
+            .unwrap()
+            .insert("key", "value")
+            .unwrap();
+        sled_db
+            .open_tree("freq_tree")
+            .unwrap()
+            .insert("key", legacy)
+            .unwrap();
+        sled_db.flush().unwrap();
+    }
+
+    {
+        let db = reopen(|| DB::new(dir.path())).unwrap();
+        let meta = db.get_metadata("key").unwrap().unwrap();
+        assert_eq!(3, meta.freq);
+        assert_eq!(now, meta.created_at);
+        assert_eq!(1, meta.version);
+        assert_eq!(5, meta.value_len, "The value length should be filled in.");
+        assert_eq!(
+            vec![("key".to_string(), 3)],
+            db.top_k_by_frequency(1).unwrap()
+        );
+    }
+
+    let sled_db = reopen(|| sled::open(dir.path())).unwrap();
+    let stored = sled_db
+        .open_tree("freq_tree")
+        .unwrap()
+        .get("key")
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        0xFF, stored[0],
+        "The metadata should be rewritten in the envelope."
+    );
+    let version = sled_db
+        .open_tree("state_tree")
+        .unwrap()
+        .get("format_version")
+        .unwrap()
+        .unwrap();
+    assert_eq!(FORMAT_VERSION.to_be_bytes(), version.as_ref());
+}
+
+#[test]
+fn test_newer_format_is_refused() {
+    let dir = tempdir().unwrap();
+    {
+        let sled_db = sled::open(dir.path()).unwrap();
+        sled_db
+            .open_tree("state_tree")
+            .unwrap()
+            .insert("format_version", &(FORMAT_VERSION + 1).to_be_bytes())
+            .unwrap();
+        sled_db.flush().unwrap();
+    }
+
+    let err = reopen(|| DB::new(dir.path())).unwrap_err();
+    assert!(matches!(err, sled::Error::Unsupported(_)));
+    assert!(err.to_string().contains("format version"));
+}
+
+#[test]
+fn test_reopening_keeps_data() {
+    let dir = tempdir().unwrap();
+    {
+        let db = DB::new(dir.path()).unwrap();
+        db.set("key", "value", Some(Duration::from_secs(60)))
+            .unwrap();
+        db.increment_frequency("key").unwrap();
+    }
+
+    let db = reopen(|| DB::new(dir.path())).unwrap();
+    assert_eq!("value", db.get("key").unwrap().unwrap());
+    let meta = db.get_metadata("key").unwrap().unwrap();
+    assert_eq!(1, meta.freq);
+    assert!(meta.ttl.is_some());
+    assert!(db.first_seq().unwrap().is_some(), "The log should be kept.");
+}
+
+#[test]
+fn test_metadata_envelope() {
+    let meta = Metadata::new(Some(42));
+    let mut bytes = meta.to_u8().unwrap();
+    assert_eq!(meta, Metadata::from_u8(&bytes).unwrap());
+
+    bytes[1] = u8::MAX;
+    assert!(
+        Metadata::from_u8(&bytes).is_err(),
+        "Metadata from a newer version should not be misread."
+    );
+}
@@ -0,0 +1,207 @@
+use std::{thread::sleep, time::Duration};
+
+use epoch_db::{
+    DB, DBConfig, FrequencyTracking,
+    namespace::{EvictionPolicy, NamespaceConfig},
+};
+use tempfile::tempdir;
+
+mod common;
+
+use common::reopen;
+
+#[test]
+fn test_manual_tracking_by_default() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+
+    db.set("user:1", "Alice", None).unwrap();
+    for _ in 0..5 {
+        db.get("user:1").unwrap();
+    }
+
+    assert_eq!(0, db.get_metadata("user:1").unwrap().unwrap().freq);
+}
+
+#[test]
+fn test_reads_without_frequency_changes_are_not_logged() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+
+    db.set("user:1", "Alice", None).unwrap();
+    let seq = db.last_seq().unwrap();
+    for _ in 0..5 {
+        db.get("user:1").unwrap();
+    }
+    sleep(Duration::from_millis(500));
+
+    assert_eq!(seq, db.last_seq().unwrap());
+}
+
+#[test]
+fn test_reads_of_corrupted_metadata_are_skipped() {
+    let temp_dir = tempdir().unwrap();
+    {
+        let db = DB::new(temp_dir.path()).unwrap();
+        db.set("broken", "value", None).unwrap();
+        db.set("later", "value", Some(Duration::from_secs(2)))
+            .unwrap();
+    }
+    {
+        let sled_db = reopen(|| sled::open(temp_dir.path())).unwrap();
+        let meta = sled_db.open_tree("freq_tree").unwrap();
+        meta.insert("broken", &[0xFF, 0xEE, 1, 2, 3]).unwrap();
+        sled_db.flush().unwrap();
+    }
+
+    let db = reopen(|| DB::new(temp_dir.path())).unwrap();
+    db.get("broken").unwrap();
+    sleep(Duration::from_secs(4));
+
+    assert_eq!(
+        None,
+        db.get("later").unwrap(),
+        "The background thread should keep expiring keys."
+    );
+}
+
+#[test]
+fn test_sampled_tracking() {
+    let temp_dir = tempdir().unwrap();
+    let db = DBConfig::new(temp_dir.path())
+        .frequency_tracking(FrequencyTracking::Sampled { one_in: 4 })
+        .open()
+        .unwrap();
+
+    db.set("user:1", "Alice", None).unwrap();
+    for _ in 0..3 {
+        db.get("user:1").unwrap();
+    }
+    assert_eq!(0, db.get_metadata("user:1").unwrap().unwrap().freq);
+
+    db.get("user:1").unwrap();
+    assert_eq!(
+        4,
+        db.get_metadata("user:1").unwrap().unwrap().freq,
+        "The sampled read should account for the reads that were skipped."
+    );
+
+    db.get("user:missing").unwrap();
+    assert_eq!(4, db.get_metadata("user:1").unwrap().unwrap().freq);
+}
+
+#[test]
+fn test_buffered_tracking() {
+    let temp_dir = tempdir().unwrap();
+    let db = DBConfig::new(temp_dir.path())
+        .frequency_tracking(FrequencyTracking::Buffered)
+        .open()
+        .unwrap();
+
+    db.set("user:1", "Alice", None).unwrap();
+    db.set("user:2", "Bob", None).unwrap();
+    for _ in 0..10 {
+        db.get("user:1").unwrap();
+    }
+    db.mget(&["user:1", "user:2"]).unwrap();
+
+    sleep(Duration::from_millis(500));
+
+    assert_eq!(11, db.get_metadata("user:1").unwrap().unwrap().freq);
+    assert_eq!(1, db.get_metadata("user:2").unwrap().unwrap().freq);
+}
+
+#[test]
+fn test_buffered_tracking_on_drop() {
+    let temp_dir = tempdir().unwrap();
+
+    {
+        let db = DBConfig::new(temp_dir.path())
+            .frequency_tracking(FrequencyTracking::Buffered)
+            .open()
+            .unwrap();
+        let cache = db.namespace("cache").unwrap();
+        cache.set("a", "1", None).unwrap();
+        for _ in 0..3 {
+            cache.get("a").unwrap();
+        }
+    }
+
+    let db = DB::new(temp_dir.path()).unwrap();
+    assert_eq!(
+        3,
+        db.namespace("cache")
+            .unwrap()
+            .get_metadata("a")
+            .unwrap()
+            .unwrap()
+            .freq,
+        "Buffered reads should be written when the DB is closed."
+    );
+}
+
+#[test]
+fn test_frequency_decay() {
+    let temp_dir = tempdir().unwrap();
+    let db = DBConfig::new(temp_dir.path())
+        .frequency_half_life(Some(Duration::from_secs(1)))
+        .open()
+        .unwrap();
+
+    db.set("user:1", "Alice", None).unwrap();
+    for _ in 0..4 {
+        db.increment_frequency("user:1").unwrap();
+    }
+
+    let meta = db.get_metadata("user:1").unwrap().unwrap();
+    assert_eq!(4, meta.freq);
+    assert_eq!(4, meta.windows.hour);
+    assert_eq!(4, meta.windows.day);
+
+    sleep(Duration::from_secs(2));
+
+    let meta = db.get_metadata("user:1").unwrap().unwrap();
+    assert_eq!(4, meta.freq, "The raw frequency should not decay.");
+    assert!(
+        meta.score > 0.0 && meta.score <= 1.0,
+        "The score should have halved at least twice, got {}",
+        meta.score
+    );
+}
+
+#[test]
+fn test_eviction_uses_decayed_score() {
+    let temp_dir = tempdir().unwrap();
+    let db = DBConfig::new(temp_dir.path())
+        .frequency_half_life(Some(Duration::from_secs(1)))
+        .open()
+        .unwrap();
+
+    let cache = db
+        .namespace_with(
+            "cache",
+            NamespaceConfig::new().eviction(EvictionPolicy::Lifecycle {
+                grace_period: Duration::from_secs(3),
+                min_freq: 1,
+            }),
+        )
+        .unwrap();
+
+    cache.set("old", "value", None).unwrap();
+    for _ in 0..8 {
+        cache.increment_frequency("old").unwrap();
+    }
+
+    sleep(Duration::from_secs(5));
+
+    cache.set("new", "value", None).unwrap();
+    cache.increment_frequency("new").unwrap();
+
+    sleep(Duration::from_millis(1500));
+
+    assert!(
+        cache.get("old").unwrap().is_none(),
+        "A key that was hot but went cold should be evicted."
+    );
+    assert!(cache.get("new").unwrap().is_some());
+}
@@ -0,0 +1,149 @@
+use std::{
+    sync::{Arc, Barrier},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use epoch_db::{DB, db::errors::TransientError};
+use tempfile::tempdir;
+
+const TTL: Duration = Duration::from_secs(60);
+
+fn is_lost(err: Box<dyn std::error::Error>) -> bool {
+    matches!(
+        err.downcast_ref::<TransientError>(),
+        Some(TransientError::LeaseLost)
+    )
+}
+
+#[test]
+fn test_leases_exclude_other_owners() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+
+    let lease = db.acquire_lease("leader", "node-1", TTL).unwrap().unwrap();
+    assert_eq!("node-1", lease.owner());
+    assert!(db.acquire_lease("leader", "node-2", TTL).unwrap().is_none());
+    assert!(db.get_metadata("leader").unwrap().unwrap().ttl.is_some());
+
+    lease.renew(TTL).unwrap();
+    let first = lease.token();
+    lease.release().unwrap();
+
+    let lease = db.acquire_lease("leader", "node-2", TTL).unwrap().unwrap();
+    assert!(lease.token() > first, "Fencing tokens should increase.");
+    drop(lease);
+    assert!(
+        db.get("leader").unwrap().is_none(),
+        "Dropping the guard should release the lease."
+    );
+}
+
+#[test]
+fn test_expired_leases_are_lost() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+
+    let lease = db
+        .acquire_lease("leader", "node-1", Duration::from_secs(1))
+        .unwrap()
+        .unwrap();
+    thread::sleep(Duration::from_millis(2500));
+
+    assert!(
+        db.get_metadata("leader").unwrap().is_none(),
+        "The background thread should remove the expired lease."
+    );
+    assert!(is_lost(lease.renew(TTL).unwrap_err()));
+    let next = db.acquire_lease("leader", "node-2", TTL).unwrap().unwrap();
+    assert!(is_lost(lease.release().unwrap_err()));
+    next.renew(TTL).unwrap();
+}
+
+#[test]
+fn test_leases_last_their_whole_ttl() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+
+    // NOTE: Acquired just before a second boundary, where a truncated deadline
+    // would free the lease right after the boundary.
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let boundary = Duration::from_secs(now.as_secs() + 1);
+    thread::sleep((boundary - now).saturating_sub(Duration::from_millis(50)));
+    let lease = db
+        .acquire_lease("leader", "node-1", Duration::from_secs(1))
+        .unwrap()
+        .unwrap();
+    thread::sleep(Duration::from_millis(200));
+
+    assert!(db.acquire_lease("leader", "node-2", TTL).unwrap().is_none());
+    lease.renew(TTL).unwrap();
+}
+
+#[test]
+fn test_owner_takes_over_its_own_lease() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+
+    let old = db.acquire_lease("leader", "node-1", TTL).unwrap().unwrap();
+    let new = db.acquire_lease("leader", "node-1", TTL).unwrap().unwrap();
+    assert!(new.token() > old.token());
+    assert!(is_lost(old.renew(TTL).unwrap_err()));
+
+    drop(old);
+    assert!(
+        db.acquire_lease("leader", "node-2", TTL).unwrap().is_none(),
+        "Dropping a lost lease should not release the new one."
+    );
+    new.renew(TTL).unwrap();
+}
+
+#[test]
+fn test_only_one_concurrent_owner_wins() {
+    let temp_dir = tempdir().unwrap();
+    let db = Arc::new(DB::new(temp_dir.path()).unwrap());
+    let barrier = Arc::new(Barrier::new(8));
+
+    let handles: Vec<_> = (0..8)
+        .map(|i| {
+            let db = Arc::clone(&db);
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || {
+                let locks = db.namespace("locks").unwrap();
+                barrier.wait();
+                let lease = locks
+                    .acquire_lease("job", &format!("worker-{i}"), TTL)
+                    .unwrap();
+                let won = lease.is_some();
+                barrier.wait();
+                won
+            })
+        })
+        .collect();
+    let winners = handles
+        .into_iter()
+        .map(|h| h.join().unwrap())
+        .filter(|won| *won)
+        .count();
+
+    assert_eq!(1, winners);
+    assert!(db.get("job").unwrap().is_none());
+    assert!(
+        db.namespace("locks").unwrap().get("job").unwrap().is_none(),
+        "Every lease should be released when its thread ends."
+    );
+}
+
+#[test]
+fn test_keys_that_are_not_leases() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+    db.set("config", "not a lease", None).unwrap();
+
+    let err = db.acquire_lease("config", "node-1", TTL).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<TransientError>(),
+        Some(TransientError::NotALease)
+    ));
+    assert_eq!(Some("not a lease".to_string()), db.get("config").unwrap());
+}
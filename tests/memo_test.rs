@@ -0,0 +1,157 @@
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::Duration,
+};
+
+use epoch_db::{DB, namespace::NamespaceConfig};
+use tempfile::tempdir;
+
+#[test]
+fn test_get_or_insert_with() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+    let calls = AtomicUsize::new(0);
+    let compute = || {
+        calls.fetch_add(1, Ordering::SeqCst);
+        "Alice".to_string()
+    };
+
+    let ttl = Some(Duration::from_secs(1));
+    assert_eq!(
+        "Alice",
+        db.get_or_insert_with("user:1", ttl, compute).unwrap()
+    );
+    assert_eq!(
+        "Alice",
+        db.get_or_insert_with("user:1", ttl, compute).unwrap()
+    );
+    assert_eq!(1, calls.load(Ordering::SeqCst));
+    assert!(db.get_metadata("user:1").unwrap().unwrap().ttl.is_some());
+
+    // NOTE: An expired value is computed again, even if it wasn't removed yet.
+    thread::sleep(Duration::from_secs(2));
+    assert_eq!(
+        "Alice",
+        db.get_or_insert_with("user:1", ttl, compute).unwrap()
+    );
+    assert_eq!(2, calls.load(Ordering::SeqCst));
+}
+
+#[test]
+fn test_failed_computations_set_nothing() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+
+    let res = db.try_get_or_insert_with("user:1", None, || Err("backend down"));
+    assert_eq!("backend down", res.unwrap_err().to_string());
+    assert_eq!(None, db.get("user:1").unwrap());
+
+    let res = db.try_get_or_insert_with("user:1", None, || Ok::<_, String>("Bob".to_string()));
+    assert_eq!("Bob", res.unwrap());
+    assert_eq!(None, db.get_metadata("user:1").unwrap().unwrap().ttl);
+}
+
+#[test]
+fn test_namespace_get_or_insert_with() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+    let config = NamespaceConfig::new().default_ttl(Duration::from_secs(60));
+    let cache = db.namespace_with("cache", config).unwrap();
+
+    assert_eq!(
+        "1",
+        cache
+            .get_or_insert_with("k", None, || "1".to_string())
+            .unwrap()
+    );
+    assert_eq!(
+        "1",
+        cache
+            .get_or_insert_with("k", None, || "2".to_string())
+            .unwrap()
+    );
+    assert!(cache.get_metadata("k").unwrap().unwrap().ttl.is_some());
+    assert_eq!(None, db.get("k").unwrap());
+}
+
+#[cfg(feature = "async")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_concurrent_misses_compute_once() {
+    use std::sync::Arc;
+
+    use epoch_db::{async_db::AsyncDB, db::errors::TransientError};
+
+    let temp_dir = tempdir().unwrap();
+    let db = AsyncDB::open(temp_dir.path()).await.unwrap();
+    let cache = db.namespace("cache").await.unwrap();
+    let calls = Arc::new(AtomicUsize::new(0));
+
+    let tasks: Vec<_> = (0..10)
+        .map(|_| {
+            let (cache, calls) = (cache.clone(), calls.clone());
+            tokio::spawn(async move {
+                cache
+                    .get_or_insert_with("report", None, || async move {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(200)).await;
+                        Ok::<_, TransientError>("done".to_string())
+                    })
+                    .await
+                    .unwrap()
+            })
+        })
+        .collect();
+    for task in tasks {
+        assert_eq!("done", task.await.unwrap());
+    }
+    assert_eq!(1, calls.load(Ordering::SeqCst));
+
+    // NOTE: The same key of another keyspace is another value.
+    let val = db
+        .get_or_insert_with("report", None, || async {
+            Err::<String, _>(TransientError::ReadOnly)
+        })
+        .await;
+    assert!(matches!(val, Err(TransientError::ReadOnly)));
+    assert_eq!(None, db.get("report").await.unwrap());
+}
+
+#[cfg(feature = "async")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_stale_values_are_revalidated_in_the_background() {
+    use std::sync::Arc;
+
+    use epoch_db::{async_db::AsyncDB, db::errors::TransientError};
+
+    let temp_dir = tempdir().unwrap();
+    let db = AsyncDB::open(temp_dir.path()).await.unwrap();
+    let calls = Arc::new(AtomicUsize::new(0));
+    let read = |db: AsyncDB, calls: Arc<AtomicUsize>| async move {
+        let (ttl, stale) = (Duration::from_secs(1), Duration::from_secs(5));
+        db.get_or_revalidate("price", ttl, stale, move || async move {
+            let n = calls.fetch_add(1, Ordering::SeqCst) + 1;
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            Ok::<_, TransientError>(format!("v{n}"))
+        })
+        .await
+        .unwrap()
+    };
+
+    assert_eq!("v1", read(db.clone(), calls.clone()).await);
+    assert_eq!("v1", read(db.clone(), calls.clone()).await);
+    assert_eq!(1, calls.load(Ordering::SeqCst));
+
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    for _ in 0..5 {
+        assert_eq!("v1", read(db.clone(), calls.clone()).await);
+    }
+    for _ in 0..100 {
+        if db.get("price").await.unwrap().as_deref() == Some("v2") {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!("v2", read(db.clone(), calls.clone()).await);
+    assert_eq!(2, calls.load(Ordering::SeqCst));
+}
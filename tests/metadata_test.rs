@@ -0,0 +1,99 @@
+use std::{collections::BTreeSet, thread::sleep, time::Duration};
+
+use epoch_db::{DB, metadata::MAX_TAGS};
+use tempfile::tempdir;
+
+#[test]
+fn test_writes_update_metadata() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+
+    db.set("user:1", "Alice", None).unwrap();
+    let first = db.get_metadata("user:1").unwrap().unwrap();
+    assert_eq!(1, first.version);
+    assert_eq!(5, first.value_len);
+    assert_eq!(first.created_at, first.updated_at);
+
+    sleep(Duration::from_millis(1100));
+    db.set("user:1", "Alice Smith", None).unwrap();
+    let second = db.get_metadata("user:1").unwrap().unwrap();
+    assert_eq!(2, second.version);
+    assert_eq!(11, second.value_len);
+    assert_eq!(first.created_at, second.created_at);
+    assert!(second.updated_at > first.updated_at);
+
+    db.mset(&[("user:1", "A", None)]).unwrap();
+    assert_eq!(3, db.get_metadata("user:1").unwrap().unwrap().version);
+}
+
+#[test]
+fn test_tags() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+
+    db.set("user:1", "Alice", None).unwrap();
+    assert!(db.set_tags("user:1", &["tenant:42", "vip"]).unwrap());
+    assert!(!db.set_tags("user:missing", &["vip"]).unwrap());
+
+    db.set("user:1", "Alice Smith", None).unwrap();
+    let meta = db.get_metadata("user:1").unwrap().unwrap();
+    assert_eq!(
+        BTreeSet::from(["tenant:42".to_string(), "vip".to_string()]),
+        meta.tags,
+        "Tags should survive an overwrite."
+    );
+    assert_eq!(2, meta.version, "Tagging shouldn't bump the version.");
+
+    db.set_tags("user:1", &[]).unwrap();
+    assert!(db.get_metadata("user:1").unwrap().unwrap().tags.is_empty());
+}
+
+#[test]
+fn test_invalid_tags() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+    db.set("user:1", "Alice", None).unwrap();
+
+    assert!(db.set_tags("user:1", &[""]).is_err());
+    let long = "x".repeat(1000);
+    assert!(db.set_tags("user:1", &[&long]).is_err());
+    let many: Vec<String> = (0..=MAX_TAGS).map(|i| format!("tag:{i}")).collect();
+    let many: Vec<&str> = many.iter().map(String::as_str).collect();
+    assert!(db.set_tags("user:1", &many).is_err());
+
+    assert!(db.get_metadata("user:1").unwrap().unwrap().tags.is_empty());
+}
+
+#[test]
+fn test_reads_update_last_access() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+
+    db.set("user:1", "Alice", None).unwrap();
+    let created = db.get_metadata("user:1").unwrap().unwrap();
+    assert_eq!(created.created_at, created.last_accessed_at);
+
+    sleep(Duration::from_millis(1100));
+    db.get("user:1").unwrap();
+    sleep(Duration::from_millis(500));
+
+    let read = db.get_metadata("user:1").unwrap().unwrap();
+    assert!(read.last_accessed_at > created.last_accessed_at);
+    assert_eq!(0, read.freq, "Manual tracking shouldn't count reads.");
+    assert_eq!(created.updated_at, read.updated_at);
+}
+
+#[test]
+fn test_namespace_tags() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+    let cache = db.namespace("cache").unwrap();
+
+    cache.set("page:1", "<html>", None).unwrap();
+    assert!(cache.set_tags("page:1", &["site:a"]).unwrap());
+    assert!(db.get_metadata("page:1").unwrap().is_none());
+
+    let meta = cache.get_metadata("page:1").unwrap().unwrap();
+    assert!(meta.tags.contains("site:a"));
+    assert_eq!(6, meta.value_len);
+}
@@ -0,0 +1,160 @@
+use std::{thread::sleep, time::Duration};
+
+use epoch_db::{
+    DB,
+    namespace::{EvictionPolicy, NamespaceConfig},
+};
+use tempfile::tempdir;
+
+#[test]
+fn test_namespace_isolation() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+
+    let sessions = db.namespace("sessions").unwrap();
+
+    db.set("user:1", "default", None).unwrap();
+    sessions.set("user:1", "session", None).unwrap();
+
+    assert_eq!("default", db.get("user:1").unwrap().unwrap());
+    assert_eq!("session", sessions.get("user:1").unwrap().unwrap());
+
+    sessions.remove("user:1").unwrap();
+
+    assert!(sessions.get("user:1").unwrap().is_none());
+    assert_eq!(
+        "default",
+        db.get("user:1").unwrap().unwrap(),
+        "Removing a key from a namespace should not touch the default keyspace."
+    );
+}
+
+#[test]
+fn test_namespace_default_ttl() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+
+    let sessions = db
+        .namespace_with(
+            "sessions",
+            NamespaceConfig::new().default_ttl(Duration::from_secs(2)),
+        )
+        .unwrap();
+
+    sessions.set("session:1", "token", None).unwrap();
+    sessions
+        .set("session:2", "token", Some(Duration::from_secs(120)))
+        .unwrap();
+
+    assert!(
+        sessions
+            .get_metadata("session:1")
+            .unwrap()
+            .unwrap()
+            .ttl
+            .is_some()
+    );
+
+    sleep(Duration::from_secs(3));
+
+    assert!(
+        sessions.get("session:1").unwrap().is_none(),
+        "Key should expire with the default TTL of its namespace."
+    );
+    assert!(
+        sessions.get("session:2").unwrap().is_some(),
+        "An explicit TTL should override the default TTL."
+    );
+    assert_eq!(1, sessions.stats().unwrap().expirations);
+}
+
+#[test]
+fn test_namespace_clear() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+
+    let cache = db
+        .namespace_with(
+            "cache",
+            NamespaceConfig::new().default_ttl(Duration::from_secs(60)),
+        )
+        .unwrap();
+    let other = db.namespace("other").unwrap();
+
+    for i in 0..10 {
+        cache.set(&format!("key:{i}"), "value", None).unwrap();
+    }
+    other.set("key:0", "value", None).unwrap();
+
+    cache.clear().unwrap();
+
+    assert_eq!(0, cache.stats().unwrap().keys);
+    assert!(cache.get("key:0").unwrap().is_none());
+    assert!(other.get("key:0").unwrap().is_some());
+    assert_eq!(
+        NamespaceConfig::new().default_ttl(Duration::from_secs(60)),
+        cache.config().unwrap(),
+        "Clearing a namespace should keep its settings."
+    );
+
+    cache.set("key:0", "again", None).unwrap();
+    assert_eq!("again", cache.get("key:0").unwrap().unwrap());
+}
+
+#[test]
+fn test_namespace_drop_and_reopen() {
+    let temp_dir = tempdir().unwrap();
+
+    {
+        let db = DB::new(temp_dir.path()).unwrap();
+        db.namespace("kept").unwrap().set("a", "1", None).unwrap();
+        let dropped = db.namespace("dropped").unwrap();
+        dropped.set("b", "2", None).unwrap();
+
+        assert!(db.drop_namespace("dropped").unwrap());
+        assert!(!db.drop_namespace("dropped").unwrap());
+        assert!(
+            dropped.get("b").is_err(),
+            "A handle to a dropped namespace should fail."
+        );
+        assert_eq!(vec!["kept".to_string()], db.namespaces());
+    }
+
+    let db = DB::new(temp_dir.path()).unwrap();
+    assert_eq!(vec!["kept".to_string()], db.namespaces());
+    assert_eq!(
+        "1",
+        db.namespace("kept").unwrap().get("a").unwrap().unwrap()
+    );
+}
+
+#[test]
+fn test_namespace_lfu_eviction() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+
+    let cache = db
+        .namespace_with(
+            "cache",
+            NamespaceConfig::new().eviction(EvictionPolicy::LeastFrequentlyUsed { max_keys: 3 }),
+        )
+        .unwrap();
+
+    for i in 0..5 {
+        cache.set(&format!("key:{i}"), "value", None).unwrap();
+    }
+    for i in 2..5 {
+        cache.increment_frequency(&format!("key:{i}")).unwrap();
+    }
+
+    sleep(Duration::from_secs(2));
+
+    let stats = cache.stats().unwrap();
+    assert_eq!(3, stats.keys);
+    assert_eq!(2, stats.evictions);
+    assert!(cache.get("key:0").unwrap().is_none());
+    assert!(cache.get("key:1").unwrap().is_none());
+    for i in 2..5 {
+        assert!(cache.get(&format!("key:{i}")).unwrap().is_some());
+    }
+}
@@ -0,0 +1,144 @@
+use std::{sync::Arc, thread, time::Duration};
+
+use epoch_db::{
+    DB,
+    ratelimit::{Limit, RateLimiter},
+};
+use tempfile::tempdir;
+
+mod common;
+
+use common::reopen;
+
+const BUCKET: Limit = Limit::TokenBucket {
+    capacity: 3,
+    refill_every: Duration::from_secs(1),
+};
+
+const WINDOW: Limit = Limit::SlidingWindow {
+    max: 2,
+    window: Duration::from_secs(1),
+};
+
+#[test]
+fn test_token_bucket() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+    let limiter = RateLimiter::new(db.namespace("ratelimit").unwrap(), BUCKET);
+
+    let remaining: Vec<_> = (0..3)
+        .map(|_| limiter.check("user:1").unwrap())
+        .inspect(|d| assert!(d.allowed))
+        .map(|d| d.remaining)
+        .collect();
+    assert_eq!(vec![2, 1, 0], remaining);
+
+    let denied = limiter.check("user:1").unwrap();
+    assert!(!denied.allowed);
+    let wait = denied.retry_after.unwrap();
+    assert!(wait > Duration::ZERO && wait <= Duration::from_secs(1));
+    assert!(
+        limiter.check("user:2").unwrap().allowed,
+        "Keys are limited apart."
+    );
+    assert!(limiter.check_n("user:2", 4).unwrap().retry_after.is_none());
+
+    thread::sleep(wait + Duration::from_millis(50));
+    assert!(limiter.check("user:1").unwrap().allowed);
+    assert!(!limiter.check("user:1").unwrap().allowed);
+}
+
+#[test]
+fn test_sliding_window_and_cleanup() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+    let limiter = RateLimiter::new(db.namespace("ratelimit").unwrap(), WINDOW);
+
+    assert!(limiter.check("ip").unwrap().allowed);
+    thread::sleep(Duration::from_millis(300));
+    assert!(limiter.check("ip").unwrap().allowed);
+    let denied = limiter.check("ip").unwrap();
+    assert!(!denied.allowed);
+    assert_eq!(0, denied.remaining);
+    assert!(
+        denied.retry_after.unwrap() <= Duration::from_millis(700),
+        "The oldest request should leave the window first."
+    );
+
+    thread::sleep(Duration::from_millis(800));
+    let allowed = limiter.check("ip").unwrap();
+    assert!(allowed.allowed);
+    assert_eq!(0, allowed.remaining);
+
+    thread::sleep(Duration::from_millis(3500));
+    assert!(
+        db.namespace("ratelimit")
+            .unwrap()
+            .get("ip")
+            .unwrap()
+            .is_none(),
+        "The state of an idle key should expire."
+    );
+}
+
+#[test]
+fn test_limits_per_prefix() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+    let login = Limit::SlidingWindow {
+        max: 1,
+        window: Duration::from_secs(60),
+    };
+    let limiter = RateLimiter::new(db.namespace("ratelimit").unwrap(), BUCKET)
+        .limit_prefix("login:", login)
+        .limit_prefix("login:admin:", WINDOW);
+
+    assert_eq!(BUCKET, limiter.limit_of("api:alice"));
+    assert_eq!(login, limiter.limit_of("login:alice"));
+    assert_eq!(WINDOW, limiter.limit_of("login:admin:root"));
+
+    assert!(limiter.check("login:alice").unwrap().allowed);
+    assert!(!limiter.check("login:alice").unwrap().allowed);
+    assert!(limiter.check_n("login:admin:root", 2).unwrap().allowed);
+    assert!(limiter.check_n("api:alice", 3).unwrap().allowed);
+}
+
+#[test]
+fn test_state_survives_restarts_until_reset() {
+    let temp_dir = tempdir().unwrap();
+    {
+        let db = DB::new(temp_dir.path()).unwrap();
+        let limiter = RateLimiter::new(db.namespace("ratelimit").unwrap(), BUCKET);
+        assert!(limiter.check_n("user:1", 3).unwrap().allowed);
+    }
+
+    let db = reopen(|| DB::new(temp_dir.path())).unwrap();
+    let limiter = RateLimiter::new(db.namespace("ratelimit").unwrap(), BUCKET);
+    assert!(!limiter.check("user:1").unwrap().allowed);
+    limiter.reset("user:1").unwrap();
+    assert_eq!(2, limiter.check("user:1").unwrap().remaining);
+}
+
+#[test]
+fn test_concurrent_checks_never_exceed_the_limit() {
+    let temp_dir = tempdir().unwrap();
+    let db = Arc::new(DB::new(temp_dir.path()).unwrap());
+    let limit = Limit::TokenBucket {
+        capacity: 20,
+        refill_every: Duration::from_secs(3600),
+    };
+
+    let handles: Vec<_> = (0..8)
+        .map(|_| {
+            let db = Arc::clone(&db);
+            thread::spawn(move || {
+                let limiter = RateLimiter::new(db.namespace("ratelimit").unwrap(), limit);
+                (0..10)
+                    .filter(|_| limiter.check("shared").unwrap().allowed)
+                    .count()
+            })
+        })
+        .collect();
+    let allowed: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+    assert_eq!(20, allowed);
+}
@@ -0,0 +1,188 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    thread,
+    time::Duration,
+};
+
+use epoch_db::{DB, DBConfig, FrequencyTracking, db::errors::TransientError};
+use tempfile::tempdir;
+
+mod common;
+
+use common::reopen;
+
+fn populate(path: &Path) {
+    let db = DB::new(path).unwrap();
+    db.set("a", "1", None).unwrap();
+    db.set("b", "2", Some(Duration::from_secs(3600))).unwrap();
+    db.set_tags("a", &["red"]).unwrap();
+    db.namespace("users")
+        .unwrap()
+        .set("alice", "x", None)
+        .unwrap();
+}
+
+fn is_read_only(err: Box<dyn std::error::Error>) -> bool {
+    matches!(
+        err.downcast_ref::<TransientError>(),
+        Some(TransientError::ReadOnly)
+    )
+}
+
+#[test]
+fn test_reads_without_writes() {
+    let temp_dir = tempdir().unwrap();
+    populate(temp_dir.path());
+
+    let db = reopen(|| DBConfig::new(temp_dir.path()).read_only(true).open()).unwrap();
+    assert_eq!(Some("1".to_string()), db.get("a").unwrap());
+    assert_eq!(vec!["a"], db.keys_with_tag("red").unwrap());
+    let users = db.namespace("users").unwrap();
+    assert_eq!(Some("x".to_string()), users.get("alice").unwrap());
+    assert!(db.verify().unwrap().is_consistent());
+
+    assert!(is_read_only(db.set("c", "3", None).unwrap_err()));
+    assert!(is_read_only(db.remove("a").unwrap_err()));
+    assert!(is_read_only(db.increment_frequency("a").unwrap_err()));
+    assert!(is_read_only(db.set_tags("b", &["blue"]).unwrap_err()));
+    assert!(is_read_only(db.mremove(&["a", "b"]).unwrap_err()));
+    assert!(is_read_only(users.set("bob", "y", None).unwrap_err()));
+    assert!(is_read_only(users.clear().unwrap_err()));
+    assert!(is_read_only(db.namespace("new").unwrap_err()));
+    assert!(is_read_only(db.repair().unwrap_err()));
+
+    assert_eq!(Some("1".to_string()), db.get("a").unwrap());
+    assert!(db.get("c").unwrap().is_none());
+    assert_eq!(vec!["users"], db.namespaces());
+}
+
+#[test]
+fn test_reads_leave_frequencies_alone() {
+    let temp_dir = tempdir().unwrap();
+    populate(temp_dir.path());
+
+    {
+        let db = reopen(|| {
+            DBConfig::new(temp_dir.path())
+                .read_only(true)
+                .frequency_tracking(FrequencyTracking::Buffered)
+                .open()
+        })
+        .unwrap();
+        for _ in 0..5 {
+            db.get("a").unwrap();
+        }
+        thread::sleep(Duration::from_millis(300));
+    }
+
+    let db = reopen(|| DB::new(temp_dir.path())).unwrap();
+    assert_eq!(0, db.get_metadata("a").unwrap().unwrap().freq);
+    assert_eq!(4, db.last_seq().unwrap(), "Nothing should be logged.");
+}
+
+#[test]
+fn test_open_refuses_missing_and_outdated_databases() {
+    let temp_dir = tempdir().unwrap();
+    let missing = temp_dir.path().join("missing");
+    assert!(DBConfig::new(&missing).read_only(true).open().is_err());
+    assert!(!missing.exists(), "Nothing should be created.");
+
+    populate(temp_dir.path());
+    {
+        let sled_db = reopen(|| sled::open(temp_dir.path())).unwrap();
+        sled_db
+            .open_tree("state_tree")
+            .unwrap()
+            .insert("format_version", &3u64.to_be_bytes())
+            .unwrap();
+        sled_db.flush().unwrap();
+    }
+
+    let err = reopen(|| DBConfig::new(temp_dir.path()).read_only(true).open()).unwrap_err();
+    assert!(matches!(err, sled::Error::Unsupported(_)));
+    reopen(|| DB::new(temp_dir.path())).unwrap();
+}
+
+#[test]
+fn test_expired_keys_stay() {
+    let temp_dir = tempdir().unwrap();
+    {
+        let db = DB::new(temp_dir.path()).unwrap();
+        db.set("short", "lived", Some(Duration::from_secs(1)))
+            .unwrap();
+    }
+
+    thread::sleep(Duration::from_millis(1500));
+    let db = reopen(|| DBConfig::new(temp_dir.path()).read_only(true).open()).unwrap();
+    thread::sleep(Duration::from_millis(300));
+    assert!(
+        db.get_metadata("short").unwrap().is_some(),
+        "A read-only database should not expire keys."
+    );
+}
+
+#[test]
+fn test_cli_inspects_a_database_in_use() {
+    let temp_dir = tempdir().unwrap();
+    populate(temp_dir.path());
+    let epoch = env!("CARGO_BIN_EXE_epoch");
+    let path = temp_dir.path().to_str().unwrap();
+
+    let db = reopen(|| DB::new(temp_dir.path())).unwrap();
+    db.set("c", "3", None).unwrap();
+    db.flush().unwrap();
+
+    let verify = Command::new(epoch).args(["verify", path]).output().unwrap();
+    assert!(verify.status.success());
+    assert!(String::from_utf8_lossy(&verify.stdout).contains("4 keys checked"));
+
+    let repair = Command::new(epoch)
+        .args(["verify", path, "--repair"])
+        .output()
+        .unwrap();
+    assert_eq!(Some(1), repair.status.code());
+    assert!(String::from_utf8_lossy(&repair.stderr).contains("Cannot open"));
+    assert_eq!(Some("3".to_string()), db.get("c").unwrap());
+}
+
+/// Returns the path and content of every file under `dir`.
+fn files(dir: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+    let mut found = Vec::new();
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            found.extend(files(&path));
+        } else {
+            found.push((path.clone(), fs::read(&path).unwrap()));
+        }
+    }
+    found.sort();
+    found
+}
+
+#[test]
+fn test_open_writes_nothing() {
+    let temp_dir = tempdir().unwrap();
+    populate(temp_dir.path());
+    // NOTE: sled finishes closing the database in its background threads.
+    thread::sleep(Duration::from_millis(200));
+    let before = files(temp_dir.path());
+
+    {
+        let db = DBConfig::new(temp_dir.path())
+            .read_only(true)
+            .open()
+            .unwrap();
+        assert_eq!(Some("1".to_string()), db.get("a").unwrap());
+        assert_eq!(2, db.top_k_by_frequency(10).unwrap().len());
+        thread::sleep(Duration::from_millis(700));
+    }
+    thread::sleep(Duration::from_millis(200));
+
+    assert!(
+        before == files(temp_dir.path()),
+        "Files should be left untouched."
+    );
+}
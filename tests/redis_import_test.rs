@@ -0,0 +1,273 @@
+use std::{
+    fs,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use epoch_db::{
+    DB,
+    db::errors::TransientError,
+    redis::{RedisImportOptions, SkipReason, SkippedKey},
+};
+use tempfile::tempdir;
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+fn rdb_string(bytes: &[u8]) -> Vec<u8> {
+    assert!(bytes.len() < 64);
+    [&[bytes.len() as u8][..], bytes].concat()
+}
+
+/// Builds a version 11 snapshot around `body`, with the checksum disabled.
+fn rdb(body: &[u8]) -> Vec<u8> {
+    let mut rdb = b"REDIS0011".to_vec();
+    rdb.push(0xFA);
+    rdb.extend(rdb_string(b"redis-ver"));
+    rdb.extend(rdb_string(b"7.2.4"));
+    rdb.extend(body);
+    rdb.push(0xFF);
+    rdb.extend([0; 8]);
+    rdb
+}
+
+fn rdb_body(now: u64) -> Vec<u8> {
+    let mut body = vec![0xFE, 0, 0xFB, 6, 2];
+    // A plain string and a string with an expiration
+    body.extend([0]);
+    body.extend(rdb_string(b"user:1"));
+    body.extend(rdb_string(b"alice"));
+    body.push(0xFC);
+    body.extend((now + 3_600_000).to_le_bytes());
+    body.extend([0]);
+    body.extend(rdb_string(b"session"));
+    body.extend(rdb_string(b"token"));
+    // An expired string
+    body.push(0xFC);
+    body.extend((now - 1000).to_le_bytes());
+    body.extend([0]);
+    body.extend(rdb_string(b"old"));
+    body.extend(rdb_string(b"gone"));
+    // A string stored as an integer and an LZF compressed one
+    body.extend([0]);
+    body.extend(rdb_string(b"counter"));
+    body.extend([0xC1, 0x39, 0x30]);
+    body.extend([0]);
+    body.extend(rdb_string(b"lzf"));
+    body.extend([0xC3, 6, 9, 2, b'a', b'b', b'c', 0x80, 2]);
+    // A list and a hash stored as a ziplist
+    body.extend([1]);
+    body.extend(rdb_string(b"queue"));
+    body.extend([2]);
+    body.extend(rdb_string(b"a"));
+    body.extend(rdb_string(b"b"));
+    body.extend([13]);
+    body.extend(rdb_string(b"profile"));
+    body.extend(rdb_string(b"opaque ziplist"));
+    // A string in database 1
+    body.extend([0xFE, 1, 0]);
+    body.extend(rdb_string(b"other"));
+    body.extend(rdb_string(b"db1"));
+    body
+}
+
+fn resp(args: &[&str]) -> String {
+    let mut command = format!("*{}\r\n", args.len());
+    for arg in args {
+        command.push_str(&format!("${}\r\n{arg}\r\n", arg.len()));
+    }
+    command
+}
+
+fn write_aof(path: &Path, commands: &[&[&str]]) {
+    let log: String = commands.iter().map(|c| resp(c)).collect();
+    fs::write(path, log).unwrap();
+}
+
+#[test]
+fn test_rdb_import_strings_and_expirations() {
+    let temp_dir = tempdir().unwrap();
+    let now = now_ms();
+    let path = temp_dir.path().join("dump.rdb");
+    fs::write(&path, rdb(&rdb_body(now))).unwrap();
+
+    let db = DB::new(&temp_dir.path().join("db")).unwrap();
+    let report = db
+        .import_redis_rdb(&path, &RedisImportOptions::new())
+        .unwrap();
+    assert_eq!(report.imported, 4);
+    assert_eq!(report.expired, 1);
+    assert_eq!(
+        report.skipped,
+        vec![
+            SkippedKey {
+                key: "queue".to_string(),
+                reason: SkipReason::UnsupportedType("list"),
+            },
+            SkippedKey {
+                key: "profile".to_string(),
+                reason: SkipReason::UnsupportedType("hash"),
+            },
+        ]
+    );
+
+    assert_eq!(db.get("user:1").unwrap().as_deref(), Some("alice"));
+    assert_eq!(db.get("counter").unwrap().as_deref(), Some("12345"));
+    assert_eq!(db.get("lzf").unwrap().as_deref(), Some("abcabcabc"));
+    assert!(db.get("old").unwrap().is_none());
+    assert!(db.get("other").unwrap().is_none());
+
+    let ttl = db.get_metadata("session").unwrap().unwrap().ttl.unwrap();
+    assert_eq!(ttl, (now + 3_600_000).div_ceil(1000));
+    assert!(db.get_metadata("user:1").unwrap().unwrap().ttl.is_none());
+}
+
+#[test]
+fn test_rdb_import_other_database_into_namespace() {
+    let temp_dir = tempdir().unwrap();
+    let path = temp_dir.path().join("dump.rdb");
+    fs::write(&path, rdb(&rdb_body(now_ms()))).unwrap();
+
+    let db = DB::new(&temp_dir.path().join("db")).unwrap();
+    let options = RedisImportOptions::new().database(1).namespace("legacy");
+    let report = db.import_redis_rdb(&path, &options).unwrap();
+    assert_eq!(report.imported, 1);
+    assert!(report.skipped.is_empty());
+
+    let legacy = db.namespace("legacy").unwrap();
+    assert_eq!(legacy.get("other").unwrap().as_deref(), Some("db1"));
+    assert!(db.get("other").unwrap().is_none());
+}
+
+#[test]
+fn test_rdb_import_rejects_corrupted_snapshots() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(&temp_dir.path().join("db")).unwrap();
+    let path = temp_dir.path().join("dump.rdb");
+
+    let mut snapshot = rdb(&rdb_body(now_ms()));
+    let len = snapshot.len();
+    snapshot[len - 8..].copy_from_slice(&[1; 8]);
+    fs::write(&path, &snapshot).unwrap();
+    let err = db
+        .import_redis_rdb(&path, &RedisImportOptions::new())
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<TransientError>(),
+        Some(TransientError::MalformedRedisFile { .. })
+    ));
+
+    fs::write(&path, b"*1\r\n$4\r\nPING\r\n").unwrap();
+    assert!(
+        db.import_redis_rdb(&path, &RedisImportOptions::new())
+            .is_err()
+    );
+}
+
+#[test]
+fn test_aof_replay() {
+    let temp_dir = tempdir().unwrap();
+    let now = now_ms();
+    let path = temp_dir.path().join("appendonly.aof");
+    let deadline = (now + 60_000).to_string();
+    let past = (now / 1000 - 10).to_string();
+    write_aof(
+        &path,
+        &[
+            &["SELECT", "0"],
+            &["SET", "user:1", "alice"],
+            &["SET", "user:2", "bob", "PXAT", &deadline],
+            &["MULTI"],
+            &["INCR", "hits"],
+            &["INCRBY", "hits", "41"],
+            &["EXEC"],
+            &["SET", "temp", "x"],
+            &["DEL", "temp"],
+            &["SET", "stale", "x"],
+            &["EXPIREAT", "stale", &past],
+            &["HSET", "profile", "name", "alice"],
+            &["RENAME", "user:1", "user:one"],
+            &["APPEND", "user:one", "!"],
+            &["SELECT", "1"],
+            &["SET", "elsewhere", "x"],
+        ],
+    );
+    let mut log = fs::read_to_string(&path).unwrap();
+    log.insert_str(0, "#TS:1700000000\r\n");
+    fs::write(&path, log).unwrap();
+
+    let db = DB::new(&temp_dir.path().join("db")).unwrap();
+    let report = db
+        .import_redis_aof(&path, &RedisImportOptions::new())
+        .unwrap();
+    assert_eq!((report.imported, report.expired), (3, 1));
+    assert_eq!(
+        report.skipped,
+        vec![SkippedKey {
+            key: "profile".to_string(),
+            reason: SkipReason::UnsupportedCommand("HSET".to_string()),
+        }]
+    );
+
+    assert_eq!(db.get("user:one").unwrap().as_deref(), Some("alice!"));
+    assert_eq!(db.get("hits").unwrap().as_deref(), Some("42"));
+    assert!(db.get("user:1").unwrap().is_none());
+    assert!(db.get("temp").unwrap().is_none());
+    assert!(db.get("elsewhere").unwrap().is_none());
+    let ttl = db.get_metadata("user:2").unwrap().unwrap().ttl.unwrap();
+    assert_eq!(ttl, (now + 60_000).div_ceil(1000));
+
+    fs::write(&path, "*2\r\n$3\r\nDEL\r\n$3\r\nab").unwrap();
+    assert!(
+        db.import_redis_aof(&path, &RedisImportOptions::new())
+            .is_err()
+    );
+}
+
+#[test]
+fn test_aof_directory_with_rdb_base() {
+    let temp_dir = tempdir().unwrap();
+    let aof_dir = temp_dir.path().join("appendonlydir");
+    fs::create_dir(&aof_dir).unwrap();
+
+    fs::write(
+        aof_dir.join("appendonly.aof.1.base.rdb"),
+        rdb(&rdb_body(now_ms())),
+    )
+    .unwrap();
+    write_aof(
+        &aof_dir.join("appendonly.aof.1.incr.aof"),
+        &[
+            &["SET", "user:1", "carol"],
+            &["DEL", "queue"],
+            &["PERSIST", "session"],
+        ],
+    );
+    fs::write(
+        aof_dir.join("appendonly.aof.manifest"),
+        "file appendonly.aof.1.base.rdb seq 1 type b\n\
+         file appendonly.aof.1.incr.aof seq 1 type i\n",
+    )
+    .unwrap();
+
+    let db = DB::new(&temp_dir.path().join("db")).unwrap();
+    let report = db
+        .import_redis_aof(&aof_dir, &RedisImportOptions::new())
+        .unwrap();
+    assert_eq!((report.imported, report.expired), (4, 1));
+    assert_eq!(
+        report.skipped,
+        vec![SkippedKey {
+            key: "profile".to_string(),
+            reason: SkipReason::UnsupportedType("hash"),
+        }]
+    );
+
+    assert_eq!(db.get("user:1").unwrap().as_deref(), Some("carol"));
+    assert_eq!(db.get("lzf").unwrap().as_deref(), Some("abcabcabc"));
+    assert!(db.get_metadata("session").unwrap().unwrap().ttl.is_none());
+}
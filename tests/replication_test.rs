@@ -0,0 +1,319 @@
+use std::{
+    sync::Arc,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use epoch_db::{
+    DB, DBConfig,
+    db::{
+        errors::TransientError,
+        export::{ExportFormat, ExportOptions},
+    },
+    namespace::{EvictionPolicy, NamespaceConfig},
+    replication::{ReplicationFollower, ReplicationPrimary},
+};
+use tempfile::tempdir;
+
+fn wait_until(mut cond: impl FnMut() -> bool) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while Instant::now() < deadline {
+        if cond() {
+            return true;
+        }
+        sleep(Duration::from_millis(20));
+    }
+    false
+}
+
+#[test]
+fn test_follower_receives_writes() {
+    let primary_dir = tempdir().unwrap();
+    let follower_dir = tempdir().unwrap();
+    let primary_db = Arc::new(DB::new(primary_dir.path()).unwrap());
+    let follower_db = Arc::new(DB::new(follower_dir.path()).unwrap());
+
+    let primary = ReplicationPrimary::start(Arc::clone(&primary_db), "127.0.0.1:0").unwrap();
+    let _follower = ReplicationFollower::start(Arc::clone(&follower_db), primary.local_addr());
+
+    primary_db
+        .set("user:1", "Alice", Some(Duration::from_secs(120)))
+        .unwrap();
+    primary_db.set("user:2", "Bob", None).unwrap();
+    primary_db.set_tags("user:1", &["tenant:1"]).unwrap();
+    primary_db.increment_frequency("user:1").unwrap();
+    primary_db.increment_frequency("user:1").unwrap();
+    primary_db.remove("user:2").unwrap();
+
+    let head = primary_db.last_seq().unwrap();
+    assert!(wait_until(|| {
+        follower_db.replicated_seq().unwrap() == Some(head)
+    }));
+
+    assert_eq!(
+        primary_db.get_metadata("user:1").unwrap(),
+        follower_db.get_metadata("user:1").unwrap(),
+        "Frequency, creation time and TTL deadline should match the primary."
+    );
+    assert_eq!("Alice", follower_db.get("user:1").unwrap().unwrap());
+    assert!(follower_db.get("user:2").unwrap().is_none());
+    assert_eq!(
+        primary_db.top_k_by_frequency(10).unwrap(),
+        follower_db.top_k_by_frequency(10).unwrap()
+    );
+    assert_eq!(
+        vec!["user:1"],
+        follower_db.keys_with_tag("tenant:1").unwrap()
+    );
+}
+
+#[test]
+fn test_follower_bootstraps_from_snapshot() {
+    let primary_dir = tempdir().unwrap();
+    let follower_dir = tempdir().unwrap();
+    let primary_db = Arc::new(DB::new(primary_dir.path()).unwrap());
+    let follower_db = Arc::new(DB::new(follower_dir.path()).unwrap());
+
+    primary_db.set("user:1", "Alice", None).unwrap();
+    primary_db.increment_frequency("user:1").unwrap();
+    follower_db
+        .set("stale", "only on the follower", None)
+        .unwrap();
+
+    let primary = ReplicationPrimary::start(Arc::clone(&primary_db), "127.0.0.1:0").unwrap();
+    let _follower = ReplicationFollower::start(Arc::clone(&follower_db), primary.local_addr());
+
+    assert!(wait_until(|| follower_db.get("user:1").unwrap().is_some()));
+    assert!(
+        follower_db.get("stale").unwrap().is_none(),
+        "A bootstrap should discard data the primary doesn't have."
+    );
+    assert_eq!(1, follower_db.get_metadata("user:1").unwrap().unwrap().freq);
+
+    let followers = primary.followers().unwrap();
+    assert_eq!(1, followers.len());
+    assert_eq!(1, followers[0].bootstraps);
+}
+
+#[test]
+fn test_follower_rebootstraps_when_behind() {
+    let primary_dir = tempdir().unwrap();
+    let follower_dir = tempdir().unwrap();
+    let primary_db = Arc::new(
+        DBConfig::new(primary_dir.path())
+            .oplog_retention(5)
+            .open()
+            .unwrap(),
+    );
+    let follower_db = Arc::new(DB::new(follower_dir.path()).unwrap());
+
+    let primary = ReplicationPrimary::start(Arc::clone(&primary_db), "127.0.0.1:0").unwrap();
+
+    primary_db.set("user:0", "first", None).unwrap();
+    let follower = ReplicationFollower::start(Arc::clone(&follower_db), primary.local_addr());
+    assert!(wait_until(|| follower_db
+        .replicated_seq()
+        .unwrap()
+        .is_some()));
+    drop(follower);
+
+    for i in 1..50 {
+        primary_db.set(&format!("user:{i}"), "value", None).unwrap();
+    }
+    assert!(wait_until(|| {
+        primary_db.first_seq().unwrap().unwrap()
+            > follower_db.replicated_seq().unwrap().unwrap() + 1
+    }));
+
+    let _follower = ReplicationFollower::start(Arc::clone(&follower_db), primary.local_addr());
+    let head = primary_db.last_seq().unwrap();
+    assert!(wait_until(|| {
+        follower_db.replicated_seq().unwrap() == Some(head)
+    }));
+
+    for i in 0..50 {
+        assert!(follower_db.get(&format!("user:{i}")).unwrap().is_some());
+    }
+    assert!(wait_until(|| {
+        primary
+            .followers()
+            .unwrap()
+            .iter()
+            .any(|f| f.bootstraps == 1)
+    }));
+}
+
+#[test]
+fn test_follower_lag() {
+    let primary_dir = tempdir().unwrap();
+    let follower_dir = tempdir().unwrap();
+    let primary_db = Arc::new(DB::new(primary_dir.path()).unwrap());
+    let follower_db = Arc::new(DB::new(follower_dir.path()).unwrap());
+
+    let primary = ReplicationPrimary::start(Arc::clone(&primary_db), "127.0.0.1:0").unwrap();
+    let follower = ReplicationFollower::start(Arc::clone(&follower_db), primary.local_addr());
+    assert!(wait_until(|| follower.is_connected()));
+
+    for i in 0..20 {
+        primary_db.set(&format!("user:{i}"), "value", None).unwrap();
+    }
+
+    assert!(wait_until(|| {
+        let followers = primary.followers().unwrap();
+        followers.len() == 1 && followers[0].lag == 0
+    }));
+    assert_eq!(
+        primary_db.last_seq().unwrap(),
+        primary.followers().unwrap()[0].acked_seq
+    );
+}
+
+#[test]
+fn test_expiration_is_replicated() {
+    let primary_dir = tempdir().unwrap();
+    let follower_dir = tempdir().unwrap();
+    let primary_db = Arc::new(DB::new(primary_dir.path()).unwrap());
+    let follower_db = Arc::new(DB::new(follower_dir.path()).unwrap());
+
+    let primary = ReplicationPrimary::start(Arc::clone(&primary_db), "127.0.0.1:0").unwrap();
+    let _follower = ReplicationFollower::start(Arc::clone(&follower_db), primary.local_addr());
+
+    primary_db
+        .set("session:1", "token", Some(Duration::from_secs(3)))
+        .unwrap();
+    assert!(wait_until(|| follower_db
+        .get("session:1")
+        .unwrap()
+        .is_some()));
+
+    assert!(wait_until(|| primary_db
+        .get("session:1")
+        .unwrap()
+        .is_none()));
+    assert!(wait_until(|| follower_db
+        .get("session:1")
+        .unwrap()
+        .is_none()));
+    assert!(follower_db.get_metadata("session:1").unwrap().is_none());
+}
+
+#[test]
+fn test_namespaces_are_replicated() {
+    let primary_dir = tempdir().unwrap();
+    let follower_dir = tempdir().unwrap();
+    let primary_db = Arc::new(DB::new(primary_dir.path()).unwrap());
+    let follower_db = Arc::new(DB::new(follower_dir.path()).unwrap());
+
+    primary_db
+        .namespace("before")
+        .unwrap()
+        .set("key", "snapshot", None)
+        .unwrap();
+
+    let primary = ReplicationPrimary::start(Arc::clone(&primary_db), "127.0.0.1:0").unwrap();
+    let _follower = ReplicationFollower::start(Arc::clone(&follower_db), primary.local_addr());
+
+    let sessions = primary_db.namespace("sessions").unwrap();
+    sessions.set("session:1", "token", None).unwrap();
+    sessions.set("session:2", "token", None).unwrap();
+    sessions.clear().unwrap();
+    sessions.set("session:3", "token", None).unwrap();
+    primary_db.drop_namespace("before").unwrap();
+
+    let head = primary_db.last_seq().unwrap();
+    assert!(wait_until(|| {
+        follower_db.replicated_seq().unwrap() == Some(head)
+    }));
+
+    assert_eq!(vec!["sessions".to_string()], follower_db.namespaces());
+    let replica = follower_db.namespace("sessions").unwrap();
+    assert!(replica.get("session:1").unwrap().is_none());
+    assert!(replica.get("session:3").unwrap().is_some());
+}
+
+#[test]
+fn test_namespace_settings_are_replicated() {
+    let primary_dir = tempdir().unwrap();
+    let follower_dir = tempdir().unwrap();
+    let primary_db = Arc::new(DB::new(primary_dir.path()).unwrap());
+    let follower_db = Arc::new(DB::new(follower_dir.path()).unwrap());
+
+    let lfu = NamespaceConfig::new().eviction(EvictionPolicy::LeastFrequentlyUsed { max_keys: 10 });
+    primary_db.namespace_with("before", lfu.clone()).unwrap();
+
+    let primary = ReplicationPrimary::start(Arc::clone(&primary_db), "127.0.0.1:0").unwrap();
+    let _follower = ReplicationFollower::start(Arc::clone(&follower_db), primary.local_addr());
+    assert!(wait_until(|| follower_db
+        .replicated_seq()
+        .unwrap()
+        .is_some()));
+    assert_eq!(
+        lfu,
+        follower_db.namespace("before").unwrap().config().unwrap(),
+        "A bootstrap should carry the settings of every namespace."
+    );
+
+    let ttl = NamespaceConfig::new().default_ttl(Duration::from_secs(60));
+    primary_db.namespace_with("before", ttl.clone()).unwrap();
+    primary_db
+        .namespace_with("sessions", ttl.clone())
+        .unwrap()
+        .set("session:1", "token", None)
+        .unwrap();
+
+    let head = primary_db.last_seq().unwrap();
+    assert!(wait_until(|| {
+        follower_db.replicated_seq().unwrap() == Some(head)
+    }));
+
+    assert_eq!(
+        ttl,
+        follower_db.namespace("before").unwrap().config().unwrap()
+    );
+    let replica = follower_db.namespace("sessions").unwrap();
+    assert_eq!(ttl, replica.config().unwrap());
+    assert_eq!(
+        primary_db
+            .namespace("sessions")
+            .unwrap()
+            .get_metadata("session:1")
+            .unwrap(),
+        replica.get_metadata("session:1").unwrap()
+    );
+}
+
+#[test]
+fn test_snapshots_and_following_exclude_each_other() {
+    let primary_dir = tempdir().unwrap();
+    let follower_dir = tempdir().unwrap();
+    let primary_db = Arc::new(DB::new(primary_dir.path()).unwrap());
+    let follower_db = Arc::new(DB::new(follower_dir.path()).unwrap());
+    follower_db.set("local", "kept", None).unwrap();
+    primary_db.set("user:1", "Alice", None).unwrap();
+
+    let primary = ReplicationPrimary::start(Arc::clone(&primary_db), "127.0.0.1:0").unwrap();
+    let snapshot = follower_db.snapshot().unwrap();
+    let follower = ReplicationFollower::start(Arc::clone(&follower_db), primary.local_addr());
+
+    sleep(Duration::from_millis(500));
+    assert_eq!(None, follower_db.replicated_seq().unwrap());
+    assert_eq!("kept", snapshot.get("local").unwrap().unwrap());
+    assert!(matches!(
+        follower_db.snapshot().err().unwrap().downcast_ref(),
+        Some(TransientError::Replicating)
+    ));
+
+    drop(snapshot);
+    let head = primary_db.last_seq().unwrap();
+    assert!(wait_until(|| {
+        follower_db.replicated_seq().unwrap() == Some(head)
+    }));
+    assert_eq!(None, follower_db.get("local").unwrap());
+
+    let mut dump = Vec::new();
+    let options = ExportOptions::new(ExportFormat::Binary);
+    assert_eq!(1, follower_db.export(&mut dump, &options).unwrap());
+
+    drop(follower);
+    assert!(follower_db.snapshot().is_ok());
+}
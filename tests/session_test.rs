@@ -0,0 +1,182 @@
+use std::{sync::Arc, thread, time::Duration};
+
+use epoch_db::{
+    DB, DBConfig,
+    session::{SessionConfig, SessionStore},
+};
+use serde::{Deserialize, Serialize};
+use tempfile::tempdir;
+
+mod common;
+
+use common::reopen;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Cart {
+    user: String,
+    items: Vec<u64>,
+}
+
+fn cart(items: &[u64]) -> Cart {
+    Cart {
+        user: "alice".to_string(),
+        items: items.to_vec(),
+    }
+}
+
+#[test]
+fn test_create_load_save_destroy() {
+    let temp_dir = tempdir().unwrap();
+    let db = Arc::new(DB::new(temp_dir.path()).unwrap());
+    let store = SessionStore::new(db.clone(), SessionConfig::new()).unwrap();
+
+    let id = store.create(&cart(&[1])).unwrap();
+    assert_eq!(32, id.len());
+    assert_ne!(id, store.create(&cart(&[1])).unwrap());
+    assert_eq!(Some(cart(&[1])), store.load(&id).unwrap());
+
+    assert!(store.save(&id, &cart(&[1, 2])).unwrap());
+    assert_eq!(Some(cart(&[1, 2])), store.load(&id).unwrap());
+    assert_eq!(None, db.get(&id).unwrap());
+
+    assert!(store.destroy(&id).unwrap());
+    assert!(!store.destroy(&id).unwrap());
+    assert_eq!(None, store.load::<Cart>(&id).unwrap());
+}
+
+#[test]
+fn test_unknown_sessions_are_not_created() {
+    let temp_dir = tempdir().unwrap();
+    let db = Arc::new(DB::new(temp_dir.path()).unwrap());
+    let store = SessionStore::new(db, SessionConfig::new().namespace("web")).unwrap();
+
+    assert_eq!(None, store.load::<Cart>("missing").unwrap());
+    assert!(!store.save("missing", &cart(&[1])).unwrap());
+    assert!(!store.touch("missing").unwrap());
+    assert_eq!(None, store.load::<Cart>("missing").unwrap());
+}
+
+#[test]
+fn test_idle_timeout_slides() {
+    let temp_dir = tempdir().unwrap();
+    let db = Arc::new(DB::new(temp_dir.path()).unwrap());
+    let config = SessionConfig::new().idle_timeout(Duration::from_secs(3));
+    let store = SessionStore::new(db, config).unwrap();
+
+    let used = store.create(&cart(&[1])).unwrap();
+    let idle = store.create(&cart(&[2])).unwrap();
+    let touched = store.create(&cart(&[3])).unwrap();
+
+    thread::sleep(Duration::from_secs(2));
+    assert_eq!(Some(cart(&[1])), store.load(&used).unwrap());
+    assert!(store.touch(&touched).unwrap());
+
+    thread::sleep(Duration::from_secs(2));
+    assert_eq!(Some(cart(&[1])), store.load(&used).unwrap());
+    assert_eq!(Some(cart(&[3])), store.load(&touched).unwrap());
+    assert_eq!(None, store.load::<Cart>(&idle).unwrap());
+    assert!(!store.save(&idle, &cart(&[2])).unwrap());
+}
+
+#[test]
+fn test_absolute_timeout_caps_the_idle_timeout() {
+    let temp_dir = tempdir().unwrap();
+    let db = Arc::new(DB::new(temp_dir.path()).unwrap());
+    let config = SessionConfig::new()
+        .idle_timeout(Duration::from_secs(60))
+        .absolute_timeout(Duration::from_secs(2));
+    let store = SessionStore::new(db.clone(), config).unwrap();
+
+    let id = store.create(&cart(&[1])).unwrap();
+    let meta = db
+        .namespace("sessions")
+        .unwrap()
+        .get_metadata(&id)
+        .unwrap()
+        .unwrap();
+    assert_eq!(Some(meta.created_at + 2), meta.ttl);
+
+    thread::sleep(Duration::from_secs(1));
+    assert!(store.save(&id, &cart(&[1, 2])).unwrap());
+    let ttl = db
+        .namespace("sessions")
+        .unwrap()
+        .get_metadata(&id)
+        .unwrap()
+        .unwrap()
+        .ttl;
+    assert_eq!(Some(meta.created_at + 2), ttl);
+
+    thread::sleep(Duration::from_secs(2));
+    assert!(!store.touch(&id).unwrap());
+    assert_eq!(None, store.load::<Cart>(&id).unwrap());
+}
+
+#[test]
+fn test_expired_sessions_leave_no_chunks() {
+    let temp_dir = tempdir().unwrap();
+    {
+        let db = DBConfig::new(temp_dir.path())
+            .chunk_size(64)
+            .open()
+            .unwrap();
+        let config = SessionConfig::new().absolute_timeout(Duration::ZERO);
+        let store = SessionStore::new(Arc::new(db), config).unwrap();
+
+        let id = store.create(&cart(&[1; 64])).unwrap();
+        assert_eq!(None, store.load::<Cart>(&id).unwrap());
+    }
+
+    let sled_db = reopen(|| sled::open(temp_dir.path())).unwrap();
+    let chunks: usize = sled_db
+        .tree_names()
+        .iter()
+        .filter(|name| name.ends_with(b"blob_tree"))
+        .map(|name| sled_db.open_tree(name).unwrap().len())
+        .sum();
+    assert_eq!(0, chunks);
+}
+
+#[test]
+fn test_sessions_survive_restarts() {
+    let temp_dir = tempdir().unwrap();
+    let id = {
+        let db = Arc::new(DB::new(temp_dir.path()).unwrap());
+        let store = SessionStore::new(db, SessionConfig::new()).unwrap();
+        store.create(&cart(&[7])).unwrap()
+    };
+
+    let db = Arc::new(reopen(|| DB::new(temp_dir.path())).unwrap());
+    let store = SessionStore::new(db, SessionConfig::new()).unwrap();
+    assert_eq!(Some(cart(&[7])), store.load(&id).unwrap());
+}
+
+#[cfg(feature = "tower-sessions")]
+#[tokio::test]
+async fn test_tower_sessions_store() {
+    use tower_sessions_core::{Session, SessionStore as TowerSessionStore, session::Id};
+
+    let temp_dir = tempdir().unwrap();
+    let db = Arc::new(DB::new(temp_dir.path()).unwrap());
+    let store = Arc::new(SessionStore::new(db, SessionConfig::new()).unwrap());
+
+    let session = Session::new(None, store.clone(), None);
+    session.insert("user", "alice").await.unwrap();
+    session.save().await.unwrap();
+    let id = session.id().unwrap();
+
+    let session = Session::new(Some(id), store.clone(), None);
+    assert_eq!(
+        Some("alice".to_string()),
+        session.get::<String>("user").await.unwrap()
+    );
+    assert_eq!(
+        None,
+        TowerSessionStore::load(&*store, &Id::default())
+            .await
+            .unwrap()
+    );
+
+    session.delete().await.unwrap();
+    assert_eq!(None, TowerSessionStore::load(&*store, &id).await.unwrap());
+}
@@ -0,0 +1,243 @@
+use std::{sync::Arc, thread, time::Duration};
+
+use epoch_db::{
+    DB, DBConfig,
+    db::errors::TransientError,
+    session::{SessionConfig, SessionStore},
+};
+use tempfile::tempdir;
+
+mod common;
+
+use common::reopen;
+
+#[test]
+fn test_reads_as_of_creation() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+    db.set("a", "1", None).unwrap();
+    db.set("b", "2", None).unwrap();
+
+    let snapshot = db.snapshot().unwrap();
+    assert_eq!(db.last_seq().unwrap(), snapshot.seq());
+    db.set("a", "one", None).unwrap();
+    db.set("a", "uno", None).unwrap();
+    db.remove("b").unwrap();
+    db.set("c", "3", None).unwrap();
+
+    assert_eq!(Some("1".to_string()), snapshot.get("a").unwrap());
+    assert_eq!(1, snapshot.get_metadata("a").unwrap().unwrap().version);
+    assert_eq!(Some("2".to_string()), snapshot.get("b").unwrap());
+    assert!(snapshot.get("c").unwrap().is_none());
+
+    let later = db.snapshot().unwrap();
+    db.set("a", "eins", None).unwrap();
+    assert_eq!(Some("uno".to_string()), later.get("a").unwrap());
+    assert!(later.get("b").unwrap().is_none());
+    assert_eq!(Some("1".to_string()), snapshot.get("a").unwrap());
+    assert_eq!(Some("eins".to_string()), db.get("a").unwrap());
+}
+
+#[test]
+fn test_scan_is_consistent_while_keys_change() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+    for i in 0..50 {
+        db.set(&format!("key:{i:02}"), &i.to_string(), None)
+            .unwrap();
+    }
+    db.set("other", "x", None).unwrap();
+
+    let snapshot = db.snapshot().unwrap();
+    let mut seen = Vec::new();
+    for (n, entry) in snapshot.scan_prefix("key:").enumerate() {
+        let (key, (value, _)) = entry.unwrap();
+        seen.push((key, value));
+        // NOTE: Change the keys ahead of and behind the scan.
+        db.remove(&format!("key:{:02}", 49 - n)).unwrap();
+        db.set(&format!("key:{n:02}"), "changed", None).unwrap();
+        db.set(&format!("key:{n:02}:new"), "new", None).unwrap();
+    }
+
+    let expected: Vec<_> = (0..50)
+        .map(|i| (format!("key:{i:02}"), i.to_string()))
+        .collect();
+    assert_eq!(expected, seen);
+    assert_eq!(51, snapshot.iter().count());
+    assert_eq!(75, db.snapshot().unwrap().scan_prefix("key:").count());
+}
+
+#[test]
+fn test_expirations_and_chunked_values() {
+    let temp_dir = tempdir().unwrap();
+    let db = DBConfig::new(temp_dir.path())
+        .chunk_size(16)
+        .open()
+        .unwrap();
+    let large = "x".repeat(100);
+    db.set("large", &large, None).unwrap();
+    db.set("short", "lived", Some(Duration::from_secs(1)))
+        .unwrap();
+
+    let snapshot = db.snapshot().unwrap();
+    db.set("large", "small", None).unwrap();
+    thread::sleep(Duration::from_millis(2500));
+
+    assert!(db.get("short").unwrap().is_none());
+    assert_eq!(
+        Some("lived".to_string()),
+        snapshot.get("short").unwrap(),
+        "Expired keys should stay in the snapshot."
+    );
+    assert_eq!(Some(large), snapshot.get("large").unwrap());
+    assert!(db.verify().unwrap().is_consistent());
+
+    drop(snapshot);
+    assert!(
+        db.verify().unwrap().is_consistent(),
+        "The chunks of the old value should be removed."
+    );
+}
+
+#[test]
+fn test_tags_as_of_creation() {
+    let temp_dir = tempdir().unwrap();
+    let db = DBConfig::new(temp_dir.path())
+        .chunk_size(16)
+        .open()
+        .unwrap();
+    let large = "x".repeat(100);
+    db.set("large", &large, None).unwrap();
+    db.set("kept", &large, None).unwrap();
+    db.set_tags("large", &["old"]).unwrap();
+
+    let snapshot = db.snapshot().unwrap();
+    db.set_tags("large", &["new"]).unwrap();
+    db.set_tags("kept", &["new"]).unwrap();
+    db.set("large", "small", None).unwrap();
+
+    let meta = snapshot.get_metadata("large").unwrap().unwrap();
+    assert_eq!(vec!["old"], meta.tags.iter().collect::<Vec<_>>());
+    assert!(
+        snapshot
+            .get_metadata("kept")
+            .unwrap()
+            .unwrap()
+            .tags
+            .is_empty()
+    );
+    assert_eq!(Some(large.clone()), snapshot.get("large").unwrap());
+    assert_eq!(Some(large.clone()), snapshot.get("kept").unwrap());
+
+    drop(snapshot);
+    assert_eq!(Some(large), db.get("kept").unwrap());
+    assert!(
+        db.verify().unwrap().is_consistent(),
+        "The chunks of the value kept with its tags should be removed once."
+    );
+}
+
+#[test]
+fn test_session_ttls_as_of_creation() {
+    let temp_dir = tempdir().unwrap();
+    let db = Arc::new(DB::new(temp_dir.path()).unwrap());
+    let config = SessionConfig::new().idle_timeout(Duration::from_secs(60));
+    let store = SessionStore::new(Arc::clone(&db), config).unwrap();
+    let id = store.create("cart").unwrap();
+    let sessions = db.namespace("sessions").unwrap();
+    let ttl = sessions.get_metadata(&id).unwrap().unwrap().ttl;
+
+    let snapshot = db.snapshot().unwrap();
+    thread::sleep(Duration::from_millis(1100));
+    assert!(store.touch(&id).unwrap());
+
+    assert_ne!(ttl, sessions.get_metadata(&id).unwrap().unwrap().ttl);
+    let view = snapshot.namespace("sessions").unwrap();
+    assert_eq!(ttl, view.get_metadata(&id).unwrap().unwrap().ttl);
+}
+
+#[test]
+fn test_namespaces_cleared_or_dropped() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+    db.namespace("users")
+        .unwrap()
+        .set("alice", "1", None)
+        .unwrap();
+    db.namespace("jobs")
+        .unwrap()
+        .set("build", "2", None)
+        .unwrap();
+
+    let snapshot = db.snapshot().unwrap();
+    db.namespace("users").unwrap().clear().unwrap();
+    db.drop_namespace("jobs").unwrap();
+    db.namespace("jobs")
+        .unwrap()
+        .set("deploy", "3", None)
+        .unwrap();
+    db.namespace("later").unwrap();
+
+    assert_eq!(vec!["jobs", "users"], snapshot.namespaces());
+    let users = snapshot.namespace("users").unwrap();
+    assert_eq!(Some("1".to_string()), users.get("alice").unwrap());
+    let jobs = snapshot.namespace("jobs").unwrap();
+    assert_eq!(Some("2".to_string()), jobs.get("build").unwrap());
+    assert!(jobs.get("deploy").unwrap().is_none());
+    let err = snapshot.namespace("later").err().unwrap();
+    assert!(matches!(
+        err.downcast_ref::<TransientError>(),
+        Some(TransientError::NamespaceNotFound)
+    ));
+
+    let recreated = db.namespace("jobs").unwrap();
+    assert!(
+        recreated.get("build").unwrap().is_none(),
+        "A recreated namespace should not see the trees of the dropped one."
+    );
+    drop((snapshot, users, jobs));
+    assert_eq!(Some("3".to_string()), recreated.get("deploy").unwrap());
+    assert!(
+        db.namespace("users")
+            .unwrap()
+            .get("alice")
+            .unwrap()
+            .is_none()
+    );
+}
+
+#[test]
+fn test_released_snapshots_leave_nothing_behind() {
+    let temp_dir = tempdir().unwrap();
+    {
+        let db = DB::new(temp_dir.path()).unwrap();
+        db.set("a", "1", None).unwrap();
+        let snapshot = db.snapshot().unwrap();
+        db.set("a", "2", None).unwrap();
+        db.remove("a").unwrap();
+        assert_eq!(Some("1".to_string()), snapshot.get("a").unwrap());
+    }
+
+    {
+        let sled_db = reopen(|| sled::open(temp_dir.path())).unwrap();
+        let snapshots = sled_db.open_tree("snapshot_tree").unwrap();
+        let state = sled_db.open_tree("state_tree").unwrap();
+        assert!(snapshots.is_empty());
+        assert!(state.get("snapshot_seq").unwrap().is_none());
+
+        // NOTE: Stands for a process killed while a snapshot is live.
+        let pre_image = [&b"p\0a\0\0"[..], &3u64.to_be_bytes()].concat();
+        snapshots.insert(pre_image, &[]).unwrap();
+        state.insert("snapshot_seq", &2u64.to_be_bytes()).unwrap();
+        sled_db.flush().unwrap();
+    }
+
+    drop(reopen(|| DB::new(temp_dir.path())).unwrap());
+    let sled_db = reopen(|| sled::open(temp_dir.path())).unwrap();
+    assert!(
+        sled_db.open_tree("snapshot_tree").unwrap().is_empty(),
+        "Opening the database should discard the snapshots of the previous run."
+    );
+    let state = sled_db.open_tree("state_tree").unwrap();
+    assert!(state.get("snapshot_seq").unwrap().is_none());
+}
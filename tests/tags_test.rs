@@ -0,0 +1,112 @@
+use std::{thread::sleep, time::Duration};
+
+use epoch_db::DB;
+use tempfile::tempdir;
+
+#[test]
+fn test_keys_with_tag() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+
+    db.set("user:1", "Alice", None).unwrap();
+    db.set("user:2", "Bob", None).unwrap();
+    db.set("user:3", "Carol", None).unwrap();
+    db.set_tags("user:1", &["tenant:4", "vip"]).unwrap();
+    db.set_tags("user:2", &["tenant:4"]).unwrap();
+    db.set_tags("user:3", &["tenant:42"]).unwrap();
+
+    assert_eq!(
+        vec!["user:1", "user:2"],
+        db.keys_with_tag("tenant:4").unwrap()
+    );
+    assert_eq!(vec!["user:3"], db.keys_with_tag("tenant:42").unwrap());
+    assert!(db.keys_with_tag("tenant").unwrap().is_empty());
+
+    db.set_tags("user:1", &["vip"]).unwrap();
+    db.set("user:1", "Alice Smith", None).unwrap();
+    assert_eq!(vec!["user:2"], db.keys_with_tag("tenant:4").unwrap());
+    assert_eq!(vec!["user:1"], db.keys_with_tag("vip").unwrap());
+
+    db.remove("user:1").unwrap();
+    assert!(db.keys_with_tag("vip").unwrap().is_empty());
+}
+
+#[test]
+fn test_remove_by_tag() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+
+    for i in 0..5 {
+        let key = format!("page:{i}");
+        db.set(&key, "<html>", None).unwrap();
+        let tenant = format!("tenant:{}", i % 2);
+        db.set_tags(&key, &[&tenant, "page"]).unwrap();
+    }
+
+    assert_eq!(3, db.remove_by_tag("tenant:0").unwrap());
+    assert!(db.get("page:0").unwrap().is_none());
+    assert!(db.get("page:1").unwrap().is_some());
+    assert_eq!(vec!["page:1", "page:3"], db.keys_with_tag("page").unwrap());
+    assert_eq!(2, db.top_k_by_frequency(10).unwrap().len());
+
+    assert_eq!(0, db.remove_by_tag("tenant:0").unwrap());
+}
+
+#[test]
+fn test_expired_keys_leave_the_tag_index() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+
+    db.set("session:1", "token", Some(Duration::from_secs(1)))
+        .unwrap();
+    db.set_tags("session:1", &["user:1"]).unwrap();
+    assert_eq!(vec!["session:1"], db.keys_with_tag("user:1").unwrap());
+
+    sleep(Duration::from_millis(2500));
+    assert!(db.keys_with_tag("user:1").unwrap().is_empty());
+}
+
+#[test]
+fn test_namespace_tags_are_isolated() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+    let cache = db.namespace("cache").unwrap();
+
+    db.set("key", "default", None).unwrap();
+    db.set_tags("key", &["shared"]).unwrap();
+    cache.set("key", "cache", None).unwrap();
+    cache.set_tags("key", &["shared"]).unwrap();
+
+    assert_eq!(1, cache.remove_by_tag("shared").unwrap());
+    assert!(cache.get("key").unwrap().is_none());
+    assert_eq!(vec!["key"], db.keys_with_tag("shared").unwrap());
+
+    cache.set("other", "cache", None).unwrap();
+    cache.set_tags("other", &["shared"]).unwrap();
+    cache.clear().unwrap();
+    assert!(cache.keys_with_tag("shared").unwrap().is_empty());
+}
+
+#[test]
+fn test_tag_index_is_built_on_upgrade() {
+    let temp_dir = tempdir().unwrap();
+    {
+        let db = DB::new(temp_dir.path()).unwrap();
+        db.set("user:1", "Alice", None).unwrap();
+        db.set_tags("user:1", &["vip"]).unwrap();
+    }
+    {
+        // NOTE: Roll the database back to the format without a tag index.
+        let sled_db = sled::open(temp_dir.path()).unwrap();
+        sled_db.drop_tree("tag_tree").unwrap();
+        sled_db
+            .open_tree("state_tree")
+            .unwrap()
+            .insert("format_version", &2u64.to_be_bytes())
+            .unwrap();
+        sled_db.flush().unwrap();
+    }
+
+    let db = DB::new(temp_dir.path()).unwrap();
+    assert_eq!(vec!["user:1"], db.keys_with_tag("vip").unwrap());
+}
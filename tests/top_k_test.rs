@@ -0,0 +1,112 @@
+use std::{thread::sleep, time::Duration};
+
+use epoch_db::DB;
+use tempfile::tempdir;
+
+fn bump(db: &DB, key: &str, times: usize) {
+    for _ in 0..times {
+        db.increment_frequency(key).unwrap();
+    }
+}
+
+#[test]
+fn test_top_k_and_coldest() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+
+    for key in ["a", "b", "c", "d"] {
+        db.set(key, "value", None).unwrap();
+    }
+    bump(&db, "a", 5);
+    bump(&db, "b", 1);
+    bump(&db, "c", 3);
+
+    assert_eq!(
+        vec![("a".to_string(), 5), ("c".to_string(), 3)],
+        db.top_k_by_frequency(2).unwrap()
+    );
+    assert_eq!(
+        vec![("d".to_string(), 0), ("b".to_string(), 1)],
+        db.coldest(2).unwrap()
+    );
+    assert_eq!(4, db.top_k_by_frequency(10).unwrap().len());
+
+    db.set("a", "updated", None).unwrap();
+    assert_eq!(
+        ("a".to_string(), 5),
+        db.top_k_by_frequency(1).unwrap()[0],
+        "Updating a value should keep its frequency."
+    );
+
+    db.remove("a").unwrap();
+    db.mremove(&["d"]).unwrap();
+    assert_eq!(
+        vec![("c".to_string(), 3), ("b".to_string(), 1)],
+        db.top_k_by_frequency(10).unwrap()
+    );
+}
+
+#[test]
+fn test_top_k_after_expiration() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+
+    db.set("session:1", "token", Some(Duration::from_secs(1)))
+        .unwrap();
+    db.set("session:2", "token", None).unwrap();
+    bump(&db, "session:1", 3);
+
+    sleep(Duration::from_secs(2));
+
+    assert_eq!(
+        vec![("session:2".to_string(), 0)],
+        db.top_k_by_frequency(10).unwrap()
+    );
+}
+
+#[test]
+fn test_top_k_namespace() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+
+    let cache = db.namespace("cache").unwrap();
+    db.set("a", "default", None).unwrap();
+    cache.set("b", "cached", None).unwrap();
+    cache.increment_frequency("b").unwrap();
+
+    assert_eq!(
+        vec![("a".to_string(), 0)],
+        db.top_k_by_frequency(10).unwrap()
+    );
+    assert_eq!(
+        vec![("b".to_string(), 1)],
+        cache.top_k_by_frequency(10).unwrap()
+    );
+
+    cache.clear().unwrap();
+    assert!(cache.coldest(10).unwrap().is_empty());
+}
+
+#[test]
+fn test_index_rebuilt_on_open() {
+    let temp_dir = tempdir().unwrap();
+
+    {
+        let db = DB::new(temp_dir.path()).unwrap();
+        db.set("a", "1", None).unwrap();
+        db.set("b", "2", None).unwrap();
+        bump(&db, "b", 2);
+    }
+
+    {
+        // NOTE: Simulates a database written before the frequency index existed
+        let sled_db = sled::open(temp_dir.path()).unwrap();
+        sled_db.drop_tree("freq_index_tree").unwrap();
+    }
+
+    let db = DB::new(temp_dir.path()).unwrap();
+    assert_eq!(
+        vec![("b".to_string(), 2), ("a".to_string(), 0)],
+        db.top_k_by_frequency(10).unwrap()
+    );
+}
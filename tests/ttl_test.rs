@@ -1,8 +1,14 @@
+#![allow(clippy::needless_borrow)]
+
 use std::{thread::sleep, time::Duration};
 
 use tempfile::tempdir;
 use epoch_db::DB;
 
+mod common;
+
+use common::reopen;
+
 #[test]
 fn test_ttl() {
     let temp_dir = tempdir().unwrap();
@@ -99,3 +105,27 @@ fn test_manual_removal_of_ttl_key() {
         "Metadata should be gone after manual remove."
     );
 }
+
+#[test]
+fn test_undecodable_metadata_does_not_stop_expiration() {
+    let temp_dir = tempdir().unwrap();
+    {
+        let db = DB::new(temp_dir.path()).unwrap();
+        db.set("broken", "Alice", Some(Duration::from_secs(1)))
+            .unwrap();
+        db.set("later", "Bob", Some(Duration::from_secs(2)))
+            .unwrap();
+    }
+    {
+        let sled_db = reopen(|| sled::open(temp_dir.path())).unwrap();
+        let meta = sled_db.open_tree("freq_tree").unwrap();
+        meta.insert("broken", &[0xFF, 0xEE, 1, 2, 3]).unwrap();
+        sled_db.flush().unwrap();
+    }
+
+    let db = reopen(|| DB::new(temp_dir.path())).unwrap();
+    sleep(Duration::from_secs(3));
+
+    assert_eq!(None, db.get("broken").unwrap());
+    assert_eq!(None, db.get("later").unwrap());
+}
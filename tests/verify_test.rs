@@ -0,0 +1,174 @@
+use std::{path::Path, process::Command, time::Duration};
+
+use epoch_db::{
+    DB, DBConfig,
+    db::verify::{Inconsistency, Issue},
+};
+use tempfile::tempdir;
+
+/// Writes a few keys, then breaks the trees behind the back of the `DB`.
+fn corrupt(path: &Path) {
+    {
+        let db = DBConfig::new(path).chunk_size(16).open().unwrap();
+        db.set("good", "value", Some(Duration::from_secs(3600)))
+            .unwrap();
+        db.set("no_meta", "value", None).unwrap();
+        db.set("no_data", "value", None).unwrap();
+        db.set("bad_meta", "value", None).unwrap();
+        db.set("tagged", "value", None).unwrap();
+        db.set_tags("tagged", &["red"]).unwrap();
+        db.set("chunked", &"a".repeat(64), None).unwrap();
+    }
+
+    let sled_db = sled::open(path).unwrap();
+    let data = sled_db.open_tree("data_tree").unwrap();
+    let meta = sled_db.open_tree("freq_tree").unwrap();
+    let ttl = sled_db.open_tree("ttl_tree").unwrap();
+    let index = sled_db.open_tree("freq_index_tree").unwrap();
+    let blobs = sled_db.open_tree("blob_tree").unwrap();
+
+    meta.remove("no_meta").unwrap();
+    data.remove("no_data").unwrap();
+    meta.insert("bad_meta", &[0xFF, 0xEE, 1, 2, 3]).unwrap();
+    ttl.insert([&42u64.to_be_bytes()[..], b"tagged"].concat(), "tagged")
+        .unwrap();
+    ttl.insert("bad", "bad").unwrap();
+    index
+        .remove([&0u64.to_be_bytes()[..], b"tagged"].concat())
+        .unwrap();
+    let first_chunk = blobs.first().unwrap().unwrap().0;
+    blobs.remove(first_chunk).unwrap();
+    sled_db.flush().unwrap();
+}
+
+fn has(issues: &[Issue], key: &str, kind: Inconsistency) -> bool {
+    issues
+        .iter()
+        .any(|i| i.key == key.as_bytes() && i.kind == kind)
+}
+
+#[test]
+fn test_verify_reports_inconsistencies() {
+    let temp_dir = tempdir().unwrap();
+    corrupt(temp_dir.path());
+
+    let db = DB::new(temp_dir.path()).unwrap();
+    let report = db.verify().unwrap();
+    let issues = &report.issues;
+
+    assert!(!report.is_consistent());
+    assert!(has(issues, "no_meta", Inconsistency::OrphanData));
+    assert!(has(issues, "no_data", Inconsistency::OrphanMetadata));
+    assert!(has(issues, "bad_meta", Inconsistency::UndecodableMetadata));
+    assert!(has(issues, "chunked", Inconsistency::MissingChunks));
+    assert!(has(
+        issues,
+        "tagged",
+        Inconsistency::OrphanTtl { deadline: 42 }
+    ));
+    assert!(has(issues, "tagged", Inconsistency::MissingFrequencyIndex));
+    assert!(has(
+        issues,
+        "bad",
+        Inconsistency::MalformedEntry { tree: "ttl_tree" }
+    ));
+    assert!(
+        !issues.iter().any(|i| i.key == b"good"),
+        "Consistent keys should not be reported."
+    );
+}
+
+#[test]
+fn test_repair() {
+    let temp_dir = tempdir().unwrap();
+    corrupt(temp_dir.path());
+
+    let db = DB::new(temp_dir.path()).unwrap();
+    let repaired = db.repair().unwrap();
+    assert!(!repaired.is_consistent());
+
+    let report = db.verify().unwrap();
+    assert!(report.is_consistent(), "{:?}", report.issues);
+
+    assert_eq!("value", db.get("good").unwrap().unwrap());
+    assert!(db.get("no_meta").unwrap().is_none());
+    assert!(db.get("chunked").unwrap().is_none());
+    assert!(db.get_metadata("no_data").unwrap().is_none());
+    assert_eq!("value", db.get("bad_meta").unwrap().unwrap());
+    assert_eq!(5, db.get_metadata("bad_meta").unwrap().unwrap().value_len);
+    assert_eq!(vec!["tagged"], db.keys_with_tag("red").unwrap());
+    db.remove("bad_meta").unwrap();
+}
+
+#[test]
+fn test_namespaces_are_verified() {
+    let temp_dir = tempdir().unwrap();
+    {
+        let db = DB::new(temp_dir.path()).unwrap();
+        let ns = db.namespace("users").unwrap();
+        ns.set("alice", "1", None).unwrap();
+        ns.set("bob", "2", None).unwrap();
+    }
+    {
+        let sled_db = sled::open(temp_dir.path()).unwrap();
+        let name = sled_db
+            .tree_names()
+            .into_iter()
+            .find(|n| n.ends_with(b":freq_tree"))
+            .unwrap();
+        sled_db.open_tree(name).unwrap().remove("bob").unwrap();
+        sled_db.flush().unwrap();
+    }
+
+    let db = DB::new(temp_dir.path()).unwrap();
+    let report = db.verify().unwrap();
+    assert_eq!(1, report.keys);
+    let issue = |kind| Issue {
+        namespace: Some("users".to_string()),
+        key: b"bob".to_vec(),
+        kind,
+    };
+    assert_eq!(
+        vec![
+            issue(Inconsistency::OrphanData),
+            issue(Inconsistency::StaleFrequencyIndex { freq: 0 }),
+        ],
+        report.issues
+    );
+
+    db.repair().unwrap();
+    assert!(db.verify().unwrap().is_consistent());
+    let ns = db.namespace("users").unwrap();
+    assert!(ns.get("bob").unwrap().is_none());
+    assert_eq!("1", ns.get("alice").unwrap().unwrap());
+}
+
+#[test]
+fn test_cli() {
+    let temp_dir = tempdir().unwrap();
+    corrupt(temp_dir.path());
+    let epoch = env!("CARGO_BIN_EXE_epoch");
+
+    let verify = Command::new(epoch)
+        .args(["verify", temp_dir.path().to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert_eq!(Some(1), verify.status.code());
+    assert!(String::from_utf8_lossy(&verify.stdout).contains("\"no_meta\" has no metadata"));
+
+    let repair = Command::new(epoch)
+        .args(["verify", temp_dir.path().to_str().unwrap(), "--repair"])
+        .output()
+        .unwrap();
+    assert!(repair.status.success());
+
+    let verify = Command::new(epoch)
+        .args(["verify", temp_dir.path().to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(verify.status.success());
+    assert!(String::from_utf8_lossy(&verify.stdout).contains("0 inconsistencies found"));
+
+    let usage = Command::new(epoch).arg("check").output().unwrap();
+    assert_eq!(Some(2), usage.status.code());
+}
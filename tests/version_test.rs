@@ -0,0 +1,136 @@
+use std::{sync::Arc, thread, time::Duration};
+
+use epoch_db::{DB, DBConfig, db::errors::TransientError, namespace::NamespaceConfig};
+use tempfile::tempdir;
+
+/// Returns the versions of a conflict, `None` for any other error.
+fn conflict(err: Box<dyn std::error::Error>) -> Option<(u64, u64)> {
+    match err.downcast_ref::<TransientError>() {
+        Some(TransientError::VersionConflict { expected, actual }) => Some((*expected, *actual)),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_versions_bump_on_writes() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+    assert!(db.get_with_version("a").unwrap().is_none());
+
+    db.set("a", "1", None).unwrap();
+    assert_eq!(
+        Some(("1".to_string(), 1)),
+        db.get_with_version("a").unwrap()
+    );
+    db.set("a", "2", None).unwrap();
+    db.increment_frequency("a").unwrap();
+    db.set_tags("a", &["red"]).unwrap();
+    assert_eq!(
+        Some(("2".to_string(), 2)),
+        db.get_with_version("a").unwrap(),
+        "Only writes of the value should bump the version."
+    );
+
+    db.remove("a").unwrap();
+    db.set("a", "3", None).unwrap();
+    assert_eq!(
+        Some(("3".to_string(), 1)),
+        db.get_with_version("a").unwrap()
+    );
+}
+
+#[test]
+fn test_set_if_version_detects_conflicts() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+
+    assert_eq!(1, db.set_if_version("a", "1", None, 0).unwrap());
+    let err = db.set_if_version("a", "again", None, 0).unwrap_err();
+    assert_eq!(Some((0, 1)), conflict(err), "The key exists already.");
+
+    assert_eq!(2, db.set_if_version("a", "2", None, 1).unwrap());
+    let err = db.set_if_version("a", "stale", None, 1).unwrap_err();
+    assert_eq!(Some((1, 2)), conflict(err));
+    assert_eq!(Some("2".to_string()), db.get("a").unwrap());
+
+    db.remove("a").unwrap();
+    let err = db.set_if_version("a", "gone", None, 2).unwrap_err();
+    assert_eq!(Some((2, 0)), conflict(err));
+    assert!(db.get("a").unwrap().is_none());
+
+    db.set_if_version("b", "1", Some(Duration::from_secs(60)), 0)
+        .unwrap();
+    assert!(db.get_metadata("b").unwrap().unwrap().ttl.is_some());
+}
+
+#[test]
+fn test_concurrent_writers_never_lose_updates() {
+    let temp_dir = tempdir().unwrap();
+    let db = Arc::new(DB::new(temp_dir.path()).unwrap());
+    db.set("counter", "0", None).unwrap();
+
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let db = Arc::clone(&db);
+            thread::spawn(move || {
+                for _ in 0..25 {
+                    loop {
+                        let (val, version) = db.get_with_version("counter").unwrap().unwrap();
+                        let next = (val.parse::<u64>().unwrap() + 1).to_string();
+                        match db.set_if_version("counter", &next, None, version) {
+                            Ok(_) => break,
+                            Err(e) => assert!(conflict(e).is_some()),
+                        }
+                    }
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    assert_eq!(
+        Some(("100".to_string(), 101)),
+        db.get_with_version("counter").unwrap()
+    );
+}
+
+#[test]
+fn test_namespaces() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+    let sessions = db
+        .namespace_with(
+            "sessions",
+            NamespaceConfig::new().default_ttl(Duration::from_secs(60)),
+        )
+        .unwrap();
+
+    assert_eq!(1, sessions.set_if_version("s", "a", None, 0).unwrap());
+    assert!(db.get_with_version("s").unwrap().is_none());
+    assert_eq!(
+        Some(("a".to_string(), 1)),
+        sessions.get_with_version("s").unwrap()
+    );
+    assert!(
+        sessions.get_metadata("s").unwrap().unwrap().ttl.is_some(),
+        "The default TTL of the namespace should apply."
+    );
+
+    sessions.clear().unwrap();
+    let err = sessions.set_if_version("s", "b", None, 1).unwrap_err();
+    assert_eq!(Some((1, 0)), conflict(err));
+}
+
+#[test]
+fn test_conflicts_leave_no_chunks_behind() {
+    let temp_dir = tempdir().unwrap();
+    let db = DBConfig::new(temp_dir.path()).chunk_size(8).open().unwrap();
+    let large = "x".repeat(100);
+
+    db.set_if_version("a", &large, None, 0).unwrap();
+    assert!(db.set_if_version("a", &"y".repeat(100), None, 0).is_err());
+    assert_eq!(Some((large, 1)), db.get_with_version("a").unwrap());
+    assert!(db.verify().unwrap().is_consistent());
+}
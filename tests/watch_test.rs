@@ -0,0 +1,98 @@
+use std::time::Duration;
+
+use epoch_db::{DB, db::errors::TransientError, oplog::Mutation};
+use tempfile::tempdir;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[test]
+fn test_watch_prefix() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+
+    let mut watcher = db.watch("user:");
+
+    db.set("order:1", "ignored", None).unwrap();
+    db.set("user:1", "Alice", None).unwrap();
+    db.increment_frequency("user:1").unwrap();
+    db.remove("user:1").unwrap();
+
+    let entry = watcher.next_timeout(TIMEOUT).unwrap().unwrap();
+    assert!(matches!(entry.mutation, Mutation::Set { key, .. } if key == b"user:1"));
+    let entry = watcher.next_timeout(TIMEOUT).unwrap().unwrap();
+    assert!(matches!(entry.mutation, Mutation::Meta { meta, .. } if meta.freq == 1));
+    let entry = watcher.next_timeout(TIMEOUT).unwrap().unwrap();
+    assert!(matches!(entry.mutation, Mutation::Remove { .. }));
+
+    assert!(
+        watcher.next_timeout(Duration::from_millis(200)).is_none(),
+        "Keys outside of the prefix should not be reported."
+    );
+}
+
+#[test]
+fn test_watch_expirations() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+
+    let mut watcher = db.watch_expirations("");
+
+    db.set("session:1", "token", Some(Duration::from_secs(1)))
+        .unwrap();
+    db.set("session:2", "token", None).unwrap();
+
+    let entry = watcher.next_timeout(TIMEOUT).unwrap().unwrap();
+    assert_eq!(
+        Mutation::Expire {
+            key: b"session:1".to_vec()
+        },
+        entry.mutation
+    );
+    assert!(db.get("session:1").unwrap().is_none());
+}
+
+#[test]
+fn test_watch_namespace() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+
+    let cache = db.namespace("cache").unwrap();
+    let mut watcher = cache.watch("");
+
+    db.set("a", "default", None).unwrap();
+    cache.set("a", "cached", None).unwrap();
+    cache.clear().unwrap();
+
+    let entry = watcher.next_timeout(TIMEOUT).unwrap().unwrap();
+    assert_eq!(Some("cache".to_string()), entry.namespace);
+    assert!(matches!(entry.mutation, Mutation::Set { value, .. } if value == b"cached"));
+    let entry = watcher.next_timeout(TIMEOUT).unwrap().unwrap();
+    assert_eq!(Mutation::Clear, entry.mutation);
+}
+
+#[test]
+fn test_unread_watchers_lag_instead_of_blocking_writers() {
+    let temp_dir = tempdir().unwrap();
+    let db = DB::new(temp_dir.path()).unwrap();
+
+    let mut lagging = db.watch("");
+    let mut other = db.watch("other:");
+
+    // NOTE: The writes would block once 1024 changes are buffered if the watcher held them back.
+    for i in 0..1100 {
+        db.set(&format!("user:{i}"), "Alice", None).unwrap();
+    }
+    db.set("other:1", "Bob", None).unwrap();
+
+    let entry = other.next_timeout(TIMEOUT).unwrap().unwrap();
+    assert!(matches!(entry.mutation, Mutation::Set { key, .. } if key == b"other:1"));
+
+    for _ in 0..1024 {
+        assert!(lagging.next().unwrap().is_ok());
+    }
+    assert!(matches!(
+        lagging.next(),
+        Some(Err(TransientError::WatcherLagged))
+    ));
+    assert!(lagging.next().is_none());
+}